@@ -3,14 +3,7 @@ use std::str::FromStr;
 use std::fs::File;
 use std::io::{Cursor, Read};
 
-
-#[derive(PartialEq, Eq)]
-#[repr(u8)]
-pub enum MessageType {
-    File,
-    Image,
-    Text,
-}
+use shared::Message;
 
 
 /// `Command` represent all the available commands over known by the client.
@@ -20,9 +13,18 @@ pub enum MessageType {
 pub enum Command {
     Empty,
     Quit,
+    Who,
+    Mentions,
+    Search{query: String},
     Text{text: String},
+    Reply{id: i64, text: String},
     File{path: String, content: Vec<u8>},
     Image{path: String, content: Vec<u8>},
+    Download{id: i64},
+    Edit{id: i64, text: String},
+    Delete{id: i64},
+    React{id: i64, emoji: String},
+    Unreact{id: i64, emoji: String},
 }
 
 
@@ -32,9 +34,18 @@ impl fmt::Display for Command {
         let key = match self {
             Command::Empty => "",
             Command::Quit => "",
+            Command::Who => "Who",
+            Command::Mentions => "Mentions",
+            Command::Search {..} => "Search",
             Command::Text {..} => "Text",
+            Command::Reply {..} => "Reply",
             Command::Image {..} => "Image",
             Command::File {..} => "File",
+            Command::Download {..} => "Download",
+            Command::Edit {..} => "Edit",
+            Command::Delete {..} => "Delete",
+            Command::React {..} => "React",
+            Command::Unreact {..} => "Unreact",
         };
 
         write!(f, "{}", key)
@@ -62,6 +73,31 @@ impl FromStr for Command {
             ".file" => Command::File {path: String::new(), content: vec![]},
             ".image" => Command::Image {path: String::new(), content: vec![]},
             ".quit" => return Ok(Command::Quit),
+            ".who" => return Ok(Command::Who),
+            ".mentions" => return Ok(Command::Mentions),
+            ".search" => return match parts.next() {
+                Some(query) if !query.trim().is_empty() =>
+                    Ok(Command::Search {query: query.trim().to_string()}),
+                _ => Err("missing query argument".to_string()),
+            },
+            ".edit" => {
+                let (id, text) = parse_message_id_with(parts.next(), "text")?;
+                return Ok(Command::Edit {id, text})
+            },
+            ".reply" => {
+                let (id, text) = parse_message_id_with(parts.next(), "text")?;
+                return Ok(Command::Reply {id, text})
+            },
+            ".delete" => return Ok(Command::Delete {id: parse_message_id(parts.next())?}),
+            ".download" => return Ok(Command::Download {id: parse_message_id(parts.next())?}),
+            ".react" => {
+                let (id, emoji) = parse_message_id_with(parts.next(), "emoji")?;
+                return Ok(Command::React {id, emoji})
+            },
+            ".unreact" => {
+                let (id, emoji) = parse_message_id_with(parts.next(), "emoji")?;
+                return Ok(Command::Unreact {id, emoji})
+            },
             _ => return Ok(Command::Text {text: line.trim().to_owned()}),
         };
 
@@ -99,6 +135,40 @@ impl FromStr for Command {
 }
 
 
+/// `parse_message_id_with` parse arguments of commands of form `.command <id> <argument>`
+/// (e.g. `.edit <id> <text>`). The `name` of argument is used in error message.
+fn parse_message_id_with(
+    arguments: Option<String>,
+    name: &str,
+) -> Result<(i64, String), String> {
+    let arguments = arguments.unwrap_or_default();
+    let mut parts = arguments.trim().splitn(2, char::is_whitespace);
+
+    let id = parse_message_id(parts.next().map(str::to_string))?;
+    let argument = match parts.next().map(str::trim) {
+        Some(argument) if !argument.is_empty() => argument.to_string(),
+        _ => return Err(format!("missing {} argument", name)),
+    };
+
+    Ok((id, argument))
+}
+
+
+/// `parse_message_id` parse a server-side message ID given as a command argument.
+fn parse_message_id(argument: Option<String>) -> Result<i64, String> {
+    let argument = match argument {
+        Some(argument) if !argument.trim().is_empty() => argument,
+        _ => return Err("missing message id argument".to_string()),
+    };
+
+    argument
+        .trim()
+        .trim_start_matches('#')
+        .parse::<i64>()
+        .map_err(|_| format!("invalid message id: {}", argument.trim()))
+}
+
+
 /// `check_image` implement transparent conversion of any possible (tested just with jpeg format)
 /// image file format into the PNG file format.
 fn check_image(content: &mut Vec<u8>) -> Result<(), String> {
@@ -122,20 +192,48 @@ fn check_image(content: &mut Vec<u8>) -> Result<(), String> {
 
 
 impl Command {
-    /// `extract` is used for extraction of data of command into 3-tuple to be passed via channel.
-    pub fn extract(self) -> (MessageType, Option<String>, Option<Vec<u8>>) {
+    /// `into_message` turns the command into a message to be passed via channel and sent to
+    /// the server. Commands handled locally by the client have no message.
+    pub fn into_message(self) -> Option<Message> {
         match self {
             Command::Text {text} =>
-                (MessageType::Text, Some(text), None),
+                Some(Message::Text(text)),
+
+            Command::Reply {id, text} =>
+                Some(Message::Reply {reply_to: id, text}),
 
             Command::File {path, content} =>
-                (MessageType::File, Some(path), Some(content)),
+                Some(Message::File {filename: path, payload: content}),
+
+            Command::Image {content, ..} =>
+                Some(Message::Image(content)),
+
+            Command::Download {id} =>
+                Some(Message::Download {id}),
+
+            Command::Who =>
+                Some(Message::Who),
+
+            Command::Mentions =>
+                Some(Message::UnreadMentions),
+
+            Command::Search {query} =>
+                Some(Message::Search {query, page: 1}),
+
+            Command::Edit {id, text} =>
+                Some(Message::Edit {id, text}),
+
+            Command::Delete {id} =>
+                Some(Message::Delete {id}),
+
+            Command::React {id, emoji} =>
+                Some(Message::AddReaction {id, emoji}),
 
-            Command::Image {path, content} =>
-                (MessageType::Image, Some(path), Some(content)),
+            Command::Unreact {id, emoji} =>
+                Some(Message::RemoveReaction {id, emoji}),
 
             Command::Quit | Command::Empty =>
-                (MessageType::Text, None, None),
+                None,
         }
     }
 }
@@ -0,0 +1,110 @@
+use std::io::{self, BufRead, IsTerminal};
+use std::sync::Mutex;
+
+use rustyline::error::ReadlineError;
+use rustyline::{
+    Cmd,
+    ConditionalEventHandler,
+    DefaultEditor,
+    Event,
+    EventContext,
+    EventHandler,
+    KeyCode,
+    KeyEvent,
+    Modifiers,
+    RepeatCount,
+};
+
+
+/// `LineReader` reads lines of the standard input. Lines typed on a terminal are edited by
+/// [rustyline] (with the usual editing keys and history) and every typed character is reported
+/// (see [LineReader::new]), otherwise the standard input is read as plain buffered lines.
+///
+/// Reading blocks the thread, so the reader is meant to be used from a blocking task
+/// (`tokio::task::spawn_blocking`).
+pub enum LineReader {
+    Terminal(Box<DefaultEditor>),
+    Plain,
+}
+
+
+impl LineReader {
+    /// `new` creates reader of the standard input. `on_key` gets the (incomplete) line whenever
+    /// a character is typed on a terminal.
+    pub fn new<F>(on_key: F) -> LineReader
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        if !io::stdin().is_terminal() {
+            return LineReader::Plain;
+        }
+        let Ok(mut editor) = DefaultEditor::new() else {
+            return LineReader::Plain;
+        };
+
+        let handler = KeyHandler { on_key: Mutex::new(Box::new(on_key)) };
+        editor.bind_sequence(Event::Any, EventHandler::Conditional(Box::new(handler)));
+        LineReader::Terminal(Box::new(editor))
+    }
+
+    /// `read_line` reads the next line, `None` means the end of the input (Ctrl+D, or Ctrl+C
+    /// on a terminal).
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        match self {
+            LineReader::Terminal(editor) => match editor.readline("") {
+                Ok(line) => {
+                    let _ = editor.add_history_entry(line.as_str());
+                    Ok(Some(line))
+                },
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+                Err(ReadlineError::Io(err)) => Err(err),
+                Err(err) => Err(io::Error::other(err)),
+            },
+            LineReader::Plain => {
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line)? {
+                    0 => Ok(None),
+                    _ => Ok(Some(line)),
+                }
+            },
+        }
+    }
+}
+
+
+/// Callback of [LineReader] getting lines edited on a terminal.
+type KeyCallback = Box<dyn FnMut(&str) + Send>;
+
+
+/// `KeyHandler` reports lines edited on a terminal to the callback of [LineReader] whenever
+/// a character is typed, keys keep their default behaviour.
+struct KeyHandler {
+    on_key: Mutex<KeyCallback>,
+}
+
+
+impl ConditionalEventHandler for KeyHandler {
+    fn handle(
+        &self,
+        event: &Event,
+        _: RepeatCount,
+        _: bool,
+        context: &EventContext,
+    ) -> Option<Cmd> {
+        // The key is not applied yet, so the character is inserted into a copy of the line
+        // (keys with Ctrl or Alt are editing commands).
+        let typed = match event.get(0) {
+            Some(KeyEvent(KeyCode::Char(character), modifiers))
+                if !modifiers.intersects(Modifiers::CTRL | Modifiers::ALT) => Some(*character),
+            _ => None,
+        };
+        if let Some(character) = typed {
+            let mut line = context.line().to_string();
+            line.insert(context.pos(), character);
+            if let Ok(mut on_key) = self.on_key.lock() {
+                on_key(&line);
+            }
+        }
+        None
+    }
+}
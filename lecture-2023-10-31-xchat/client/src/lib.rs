@@ -1,10 +1,12 @@
 mod commands;
+mod input;
 
+use std::collections::HashMap;
 use std::io;
 use std::io::Write;
 use std::path::Path;
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::Instant;
 
 use tokio::net::TcpStream;
 use tokio::io::{AsyncWriteExt};
@@ -17,23 +19,63 @@ use color_eyre::eyre;
 use ::anyhow as eyre;
 use eyre::{anyhow, bail, Result, Context};
 
-use commands::{Command, MessageType};
-use shared::{Message, timestamp_to_string};
+use commands::Command;
+use input::LineReader;
+use shared::{Message, is_mentioned};
+
+
+/// How long is "X is typing…" indicator considered valid without being refreshed.
+const TYPING_EXPIRATION: Duration = Duration::from_secs(5);
+
+/// Minimal delay between two typing notifications sent to the server (the same as default
+/// throttling of the server).
+const TYPING_THROTTLE: Duration = Duration::from_secs(2);
+
+/// ANSI escape sequences enclosing chat messages which mention the user (bold yellow).
+const HIGHLIGHT: (&str, &str) = ("\x1b[1;33m", "\x1b[0m");
 
 
 #[repr(u8)]
 enum OutputType {
     StandardOutput,
     ErrorOutput,
+    /// Terminal bell (the text is ignored).
+    Bell,
+}
+
+
+/// `init_logging` installs global `tracing` subscriber writing diagnostic log records
+/// (filtered by the given filter, e.g. `warn,client=debug`) to the standard error output,
+/// so they do not mix with the chat printed to the standard output.
+pub fn init_logging(filter: &str, json: bool) -> Result<()> {
+    let filter = match tracing_subscriber::EnvFilter::try_new(filter) {
+        Ok(filter) => filter,
+        Err(err) => bail!("invalid log filter: {}", err.to_string()),
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    let result = if json {
+        builder.json().flatten_event(true).try_init()
+    } else {
+        builder.try_init()
+    };
+
+    result.map_err(|err| anyhow!("failed to initialize logging: {}", err.to_string()))
 }
 
 
 /// `run_interactive` is an entry point for interactive mode of this program.
 /// It spins up three async tasks (input processing, server communication, and printing).
+/// A non-empty session `token` is used for log-in instead of login and password. With `bell`
+/// the terminal bell rings whenever the user is mentioned.
 pub async fn run_interactive(
         address: &str,
         user_login: &str,
         user_pass: &str,
+        token: &str,
+        bell: bool,
 ) -> Result<()> {
     #[cfg(debug_assertions)]
     color_eyre::install()?;
@@ -44,45 +86,59 @@ pub async fn run_interactive(
         Ok(stream) => stream,
         Err(err) => bail!("failed to connect: {}", err.to_string()),
     };
+    tracing::info!(%address, "connected to server");
 
     // Login process.
-    match _login(&mut stream, user_login, user_pass).await {
-        Ok(motd) => println!("connected!\n{}", motd),
+    match _login(&mut stream, user_login, user_pass, token).await {
+        Ok(motd) => {
+            tracing::info!(login = user_login, "authenticated");
+            println!("connected!\n{}", motd)
+        },
         Err(err) => bail!("failed to authenticate: {}", err.to_string()),
     }
 
-    // Channel for sending of commands from input task to processing task.
-    let (tx_cmd, rx_cmd) =
-        flume::unbounded::<(MessageType, Option<String>, Option<Vec<u8>>)>();
+    // Channel for sending of messages (made of commands) from input task to processing task.
+    let (tx_cmd, rx_cmd) = flume::unbounded::<Message>();
 
     // Channel for accepting of any messages to be print out on a stdout or stderr.
     let (tx_print, rx_print) = flume::unbounded::<(OutputType, String)>();
 
     // Input task takes care of reading from stdio, parsing `Action` and sending it together with
-    // the rest text on the line over the channel to the processing task.
+    // the rest text on the line over the channel to the processing task. Reading blocks, so it
+    // runs as a blocking task not to hold up the other tasks.
     let tx_print_for_input_task = tx_print.clone();
-    let input_task = tokio::spawn(async move {
+    let input_task = tokio::task::spawn_blocking(move || {
         let tx_print = tx_print_for_input_task;
-        let mut text: String = String::new();
-        loop {
-            text.clear();
 
-            let count = std::io::stdin().read_line(&mut text);
-            if count.is_err() {
-                bail!("failed to read from stdin: {}", count.err().unwrap().to_string());
-            }
-            if let Ok(0) = count { // no \n character -> finished by Ctrl+D
-                return Ok(());
+        // Other users are told that this one is composing a message (on a terminal only).
+        let tx_typing = tx_cmd.clone();
+        let mut last_typing: Option<Instant> = None;
+        let on_key = move |line: &str| {
+            let Some(to) = composed_message(line) else { return };
+            let throttled = last_typing.is_some_and(|last| last.elapsed() < TYPING_THROTTLE);
+            if !throttled {
+                last_typing = Some(Instant::now());
+                let _ = tx_typing.send(Message::Typing { to });
             }
+        };
+        let mut reader = LineReader::new(on_key);
+
+        loop {
+            let text = match reader.read_line() {
+                Ok(Some(text)) => text,
+                Ok(None) => return Ok(()), // finished by Ctrl+D
+                Err(err) => bail!("failed to read from stdin: {}", err.to_string()),
+            };
 
             match Command::from_str(&text) {
                 Ok(Command::Quit) => return Ok(()),
                 Ok(Command::Empty) => continue,
-                Ok(command) => match tx_cmd.send(command.extract()) {
-                    Ok(_) => {},
-                    Err(err) => tx_print.
-                        send((OutputType::ErrorOutput, err.to_string())).
-                        unwrap(),
+                Ok(command) => if let Some(message) = command.into_message() {
+                    if let Err(err) = tx_cmd.send(message) {
+                        tx_print.
+                            send((OutputType::ErrorOutput, err.to_string())).
+                            unwrap();
+                    }
                 },
                 Err(err) => tx_print.send((
                     OutputType::ErrorOutput,
@@ -94,26 +150,23 @@ pub async fn run_interactive(
 
     // Processing task awaits a tuples (with action and text to be processed) from the input
     // channel, process the input text and prints output to the stdout.
+    // Login used by a resumed session is known just from its `Session` message.
+    let mut own_login = user_login.to_string();
     let process_task = tokio::spawn(async move {
         let tx_print = tx_print;    // takes ownership
         let mut processed = (false, false);
         let delay = Duration::from_millis(10);
+        let mut typing_users: HashMap<String, Instant> = HashMap::new();
 
         loop {
+            // Expiration of typing indicators that were not refreshed for a while.
+            typing_users.retain(|_, since| since.elapsed() < TYPING_EXPIRATION);
+
             // Processing command for sending a message to the server.
             processed.0 = true;
             match rx_cmd.try_recv() {
-                Ok(request) => {
-                    let message = match request {
-                        (MessageType::File, Some(path), Some(content)) =>
-                            Message::File {filename: path, payload: content},
-                        (MessageType::Image, _, Some(content)) =>
-                            Message::Image(content),
-                        (MessageType::Text, Some(text), None) =>
-                            Message::Text(text),
-                        _ => continue,
-                    };
-
+                Ok(message) => {
+                    tracing::debug!("sending message to the server");
                     match message.send(&mut stream).await {
                         Ok(_) => {},
                         Err(err) => tx_print.
@@ -136,34 +189,217 @@ pub async fn run_interactive(
                     tx_print.send((OutputType::StandardOutput, text)).unwrap();
                 },
 
-                // received image should be saved as png file into the images subdirectory
-                Ok(Some(Message::Image(payload))) => {
-                    tx_print
-                        .send((OutputType::StandardOutput, "Receiving image...".to_string()))
-                        .unwrap();
+                // received file should be saved into the files subdirectory, received image
+                // (or thumbnail of an image) into the images subdirectory (all of them are
+                // validated by the server)
+                Ok(Some(Message::Attachment(attachment))) => {
+                    let name = match &attachment.filename {
+                        Some(filename) => filename.clone(),
+                        None => format!("image #{}", attachment.id),
+                    };
+                    let mut info_text = format!(
+                        "Receiving {} from {} ({}, {} bytes)",
+                        name,
+                        attachment.login,
+                        attachment.mime,
+                        attachment.size,
+                    );
+                    if attachment.thumbnail.is_some() {
+                        info_text.push_str(&format!(
+                            ", thumbnail only (.download {} to get the original)",
+                            attachment.id,
+                        ));
+                    }
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
 
-                    if let Err(err) = save_image(payload).await {
-                        let error_message = format!("Failed to save image: {}", err);
+                    let saving = match (&attachment.filename, attachment.thumbnail) {
+                        (Some(filename), _) => save_file(filename, attachment.payload).await,
+                        (None, Some(thumbnail)) => save_thumbnail(attachment.id, thumbnail).await,
+                        (None, None) =>
+                            save_image(attachment.id, &attachment.mime, attachment.payload).await,
+                    };
+                    if let Err(err) = saving {
+                        let error_message = format!("Failed to save attachment: {}", err);
                         tx_print
                             .send((OutputType::ErrorOutput, error_message))
                             .unwrap();
                     }
                 },
 
-                // received file should be saved into the files subdirectory
-                Ok(Some(Message::File{filename, payload})) => {
-                    let info_text = format!("Receiving {}", filename);
+                // chat message stored by the server is printed with its ID to be referenced,
+                // replies are preceded by a quoted excerpt of the parent message, messages
+                // mentioning this user (by others) are highlighted
+                Ok(Some(Message::Chat{id, login, display_name, text, reply_to, ..})) => {
+                    typing_users.remove(&login);
+                    let author = author_name(&login, &display_name);
+                    let mentioned = login != own_login && is_mentioned(&text, &own_login);
+                    let mut text = format!("[#{}] {}: {}", id, author, text);
+                    if mentioned {
+                        text = format!("{}{}{}", HIGHLIGHT.0, text, HIGHLIGHT.1);
+                    }
+                    if let Some(quote) = reply_to {
+                        let quote = format!("> [#{}] {}: {}", quote.id, quote.login, quote.excerpt);
+                        text = format!("{}\n{}", quote, text);
+                    }
+                    tx_print.send((OutputType::StandardOutput, text)).unwrap();
+                },
+
+                // action of a user (`/me`) is written in third person
+                Ok(Some(Message::Action{login, display_name, text, ..})) => {
+                    typing_users.remove(&login);
+                    let info_text = format!("* {} {}", display_name.unwrap_or(login), text);
                     tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
 
-                    if let Err(err) = save_file(&filename, payload).await {
-                        let error_message = format!("Failed to save file: {}", err);
-                        tx_print
-                            .send((OutputType::ErrorOutput, error_message))
-                            .unwrap();
+                Ok(Some(Message::NickChanged{login, display_name})) => {
+                    let info_text = match display_name {
+                        Some(display_name) =>
+                            format!("* {} is now known as {}", login, display_name),
+                        None => format!("* {} cleared the display name", login),
+                    };
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                Ok(Some(Message::Topic{topic, login, timestamp})) => {
+                    let info_text = if topic.is_empty() {
+                        format!("* topic cleared by {} ({})", login, timestamp)
+                    } else {
+                        format!("* topic: {} (set by {} at {})", topic, login, timestamp)
+                    };
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // direct message (`/msg`) sent or received by this user
+                Ok(Some(Message::Direct{login, display_name, to, text, ..})) => {
+                    typing_users.remove(&login);
+                    let author = author_name(&login, &display_name);
+                    let info_text = format!("[DM {} -> {}] {}", author, to, text);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // number of direct messages sent while this user was offline (they follow)
+                Ok(Some(Message::QueuedDirects{count})) => {
+                    let info_text = format!(
+                        "* {} direct message(s) arrived while you were away:",
+                        count,
+                    );
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // session issued at log-in, it might be resumed later by `--token`
+                Ok(Some(Message::Session{login, token, expires_at})) => {
+                    own_login = login;
+                    let info_text = format!(
+                        "* session valid until {} (log in again by --token {})",
+                        expires_at,
+                        token,
+                    );
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // the highlighted chat message is printed already, the mention just rings
+                Ok(Some(Message::Mentioned(_))) => {
+                    if bell {
+                        tx_print.send((OutputType::Bell, String::new())).unwrap();
+                    }
+                },
+
+                // response to the `.mentions` command
+                Ok(Some(Message::MentionList{mentions})) => {
+                    let mut lines = vec![format!("Unread mentions ({}):", mentions.len())];
+                    for mention in mentions {
+                        lines.push(format!(
+                            "  [#{}] {} ({}): {}",
+                            mention.id,
+                            mention.login,
+                            mention.timestamp,
+                            mention.excerpt,
+                        ));
+                    }
+                    tx_print.send((OutputType::StandardOutput, lines.join("\n"))).unwrap();
+                },
+
+                // output of server commands (e.g. `/help` or `/whois`)
+                Ok(Some(Message::Info(text))) => {
+                    tx_print.send((OutputType::StandardOutput, text)).unwrap();
+                },
+
+                Ok(Some(Message::Edited{id, text, timestamp})) => {
+                    let info_text = format!("* message #{} edited ({}): {}", id, timestamp, text);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                Ok(Some(Message::Deleted{id, timestamp})) => {
+                    let info_text = format!("* message #{} deleted ({})", id, timestamp);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                Ok(Some(Message::ReactionAdded{id, login, emoji})) => {
+                    let info_text = format!("* {} reacted {} to message #{}", login, emoji, id);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                Ok(Some(Message::ReactionRemoved{id, login, emoji})) => {
+                    let info_text = format!("* {} removed {} from message #{}", login, emoji, id);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // rejected request
+                Ok(Some(Message::Error(reason))) => {
+                    tx_print.send((OutputType::ErrorOutput, reason)).unwrap();
+                },
+
+                // presence notifications about other users
+                Ok(Some(Message::UserJoined{login, timestamp})) => {
+                    let info_text = format!("* {} joined ({})", login, timestamp);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                Ok(Some(Message::UserLeft{login, timestamp})) => {
+                    let info_text = format!("* {} left ({})", login, timestamp);
+                    tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                },
+
+                // typing indicator is shown only once until it expires
+                Ok(Some(Message::UserTyping{login, to})) => {
+                    if typing_users.insert(login.clone(), Instant::now()).is_none() {
+                        let info_text = match to {
+                            Some(_) => format!("{} is typing a direct message to you…", login),
+                            None => format!("{} is typing…", login),
+                        };
+                        tx_print.send((OutputType::StandardOutput, info_text)).unwrap();
+                    }
+                },
+
+                // response to the `.search` command (matches are highlighted by `**`)
+                Ok(Some(Message::SearchResults{query, page, total, hits})) => {
+                    let mut lines = vec![
+                        format!("Search results for \"{}\" ({} found, page {}):", query, total, page),
+                    ];
+                    for hit in hits {
+                        lines.push(format!(
+                            "  [#{}] {} {}: {}",
+                            hit.id,
+                            hit.timestamp,
+                            hit.login,
+                            hit.snippet,
+                        ));
+                    }
+                    tx_print.send((OutputType::StandardOutput, lines.join("\n"))).unwrap();
+                },
+
+                // response to the `.who` command
+                Ok(Some(Message::OnlineUsers{users})) => {
+                    let mut lines = vec![format!("Online users ({}):", users.len())];
+                    for user in users {
+                        let author = author_name(&user.login, &user.display_name);
+                        lines.push(format!("  {} (since {})", author, user.connected_at));
                     }
+                    tx_print.send((OutputType::StandardOutput, lines.join("\n"))).unwrap();
                 },
 
                 Ok(Some(_)) => {
+                    tracing::warn!("unexpected message from the server");
                     tx_print
                         .send((OutputType::ErrorOutput, "invalid message".to_string()))
                         .unwrap();
@@ -172,6 +408,7 @@ pub async fn run_interactive(
                 // write error message for any error that could possibly occur
                 Err(err) => {
                     let error_message = err.to_string();
+                    tracing::error!(error = %error_message, "failed to receive a message");
                     tx_print.send((OutputType::ErrorOutput, error_message.clone())).unwrap();
                     bail!("failed to receive a message from the server: {}", error_message);
                 }
@@ -192,6 +429,10 @@ pub async fn run_interactive(
             match request {
                 (OutputType::StandardOutput, text) => println!("{}", text),
                 (OutputType::ErrorOutput, text) => eprintln!("{}{}", ERROR_PREFIX, text),
+                (OutputType::Bell, _) => {
+                    print!("\x07");
+                    let _ = io::stdout().flush();
+                },
             }
         }
 
@@ -204,11 +445,47 @@ pub async fn run_interactive(
 }
 
 
-/// `save_image` save image as <timestamp>.png file under `images/` subdirectory. It expects, that
-/// conversion of any image format was done by the client that sent image.
-async fn save_image(payload: Vec<u8>) -> Result<()> {
-    let timestamp = timestamp_to_string(SystemTime::now());
-    let filepath_str = format!("./images/{}.png", timestamp);
+/// `composed_message` tells whom a message being composed on the given (incomplete) input line
+/// is for: `Some(None)` for the room, `Some(Some(login))` for a direct message (`/msg login text`)
+/// and `None` if no message is composed (e.g. a command is typed).
+fn composed_message(line: &str) -> Option<Option<String>> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('.') {
+        return None;
+    }
+    if !line.starts_with('/') || line.starts_with("//") {
+        return Some(None);
+    }
+
+    // Of server commands, just `/me` and `/msg` compose a message (once their name is complete).
+    let (command, args) = line.split_once(char::is_whitespace)?;
+    match command {
+        "/me" => Some(None),
+        "/msg" => {
+            // The recipient is known once its login is complete.
+            let (login, _) = args.trim_start().split_once(char::is_whitespace)?;
+            Some(Some(login.to_string()))
+        },
+        _ => None,
+    }
+}
+
+
+/// `author_name` returns login of a user together with the display name (if any).
+fn author_name(login: &str, display_name: &Option<String>) -> String {
+    match display_name {
+        Some(display_name) => format!("{} ({})", display_name, login),
+        None => login.to_string(),
+    }
+}
+
+
+/// `save_image` save image as <id>.<extension> file under `images/` subdirectory, the extension
+/// is taken from the MIME type (e.g. `png` of `image/png`).
+async fn save_image(id: i64, mime: &str, payload: Vec<u8>) -> Result<()> {
+    let subtype = mime.rsplit('/').next().unwrap_or("png");
+    let extension = subtype.trim_start_matches("x-");
+    let filepath_str = format!("./images/{}.{}", id, extension);
     let filepath = Path::new(filepath_str.as_str());
 
     _save_file(&filepath, payload).await.with_context(||
@@ -217,9 +494,25 @@ async fn save_image(payload: Vec<u8>) -> Result<()> {
 }
 
 
-/// `save_file` save general file into `files/` subdirectory.
-async fn save_file(filename: &String, payload: Vec<u8>) -> Result<()> {
-    let filepath_str = format!("./files/{}", filename);
+/// `save_thumbnail` save PNG thumbnail of an image as <id>.png file under `images/thumbnails/`
+/// subdirectory.
+async fn save_thumbnail(id: i64, thumbnail: Vec<u8>) -> Result<()> {
+    let filepath_str = format!("./images/thumbnails/{}.png", id);
+    let filepath = Path::new(filepath_str.as_str());
+
+    _save_file(filepath, thumbnail).await.with_context(||
+        format!("saving thumbnail: {}", filepath_str)
+    )
+}
+
+
+/// `save_file` save general file into `files/` subdirectory. Just the last component of the
+/// received file name is used; absolute paths and paths with `..` are refused.
+async fn save_file(filename: &str, payload: Vec<u8>) -> Result<()> {
+    let Some(name) = safe_file_name(filename) else {
+        bail!("refusing to save file with unsafe name {:?}", filename);
+    };
+    let filepath_str = format!("./files/{}", name);
     let filepath = Path::new(filepath_str.as_str());
 
     _save_file(&filepath, payload).await.with_context(||
@@ -228,6 +521,23 @@ async fn save_file(filename: &String, payload: Vec<u8>) -> Result<()> {
 }
 
 
+/// `safe_file_name` returns the last component of the given path, `None` if the path is absolute,
+/// it contains `..` or the name is empty (or it contains control characters).
+fn safe_file_name(path: &str) -> Option<&str> {
+    let is_absolute = path.starts_with(['/', '\\'])
+        || Path::new(path).is_absolute()
+        || path.as_bytes().get(1) == Some(&b':');
+    let components: Vec<&str> = path.split(['/', '\\']).collect();
+    if is_absolute || components.contains(&"..") {
+        return None;
+    }
+
+    let name = components.last()?.trim();
+    let invalid = name.is_empty() || name == "." || name.chars().any(|c| c.is_control());
+    (!invalid).then_some(name)
+}
+
+
 /// `_save_file` is just a helper function that saved what is needed in the given filepath.
 async fn _save_file(filepath: &Path, content: Vec<u8>) -> Result<()> {
     // create needed directories on path to the target file (if needed)
@@ -250,13 +560,23 @@ async fn _save_file(filepath: &Path, content: Vec<u8>) -> Result<()> {
 
 
 /// `login` take care of client authentication right after establishing a connection to the server.
-pub async fn _login(stream: &mut TcpStream, login: &str, pass: &str) -> Result<String> {
+/// Session is resumed by a non-empty `token`, otherwise login and password are used.
+pub async fn _login(
+        stream: &mut TcpStream,
+        login: &str,
+        pass: &str,
+        token: &str,
+) -> Result<String> {
     print!("Connection in progress...");
     let _ = io::stdout().flush();
 
-    let message = Message::Login {
-        login: login.to_string(),
-        pass: format!("{:x}", md5::compute(pass)),
+    let message = if token.is_empty() {
+        Message::Login {
+            login: login.to_string(),
+            pass: format!("{:x}", md5::compute(pass)),
+        }
+    } else {
+        Message::Resume { token: token.to_string() }
     };
 
     match message.send(stream).await {
@@ -264,9 +584,17 @@ pub async fn _login(stream: &mut TcpStream, login: &str, pass: &str) -> Result<S
         Err(err) => bail!("failed to send authentication: {}", err.to_string()),
     };
 
-    match Message::receive_with_timeout(stream, Duration::from_secs(5)).await {
-        Ok(Some(Message::Welcome {motd})) => Ok(motd),
-        Ok(_) => Err(anyhow!("authentication failed")),
-        Err(err) => Err(err),
+    // Other messages that might come before the response (e.g. notifications) are skipped.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match Message::receive_with_timeout(stream, remaining).await {
+            Ok(Some(Message::Welcome {motd})) => return Ok(motd),
+            Ok(Some(Message::Error(text))) =>
+                return Err(anyhow!("authentication failed: {}", text)),
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(anyhow!("authentication failed")),
+            Err(err) => return Err(err),
+        }
     }
 }
@@ -1,4 +1,4 @@
-use client::run_interactive;
+use client::{init_logging, run_interactive};
 
 
 #[tokio::main]
@@ -7,12 +7,30 @@ async fn main() {
     let mut port = 11111_u16;
     let mut login = String::new();
     let mut pass = String::new();
-
-    parse_arguments(&mut hostname, &mut port, &mut login, &mut pass);
+    let mut token = String::new();
+    let mut bell = false;
+    let mut log_filter = "warn".to_string();
+    let mut log_format = "text".to_string();
+
+    parse_arguments(
+        &mut hostname,
+        &mut port,
+        &mut login,
+        &mut pass,
+        &mut token,
+        &mut bell,
+        &mut log_filter,
+        &mut log_format,
+    );
+
+    if let Err(err) = init_logging(&log_filter, log_format == "json") {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 
     let address = format!("{}:{}", hostname, port);
 
-    if let Err(err) = run_interactive(&address, &login, &pass).await {
+    if let Err(err) = run_interactive(&address, &login, &pass, &token, bell).await {
         eprintln!("{}", err.to_string());
     }
 }
@@ -20,13 +38,18 @@ async fn main() {
 
 /// `parse_arguments` uses [argparse](https://crates.io/crates/argparse) crate to parse command-line
 /// options.
+#[allow(clippy::too_many_arguments)]
 fn parse_arguments(
     hostname: &mut String,
     port: &mut u16,
     login: &mut String,
     pass: &mut String,
+    token: &mut String,
+    bell: &mut bool,
+    log_filter: &mut String,
+    log_format: &mut String,
 ) {
-    use argparse::{ArgumentParser, Store};
+    use argparse::{ArgumentParser, Store, StoreTrue};
     use std::process::exit;
 
     let mut _port = port.to_string();
@@ -48,6 +71,18 @@ fn parse_arguments(
         ap.refer(pass)
             .add_option(&["--password"], Store, "Password.");
 
+        ap.refer(token)
+            .add_option(&["--token"], Store, "Session token (log-in without login and password).");
+
+        ap.refer(bell)
+            .add_option(&["--bell"], StoreTrue, "Ring the terminal bell when you are mentioned.");
+
+        ap.refer(log_filter)
+            .add_option(&["--log-filter"], Store, "Log filter (e.g. `warn,client=debug`).");
+
+        ap.refer(log_format)
+            .add_option(&["--log-format"], Store, "Log format (`text` or `json`).");
+
         if let Err(error_code) = ap.parse_args() {
             exit(error_code);
         }
@@ -62,8 +97,14 @@ fn parse_arguments(
         }
     }
 
-    // Ensure login option is given.
-    if login.is_empty() {
+    // Ensure log format is a known one.
+    if log_format != "text" && log_format != "json" {
+        eprintln!("unknown log format {} (expected `text` or `json`)", log_format);
+        exit(1);
+    }
+
+    // Ensure login option is given (unless a session is resumed).
+    if login.is_empty() && token.is_empty() {
         eprintln!("missing login");
         exit(2);
     }
@@ -0,0 +1,7 @@
+-- Edited messages keep their row with a timestamp of the last edit, deleted messages are kept
+-- as tombstones (empty text with a deletion timestamp) so that IDs stay valid.
+ALTER TABLE chat_messages ADD COLUMN edited_at TEXT;
+ALTER TABLE chat_messages ADD COLUMN deleted_at TEXT;
+
+-- Role of a user; `moderator` may edit and delete messages of others.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
@@ -0,0 +1,12 @@
+-- Reactions (Unicode emoji or `:shortcode:`) of users to chat messages; each user might use
+-- a single emoji just once per message.
+CREATE TABLE IF NOT EXISTS message_reactions (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    message_id  INTEGER NOT NULL,
+    user_id     INTEGER NOT NULL,
+    emoji       TEXT NOT NULL,
+    timestamp   TEXT NOT NULL,
+    UNIQUE(message_id, user_id, emoji),
+    FOREIGN KEY(message_id) REFERENCES chat_messages(id),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
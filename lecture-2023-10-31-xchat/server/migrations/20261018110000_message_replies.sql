@@ -0,0 +1,2 @@
+-- Optional parent message of a reply (threads are formed by chains of replies).
+ALTER TABLE chat_messages ADD COLUMN reply_to INTEGER REFERENCES chat_messages(id);
@@ -0,0 +1,23 @@
+-- Full-text index over text of chat messages. It is an external content FTS5 table, so the text
+-- is not duplicated; the index is kept in sync by triggers (edits and tombstones are updates).
+CREATE VIRTUAL TABLE IF NOT EXISTS chat_messages_fts USING fts5(
+    text,
+    content='chat_messages',
+    content_rowid='id'
+);
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_insert AFTER INSERT ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_delete AFTER DELETE ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (chat_messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+END;
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_update AFTER UPDATE OF text ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (chat_messages_fts, rowid, text) VALUES ('delete', old.id, old.text);
+    INSERT INTO chat_messages_fts (rowid, text) VALUES (new.id, new.text);
+END;
+
+-- Indexing of already existing chat messages.
+INSERT INTO chat_messages_fts (chat_messages_fts) VALUES ('rebuild');
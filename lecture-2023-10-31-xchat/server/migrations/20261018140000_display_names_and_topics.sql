@@ -0,0 +1,12 @@
+-- Optional display name chosen by the user (`/nick`); the login stays the identity of the user.
+ALTER TABLE users ADD COLUMN display_name TEXT;
+
+-- Topics of the room set by `/topic` (the newest one is the current topic, an empty one clears
+-- it).
+CREATE TABLE IF NOT EXISTS room_topics (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    user_id     INTEGER NOT NULL,
+    timestamp   TEXT NOT NULL,
+    topic       TEXT NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
@@ -0,0 +1,11 @@
+-- Log-in sessions (a user may have several at once). Just SHA-256 of the session token is
+-- stored; revoked and expired sessions are refused.
+CREATE TABLE IF NOT EXISTS sessions (
+    id          INTEGER PRIMARY KEY NOT NULL,
+    user_id     INTEGER NOT NULL,
+    token_hash  TEXT NOT NULL UNIQUE,
+    created_at  TEXT NOT NULL,
+    expires_at  TEXT NOT NULL,
+    revoked_at  TEXT,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
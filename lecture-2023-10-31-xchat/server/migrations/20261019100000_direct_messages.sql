@@ -0,0 +1,14 @@
+-- Direct messages (`/msg`) sent to offline users, they are delivered at the next log-in of
+-- the recipient.
+CREATE TABLE IF NOT EXISTS direct_messages (
+    id              INTEGER PRIMARY KEY NOT NULL,
+    sender_id       INTEGER NOT NULL,
+    recipient_id    INTEGER NOT NULL,
+    timestamp       TEXT NOT NULL,
+    text            TEXT NOT NULL,
+    delivered_at    TEXT,
+    FOREIGN KEY(sender_id) REFERENCES users(id),
+    FOREIGN KEY(recipient_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS direct_messages_recipient ON direct_messages(recipient_id, delivered_at);
@@ -0,0 +1,12 @@
+-- Users mentioned (`@login`) in chat messages, mentions delivered to a connected user are read
+-- already, the others stay unread until the user lists them.
+CREATE TABLE IF NOT EXISTS mentions (
+    id              INTEGER PRIMARY KEY NOT NULL,
+    message_id      INTEGER NOT NULL,
+    user_id         INTEGER NOT NULL,
+    read_at         TEXT,
+    FOREIGN KEY(message_id) REFERENCES chat_messages(id),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS mentions_user ON mentions(user_id, read_at);
@@ -0,0 +1,12 @@
+-- Files and images sent by users (just their metadata), they are counted into daily quotas.
+CREATE TABLE IF NOT EXISTS attachments (
+    id              INTEGER PRIMARY KEY NOT NULL,
+    user_id         INTEGER NOT NULL,
+    timestamp       TEXT NOT NULL,
+    kind            TEXT NOT NULL,
+    filename        TEXT,
+    size            INTEGER NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS attachments_user ON attachments(user_id, timestamp);
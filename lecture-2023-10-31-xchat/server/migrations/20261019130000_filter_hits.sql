@@ -0,0 +1,11 @@
+-- Hits of content filter rules (without the matched text), they form an audit trail.
+CREATE TABLE IF NOT EXISTS filter_hits (
+    id              INTEGER PRIMARY KEY NOT NULL,
+    timestamp       TEXT NOT NULL,
+    user_id         INTEGER NOT NULL,
+    rule            TEXT NOT NULL,
+    action          TEXT NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+CREATE INDEX IF NOT EXISTS filter_hits_user ON filter_hits(user_id);
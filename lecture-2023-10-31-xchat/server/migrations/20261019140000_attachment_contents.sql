@@ -0,0 +1,8 @@
+-- Originals and thumbnails of images, originals are downloaded by clients on demand.
+CREATE TABLE IF NOT EXISTS attachment_contents (
+    attachment_id   INTEGER PRIMARY KEY NOT NULL,
+    mime            TEXT NOT NULL,
+    payload         BLOB NOT NULL,
+    thumbnail       BLOB NOT NULL,
+    FOREIGN KEY(attachment_id) REFERENCES attachments(id)
+);
@@ -0,0 +1,48 @@
+-- Schema of PostgreSQL storage backend equivalent to all SQLite migrations in `migrations/`.
+-- Timestamps are kept as text (in the same format as in SQLite).
+CREATE TABLE IF NOT EXISTS users (
+    id          BIGSERIAL PRIMARY KEY,
+    login       TEXT NOT NULL UNIQUE,
+    password    TEXT NOT NULL,
+    role        TEXT NOT NULL DEFAULT 'user'
+);
+
+CREATE TABLE IF NOT EXISTS chat_messages (
+    id          BIGSERIAL PRIMARY KEY,
+    user_id     BIGINT NOT NULL REFERENCES users(id),
+    timestamp   TEXT NOT NULL,
+    text        TEXT NOT NULL,
+    edited_at   TEXT,
+    deleted_at  TEXT,
+    reply_to    BIGINT REFERENCES chat_messages(id)
+);
+
+-- Full-text index of chat messages (language-neutral `simple` configuration).
+CREATE INDEX IF NOT EXISTS chat_messages_text_fts
+ON chat_messages USING GIN (to_tsvector('simple', text));
+
+CREATE TABLE IF NOT EXISTS client_logins (
+    id          BIGSERIAL PRIMARY KEY,
+    user_id     BIGINT NOT NULL REFERENCES users(id),
+    timestamp   TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS message_reactions (
+    id          BIGSERIAL PRIMARY KEY,
+    message_id  BIGINT NOT NULL REFERENCES chat_messages(id),
+    user_id     BIGINT NOT NULL REFERENCES users(id),
+    emoji       TEXT NOT NULL,
+    timestamp   TEXT NOT NULL,
+    UNIQUE(message_id, user_id, emoji)
+);
+
+INSERT INTO users
+(login, password, role)
+VALUES
+-- password = MD5 of string '1'
+('TheOne', 'c4ca4238a0b923820dcc509a6f75849b', 'user'),
+-- password = MD5 of string '2'
+('JustTwo', 'c81e728d9d4c2f636f067f89cc14862c', 'user'),
+-- password = MD5 of string '3'
+('Threesome', 'eccbc87e4b5ce2fe28308fd9f2a7baf3', 'user')
+ON CONFLICT (login) DO NOTHING;
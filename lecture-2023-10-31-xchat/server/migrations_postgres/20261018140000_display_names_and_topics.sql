@@ -0,0 +1,11 @@
+-- Optional display name chosen by the user (`/nick`); the login stays the identity of the user.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name TEXT;
+
+-- Topics of the room set by `/topic` (the newest one is the current topic, an empty one clears
+-- it).
+CREATE TABLE IF NOT EXISTS room_topics (
+    id          BIGSERIAL PRIMARY KEY,
+    user_id     BIGINT NOT NULL REFERENCES users(id),
+    timestamp   TEXT NOT NULL,
+    topic       TEXT NOT NULL
+);
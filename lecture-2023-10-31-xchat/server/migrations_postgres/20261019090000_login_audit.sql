@@ -0,0 +1,13 @@
+-- Peer address of successful log-ins (unknown for older records).
+ALTER TABLE client_logins ADD COLUMN address TEXT;
+
+-- Failed log-in attempts (`login` is missing for attempts by a session token).
+CREATE TABLE IF NOT EXISTS failed_logins (
+    id          BIGSERIAL PRIMARY KEY,
+    timestamp   TEXT NOT NULL,
+    login       TEXT,
+    address     TEXT NOT NULL,
+    reason      TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS failed_logins_timestamp ON failed_logins(timestamp);
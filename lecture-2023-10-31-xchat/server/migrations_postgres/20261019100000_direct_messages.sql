@@ -0,0 +1,12 @@
+-- Direct messages (`/msg`) sent to offline users, they are delivered at the next log-in of
+-- the recipient.
+CREATE TABLE IF NOT EXISTS direct_messages (
+    id              BIGSERIAL PRIMARY KEY,
+    sender_id       BIGINT NOT NULL REFERENCES users(id),
+    recipient_id    BIGINT NOT NULL REFERENCES users(id),
+    timestamp       TEXT NOT NULL,
+    text            TEXT NOT NULL,
+    delivered_at    TEXT
+);
+
+CREATE INDEX IF NOT EXISTS direct_messages_recipient ON direct_messages(recipient_id, delivered_at);
@@ -0,0 +1,10 @@
+-- Users mentioned (`@login`) in chat messages, mentions delivered to a connected user are read
+-- already, the others stay unread until the user lists them.
+CREATE TABLE IF NOT EXISTS mentions (
+    id              BIGSERIAL PRIMARY KEY,
+    message_id      BIGINT NOT NULL REFERENCES chat_messages(id),
+    user_id         BIGINT NOT NULL REFERENCES users(id),
+    read_at         TEXT
+);
+
+CREATE INDEX IF NOT EXISTS mentions_user ON mentions(user_id, read_at);
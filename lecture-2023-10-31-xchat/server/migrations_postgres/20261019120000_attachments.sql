@@ -0,0 +1,11 @@
+-- Files and images sent by users (just their metadata), they are counted into daily quotas.
+CREATE TABLE IF NOT EXISTS attachments (
+    id              BIGSERIAL PRIMARY KEY,
+    user_id         BIGINT NOT NULL REFERENCES users(id),
+    timestamp       TEXT NOT NULL,
+    kind            TEXT NOT NULL,
+    filename        TEXT,
+    size            BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS attachments_user ON attachments(user_id, timestamp);
@@ -0,0 +1,10 @@
+-- Hits of content filter rules (without the matched text), they form an audit trail.
+CREATE TABLE IF NOT EXISTS filter_hits (
+    id              BIGSERIAL PRIMARY KEY,
+    timestamp       TEXT NOT NULL,
+    user_id         BIGINT NOT NULL REFERENCES users(id),
+    rule            TEXT NOT NULL,
+    action          TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS filter_hits_user ON filter_hits(user_id);
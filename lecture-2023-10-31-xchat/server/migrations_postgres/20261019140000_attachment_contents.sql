@@ -0,0 +1,7 @@
+-- Originals and thumbnails of images, originals are downloaded by clients on demand.
+CREATE TABLE IF NOT EXISTS attachment_contents (
+    attachment_id   BIGINT PRIMARY KEY REFERENCES attachments(id),
+    mime            TEXT NOT NULL,
+    payload         BYTEA NOT NULL,
+    thumbnail       BYTEA NOT NULL
+);
@@ -0,0 +1,331 @@
+use std::io::Cursor;
+use std::time::SystemTime;
+
+use image::ImageOutputFormat;
+use shared::{Attachment, Message, timestamp_to_string};
+use crate::config::{AttachmentsConfig, Config, QuotasConfig};
+use crate::error::ServerError;
+use crate::mime::{is_allowed, sniff_type, type_by_extension};
+use crate::storage::Storage;
+use crate::{broadcast, send_to, Clients, MessageRecord};
+
+
+/// `share_attachment` sends a file or an image of the sender to every other client as
+/// [Message::Attachment] unless it is refused (then the sender gets an error). An attachment is
+/// refused if its type (sniffed from the content) is not allowed, if an image cannot be decoded
+/// or if it exceeds daily quotas of the sender. Sent attachments are recorded into the storage.
+///
+/// Images are stored and just their thumbnails are sent, clients download originals on demand
+/// (see [send_original]).
+pub(crate) async fn share_attachment(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+        config: &Config,
+) -> Result<(), ServerError> {
+    let address = message_record.address;
+    let (kind, filename, payload) = match message_record.message {
+        Message::File {filename, payload} => match safe_filename(&filename) {
+            Some(filename) => ("file", Some(filename), payload),
+            None => {
+                let reason = format!("invalid file name `{}`", filename);
+                tracing::info!(kind = "file", reason, "attachment refused");
+                return send_to(clients, &address, &Message::Error(reason)).await;
+            },
+        },
+        Message::Image(payload) => ("image", None, payload),
+        _ => return Ok(()),
+    };
+    let size = payload.len();
+
+    let mime = sniff_type(&payload);
+    if let Err(reason) = validate_attachment(&config.attachments, kind, filename.as_deref(), mime)
+    {
+        tracing::info!(kind, mime, size, reason, "attachment refused");
+        return send_to(clients, &address, &Message::Error(reason)).await;
+    }
+    let (payload, thumbnail) = match kind {
+        "image" => match create_thumbnail(payload, config.attachments.thumbnail_size).await? {
+            Ok((payload, thumbnail)) => (payload, Some(thumbnail)),
+            Err(reason) => {
+                tracing::info!(kind, mime, size, reason, "attachment refused");
+                return send_to(clients, &address, &Message::Error(reason)).await;
+            },
+        },
+        _ => (payload, None),
+    };
+
+    let now = timestamp_to_string(SystemTime::now());
+    let user_id = message_record.user_id;
+    let usage = storage.fetch_attachment_usage(Some(user_id), &day_start(&now)).await?;
+    let (count, bytes) = usage
+        .first()
+        .map_or((0, 0), |usage| (usage.count as u64, usage.bytes as u64));
+    if let Some(reason) = quota_violation(&config.quotas, count, bytes, size as u64) {
+        tracing::info!(kind, size, reason, "attachment refused");
+        return send_to(clients, &address, &Message::Error(reason)).await;
+    }
+
+    let id = storage.insert_attachment(user_id, &now, kind, filename.as_deref(), size as i64)
+        .await?;
+    let payload = match &thumbnail {
+        Some(thumbnail) => {
+            storage.insert_attachment_content(id, mime, &payload, thumbnail).await?;
+            vec![]
+        },
+        None => payload,
+    };
+    let attachment = Message::Attachment(Attachment {
+        id,
+        login: message_record.login,
+        timestamp: now,
+        filename,
+        mime: mime.to_string(),
+        size: size as u64,
+        payload,
+        thumbnail,
+    });
+    broadcast(clients, &attachment, Some(&address)).await
+}
+
+
+/// `send_original` answers [Message::Download] request by sending the original of the stored
+/// image back to the sender (or an error if there is no such image).
+pub(crate) async fn send_original(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+) -> Result<(), ServerError> {
+    let Message::Download {id} = message_record.message else {
+        return Ok(());
+    };
+
+    let response = match storage.fetch_attachment_content(id).await? {
+        Some(content) => Message::Attachment(Attachment {
+            id,
+            login: content.login,
+            timestamp: content.timestamp,
+            filename: None,
+            mime: content.mime,
+            size: content.payload.len() as u64,
+            payload: content.payload,
+            thumbnail: None,
+        }),
+        None => Message::Error(format!("there is no image attachment #{}", id)),
+    };
+    send_to(clients, &message_record.address, &response).await
+}
+
+
+/// `validate_attachment` checks the sniffed type of an attachment (and the type implied by
+/// extension of its file name) is allowed and that an image is really an image.
+fn validate_attachment(
+        config: &AttachmentsConfig,
+        kind: &str,
+        filename: Option<&str>,
+        mime: &str,
+) -> Result<(), String> {
+    if kind == "image" && !mime.starts_with("image/") {
+        return Err(format!("the image is not an image (it is {})", mime));
+    }
+    if !is_allowed(config, mime) {
+        return Err(format!("attachments of type {} are not allowed", mime));
+    }
+    if let Some(implied) = filename.and_then(type_by_extension) {
+        if !is_allowed(config, implied) {
+            return Err(format!("attachments of type {} are not allowed", implied));
+        }
+    }
+    Ok(())
+}
+
+
+/// `create_thumbnail` decodes the image and creates its thumbnail (in a blocking task). The
+/// original is given back together with the thumbnail on success, the reason of failure
+/// otherwise.
+async fn create_thumbnail(
+        payload: Vec<u8>,
+        size: u32,
+) -> Result<Result<(Vec<u8>, Vec<u8>), String>, ServerError> {
+    let creation = tokio::task::spawn_blocking(move || {
+        thumbnail(&payload, size).map(|thumbnail| (payload, thumbnail))
+    });
+    match creation.await {
+        Ok(result) => Ok(result),
+        Err(err) => Err(ServerError::JoinError(err.to_string())),
+    }
+}
+
+
+/// `thumbnail` returns PNG thumbnail of the image fitting into `size` x `size` pixels (with
+/// the aspect ratio kept, smaller images are not enlarged).
+pub(crate) fn thumbnail(payload: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    let image = match image::load_from_memory(payload) {
+        Ok(image) => image,
+        Err(err) => Err(format!("the image cannot be decoded: {}", err))?,
+    };
+    let thumbnail = match image.width() > size || image.height() > size {
+        true => image.thumbnail(size, size),
+        false => image,
+    };
+
+    let mut png = Cursor::new(vec![]);
+    match thumbnail.write_to(&mut png, ImageOutputFormat::Png) {
+        Ok(()) => Ok(png.into_inner()),
+        Err(err) => Err(format!("the thumbnail cannot be created: {}", err)),
+    }
+}
+
+
+/// `safe_filename` returns just the name of the file without directories (`None` if nothing
+/// usable remains), so receivers never write outside of their download directory.
+fn safe_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    let invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.chars().any(|c| c.is_control());
+    (!invalid).then(|| name.to_string())
+}
+
+
+/// `quota_violation` tells why an attachment of `size` bytes exceeds the daily quotas of a user
+/// who sent `count` attachments of `bytes` total size today (`None` if it fits).
+fn quota_violation(
+        quotas: &QuotasConfig,
+        count: u64,
+        bytes: u64,
+        size: u64,
+) -> Option<String> {
+    let max_count = quotas.attachments_per_day as u64;
+    if max_count > 0 && count >= max_count {
+        return Some(format!(
+            "daily attachment quota exceeded: {} attachments per day already sent",
+            max_count,
+        ));
+    }
+
+    let max_bytes = quotas.attachment_bytes_per_day;
+    if max_bytes > 0 && bytes.saturating_add(size) > max_bytes {
+        return Some(format!(
+            "daily attachment quota exceeded: {} would be over {} per day ({} already sent)",
+            format_bytes(size),
+            format_bytes(max_bytes),
+            format_bytes(bytes),
+        ));
+    }
+
+    None
+}
+
+
+/// `day_start` returns timestamp of the start of the day of the given timestamp (see
+/// [shared::timestamp_to_string]).
+pub(crate) fn day_start(timestamp: &str) -> String {
+    let date = timestamp.split('T').next().unwrap_or(timestamp);
+    format!("{}T00:00:00", date)
+}
+
+
+/// `format_bytes` formats the size in bytes with a binary unit (e.g. `1.5 MiB`).
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, RgbImage};
+
+    use super::{day_start, format_bytes, quota_violation, safe_filename, thumbnail};
+    use super::validate_attachment;
+    use crate::config::{AttachmentsConfig, QuotasConfig};
+
+
+    #[test]
+    fn test_quota_violation() {
+        let quotas = QuotasConfig {
+            attachments_per_day: 3,
+            attachment_bytes_per_day: 1000,
+        };
+        assert_eq!(quota_violation(&quotas, 2, 500, 500), None);
+
+        let reason = quota_violation(&quotas, 3, 0, 1).unwrap();
+        assert!(reason.contains("3 attachments per day"));
+        let reason = quota_violation(&quotas, 0, 500, 501).unwrap();
+        assert!(reason.contains("501 B would be over 1000 B per day (500 B already sent)"));
+
+        let unlimited = QuotasConfig {
+            attachments_per_day: 0,
+            attachment_bytes_per_day: 0,
+        };
+        assert_eq!(quota_violation(&unlimited, 1000, u64::MAX, u64::MAX), None);
+    }
+
+    #[test]
+    fn test_validate_attachment() {
+        let config = AttachmentsConfig::default();
+        assert!(validate_attachment(&config, "image", None, "image/png").is_ok());
+        assert!(validate_attachment(&config, "file", Some("a.txt"), "text/plain").is_ok());
+
+        let error = validate_attachment(&config, "image", None, "text/plain").unwrap_err();
+        assert!(error.contains("not an image"));
+        let error = validate_attachment(&config, "file", Some("a"), "application/x-executable");
+        assert!(error.unwrap_err().contains("application/x-executable are not allowed"));
+        let error = validate_attachment(&config, "file", Some("a.exe"), "text/plain");
+        assert!(error.unwrap_err().contains("application/x-msdownload are not allowed"));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut png = Cursor::new(vec![]);
+        RgbImage::new(400, 100).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        let png = png.into_inner();
+
+        let small = image::load_from_memory(&thumbnail(&png, 160).unwrap()).unwrap();
+        assert_eq!((small.width(), small.height()), (160, 40));
+        let same = image::load_from_memory(&thumbnail(&png, 1000).unwrap()).unwrap();
+        assert_eq!((same.width(), same.height()), (400, 100));
+
+        let error = thumbnail(b"\x89PNG\r\n\x1a\nbroken", 160).unwrap_err();
+        assert!(error.contains("cannot be decoded"));
+    }
+
+    #[test]
+    fn test_safe_filename() {
+        assert_eq!(safe_filename("/tmp/report.pdf").as_deref(), Some("report.pdf"));
+        assert_eq!(safe_filename("..\\..\\boot.ini").as_deref(), Some("boot.ini"));
+        assert_eq!(safe_filename(" notes.txt ").as_deref(), Some("notes.txt"));
+        assert_eq!(safe_filename("../.."), None);
+        assert_eq!(safe_filename("dir/"), None);
+        assert_eq!(safe_filename("bad\nname"), None);
+    }
+
+    #[test]
+    fn test_day_start() {
+        assert_eq!(day_start("2023-12-04T13:45:01"), "2023-12-04T00:00:00");
+        assert_eq!(day_start("2023-12-04"), "2023-12-04T00:00:00");
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(50 * 1024 * 1024), "50.0 MiB");
+        assert_eq!(format_bytes(u64::MAX), "16777216.0 TiB");
+    }
+}
@@ -0,0 +1,230 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::Mutex;
+
+use shared::timestamp_to_string;
+use crate::config::BackupConfig;
+use crate::db_queries::check_database_file;
+use crate::error::ServerError;
+use crate::storage::{SharedStorage, Storage, StorageBackend};
+use crate::web_prometheus::BACKUP_COUNTER;
+
+
+/// Prefix of names of backup files (followed by timestamp of the backup).
+const BACKUP_PREFIX: &str = "xchat-";
+
+/// Extension of backup files.
+const BACKUP_EXTENSION: &str = ".db";
+
+
+/// `BACKUP_LOCK` serializes backups (scheduled ones and the ones requested by admin might meet).
+static BACKUP_LOCK: Mutex<()> = Mutex::const_new(());
+
+
+/// `backup_periodically` creates a backup every `interval_secs` (the first one after the first
+/// interval elapses). Failed backup is just logged and retried next time.
+pub async fn backup_periodically(
+        storage: SharedStorage,
+        backup: BackupConfig,
+) -> Result<(), ServerError> {
+    let period = Duration::from_secs(backup.interval_secs);
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+
+        match create_backup(storage.as_ref(), &backup, SystemTime::now()).await {
+            Ok(path) => tracing::info!(path = %path.display(), "database backed up"),
+            Err(err) => tracing::warn!(error = %err, "backup of database failed"),
+        }
+    }
+}
+
+
+/// `create_backup` writes a backup of the (running) database into the backup directory and
+/// deletes the oldest backups over the `keep` limit. Returns path of the new backup.
+///
+/// The backup is written under a temporary name and renamed once complete, so the directory
+/// never contains a partial backup under a backup name.
+pub async fn create_backup(
+        storage: &dyn Storage,
+        backup: &BackupConfig,
+        now: SystemTime,
+) -> Result<PathBuf, ServerError> {
+    let Some(dir) = &backup.dir else {
+        return Err(ServerError::BackupError("backup.dir is not configured".to_string()));
+    };
+    let backup_error = |err: std::io::Error| {
+        ServerError::BackupError(format!("{}: {}", dir.display(), err))
+    };
+
+    let _guard = BACKUP_LOCK.lock().await;
+    fs::create_dir_all(dir).map_err(backup_error)?;
+
+    let path = dir.join(backup_file_name(now));
+    let partial_path = path.with_extension("db.partial");
+    // Leftover of a crashed backup would make `VACUUM INTO` fail.
+    if partial_path.exists() {
+        fs::remove_file(&partial_path).map_err(backup_error)?;
+    }
+
+    if let Err(err) = storage.backup(&partial_path).await {
+        let _ = fs::remove_file(&partial_path);
+        return Err(err);
+    }
+    fs::rename(&partial_path, &path).map_err(backup_error)?;
+    BACKUP_COUNTER.inc();
+
+    for removed in rotate_backups(dir, backup.keep as usize)? {
+        tracing::info!(path = %removed.display(), "old backup deleted");
+    }
+    Ok(path)
+}
+
+
+/// `restore_backup` replaces SQLite database of the given DB URL by the given backup. The server
+/// must not be running.
+///
+/// The backup is copied next to the database first and the copy is verified (integrity and
+/// schema version); nothing else is changed if the check fails. Then the files are swapped,
+/// the replaced database is kept with `.pre-restore` suffix.
+pub async fn restore_backup(backup_path: &Path, db_url: &str) -> Result<PathBuf, ServerError> {
+    if StorageBackend::from_url(db_url) != Ok(StorageBackend::Sqlite) {
+        return Err(ServerError::BackupError("restore is supported for SQLite only".to_string()));
+    }
+    let db_path = sqlite_path(db_url);
+    let io_error = |path: &Path, err: std::io::Error| {
+        ServerError::BackupError(format!("{}: {}", path.display(), err))
+    };
+
+    let restore_path = with_suffix(&db_path, ".restore");
+    fs::copy(backup_path, &restore_path).map_err(|err| io_error(backup_path, err))?;
+    match check_database_file(&restore_path).await {
+        Ok(pending) => tracing::info!(path = %backup_path.display(), pending, "backup verified"),
+        Err(err) => {
+            let _ = fs::remove_file(&restore_path);
+            return Err(err);
+        },
+    }
+
+    // The current database is moved aside together with its WAL files (they belong to it).
+    for suffix in ["", "-wal", "-shm"] {
+        let path = with_suffix(&db_path, suffix);
+        if path.exists() {
+            let aside = with_suffix(&db_path, &format!(".pre-restore{}", suffix));
+            fs::rename(&path, &aside).map_err(|err| io_error(&path, err))?;
+        }
+    }
+    fs::rename(&restore_path, &db_path).map_err(|err| io_error(&db_path, err))?;
+    Ok(db_path)
+}
+
+
+/// `rotate_backups` deletes all but the newest `keep` backups in the directory and returns
+/// paths of deleted ones. Other files in the directory are left alone.
+fn rotate_backups(dir: &Path, keep: usize) -> Result<Vec<PathBuf>, ServerError> {
+    let backup_error = |err: std::io::Error| {
+        ServerError::BackupError(format!("{}: {}", dir.display(), err))
+    };
+
+    let mut backups: Vec<PathBuf> = vec![];
+    for entry in fs::read_dir(dir).map_err(backup_error)? {
+        let path = entry.map_err(backup_error)?.path();
+        let is_backup = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+            });
+        if is_backup {
+            backups.push(path);
+        }
+    }
+    // Names contain timestamps, so they sort from the oldest one.
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = backups.into_iter().take(excess).collect();
+    for path in removed.iter() {
+        fs::remove_file(path).map_err(backup_error)?;
+    }
+    Ok(removed)
+}
+
+
+/// `backup_file_name` returns name of backup file of the given time (e.g.
+/// `xchat-2024-01-31T10-00-00.db`; colons are avoided for sake of some file systems).
+fn backup_file_name(now: SystemTime) -> String {
+    let timestamp = timestamp_to_string(now).replace(':', "-");
+    format!("{}{}{}", BACKUP_PREFIX, timestamp, BACKUP_EXTENSION)
+}
+
+
+/// `sqlite_path` returns path of database file of SQLite DB URL (e.g. `data.db` of
+/// `sqlite://data.db?mode=rwc`).
+fn sqlite_path(db_url: &str) -> PathBuf {
+    let path = db_url
+        .trim_start_matches("sqlite:")
+        .trim_start_matches("//");
+    PathBuf::from(path.split('?').next().unwrap_or_default())
+}
+
+
+/// `with_suffix` appends the suffix to the whole file name (e.g. `data.db` -> `data.db-wal`).
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use super::{backup_file_name, rotate_backups, sqlite_path, with_suffix};
+
+
+    #[test]
+    fn test_backup_file_name() {
+        let name = backup_file_name(SystemTime::now());
+        assert!(name.starts_with("xchat-20"));
+        assert!(name.ends_with(".db"));
+        assert!(!name.contains(':'));
+    }
+
+    #[test]
+    fn test_sqlite_path() {
+        assert_eq!(sqlite_path("sqlite:data.db"), Path::new("data.db"));
+        assert_eq!(sqlite_path("sqlite://data.db?mode=rwc"), Path::new("data.db"));
+        assert_eq!(sqlite_path("sqlite:///var/xchat/data.db"), Path::new("/var/xchat/data.db"));
+        assert_eq!(with_suffix(Path::new("x/data.db"), "-wal"), Path::new("x/data.db-wal"));
+    }
+
+    #[test]
+    fn test_rotate_backups() {
+        let dir = std::env::temp_dir().join(format!("xchat-test-backups-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let start = SystemTime::now();
+        let names: Vec<String> = (0..4)
+            .map(|hours| backup_file_name(start + Duration::from_secs(hours * 3600)))
+            .collect();
+        for name in names.iter().chain([&"notes.txt".to_string()]) {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let removed = rotate_backups(&dir, 2).unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        remaining.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(removed, vec![dir.join(&names[0]), dir.join(&names[1])]);
+        assert_eq!(remaining, vec!["notes.txt", names[2].as_str(), names[3].as_str()]);
+    }
+}
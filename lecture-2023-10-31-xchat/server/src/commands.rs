@@ -0,0 +1,683 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use shared::{FederatedKind, Message, timestamp_to_string};
+use crate::error::ServerError;
+use crate::federation::Federation;
+use crate::plugin::{Author, HookVerdict, Plugins};
+use crate::storage::{HistoryFilter, Storage};
+use crate::{broadcast, send_to, send_to_user, Clients, MessageRecord};
+
+
+/// Maximal length (in characters) of a display name set by `/nick`.
+const MAX_DISPLAY_NAME_LENGTH: usize = 32;
+
+/// Maximal length (in characters) of a topic set by `/topic`.
+const MAX_TOPIC_LENGTH: usize = 200;
+
+
+/// `is_command` tells whether the text is a server command (it starts with `/`). Text starting
+/// with `//` is an ordinary text message with escaped leading slash.
+pub(crate) fn is_command(text: &str) -> bool {
+    text.starts_with('/') && !text.starts_with("//")
+}
+
+
+/// `unescape` turns leading `//` of a text message into a single `/`.
+pub(crate) fn unescape(text: &mut String) {
+    if text.starts_with("//") {
+        text.remove(0);
+    }
+}
+
+
+/// `CommandError` is a reason why a command did not succeed.
+pub(crate) enum CommandError {
+    /// Invalid arguments, the caller gets usage of the command.
+    Usage,
+    /// The command was refused, the caller gets the reason.
+    Rejected(String),
+    /// The command failed on the server side.
+    Failed(ServerError),
+}
+
+
+impl From<ServerError> for CommandError {
+    fn from(err: ServerError) -> Self {
+        CommandError::Failed(err)
+    }
+}
+
+
+/// `CommandContext` gives a command access to the server and to the caller.
+pub(crate) struct CommandContext<'a> {
+    pub clients: &'a Clients,
+    pub storage: &'a dyn Storage,
+    pub plugins: &'a Plugins,
+    pub federation: &'a Federation,
+    pub registry: &'a CommandRegistry,
+    pub caller: &'a MessageRecord,
+}
+
+
+impl CommandContext<'_> {
+    /// `reply` sends the text as [Message::Info] just to the caller.
+    async fn reply(&self, text: String) -> Result<(), ServerError> {
+        send_to(self.clients, &self.caller.address, &Message::Info(text)).await
+    }
+
+    /// `display_name` returns the current display name of the caller.
+    async fn display_name(&self) -> Option<String> {
+        display_name_of(self.clients, &self.caller.address).await
+    }
+}
+
+
+/// `ServerCommand` is a command interpreted by the server (text message `/<name> <arguments>`).
+#[async_trait]
+pub(crate) trait ServerCommand: Send + Sync {
+    /// `name` of the command (without the leading slash, in lowercase).
+    fn name(&self) -> &'static str;
+
+    /// `usage` describes arguments of the command (e.g. `<login>`).
+    fn usage(&self) -> &'static str;
+
+    /// `help` is a single line description of the command.
+    fn help(&self) -> &'static str;
+
+    /// `moderator_only` tells whether the command is available to moderators only.
+    fn moderator_only(&self) -> bool {
+        false
+    }
+
+    /// `run` executes the command with the given (trimmed) arguments.
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError>;
+}
+
+
+/// `CommandRegistry` holds server commands by their names (in order of registration).
+pub(crate) struct CommandRegistry {
+    commands: Vec<Arc<dyn ServerCommand>>,
+}
+
+
+impl CommandRegistry {
+    /// `builtin` creates registry of all the built-in commands.
+    pub fn builtin() -> CommandRegistry {
+        let mut registry = CommandRegistry { commands: vec![] };
+        registry.register(Arc::new(MeCommand));
+        registry.register(Arc::new(MsgCommand));
+        registry.register(Arc::new(NickCommand));
+        registry.register(Arc::new(TopicCommand));
+        registry.register(Arc::new(WhoisCommand));
+        registry.register(Arc::new(SessionsCommand));
+        registry.register(Arc::new(HelpCommand));
+        registry
+    }
+
+    /// `register` adds the command (it replaces an already registered one of the same name).
+    pub fn register(&mut self, command: Arc<dyn ServerCommand>) {
+        self.commands.retain(|registered| registered.name() != command.name());
+        self.commands.push(command);
+    }
+
+    /// `find` returns the command of the given name.
+    pub fn find(&self, name: &str) -> Option<&Arc<dyn ServerCommand>> {
+        self.commands.iter().find(|command| command.name() == name)
+    }
+
+    /// `available` returns commands available to the user of the given role.
+    pub fn available(&self, is_moderator: bool) -> impl Iterator<Item = &Arc<dyn ServerCommand>> {
+        self.commands.iter().filter(move |command| is_moderator || !command.moderator_only())
+    }
+
+    /// `execute` runs the command of the given text sent by the given client. Unknown command,
+    /// command not available to the caller or its failure is reported to the caller as
+    /// [Message::Error].
+    pub async fn execute(
+            &self,
+            clients: &Clients,
+            storage: &dyn Storage,
+            plugins: &Plugins,
+            federation: &Federation,
+            message_record: &MessageRecord,
+            text: &str,
+    ) -> Result<(), ServerError> {
+        let (name, args) = split_command(text);
+        let rejection = match self.find(&name) {
+            None if name.is_empty() => Some("missing command name (see /help)".to_string()),
+            None => Some(format!(
+                "unknown command /{} (see /help, start the text with // to send it as is)",
+                name,
+            )),
+            Some(command) if command.moderator_only() && !message_record.is_moderator =>
+                Some(format!("/{} is available to moderators only", name)),
+            Some(command) => {
+                tracing::debug!(command = %name, "running command");
+                let context = CommandContext {
+                    clients,
+                    storage,
+                    plugins,
+                    federation,
+                    registry: self,
+                    caller: message_record,
+                };
+                match command.run(&context, args).await {
+                    Ok(()) => None,
+                    Err(CommandError::Usage) => Some(format!("usage: {}", synopsis(command))),
+                    Err(CommandError::Rejected(reason)) => Some(reason),
+                    Err(CommandError::Failed(err)) => {
+                        let error = Message::Error(format!("/{} failed", name));
+                        send_to(clients, &message_record.address, &error).await?;
+                        Err(err)?
+                    },
+                }
+            },
+        };
+
+        match rejection {
+            Some(reason) =>
+                send_to(clients, &message_record.address, &Message::Error(reason)).await,
+            None => Ok(()),
+        }
+    }
+}
+
+
+/// `split_command` splits command text into lowercase name (without slash) and trimmed
+/// arguments.
+fn split_command(text: &str) -> (String, &str) {
+    let text = text.trim().trim_start_matches('/');
+    let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    (name.to_lowercase(), args.trim())
+}
+
+
+/// `synopsis` returns the command with its arguments (e.g. `/whois <login>`).
+fn synopsis(command: &Arc<dyn ServerCommand>) -> String {
+    format!("/{} {}", command.name(), command.usage()).trim_end().to_string()
+}
+
+
+/// `display_name_of` returns the current display name of the client with the given address.
+pub(crate) async fn display_name_of(clients: &Clients, address: &SocketAddr) -> Option<String> {
+    clients
+        .lock()
+        .await
+        .get(address)
+        .and_then(|client_record| client_record.display_name.clone())
+}
+
+
+/// `check_display_name` checks the display name is acceptable (not too long, without control
+/// characters).
+fn check_display_name(display_name: &str) -> Result<(), String> {
+    if display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err(format!(
+            "display name might have at most {} characters",
+            MAX_DISPLAY_NAME_LENGTH,
+        ));
+    }
+    if display_name.chars().any(char::is_control) {
+        return Err("display name must not contain control characters".to_string());
+    }
+    Ok(())
+}
+
+
+/// `/me <action>` sends an action of the caller to everyone.
+struct MeCommand;
+
+
+#[async_trait]
+impl ServerCommand for MeCommand {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<action>"
+    }
+
+    fn help(&self) -> &'static str {
+        "describes what you do (e.g. /me waves)"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return Err(CommandError::Usage);
+        }
+
+        let caller = context.caller;
+        let author = Author {
+            login: caller.login.clone(),
+            user_id: caller.user_id,
+            is_moderator: caller.is_moderator,
+        };
+        let mut text = args.to_string();
+        if let HookVerdict::Reject(reason) = context.plugins.on_message(&author, &mut text).await {
+            return Err(CommandError::Rejected(reason));
+        }
+
+        let display_name = context.display_name().await;
+        let timestamp = timestamp_to_string(SystemTime::now());
+        context.federation.publish(FederatedKind::Action {
+            login: caller.login.clone(),
+            display_name: display_name.clone(),
+            timestamp: timestamp.clone(),
+            text: text.clone(),
+        });
+        let message = Message::Action {
+            login: caller.login.clone(),
+            display_name,
+            timestamp,
+            text,
+        };
+        Ok(broadcast(context.clients, &message, None).await?)
+    }
+}
+
+
+/// `/msg <login> <text>` sends a direct message to another user (queued if the user is offline).
+struct MsgCommand;
+
+
+#[async_trait]
+impl ServerCommand for MsgCommand {
+    fn name(&self) -> &'static str {
+        "msg"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<login> <text>"
+    }
+
+    fn help(&self) -> &'static str {
+        "sends a direct message to a user (an offline user gets it at the next log-in)"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        let Some((name, text)) = args.split_once(char::is_whitespace) else {
+            return Err(CommandError::Usage);
+        };
+
+        let online = context.clients
+            .lock()
+            .await
+            .values()
+            .filter_map(|client_record| client_record.login.clone())
+            .find(|login| login.eq_ignore_ascii_case(name));
+
+        // An offline user gets the message queued (users who cannot log in get nothing).
+        let caller = context.caller;
+        let timestamp = timestamp_to_string(SystemTime::now());
+        let text = text.trim().to_string();
+        let recipient = match online {
+            Some(login) => login,
+            None => {
+                let users = context.storage.fetch_users().await?;
+                let user = users
+                    .iter()
+                    .find(|user| user.login == name)
+                    .or_else(|| users.iter().find(|user| user.login.eq_ignore_ascii_case(name)));
+                let Some(user) = user.filter(|user| !user.is_locked()) else {
+                    return Err(CommandError::Rejected(format!("user {} does not exist", name)));
+                };
+                context.storage
+                    .insert_direct_message(caller.user_id, user.id, &timestamp, &text)
+                    .await?;
+                context.reply(format!(
+                    "{} is offline, the message is delivered at their next log-in",
+                    user.login,
+                )).await?;
+                user.login.clone()
+            },
+        };
+
+        // Both users get the message in all their (connected) sessions.
+        let message = Message::Direct {
+            login: caller.login.clone(),
+            display_name: context.display_name().await,
+            to: recipient.clone(),
+            timestamp,
+            text,
+        };
+        send_to_user(context.clients, &recipient, &message).await?;
+        if recipient != caller.login {
+            send_to_user(context.clients, &caller.login, &message).await?;
+        }
+        Ok(())
+    }
+}
+
+
+/// `/nick [name]` sets (or clears) display name of the caller.
+struct NickCommand;
+
+
+#[async_trait]
+impl ServerCommand for NickCommand {
+    fn name(&self) -> &'static str {
+        "nick"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[name]"
+    }
+
+    fn help(&self) -> &'static str {
+        "sets your display name (without a name it is cleared)"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        let caller = context.caller;
+        let display_name = (!args.is_empty()).then_some(args);
+
+        if let Some(display_name) = display_name {
+            check_display_name(display_name).map_err(CommandError::Rejected)?;
+
+            // Nobody can pretend to be somebody else.
+            let taken = context.storage
+                .fetch_users()
+                .await?
+                .iter()
+                .filter(|user| user.id != caller.user_id)
+                .any(|user| {
+                    user.login.eq_ignore_ascii_case(display_name)
+                        || user.display_name.as_ref().is_some_and(|other| {
+                            other.eq_ignore_ascii_case(display_name)
+                        })
+                });
+            if taken {
+                return Err(CommandError::Rejected(format!(
+                    "display name {} is already used by another user",
+                    display_name,
+                )));
+            }
+        }
+
+        context.storage.update_display_name(caller.user_id, display_name).await?;
+        for client_record in context.clients.lock().await.values_mut() {
+            if client_record.user_id == Some(caller.user_id) {
+                client_record.display_name = display_name.map(str::to_string);
+            }
+        }
+        tracing::info!(display_name, "display name changed");
+
+        let message = Message::NickChanged {
+            login: caller.login.clone(),
+            display_name: display_name.map(str::to_string),
+        };
+        Ok(broadcast(context.clients, &message, None).await?)
+    }
+}
+
+
+/// `/topic <topic>` sets topic of the room (`-` clears it).
+struct TopicCommand;
+
+
+#[async_trait]
+impl ServerCommand for TopicCommand {
+    fn name(&self) -> &'static str {
+        "topic"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<topic>"
+    }
+
+    fn help(&self) -> &'static str {
+        "sets topic of the room (/topic - clears it)"
+    }
+
+    fn moderator_only(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        let topic = match args {
+            "" => return Err(CommandError::Usage),
+            "-" => "",
+            topic => topic,
+        };
+        if topic.chars().count() > MAX_TOPIC_LENGTH {
+            return Err(CommandError::Rejected(format!(
+                "topic might have at most {} characters",
+                MAX_TOPIC_LENGTH,
+            )));
+        }
+
+        let caller = context.caller;
+        let timestamp = timestamp_to_string(SystemTime::now());
+        context.storage.insert_topic(caller.user_id, &timestamp, topic).await?;
+        tracing::info!(topic, "topic changed");
+
+        let message = Message::Topic {
+            topic: topic.to_string(),
+            login: caller.login.clone(),
+            timestamp,
+        };
+        Ok(broadcast(context.clients, &message, None).await?)
+    }
+}
+
+
+/// `/whois <login>` shows information about a user (found by login or display name).
+struct WhoisCommand;
+
+
+#[async_trait]
+impl ServerCommand for WhoisCommand {
+    fn name(&self) -> &'static str {
+        "whois"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<login>"
+    }
+
+    fn help(&self) -> &'static str {
+        "shows information about a user"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return Err(CommandError::Usage);
+        }
+
+        let users = context.storage.fetch_users().await?;
+        let user = users
+            .iter()
+            .find(|user| user.login == args)
+            .or_else(|| users.iter().find(|user| {
+                user.display_name.as_ref().is_some_and(|name| name.eq_ignore_ascii_case(args))
+            }));
+        let Some(user) = user else {
+            return Err(CommandError::Rejected(format!("user {} does not exist", args)));
+        };
+
+        let mut connected: Vec<String> = context.clients
+            .lock()
+            .await
+            .values()
+            .filter(|client_record| client_record.user_id == Some(user.id))
+            .filter_map(|client_record| client_record.connected_at.clone())
+            .collect();
+        connected.sort();
+
+        let mut lines = vec![format!("{}:", user.login)];
+        if let Some(display_name) = &user.display_name {
+            lines.push(format!("  display name: {}", display_name));
+        }
+        lines.push(format!("  role: {}", user.role));
+        match connected.first() {
+            Some(since) => lines.push(format!(
+                "  online since {} ({} connection(s))",
+                since,
+                connected.len(),
+            )),
+            None => {
+                let filter = HistoryFilter {
+                    login: Some(user.login.clone()),
+                    ..Default::default()
+                };
+                let logins = context.storage.fetch_login_history(&filter).await?;
+                match logins.last() {
+                    Some(login) => lines.push(format!("  offline, last login {}", login.timestamp)),
+                    None => lines.push("  offline, never logged in".to_string()),
+                }
+            },
+        }
+
+        Ok(context.reply(lines.join("\n")).await?)
+    }
+}
+
+
+/// `/sessions` lists active sessions of the caller.
+struct SessionsCommand;
+
+
+#[async_trait]
+impl ServerCommand for SessionsCommand {
+    fn name(&self) -> &'static str {
+        "sessions"
+    }
+
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    fn help(&self) -> &'static str {
+        "lists your active sessions"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, _args: &str) -> Result<(), CommandError> {
+        let caller = context.caller;
+        let now = timestamp_to_string(SystemTime::now());
+        let sessions = context.storage.fetch_active_sessions(Some(caller.user_id), &now).await?;
+
+        let (current, connected): (Option<i64>, Vec<i64>) = {
+            let client_map = context.clients.lock().await;
+            let current = client_map
+                .get(&caller.address)
+                .and_then(|client_record| client_record.session_id);
+            let connected = client_map
+                .values()
+                .filter(|client_record| client_record.user_id == Some(caller.user_id))
+                .filter_map(|client_record| client_record.session_id)
+                .collect();
+            (current, connected)
+        };
+
+        let mut lines = vec![format!("Active sessions ({}):", sessions.len())];
+        for session in sessions {
+            let state = if Some(session.id) == current {
+                " (this one)"
+            } else if connected.contains(&session.id) {
+                " (connected)"
+            } else {
+                ""
+            };
+            lines.push(format!(
+                "  #{} since {}, expires {}{}",
+                session.id,
+                session.created_at,
+                session.expires_at,
+                state,
+            ));
+        }
+        Ok(context.reply(lines.join("\n")).await?)
+    }
+}
+
+
+/// `/help [command]` lists commands available to the caller (or describes a single one).
+struct HelpCommand;
+
+
+#[async_trait]
+impl ServerCommand for HelpCommand {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn usage(&self) -> &'static str {
+        "[command]"
+    }
+
+    fn help(&self) -> &'static str {
+        "lists available commands"
+    }
+
+    async fn run(&self, context: &CommandContext<'_>, args: &str) -> Result<(), CommandError> {
+        let is_moderator = context.caller.is_moderator;
+        let commands: Vec<&Arc<dyn ServerCommand>> = match args.trim_start_matches('/') {
+            "" => context.registry.available(is_moderator).collect(),
+            name => {
+                let name = name.to_lowercase();
+                let command = context.registry
+                    .available(is_moderator)
+                    .find(|command| command.name() == name);
+                match command {
+                    Some(command) => vec![command],
+                    None => return Err(CommandError::Rejected(format!(
+                        "unknown command /{} (see /help)",
+                        name,
+                    ))),
+                }
+            },
+        };
+
+        let mut lines = vec!["Commands:".to_string()];
+        for command in commands {
+            lines.push(format!("  {} - {}", synopsis(command), command.help()));
+        }
+        lines.push("  (start a text with // to send it as is)".to_string());
+        Ok(context.reply(lines.join("\n")).await?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{check_display_name, is_command, split_command, unescape, CommandRegistry};
+
+
+    #[test]
+    fn test_split_command() {
+        assert!(is_command("/me waves"));
+        assert!(!is_command("//me is not a command"));
+        assert!(!is_command("hello /me"));
+        assert_eq!(split_command("/ME  waves at   all "), ("me".to_string(), "waves at   all"));
+        assert_eq!(split_command("/help"), ("help".to_string(), ""));
+        assert_eq!(split_command("/"), ("".to_string(), ""));
+
+        let mut text = "//usr/bin".to_string();
+        unescape(&mut text);
+        assert_eq!(text, "/usr/bin");
+    }
+
+    #[test]
+    fn test_available_commands() {
+        let registry = CommandRegistry::builtin();
+        let names = |is_moderator| -> Vec<&str> {
+            registry.available(is_moderator).map(|command| command.name()).collect()
+        };
+        assert_eq!(names(false), vec!["me", "msg", "nick", "whois", "sessions", "help"]);
+        assert_eq!(names(true), vec!["me", "msg", "nick", "topic", "whois", "sessions", "help"]);
+        assert!(registry.find("topic").is_some_and(|command| command.moderator_only()));
+        assert!(registry.find("shrug").is_none());
+    }
+
+    #[test]
+    fn test_check_display_name() {
+        assert!(check_display_name("The Boss").is_ok());
+        assert!(check_display_name(&"x".repeat(33)).is_err());
+        assert!(check_display_name("bell\u{7}").is_err());
+    }
+}
@@ -0,0 +1,1155 @@
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::error::ServerError;
+use crate::plugin::BUILTIN_PLUGINS;
+use crate::storage::StorageBackend;
+
+
+/// Prefix of all environment variables overriding the configuration file.
+const ENV_PREFIX: &str = "XCHAT_";
+
+
+/// `Config` is complete configuration of the server.
+///
+/// Values are taken from (in order of increasing priority) built-in defaults, TOML configuration
+/// file, environment variables (see [Config::apply_env]) and command-line options
+/// (see [Config::apply_cli]).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses (`host:port`) of chat listeners.
+    pub listeners: Vec<String>,
+    /// DB URL (e.g. `sqlite:data.db`).
+    pub db_url: String,
+    /// Whether pending schema migrations are applied on startup (otherwise startup fails
+    /// if there are any).
+    pub auto_migrate: bool,
+    /// Message of the day sent within welcome message (`{login}` is replaced by user login).
+    pub motd: String,
+    pub web: WebConfig,
+    pub limits: LimitsConfig,
+    pub features: FeaturesConfig,
+    pub logging: LoggingConfig,
+    pub retention: RetentionConfig,
+    pub backup: BackupConfig,
+    pub plugins: PluginsConfig,
+    pub federation: FederationConfig,
+    pub sessions: SessionsConfig,
+    pub lockout: LockoutConfig,
+    pub quotas: QuotasConfig,
+    pub attachments: AttachmentsConfig,
+    pub filters: FiltersConfig,
+    pub connections: ConnectionsConfig,
+}
+
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    /// Address (`host:port`) of web server (user list, search and metrics).
+    pub address: String,
+}
+
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Minimal delay (in seconds) between two fanned out typing notifications of a single client.
+    pub typing_throttle_secs: u64,
+    /// Maximal number of characters of a parent message quoted in a reply.
+    pub quote_excerpt_length: usize,
+    /// Number of hits on a single page of search results sent to chat clients.
+    pub search_page_size: u32,
+    /// Number of hits on a single page of search results on the web page.
+    pub web_search_page_size: u32,
+}
+
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesConfig {
+    /// Web server with user list and search.
+    pub web: bool,
+    /// Prometheus metrics endpoint of the web server.
+    pub metrics: bool,
+    /// Join/leave notifications and list of online users.
+    pub presence: bool,
+    /// Typing indicators.
+    pub typing: bool,
+    /// Emoji reactions on messages.
+    pub reactions: bool,
+    /// Full-text search over chat history.
+    pub search: bool,
+    /// Mentions (`@login`) of users in chat messages.
+    pub mentions: bool,
+}
+
+
+/// `RetentionConfig` limits how long chat history is kept (zero disables the given limit).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Chat messages older than the given number of days are pruned.
+    pub message_max_age_days: u32,
+    /// Only the given number of the newest chat messages (of the single chat room) is kept.
+    pub message_max_count: u32,
+    /// Log-in records (and failed log-in attempts) older than the given number of days are
+    /// pruned.
+    pub login_max_age_days: u32,
+    /// Delay (in seconds) between two runs of pruning.
+    pub interval_secs: u64,
+    /// Maximal number of rows pruned (and archived) at once.
+    pub batch_size: u32,
+    /// Directory where pruned rows are archived (as gzipped JSON Lines) before they are deleted.
+    pub archive_dir: Option<PathBuf>,
+}
+
+
+/// `PluginsConfig` selects built-in plugins (see [crate::plugin]).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Names of enabled built-in plugins (e.g. `["dice", "links"]`).
+    pub enabled: Vec<String>,
+    /// Login of the bot user that plugins send messages as (it is created if missing and
+    /// nobody can log in as it).
+    pub bot_login: String,
+}
+
+
+/// `FederationConfig` sets links to other servers sharing the chat room (see
+/// [crate::federation]). Federation is enabled by `server_name`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FederationConfig {
+    /// Name of this server that tags relayed events (logins of remote users are shown as
+    /// `login@server_name`); it must be unique among linked servers.
+    pub server_name: Option<String>,
+    /// Address (`host:port`) where links from other servers are accepted.
+    pub listen: Option<String>,
+    /// Statically configured links to other servers.
+    pub links: Vec<FederationLinkConfig>,
+}
+
+
+/// `FederationLinkConfig` is a single link to another server.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FederationLinkConfig {
+    /// `server_name` of the other server.
+    pub name: String,
+    /// Address (`host:port`) of federation listener of the other server; this server connects
+    /// to it (and reconnects). Without an address the link is just accepted from the other
+    /// server.
+    pub address: Option<String>,
+    /// Secret shared by both servers of the link.
+    pub secret: String,
+}
+
+
+/// `SessionsConfig` sets log-in sessions (a session token is issued at each log-in with
+/// password, the client may log in by the token later).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Lifetime (in seconds) of a session since its log-in.
+    pub ttl_secs: u64,
+    /// Delay (in seconds) between two checks of sessions of connected clients; clients
+    /// of revoked and expired sessions are disconnected.
+    pub check_interval_secs: u64,
+}
+
+
+/// `LockoutConfig` sets protection against guessing of passwords. Log-ins from a peer address
+/// (or to a login) are refused for a while after several failed attempts, the lockout doubles
+/// with each further failure.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    /// Number of failed log-ins after which the connection is closed.
+    pub max_attempts_per_connection: u32,
+    /// Number of failed log-ins from a single IP address which locks the address out.
+    pub address_threshold: u32,
+    /// Number of failed log-ins to a single login which locks the login out.
+    pub login_threshold: u32,
+    /// Duration (in seconds) of the first lockout.
+    pub base_secs: u64,
+    /// Maximal duration (in seconds) of a lockout; failures are forgotten after this time
+    /// without another failure.
+    pub max_secs: u64,
+}
+
+
+/// `QuotasConfig` limits files and images sent by a single user per (calendar) day; zero
+/// disables the given limit.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotasConfig {
+    /// Maximal number of attachments sent by a user per day.
+    pub attachments_per_day: u32,
+    /// Maximal total size (in bytes) of attachments sent by a user per day.
+    pub attachment_bytes_per_day: u64,
+}
+
+
+/// `AttachmentsConfig` restricts types of sent files and images. Types are MIME types sniffed
+/// from content of the attachments (and guessed from extensions of file names), a pattern is
+/// either a type (`image/png`), all subtypes of a type (`image/*`) or any type (`*`).
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentsConfig {
+    /// Allowed types (everything not denied is allowed if it is empty).
+    pub allowed_types: Vec<String>,
+    /// Denied types (they are refused even if they are allowed).
+    pub denied_types: Vec<String>,
+    /// Maximal width and height (in pixels) of thumbnails of images sent instead of the
+    /// originals.
+    pub thumbnail_size: u32,
+}
+
+
+/// `FiltersConfig` sets content filtering of chat messages by rules of a separate file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiltersConfig {
+    /// Path to the file with filter rules (messages are not filtered without it).
+    pub path: Option<PathBuf>,
+    /// Delay (in seconds) between two checks whether the rules (or blocklists) changed.
+    pub reload_interval_secs: u64,
+}
+
+
+/// `ConnectionsConfig` limits client connections; zero disables the given limit.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionsConfig {
+    /// Maximal number of connected clients (logged in or not).
+    pub max_connections: u32,
+    /// Maximal number of clients connected from a single IP address.
+    pub max_connections_per_ip: u32,
+    /// Time (in seconds) a connection has to log in, it is closed afterwards.
+    pub auth_deadline_secs: u64,
+}
+
+
+/// `BackupConfig` sets online backups of SQLite database.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    /// Directory of backups (backups are disabled if it is not set).
+    pub dir: Option<PathBuf>,
+    /// Delay (in seconds) between two scheduled backups (zero disables scheduled backups,
+    /// backups are then created on request only).
+    pub interval_secs: u64,
+    /// Number of the newest backups kept in the directory (older ones are deleted).
+    pub keep: u32,
+}
+
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Filter of log records in syntax of `tracing_subscriber::EnvFilter`
+    /// (e.g. `info,server=debug,sqlx=warn`).
+    pub filter: String,
+    /// Output format of log records.
+    pub format: LogFormat,
+}
+
+
+/// `LogFormat` is output format of log records.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// JSON objects (one per line) suitable for log shipping.
+    Json,
+}
+
+
+/// `CliOverrides` holds values of command-line options that override the configuration.
+#[derive(Debug, Default)]
+pub struct CliOverrides {
+    pub config_path: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub db_url: Option<String>,
+    pub web_port: Option<u16>,
+    pub log_filter: Option<String>,
+    pub log_format: Option<LogFormat>,
+}
+
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listeners: vec!["localhost:11111".to_string()],
+            db_url: "sqlite:data.db".to_string(),
+            auto_migrate: true,
+            motd: "Welcome to x-chat {login}!".to_string(),
+            web: WebConfig::default(),
+            limits: LimitsConfig::default(),
+            features: FeaturesConfig::default(),
+            logging: LoggingConfig::default(),
+            retention: RetentionConfig::default(),
+            backup: BackupConfig::default(),
+            plugins: PluginsConfig::default(),
+            federation: FederationConfig::default(),
+            sessions: SessionsConfig::default(),
+            lockout: LockoutConfig::default(),
+            quotas: QuotasConfig::default(),
+            attachments: AttachmentsConfig::default(),
+            filters: FiltersConfig::default(),
+            connections: ConnectionsConfig::default(),
+        }
+    }
+}
+
+
+impl Default for WebConfig {
+    fn default() -> Self {
+        WebConfig {
+            address: "0.0.0.0:8080".to_string(),
+        }
+    }
+}
+
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            typing_throttle_secs: 2,
+            quote_excerpt_length: 40,
+            search_page_size: 10,
+            web_search_page_size: 20,
+        }
+    }
+}
+
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            message_max_age_days: 0,
+            message_max_count: 0,
+            login_max_age_days: 0,
+            interval_secs: 3600,
+            batch_size: 500,
+            archive_dir: None,
+        }
+    }
+}
+
+
+impl RetentionConfig {
+    /// `is_enabled` tells whether any retention limit is set (so pruning is needed at all).
+    pub fn is_enabled(&self) -> bool {
+        self.message_max_age_days > 0 || self.message_max_count > 0 || self.login_max_age_days > 0
+    }
+}
+
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        PluginsConfig {
+            enabled: vec![],
+            bot_login: "xbot".to_string(),
+        }
+    }
+}
+
+
+impl FederationConfig {
+    /// `is_enabled` tells whether this server takes part in federation.
+    pub fn is_enabled(&self) -> bool {
+        self.server_name.is_some()
+    }
+}
+
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        SessionsConfig {
+            ttl_secs: 7 * 24 * 3600,
+            check_interval_secs: 5,
+        }
+    }
+}
+
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            max_attempts_per_connection: 3,
+            address_threshold: 10,
+            login_threshold: 5,
+            base_secs: 2,
+            max_secs: 15 * 60,
+        }
+    }
+}
+
+
+impl Default for QuotasConfig {
+    fn default() -> Self {
+        QuotasConfig {
+            attachments_per_day: 100,
+            attachment_bytes_per_day: 50 * 1024 * 1024,
+        }
+    }
+}
+
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        AttachmentsConfig {
+            allowed_types: vec![],
+            denied_types: [
+                "application/x-executable",
+                "application/x-msdownload",
+                "application/x-mach-binary",
+                "application/java-archive",
+                "text/x-shellscript",
+            ].iter().map(|mime| mime.to_string()).collect(),
+            thumbnail_size: 160,
+        }
+    }
+}
+
+
+impl Default for FiltersConfig {
+    fn default() -> Self {
+        FiltersConfig {
+            path: None,
+            reload_interval_secs: 5,
+        }
+    }
+}
+
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        ConnectionsConfig {
+            max_connections: 1000,
+            max_connections_per_ip: 20,
+            auth_deadline_secs: 30,
+        }
+    }
+}
+
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            dir: None,
+            interval_secs: 0,
+            keep: 7,
+        }
+    }
+}
+
+
+impl BackupConfig {
+    /// `is_scheduled` tells whether backups are created periodically.
+    pub fn is_scheduled(&self) -> bool {
+        self.dir.is_some() && self.interval_secs > 0
+    }
+}
+
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        FeaturesConfig {
+            web: true,
+            metrics: true,
+            presence: true,
+            typing: true,
+            reactions: true,
+            search: true,
+            mentions: true,
+        }
+    }
+}
+
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            filter: "info,sqlx=warn".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim().to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:?} (expected `text` or `json`)", text)),
+        }
+    }
+}
+
+
+impl Config {
+    /// `load` builds configuration from the optional configuration file, environment variables
+    /// and command-line options (in this order of priority) and validates the result.
+    ///
+    /// The configuration file path is given by `--config` option or `XCHAT_CONFIG` environment
+    /// variable. Without any, built-in defaults are used.
+    pub fn load(cli: &CliOverrides) -> Result<Config, ServerError> {
+        let path = cli.config_path.clone().or_else(|| env_var("CONFIG"));
+
+        let mut config = match path {
+            Some(path) => Config::from_file(Path::new(&path))?,
+            None => Config::default(),
+        };
+
+        config.apply_env(env_var)?;
+        config.apply_cli(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+
+    /// `from_file` reads configuration from the given TOML file (missing values get defaults).
+    pub fn from_file(path: &Path) -> Result<Config, ServerError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => Err(ServerError::ConfigError(
+                format!("failed to read {}: {}", path.display(), err),
+            ))?,
+        };
+
+        Config::from_toml(&content).map_err(|err| match err {
+            ServerError::ConfigError(detail) =>
+                ServerError::ConfigError(format!("{}: {}", path.display(), detail)),
+            err => err,
+        })
+    }
+
+
+    /// `from_toml` parses configuration from TOML text (missing values get defaults).
+    pub fn from_toml(content: &str) -> Result<Config, ServerError> {
+        toml::from_str(content).map_err(|err| ServerError::ConfigError(err.to_string()))
+    }
+
+
+    /// `apply_env` overrides configuration by environment variables obtained by the given
+    /// `lookup` function (it gets variable name without `XCHAT_` prefix).
+    ///
+    /// Recognized variables are `XCHAT_LISTENERS` (comma separated addresses), `XCHAT_DB_URL`,
+    /// `XCHAT_AUTO_MIGRATE`, `XCHAT_MOTD`, `XCHAT_WEB_ADDRESS`, `XCHAT_LOGGING_FILTER`,
+    /// `XCHAT_LOGGING_FORMAT`, `XCHAT_RETENTION_ARCHIVE_DIR`, `XCHAT_BACKUP_DIR`,
+    /// `XCHAT_FILTERS_PATH`, `XCHAT_ATTACHMENTS_ALLOWED_TYPES` and `XCHAT_ATTACHMENTS_DENIED_TYPES`
+    /// (comma separated patterns), `XCHAT_PLUGINS_ENABLED` (comma separated names),
+    /// `XCHAT_PLUGINS_BOT_LOGIN`, `XCHAT_FEDERATION_SERVER_NAME`, `XCHAT_FEDERATION_LISTEN`,
+    /// `XCHAT_LIMITS_<NAME>`, `XCHAT_RETENTION_<NAME>`, `XCHAT_BACKUP_<NAME>`,
+    /// `XCHAT_SESSIONS_<NAME>`, `XCHAT_LOCKOUT_<NAME>`, `XCHAT_QUOTAS_<NAME>`,
+    /// `XCHAT_ATTACHMENTS_<NAME>`, `XCHAT_FILTERS_<NAME>`, `XCHAT_CONNECTIONS_<NAME>` and
+    /// `XCHAT_FEATURES_<NAME>` (where `<NAME>` is upper-cased name of the key in the configuration
+    /// file).
+    pub fn apply_env<F>(&mut self, lookup: F) -> Result<(), ServerError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(value) = lookup("LISTENERS") {
+            self.listeners = value
+                .split(',')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = lookup("DB_URL") {
+            self.db_url = value;
+        }
+        parse_env(&lookup, "AUTO_MIGRATE", &mut self.auto_migrate)?;
+        if let Some(value) = lookup("MOTD") {
+            self.motd = value;
+        }
+        if let Some(value) = lookup("WEB_ADDRESS") {
+            self.web.address = value;
+        }
+        if let Some(value) = lookup("LOGGING_FILTER") {
+            self.logging.filter = value;
+        }
+        parse_env(&lookup, "LOGGING_FORMAT", &mut self.logging.format)?;
+        if let Some(value) = lookup("RETENTION_ARCHIVE_DIR") {
+            self.retention.archive_dir = Some(PathBuf::from(value));
+        }
+        if let Some(value) = lookup("BACKUP_DIR") {
+            self.backup.dir = Some(PathBuf::from(value));
+        }
+        if let Some(value) = lookup("FILTERS_PATH") {
+            self.filters.path = Some(PathBuf::from(value));
+        }
+        if let Some(value) = lookup("ATTACHMENTS_ALLOWED_TYPES") {
+            self.attachments.allowed_types = value
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = lookup("ATTACHMENTS_DENIED_TYPES") {
+            self.attachments.denied_types = value
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = lookup("PLUGINS_ENABLED") {
+            self.plugins.enabled = value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(value) = lookup("PLUGINS_BOT_LOGIN") {
+            self.plugins.bot_login = value;
+        }
+        if let Some(value) = lookup("FEDERATION_SERVER_NAME") {
+            self.federation.server_name = Some(value);
+        }
+        if let Some(value) = lookup("FEDERATION_LISTEN") {
+            self.federation.listen = Some(value);
+        }
+
+        let limits = &mut self.limits;
+        parse_env(&lookup, "LIMITS_TYPING_THROTTLE_SECS", &mut limits.typing_throttle_secs)?;
+        parse_env(&lookup, "LIMITS_QUOTE_EXCERPT_LENGTH", &mut limits.quote_excerpt_length)?;
+        parse_env(&lookup, "LIMITS_SEARCH_PAGE_SIZE", &mut limits.search_page_size)?;
+        parse_env(&lookup, "LIMITS_WEB_SEARCH_PAGE_SIZE", &mut limits.web_search_page_size)?;
+
+        let retention = &mut self.retention;
+        parse_env(&lookup, "RETENTION_MESSAGE_MAX_AGE_DAYS", &mut retention.message_max_age_days)?;
+        parse_env(&lookup, "RETENTION_MESSAGE_MAX_COUNT", &mut retention.message_max_count)?;
+        parse_env(&lookup, "RETENTION_LOGIN_MAX_AGE_DAYS", &mut retention.login_max_age_days)?;
+        parse_env(&lookup, "RETENTION_INTERVAL_SECS", &mut retention.interval_secs)?;
+        parse_env(&lookup, "RETENTION_BATCH_SIZE", &mut retention.batch_size)?;
+
+        let backup = &mut self.backup;
+        parse_env(&lookup, "BACKUP_INTERVAL_SECS", &mut backup.interval_secs)?;
+        parse_env(&lookup, "BACKUP_KEEP", &mut backup.keep)?;
+
+        let sessions = &mut self.sessions;
+        parse_env(&lookup, "SESSIONS_TTL_SECS", &mut sessions.ttl_secs)?;
+        parse_env(&lookup, "SESSIONS_CHECK_INTERVAL_SECS", &mut sessions.check_interval_secs)?;
+
+        let lockout = &mut self.lockout;
+        parse_env(
+            &lookup,
+            "LOCKOUT_MAX_ATTEMPTS_PER_CONNECTION",
+            &mut lockout.max_attempts_per_connection,
+        )?;
+        parse_env(&lookup, "LOCKOUT_ADDRESS_THRESHOLD", &mut lockout.address_threshold)?;
+        parse_env(&lookup, "LOCKOUT_LOGIN_THRESHOLD", &mut lockout.login_threshold)?;
+        parse_env(&lookup, "LOCKOUT_BASE_SECS", &mut lockout.base_secs)?;
+        parse_env(&lookup, "LOCKOUT_MAX_SECS", &mut lockout.max_secs)?;
+
+        let quotas = &mut self.quotas;
+        parse_env(&lookup, "QUOTAS_ATTACHMENTS_PER_DAY", &mut quotas.attachments_per_day)?;
+        parse_env(
+            &lookup,
+            "QUOTAS_ATTACHMENT_BYTES_PER_DAY",
+            &mut quotas.attachment_bytes_per_day,
+        )?;
+
+        let attachments = &mut self.attachments;
+        parse_env(&lookup, "ATTACHMENTS_THUMBNAIL_SIZE", &mut attachments.thumbnail_size)?;
+
+        let filters = &mut self.filters;
+        parse_env(&lookup, "FILTERS_RELOAD_INTERVAL_SECS", &mut filters.reload_interval_secs)?;
+
+        let connections = &mut self.connections;
+        parse_env(&lookup, "CONNECTIONS_MAX_CONNECTIONS", &mut connections.max_connections)?;
+        parse_env(
+            &lookup,
+            "CONNECTIONS_MAX_CONNECTIONS_PER_IP",
+            &mut connections.max_connections_per_ip,
+        )?;
+        parse_env(&lookup, "CONNECTIONS_AUTH_DEADLINE_SECS", &mut connections.auth_deadline_secs)?;
+
+        let features = &mut self.features;
+        parse_env(&lookup, "FEATURES_WEB", &mut features.web)?;
+        parse_env(&lookup, "FEATURES_METRICS", &mut features.metrics)?;
+        parse_env(&lookup, "FEATURES_PRESENCE", &mut features.presence)?;
+        parse_env(&lookup, "FEATURES_TYPING", &mut features.typing)?;
+        parse_env(&lookup, "FEATURES_REACTIONS", &mut features.reactions)?;
+        parse_env(&lookup, "FEATURES_SEARCH", &mut features.search)?;
+        parse_env(&lookup, "FEATURES_MENTIONS", &mut features.mentions)?;
+
+        Ok(())
+    }
+
+
+    /// `apply_cli` overrides configuration by the given command-line options. Host and port
+    /// replace respective part of the first listener address (other listeners are kept),
+    /// web port replaces port of the web server address.
+    pub fn apply_cli(&mut self, cli: &CliOverrides) {
+        if cli.host.is_some() || cli.port.is_some() {
+            let first = self.listeners.first().cloned().unwrap_or_default();
+            let (host, port) = split_address(&first);
+            let host = cli.host.clone().unwrap_or(host);
+            let port = cli.port.map(|port| port.to_string()).unwrap_or(port);
+            let address = format!("{}:{}", host, port);
+            match self.listeners.first_mut() {
+                Some(first) => *first = address,
+                None => self.listeners.push(address),
+            }
+        }
+        if let Some(db_url) = &cli.db_url {
+            self.db_url = db_url.clone();
+        }
+        if let Some(web_port) = cli.web_port {
+            let (host, _) = split_address(&self.web.address);
+            self.web.address = format!("{}:{}", host, web_port);
+        }
+        if let Some(log_filter) = &cli.log_filter {
+            self.logging.filter = log_filter.clone();
+        }
+        if let Some(log_format) = cli.log_format {
+            self.logging.format = log_format;
+        }
+    }
+
+
+    /// `validate` checks the configuration and reports the first found problem.
+    pub fn validate(&self) -> Result<(), ServerError> {
+        let invalid = |detail: String| Err(ServerError::ConfigError(detail));
+
+        if self.listeners.is_empty() {
+            return invalid("at least one listener address is required".to_string());
+        }
+        for address in self.listeners.iter() {
+            validate_address("listeners", address)?;
+        }
+
+        let backend = match StorageBackend::from_url(&self.db_url) {
+            Ok(backend) => backend,
+            Err(err) => return invalid(format!("db_url: {}", err)),
+        };
+
+        if self.motd.trim().is_empty() {
+            return invalid("motd: must not be empty".to_string());
+        }
+
+        if self.features.web {
+            validate_address("web.address", &self.web.address)?;
+        }
+
+        let limits = &self.limits;
+        if limits.quote_excerpt_length == 0 {
+            return invalid("limits.quote_excerpt_length: must be positive".to_string());
+        }
+        if limits.search_page_size == 0 {
+            return invalid("limits.search_page_size: must be positive".to_string());
+        }
+        if limits.web_search_page_size == 0 {
+            return invalid("limits.web_search_page_size: must be positive".to_string());
+        }
+
+        let retention = &self.retention;
+        if retention.interval_secs == 0 {
+            return invalid("retention.interval_secs: must be positive".to_string());
+        }
+        if retention.batch_size == 0 {
+            return invalid("retention.batch_size: must be positive".to_string());
+        }
+
+        let backup = &self.backup;
+        if backup.keep == 0 {
+            return invalid("backup.keep: must be positive".to_string());
+        }
+        if backup.interval_secs > 0 && backup.dir.is_none() {
+            return invalid("backup.dir: missing (interval_secs is set)".to_string());
+        }
+        if backup.dir.is_some() && backend != StorageBackend::Sqlite {
+            return invalid("backup.dir: online backups are supported for SQLite only".to_string());
+        }
+
+        let sessions = &self.sessions;
+        if sessions.ttl_secs == 0 {
+            return invalid("sessions.ttl_secs: must be positive".to_string());
+        }
+        if sessions.check_interval_secs == 0 {
+            return invalid("sessions.check_interval_secs: must be positive".to_string());
+        }
+
+        let attachments = &self.attachments;
+        let patterns = [
+            ("attachments.allowed_types", &attachments.allowed_types),
+            ("attachments.denied_types", &attachments.denied_types),
+        ];
+        for (key, patterns) in patterns {
+            for pattern in patterns {
+                if !is_type_pattern(pattern) {
+                    return invalid(format!("{}: invalid type pattern `{}`", key, pattern));
+                }
+            }
+        }
+        if attachments.thumbnail_size == 0 {
+            return invalid("attachments.thumbnail_size: must be positive".to_string());
+        }
+
+        if self.filters.reload_interval_secs == 0 {
+            return invalid("filters.reload_interval_secs: must be positive".to_string());
+        }
+
+        if self.connections.auth_deadline_secs == 0 {
+            return invalid("connections.auth_deadline_secs: must be positive".to_string());
+        }
+
+        let lockout = &self.lockout;
+        if lockout.max_attempts_per_connection == 0 {
+            return invalid("lockout.max_attempts_per_connection: must be positive".to_string());
+        }
+        if lockout.address_threshold == 0 || lockout.login_threshold == 0 {
+            return invalid("lockout thresholds: must be positive".to_string());
+        }
+        if lockout.base_secs == 0 || lockout.base_secs > lockout.max_secs {
+            return invalid("lockout.base_secs: must be positive and at most max_secs".to_string());
+        }
+
+        for name in self.plugins.enabled.iter() {
+            if !BUILTIN_PLUGINS.contains(&name.as_str()) {
+                return invalid(format!(
+                    "plugins.enabled: unknown plugin `{}` (known are {})",
+                    name,
+                    BUILTIN_PLUGINS.join(", "),
+                ));
+            }
+        }
+        if self.plugins.bot_login.trim().is_empty() {
+            return invalid("plugins.bot_login: must not be empty".to_string());
+        }
+
+        self.federation.validate()?;
+
+        if let Err(err) = EnvFilter::try_new(&self.logging.filter) {
+            return invalid(format!("logging.filter: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+
+impl FederationConfig {
+    /// `validate` checks names of servers, addresses and secrets of links.
+    fn validate(&self) -> Result<(), ServerError> {
+        let invalid = |detail: String| Err(ServerError::ConfigError(detail));
+
+        let Some(server_name) = &self.server_name else {
+            if self.listen.is_some() || !self.links.is_empty() {
+                return invalid("federation.server_name: missing (links are set)".to_string());
+            }
+            return Ok(());
+        };
+        if let Err(detail) = validate_server_name(server_name) {
+            return invalid(format!("federation.server_name: {}", detail));
+        }
+        if let Some(listen) = &self.listen {
+            validate_address("federation.listen", listen)?;
+        }
+
+        for (index, link) in self.links.iter().enumerate() {
+            if let Err(detail) = validate_server_name(&link.name) {
+                return invalid(format!("federation.links.name: {}", detail));
+            }
+            if &link.name == server_name {
+                return invalid(format!("federation.links.name: `{}` is this server", link.name));
+            }
+            if self.links[..index].iter().any(|other| other.name == link.name) {
+                return invalid(format!("federation.links.name: `{}` is duplicated", link.name));
+            }
+            if link.secret.is_empty() {
+                return invalid(format!("federation.links.secret: missing for `{}`", link.name));
+            }
+            match &link.address {
+                Some(address) => validate_address("federation.links.address", address)?,
+                None if self.listen.is_none() => return invalid(format!(
+                    "federation.listen: missing (link `{}` has no address to connect to)",
+                    link.name,
+                )),
+                None => {},
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// `validate_server_name` checks the name is usable as a suffix of logins (`login@name`).
+fn validate_server_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(format!("`{}` might contain just letters, digits, `-`, `_` and `.`", name));
+    }
+    Ok(())
+}
+
+
+/// `is_type_pattern` tells whether the pattern of attachment types (see [AttachmentsConfig])
+/// is valid.
+fn is_type_pattern(pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let Some((kind, subtype)) = pattern.split_once('/') else {
+        return false;
+    };
+    let is_token = |part: &str| {
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    };
+    is_token(kind) && (subtype == "*" || is_token(subtype))
+}
+
+
+/// `env_var` reads an environment variable of the given name with `XCHAT_` prefix.
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(format!("{}{}", ENV_PREFIX, name)).ok()
+}
+
+
+/// `parse_env` overrides the `target` by parsed value of the given environment variable.
+fn parse_env<F, T>(lookup: &F, name: &str, target: &mut T) -> Result<(), ServerError>
+where
+    F: Fn(&str) -> Option<String>,
+    T: std::str::FromStr,
+{
+    if let Some(value) = lookup(name) {
+        match value.trim().parse::<T>() {
+            Ok(value) => *target = value,
+            Err(_) => Err(ServerError::ConfigError(
+                format!("{}{}: invalid value {:?}", ENV_PREFIX, name, value),
+            ))?,
+        }
+    }
+
+    Ok(())
+}
+
+
+/// `split_address` splits `host:port` address into its parts (defaults are used for missing).
+fn split_address(address: &str) -> (String, String) {
+    match address.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), port.to_string()),
+        None if address.is_empty() => ("localhost".to_string(), "11111".to_string()),
+        None => (address.to_string(), "11111".to_string()),
+    }
+}
+
+
+/// `validate_address` checks that the given `host:port` address is resolvable.
+fn validate_address(key: &str, address: &str) -> Result<(), ServerError> {
+    match address.to_socket_addrs() {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::ConfigError(
+            format!("{}: invalid address {:?}: {}", key, address, err),
+        )),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{CliOverrides, Config, LogFormat, WebConfig};
+
+
+    #[test]
+    fn test_from_toml_with_defaults() {
+        let config = Config::from_toml(r#"
+db_url = "sqlite:other.db"
+
+[web]
+address = "127.0.0.1:9090"
+
+[features]
+typing = false
+
+[logging]
+format = "json"
+"#).unwrap();
+
+        assert_eq!(config.db_url, "sqlite:other.db");
+        assert_eq!(config.web.address, "127.0.0.1:9090");
+        assert!(!config.features.typing);
+        assert!(config.features.search);
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(config.listeners, Config::default().listeners);
+        assert_eq!(config.limits, Config::default().limits);
+    }
+
+
+    #[test]
+    fn test_from_toml_rejects_unknown_keys() {
+        let result = Config::from_toml("[web]\nport = 8080\n");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("unknown field `port`"));
+    }
+
+
+    #[test]
+    fn test_apply_env_and_cli() {
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("LISTENERS", "0.0.0.0:1111, 0.0.0.0:2222"),
+            ("DB_URL", "sqlite:env.db"),
+            ("LIMITS_SEARCH_PAGE_SIZE", "5"),
+            ("FEATURES_REACTIONS", "false"),
+            ("QUOTAS_ATTACHMENTS_PER_DAY", "0"),
+            ("CONNECTIONS_MAX_CONNECTIONS_PER_IP", "3"),
+        ]);
+        let mut config = Config::default();
+        config.apply_env(|name| env.get(name).map(|value| value.to_string())).unwrap();
+
+        assert_eq!(config.listeners, vec!["0.0.0.0:1111", "0.0.0.0:2222"]);
+        assert_eq!(config.db_url, "sqlite:env.db");
+        assert_eq!(config.limits.search_page_size, 5);
+        assert!(!config.features.reactions);
+        assert_eq!(config.quotas.attachments_per_day, 0);
+        assert_eq!(config.connections.max_connections_per_ip, 3);
+
+        let cli = CliOverrides {
+            port: Some(3333),
+            web_port: Some(8081),
+            ..CliOverrides::default()
+        };
+        config.apply_cli(&cli);
+
+        assert_eq!(config.listeners, vec!["0.0.0.0:3333", "0.0.0.0:2222"]);
+        assert_eq!(config.db_url, "sqlite:env.db");
+        assert_eq!(config.web.address, "0.0.0.0:8081");
+    }
+
+
+    #[test]
+    fn test_apply_cli_overrides_chat_listener_only() {
+        let mut config = Config {
+            listeners: vec!["localhost:1111".to_string(), "[::1]:2222".to_string()],
+            ..Config::default()
+        };
+        config.federation.listen = Some("0.0.0.0:13000".to_string());
+        let cli = CliOverrides {
+            host: Some("0.0.0.0".to_string()),
+            ..CliOverrides::default()
+        };
+        config.apply_cli(&cli);
+
+        assert_eq!(config.listeners, vec!["0.0.0.0:1111", "[::1]:2222"]);
+        assert_eq!(config.federation.listen.as_deref(), Some("0.0.0.0:13000"));
+        assert_eq!(config.web.address, WebConfig::default().address);
+
+        let mut config = Config {
+            listeners: vec![],
+            ..Config::default()
+        };
+        let cli = CliOverrides {
+            port: Some(3333),
+            ..CliOverrides::default()
+        };
+        config.apply_cli(&cli);
+
+        assert_eq!(config.listeners, vec!["localhost:3333"]);
+    }
+
+
+    #[test]
+    fn test_apply_env_rejects_invalid_value() {
+        let mut config = Config::default();
+        let result = config.apply_env(|name| match name {
+            "FEATURES_WEB" => Some("maybe".to_string()),
+            _ => None,
+        });
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("XCHAT_FEATURES_WEB"));
+    }
+
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config {
+            listeners: vec!["localhost:not-a-port".to_string()],
+            ..Config::default()
+        };
+        assert!(config.validate().unwrap_err().to_string().contains("listeners"));
+
+        let config = Config {
+            db_url: "mysql://localhost/xchat".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().unwrap_err().to_string().contains("db_url"));
+
+        let mut config = Config::default();
+        config.backup.interval_secs = 3600;
+        assert!(config.validate().unwrap_err().to_string().contains("backup.dir"));
+        config.backup.dir = Some("backups".into());
+        assert!(config.validate().is_ok());
+        config.db_url = "memory:".to_string();
+        assert!(config.validate().unwrap_err().to_string().contains("SQLite only"));
+
+        let mut config = Config::default();
+        config.attachments.allowed_types = vec!["image/*".to_string(), "text".to_string()];
+        assert!(config.validate().unwrap_err().to_string().contains("`text`"));
+
+        let mut config = Config::default();
+        config.sessions.ttl_secs = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("sessions.ttl_secs"));
+
+        let mut config = Config::default();
+        config.connections.auth_deadline_secs = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("auth_deadline_secs"));
+
+        let mut config = Config::default();
+        config.lockout.base_secs = config.lockout.max_secs + 1;
+        assert!(config.validate().unwrap_err().to_string().contains("lockout.base_secs"));
+
+        let mut config = Config::default();
+        config.plugins.enabled = vec!["dice".to_string(), "weather".to_string()];
+        assert!(config.validate().unwrap_err().to_string().contains("`weather`"));
+
+        let mut config = Config::from_toml(r#"
+[federation]
+server_name = "prague"
+
+[[federation.links]]
+name = "brno"
+secret = "s3cret"
+"#).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("federation.listen"));
+        config.federation.links[0].address = Some("localhost:12112".to_string());
+        assert!(config.validate().is_ok());
+        config.federation.server_name = Some("brno".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("is this server"));
+        config.federation.server_name = Some("pra@gue".to_string());
+        assert!(config.validate().unwrap_err().to_string().contains("federation.server_name"));
+        config.federation.server_name = None;
+        assert!(config.validate().unwrap_err().to_string().contains("links are set"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::net::IpAddr;
+
+use crate::config::ConnectionsConfig;
+
+
+/// `Refusal` is a reason why a new connection is not accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Refusal {
+    /// Name of the exceeded limit (label of the metric).
+    pub limit: &'static str,
+    /// Explanation sent to the client.
+    pub reason: String,
+}
+
+
+/// `check_limits` tells whether a new connection from the peer IP address exceeds limits of
+/// client connections (see [ConnectionsConfig]), given IP addresses of connected clients.
+pub(crate) fn check_limits<I>(
+        config: &ConnectionsConfig,
+        connected: I,
+        ip: IpAddr,
+) -> Result<(), Refusal>
+where
+    I: IntoIterator<Item = IpAddr>,
+{
+    let mut total = 0;
+    let mut from_ip = 0;
+    for connected_ip in connected {
+        total += 1;
+        if connected_ip == ip {
+            from_ip += 1;
+        }
+    }
+
+    let max_connections = config.max_connections;
+    if max_connections > 0 && total >= max_connections {
+        return Err(Refusal {
+            limit: "max_connections",
+            reason: format!("server is full ({} connections), try again later", max_connections),
+        });
+    }
+    let max_connections_per_ip = config.max_connections_per_ip;
+    if max_connections_per_ip > 0 && from_ip >= max_connections_per_ip {
+        return Err(Refusal {
+            limit: "max_connections_per_ip",
+            reason: format!(
+                "too many connections from {} (at most {})",
+                ip,
+                max_connections_per_ip,
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::check_limits;
+    use crate::config::ConnectionsConfig;
+
+
+    #[test]
+    fn test_check_limits() {
+        let config = ConnectionsConfig {
+            max_connections: 4,
+            max_connections_per_ip: 2,
+            ..ConnectionsConfig::default()
+        };
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert_eq!(check_limits(&config, [], ip), Ok(()));
+        assert_eq!(check_limits(&config, [ip, other_ip], ip), Ok(()));
+
+        let refusal = check_limits(&config, [ip, ip, other_ip], ip).unwrap_err();
+        assert_eq!(refusal.limit, "max_connections_per_ip");
+        assert!(refusal.reason.contains("10.0.0.1"));
+        assert_eq!(check_limits(&config, [ip, ip, other_ip], other_ip), Ok(()));
+
+        let refusal = check_limits(&config, [ip, other_ip, other_ip, ip], other_ip).unwrap_err();
+        assert_eq!(refusal.limit, "max_connections");
+    }
+
+    #[test]
+    fn test_check_limits_unlimited() {
+        let config = ConnectionsConfig {
+            max_connections: 0,
+            max_connections_per_ip: 0,
+            ..ConnectionsConfig::default()
+        };
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        assert_eq!(check_limits(&config, vec![ip; 5000], ip), Ok(()));
+    }
+}
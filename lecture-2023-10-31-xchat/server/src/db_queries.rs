@@ -1,27 +1,454 @@
+use std::path::Path;
 use std::result::Result;
+use std::str::FromStr;
 
-use sqlx::{query, query_as};
-use sqlx::sqlite::{SqlitePool};
+use async_trait::async_trait;
+use sqlx::{query, query_as, query_scalar, ConnectOptions};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+use tracing::instrument;
 
 use crate::ServerError;
+use crate::storage::{
+    DbAttachmentContent,
+    DbAttachmentUsage,
+    DbChatMessage,
+    DbChatMessageAuthor,
+    DbClientLogin,
+    DbDirectMessage,
+    DbFilterHit,
+    DbImageAttachment,
+    DbLoginAttempt,
+    DbMention,
+    DbReactionSummary,
+    DbSearchHit,
+    DbSession,
+    DbTopic,
+    DbUser,
+    HistoryFilter,
+    Storage,
+    pending_migrations,
+};
 
 
-#[derive(Clone, Debug)]
-pub struct DbUser {
-    pub id: i64,
-    pub login: String,
-    #[allow(dead_code)]
-    password: String,
+/// `MIGRATOR` holds SQLite schema migrations (`migrations/`) embedded into the server.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+
+/// `SqliteStorage` is [Storage] backed by SQLite database (queries of this module).
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+
+impl SqliteStorage {
+    /// `connect` opens pool of connections to SQLite database of the given URL. Missing
+    /// database file is created (its schema is created by migrations).
+    pub async fn connect(db_url: &str) -> Result<SqliteStorage, ServerError> {
+        let options = match SqliteConnectOptions::from_str(db_url) {
+            Ok(options) => options.create_if_missing(true),
+            Err(err) => Err(ServerError::DBError(err.to_string()))?,
+        };
+
+        match SqlitePool::connect_with(options).await {
+            Ok(pool) => Ok(SqliteStorage { pool }),
+            Err(err) => Err(ServerError::DBError(err.to_string())),
+        }
+    }
+
+    /// `in_memory` opens a private in-memory SQLite database with all migrations applied
+    /// (for tests of the queries).
+    #[cfg(test)]
+    pub(crate) async fn in_memory() -> SqliteStorage {
+        // Every connection has its own in-memory database, so the pool keeps just one forever.
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        MIGRATOR.run(&pool).await.unwrap();
+        SqliteStorage { pool }
+    }
 }
 
-pub struct DbChatMessage {
-    pub login: String,
-    pub timestamp: String,
-    pub text: String,
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self, apply: bool) -> Result<usize, ServerError> {
+        let applied = {
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(err) => Err(ServerError::DBError(err.to_string()))?,
+            };
+            if let Err(err) = conn.ensure_migrations_table().await {
+                Err(ServerError::SchemaError(err.to_string()))?;
+            }
+            match conn.list_applied_migrations().await {
+                Ok(applied) => applied,
+                Err(err) => Err(ServerError::SchemaError(err.to_string()))?,
+            }
+        };
+
+        let pending = pending_migrations(&MIGRATOR, &applied, apply)?;
+        if pending > 0 {
+            if let Err(err) = MIGRATOR.run(&self.pool).await {
+                Err(ServerError::SchemaError(err.to_string()))?;
+            }
+        }
+        Ok(pending)
+    }
+
+    async fn backup(&self, path: &Path) -> Result<(), ServerError> {
+        backup_into(&self.pool, path).await
+    }
+
+    async fn fetch_user_by_login_and_password(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<DbUser>, ServerError> {
+        fetch_user_by_login_and_password(&self.pool, login, password).await
+    }
+
+    async fn fetch_users(&self) -> Result<Vec<DbUser>, ServerError> {
+        fetch_users(&self.pool).await
+    }
+
+    async fn fetch_user_by_id(&self, user_id: i64) -> Result<Option<DbUser>, ServerError> {
+        fetch_user_by_id(&self.pool, user_id).await
+    }
+
+    async fn delete_user_by_id(&self, user_id: i64) -> Result<(), ServerError> {
+        delete_user_by_id(&self.pool, user_id).await
+    }
+
+    async fn update_display_name(
+        &self,
+        user_id: i64,
+        display_name: Option<&str>,
+    ) -> Result<(), ServerError> {
+        update_display_name(&self.pool, user_id, display_name).await
+    }
+
+    async fn update_role(&self, login: &str, role: &str) -> Result<bool, ServerError> {
+        update_role(&self.pool, login, role).await
+    }
+
+    async fn insert_topic(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        topic: &str,
+    ) -> Result<(), ServerError> {
+        insert_topic(&self.pool, user_id, timestamp, topic).await
+    }
+
+    async fn fetch_topic(&self) -> Result<Option<DbTopic>, ServerError> {
+        fetch_topic(&self.pool).await
+    }
+
+    async fn insert_session(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<i64, ServerError> {
+        insert_session(&self.pool, user_id, token_hash, created_at, expires_at).await
+    }
+
+    async fn fetch_session_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<DbSession>, ServerError> {
+        fetch_session_by_token(&self.pool, token_hash).await
+    }
+
+    async fn fetch_active_sessions(
+        &self,
+        user_id: Option<i64>,
+        now: &str,
+    ) -> Result<Vec<DbSession>, ServerError> {
+        fetch_active_sessions(&self.pool, user_id, now).await
+    }
+
+    async fn revoke_session(&self, session_id: i64, revoked_at: &str) -> Result<bool, ServerError> {
+        revoke_session(&self.pool, session_id, revoked_at).await
+    }
+
+    async fn insert_direct_message(
+        &self,
+        sender_id: i64,
+        recipient_id: i64,
+        timestamp: &str,
+        text: &str,
+    ) -> Result<i64, ServerError> {
+        insert_direct_message(&self.pool, sender_id, recipient_id, timestamp, text).await
+    }
+
+    async fn fetch_undelivered_direct_messages(
+        &self,
+        recipient_id: i64,
+    ) -> Result<Vec<DbDirectMessage>, ServerError> {
+        fetch_undelivered_direct_messages(&self.pool, recipient_id).await
+    }
+
+    async fn mark_direct_messages_delivered(
+        &self,
+        message_ids: &[i64],
+        delivered_at: &str,
+    ) -> Result<u64, ServerError> {
+        mark_direct_messages_delivered(&self.pool, message_ids, delivered_at).await
+    }
+
+    async fn insert_mention(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        read_at: Option<&str>,
+    ) -> Result<i64, ServerError> {
+        insert_mention(&self.pool, message_id, user_id, read_at).await
+    }
+
+    async fn fetch_unread_mentions(&self, user_id: i64) -> Result<Vec<DbMention>, ServerError> {
+        fetch_unread_mentions(&self.pool, user_id).await
+    }
+
+    async fn mark_mentions_read(
+        &self,
+        mention_ids: &[i64],
+        read_at: &str,
+    ) -> Result<u64, ServerError> {
+        mark_mentions_read(&self.pool, mention_ids, read_at).await
+    }
+
+    async fn insert_attachment(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        kind: &str,
+        filename: Option<&str>,
+        size: i64,
+    ) -> Result<i64, ServerError> {
+        insert_attachment(&self.pool, user_id, timestamp, kind, filename, size).await
+    }
+
+    async fn fetch_attachment_usage(
+        &self,
+        user_id: Option<i64>,
+        since: &str,
+    ) -> Result<Vec<DbAttachmentUsage>, ServerError> {
+        fetch_attachment_usage(&self.pool, user_id, since).await
+    }
+
+    async fn insert_attachment_content(
+        &self,
+        attachment_id: i64,
+        mime: &str,
+        payload: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<(), ServerError> {
+        insert_attachment_content(&self.pool, attachment_id, mime, payload, thumbnail).await
+    }
+
+    async fn fetch_attachment_content(
+        &self,
+        attachment_id: i64,
+    ) -> Result<Option<DbAttachmentContent>, ServerError> {
+        fetch_attachment_content(&self.pool, attachment_id).await
+    }
+
+    async fn fetch_image_attachments(
+        &self,
+        login: &Option<String>,
+    ) -> Result<Vec<DbImageAttachment>, ServerError> {
+        fetch_image_attachments(&self.pool, login).await
+    }
+
+    async fn insert_filter_hit(
+        &self,
+        timestamp: &str,
+        user_id: i64,
+        rule: &str,
+        action: &str,
+    ) -> Result<i64, ServerError> {
+        insert_filter_hit(&self.pool, timestamp, user_id, rule, action).await
+    }
+
+    async fn fetch_filter_hits(&self, limit: i64) -> Result<Vec<DbFilterHit>, ServerError> {
+        fetch_filter_hits(&self.pool, limit).await
+    }
+
+    async fn insert_login(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        address: Option<&str>,
+    ) -> Result<(), ServerError> {
+        insert_login(&self.pool, user_id, timestamp, address).await
+    }
+
+    async fn insert_failed_login(
+        &self,
+        timestamp: &str,
+        login: Option<&str>,
+        address: &str,
+        reason: &str,
+    ) -> Result<(), ServerError> {
+        insert_failed_login(&self.pool, timestamp, login, address, reason).await
+    }
+
+    async fn fetch_login_attempts(&self, limit: i64) -> Result<Vec<DbLoginAttempt>, ServerError> {
+        fetch_login_attempts(&self.pool, limit).await
+    }
+
+    async fn fetch_chat_messages(
+        &self,
+        filter: &Option<String>,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        fetch_chat_messages(&self.pool, filter).await
+    }
+
+    async fn fetch_chat_message_by_id(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessage>, ServerError> {
+        fetch_chat_message_by_id(&self.pool, message_id).await
+    }
+
+    async fn insert_chat_message(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> Result<i64, ServerError> {
+        insert_chat_message(&self.pool, user_id, timestamp, text, reply_to).await
+    }
+
+    async fn fetch_chat_message_author(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessageAuthor>, ServerError> {
+        fetch_chat_message_author(&self.pool, message_id).await
+    }
+
+    async fn update_chat_message_text(
+        &self,
+        message_id: i64,
+        text: &str,
+        edited_at: &str,
+    ) -> Result<(), ServerError> {
+        update_chat_message_text(&self.pool, message_id, text, edited_at).await
+    }
+
+    async fn tombstone_chat_message(
+        &self,
+        message_id: i64,
+        deleted_at: &str,
+    ) -> Result<(), ServerError> {
+        tombstone_chat_message(&self.pool, message_id, deleted_at).await
+    }
+
+    async fn insert_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+        timestamp: &str,
+    ) -> Result<bool, ServerError> {
+        insert_reaction(&self.pool, message_id, user_id, emoji, timestamp).await
+    }
+
+    async fn delete_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+    ) -> Result<bool, ServerError> {
+        delete_reaction(&self.pool, message_id, user_id, emoji).await
+    }
+
+    async fn fetch_prunable_chat_messages(
+        &self,
+        older_than: Option<&str>,
+        keep_last: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        fetch_prunable_chat_messages(&self.pool, older_than, keep_last, limit).await
+    }
+
+    async fn delete_chat_messages(&self, message_ids: &[i64]) -> Result<u64, ServerError> {
+        delete_chat_messages(&self.pool, message_ids).await
+    }
+
+    async fn fetch_prunable_logins(
+        &self,
+        older_than: &str,
+        limit: i64,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        fetch_prunable_logins(&self.pool, older_than, limit).await
+    }
+
+    async fn delete_logins(&self, login_ids: &[i64]) -> Result<u64, ServerError> {
+        delete_logins(&self.pool, login_ids).await
+    }
+
+    async fn delete_failed_logins(&self, older_than: &str) -> Result<u64, ServerError> {
+        delete_failed_logins(&self.pool, older_than).await
+    }
+
+    async fn fetch_chat_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        fetch_chat_history(&self.pool, filter).await
+    }
+
+    async fn fetch_login_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        fetch_login_history(&self.pool, filter).await
+    }
+
+    async fn import_user(&self, user: &DbUser) -> Result<(i64, bool), ServerError> {
+        import_user(&self.pool, user).await
+    }
+
+    async fn import_chat_message(
+        &self,
+        user_id: i64,
+        message: &DbChatMessage,
+        reply_to: Option<i64>,
+    ) -> Result<(i64, bool), ServerError> {
+        import_chat_message(&self.pool, user_id, message, reply_to).await
+    }
+
+    async fn import_login(&self, user_id: i64, timestamp: &str) -> Result<bool, ServerError> {
+        import_login(&self.pool, user_id, timestamp).await
+    }
+
+    async fn fetch_reaction_summaries(&self) -> Result<Vec<DbReactionSummary>, ServerError> {
+        fetch_reaction_summaries(&self.pool).await
+    }
+
+    async fn search_chat_messages(
+        &self,
+        text: &str,
+        highlight: (&str, &str),
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<DbSearchHit>), ServerError> {
+        search_chat_messages(&self.pool, text, highlight, limit, offset).await
+    }
 }
 
 
 /// `fetch_user_by_login_and_password` receives a user from the `users` table.
+#[instrument(level = "debug", skip(pool, password), err)]
 pub async fn fetch_user_by_login_and_password(
         pool: &SqlitePool,
         login: &str,
@@ -48,6 +475,7 @@ WHERE
 
 
 /// `fetch_chat_messages` fetch chat messages with optional filtering.
+#[instrument(level = "debug", skip(pool), err)]
 pub async fn fetch_chat_messages(
     pool: &SqlitePool,
     filter: &Option<String>,
@@ -58,9 +486,13 @@ pub async fn fetch_chat_messages(
                 DbChatMessage,
                 r#"
 SELECT
+    cm.id AS id,
     u.login AS login,
     cm.timestamp AS timestamp,
-    cm.text AS text
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
 FROM
     chat_messages AS cm
     JOIN users AS u ON u.id = cm.user_id
@@ -77,9 +509,13 @@ ORDER BY timestamp DESC
                 DbChatMessage,
                 r#"
 SELECT
+    cm.id AS id,
     u.login AS login,
     cm.timestamp AS timestamp,
-    cm.text AS text
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
 FROM
     chat_messages AS cm
     JOIN users AS u ON u.id = cm.user_id
@@ -97,7 +533,79 @@ ORDER BY timestamp DESC
 }
 
 
+/// `search_chat_messages` search chat messages using full-text index (newest messages first).
+///
+/// The given `text` is split into words that have to be all present in a message (in any order).
+/// Matches in the returned snippets are enclosed in the given `highlight` pair of markers.
+/// Total number of found messages is returned together with the requested page of hits.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn search_chat_messages(
+    pool: &SqlitePool,
+    text: &str,
+    highlight: (&str, &str),
+    limit: i64,
+    offset: i64,
+) -> Result<(i64, Vec<DbSearchHit>), ServerError> {
+    let fts_query = fts5_query(text);
+    if fts_query.is_empty() {
+        return Ok((0, vec![]));
+    }
+
+    let total = match query!(
+        r#"
+SELECT COUNT(*) AS "total!: i64"
+FROM chat_messages_fts
+WHERE chat_messages_fts MATCH ?1
+;"#,
+        fts_query,
+    ).fetch_one(pool).await {
+        Ok(row) => row.total,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    let (highlight_start, highlight_end) = highlight;
+    match query_as!(
+        DbSearchHit,
+        r#"
+SELECT
+    cm.id AS "id!: i64",
+    u.login AS "login!: String",
+    cm.timestamp AS "timestamp!: String",
+    snippet(chat_messages_fts, 0, ?2, ?3, '…', 12) AS "snippet!: String"
+FROM
+    chat_messages_fts
+    JOIN chat_messages AS cm ON cm.id = chat_messages_fts.rowid
+    JOIN users AS u ON u.id = cm.user_id
+WHERE chat_messages_fts MATCH ?1
+ORDER BY cm.timestamp DESC, cm.id DESC
+LIMIT ?4 OFFSET ?5
+;"#,
+        fts_query,
+        highlight_start,
+        highlight_end,
+        limit,
+        offset,
+    ).fetch_all(pool).await {
+        Ok(hits) => Ok((total, hits)),
+        Err(sqlx::Error::RowNotFound) => Ok((total, vec![])),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fts5_query` turns free text into a safe FTS5 query: each word becomes a quoted string
+/// (so FTS5 operators and special characters in user input have no effect), all words are
+/// implicitly joined by `AND`.
+fn fts5_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+
 /// `fetch_users` fetch all users.
+#[instrument(level = "debug", skip(pool), err)]
 pub async fn fetch_users(
     pool: &SqlitePool,
 ) -> Result<Vec<DbUser>, ServerError> {
@@ -116,99 +624,1101 @@ ORDER BY login ASC
 }
 
 
-/// `insert_login` inserts a single complete row into the `client_logins` table.
-/// Internal ID comes from a internal DB sequence.
-pub async fn insert_login(
+/// `fetch_user_by_id` fetch a single row of the `users` table by its ID.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_user_by_id(
         pool: &SqlitePool,
         user_id: i64,
-        timestamp: &str,
-) -> Result<(), ServerError> {
-    let mut conn = match pool.acquire().await {
-        Ok(conn) => conn,
-        Err(err) => Err(ServerError::DBError(err.to_string()))?,
-    };
+) -> Result<Option<DbUser>, ServerError> {
+    match query_as!(
+        DbUser,
+        r#"
+SELECT *
+FROM users
+WHERE id = ?1
+;"#,
+        user_id,
+    ).fetch_optional(pool).await {
+        Ok(user) => Ok(user),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
 
+/// `update_display_name` sets (or clears) `display_name` column of the given user.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn update_display_name(
+        pool: &SqlitePool,
+        user_id: i64,
+        display_name: Option<&str>,
+) -> Result<(), ServerError> {
     match query!(
         r#"
-INSERT INTO client_logins
-(user_id, timestamp)
-VALUES
-(?1, ?2)
+UPDATE users
+SET display_name = ?2
+WHERE id = ?1
 ;"#,
         user_id,
-        timestamp,
-    ).execute(&mut *conn).await {
+        display_name,
+    ).execute(pool).await {
         Ok(_) => Ok(()),
         Err(err) => Err(ServerError::DBError(err.to_string())),
     }
 }
 
 
-/// `insert_chat_message` insert a single complete row into the `chat_messages` table.
-/// Internal ID comes from a internal DB sequence.
-pub async fn insert_chat_message(
+/// `update_role` sets `role` column of the user with the given login, it returns `false` if
+/// there is no such user.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn update_role(
+        pool: &SqlitePool,
+        login: &str,
+        role: &str,
+) -> Result<bool, ServerError> {
+    match query!(
+        r#"
+UPDATE users
+SET role = ?2
+WHERE login = ?1
+;"#,
+        login,
+        role,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_topic` inserts a new row into the `room_topics` table.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_topic(
         pool: &SqlitePool,
         user_id: i64,
         timestamp: &str,
-        text: &str,
+        topic: &str,
 ) -> Result<(), ServerError> {
-    let mut conn = match pool.acquire().await {
-        Ok(conn) => conn,
-        Err(err) => Err(ServerError::DBError(err.to_string()))?,
-    };
-
     match query!(
         r#"
-INSERT INTO chat_messages
-(user_id, timestamp, text)
+INSERT INTO room_topics
+(user_id, timestamp, topic)
 VALUES
 (?1, ?2, ?3)
 ;"#,
         user_id,
         timestamp,
-        text,
-    ).execute(&mut *conn).await {
+        topic,
+    ).execute(pool).await {
         Ok(_) => Ok(()),
         Err(err) => Err(ServerError::DBError(err.to_string())),
     }
 }
 
 
-/// `delete_user_by_id` delete user and all his/her related chat messages and log-in records in
-/// a database transaction.
-pub async fn delete_user_by_id(pool: &SqlitePool, user_id: i64) -> Result<(), ServerError> {
-    let mut transaction = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(err) => Err(ServerError::DBError(err.to_string()))?,
-    };
-
-    // Delete all chat messages of the given user in a transaction.
-    if let Err(err) = query!(
+/// `fetch_topic` fetch the newest row of the `room_topics` table unless its topic is empty.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_topic(pool: &SqlitePool) -> Result<Option<DbTopic>, ServerError> {
+    match query_as!(
+        DbTopic,
         r#"
-DELETE FROM chat_messages
-WHERE user_id = ?1
+SELECT
+    rt.topic,
+    u.login,
+    rt.timestamp
+FROM room_topics AS rt
+    JOIN users AS u ON u.id = rt.user_id
+ORDER BY rt.id DESC
+LIMIT 1
 ;"#,
-        user_id,
-    ).execute(&mut *transaction).await {
-        Err(ServerError::DBError(err.to_string()))?;
-    };
+    ).fetch_optional(pool).await {
+        Ok(topic) => Ok(topic.filter(|topic| !topic.topic.is_empty())),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
 
-    // Delete all log-in records of the given user in a transaction.
-    if let Err(err) = query!(
+
+/// `insert_session` inserts a new row into the `sessions` table and returns its ID.
+#[instrument(level = "debug", skip(pool, token_hash), err)]
+pub async fn insert_session(
+        pool: &SqlitePool,
+        user_id: i64,
+        token_hash: &str,
+        created_at: &str,
+        expires_at: &str,
+) -> Result<i64, ServerError> {
+    match query!(
         r#"
-DELETE FROM client_logins
-WHERE user_id = ?1
+INSERT INTO sessions
+(user_id, token_hash, created_at, expires_at)
+VALUES
+(?1, ?2, ?3, ?4)
 ;"#,
         user_id,
-    ).execute(&mut *transaction).await {
-        Err(ServerError::DBError(err.to_string()))?;
-    };
+        token_hash,
+        created_at,
+        expires_at,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
 
-    // Delete the given user in a transaction.
-    if let Err(err) = query!(
+
+/// `fetch_session_by_token` fetch a single row of the `sessions` table by hash of its token.
+#[instrument(level = "debug", skip(pool, token_hash), err)]
+pub async fn fetch_session_by_token(
+        pool: &SqlitePool,
+        token_hash: &str,
+) -> Result<Option<DbSession>, ServerError> {
+    match query_as!(
+        DbSession,
         r#"
-DELETE FROM users
-WHERE id = ?1
+SELECT
+    s.id,
+    s.user_id,
+    u.login,
+    s.created_at,
+    s.expires_at,
+    s.revoked_at
+FROM sessions AS s
+    JOIN users AS u ON u.id = s.user_id
+WHERE s.token_hash = ?1
+;"#,
+        token_hash,
+    ).fetch_optional(pool).await {
+        Ok(session) => Ok(session),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_active_sessions` fetch rows of the `sessions` table (optionally of a single user)
+/// neither revoked nor expired at `now`.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_active_sessions(
+        pool: &SqlitePool,
+        user_id: Option<i64>,
+        now: &str,
+) -> Result<Vec<DbSession>, ServerError> {
+    match query_as!(
+        DbSession,
+        r#"
+SELECT
+    s.id,
+    s.user_id,
+    u.login,
+    s.created_at,
+    s.expires_at,
+    s.revoked_at
+FROM sessions AS s
+    JOIN users AS u ON u.id = s.user_id
+WHERE
+    s.revoked_at IS NULL
+    AND s.expires_at > ?2
+    AND (?1 IS NULL OR s.user_id = ?1)
+ORDER BY u.login ASC, s.created_at ASC, s.id ASC
+;"#,
+        user_id,
+        now,
+    ).fetch_all(pool).await {
+        Ok(sessions) => Ok(sessions),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `revoke_session` sets `revoked_at` column of the given (not yet revoked) session.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn revoke_session(
+        pool: &SqlitePool,
+        session_id: i64,
+        revoked_at: &str,
+) -> Result<bool, ServerError> {
+    match query!(
+        r#"
+UPDATE sessions
+SET revoked_at = ?2
+WHERE
+    id = ?1
+    AND revoked_at IS NULL
+;"#,
+        session_id,
+        revoked_at,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_direct_message` inserts a new (undelivered) row into the `direct_messages` table
+/// and returns its ID.
+#[instrument(level = "debug", skip(pool, text), err)]
+pub async fn insert_direct_message(
+        pool: &SqlitePool,
+        sender_id: i64,
+        recipient_id: i64,
+        timestamp: &str,
+        text: &str,
+) -> Result<i64, ServerError> {
+    match query!(
+        r#"
+INSERT INTO direct_messages
+(sender_id, recipient_id, timestamp, text)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        sender_id,
+        recipient_id,
+        timestamp,
+        text,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_undelivered_direct_messages` fetch rows of the `direct_messages` table of the given
+/// recipient which were not delivered yet (oldest first).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_undelivered_direct_messages(
+        pool: &SqlitePool,
+        recipient_id: i64,
+) -> Result<Vec<DbDirectMessage>, ServerError> {
+    match query_as!(
+        DbDirectMessage,
+        r#"
+SELECT
+    dm.id,
+    s.login,
+    s.display_name,
+    r.login AS "to",
+    dm.timestamp,
+    dm.text
+FROM direct_messages AS dm
+    JOIN users AS s ON s.id = dm.sender_id
+    JOIN users AS r ON r.id = dm.recipient_id
+WHERE
+    dm.recipient_id = ?1
+    AND dm.delivered_at IS NULL
+ORDER BY dm.timestamp ASC, dm.id ASC
+;"#,
+        recipient_id,
+    ).fetch_all(pool).await {
+        Ok(messages) => Ok(messages),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `mark_direct_messages_delivered` sets `delivered_at` column of the given (not yet delivered)
+/// direct messages.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn mark_direct_messages_delivered(
+        pool: &SqlitePool,
+        message_ids: &[i64],
+        delivered_at: &str,
+) -> Result<u64, ServerError> {
+    let ids = json_array(message_ids);
+
+    match query!(
+        r#"
+UPDATE direct_messages
+SET delivered_at = ?2
+WHERE
+    id IN (SELECT value FROM json_each(?1))
+    AND delivered_at IS NULL
+;"#,
+        ids,
+        delivered_at,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_mention` inserts a new row into the `mentions` table and returns its ID.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_mention(
+        pool: &SqlitePool,
+        message_id: i64,
+        user_id: i64,
+        read_at: Option<&str>,
+) -> Result<i64, ServerError> {
+    match query!(
+        r#"
+INSERT INTO mentions
+(message_id, user_id, read_at)
+VALUES
+(?1, ?2, ?3)
+;"#,
+        message_id,
+        user_id,
+        read_at,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_unread_mentions` fetch unread rows of the `mentions` table of the given user with
+/// their (not deleted) chat messages, oldest first.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_unread_mentions(
+        pool: &SqlitePool,
+        user_id: i64,
+) -> Result<Vec<DbMention>, ServerError> {
+    match query_as!(
+        DbMention,
+        r#"
+SELECT
+    m.id,
+    m.message_id,
+    u.login,
+    cm.timestamp,
+    cm.text
+FROM mentions AS m
+    JOIN chat_messages AS cm ON cm.id = m.message_id
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    m.user_id = ?1
+    AND m.read_at IS NULL
+    AND cm.deleted_at IS NULL
+ORDER BY cm.timestamp ASC, m.id ASC
+;"#,
+        user_id,
+    ).fetch_all(pool).await {
+        Ok(mentions) => Ok(mentions),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `mark_mentions_read` sets `read_at` column of the given (not yet read) mentions.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn mark_mentions_read(
+        pool: &SqlitePool,
+        mention_ids: &[i64],
+        read_at: &str,
+) -> Result<u64, ServerError> {
+    let ids = json_array(mention_ids);
+
+    match query!(
+        r#"
+UPDATE mentions
+SET read_at = ?2
+WHERE
+    id IN (SELECT value FROM json_each(?1))
+    AND read_at IS NULL
+;"#,
+        ids,
+        read_at,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_attachment` inserts a new row into the `attachments` table and returns its ID.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_attachment(
+        pool: &SqlitePool,
+        user_id: i64,
+        timestamp: &str,
+        kind: &str,
+        filename: Option<&str>,
+        size: i64,
+) -> Result<i64, ServerError> {
+    match query!(
+        r#"
+INSERT INTO attachments
+(user_id, timestamp, kind, filename, size)
+VALUES
+(?1, ?2, ?3, ?4, ?5)
+;"#,
+        user_id,
+        timestamp,
+        kind,
+        filename,
+        size,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_attachment_usage` fetch number and total size of rows of the `attachments` table
+/// (optionally of a single user) not older than `since` timestamp, grouped by user.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_attachment_usage(
+        pool: &SqlitePool,
+        user_id: Option<i64>,
+        since: &str,
+) -> Result<Vec<DbAttachmentUsage>, ServerError> {
+    match query_as!(
+        DbAttachmentUsage,
+        r#"
+SELECT
+    a.user_id,
+    u.login,
+    COUNT(*) AS "count!: i64",
+    SUM(a.size) AS "bytes!: i64"
+FROM attachments AS a
+    JOIN users AS u ON u.id = a.user_id
+WHERE
+    a.timestamp >= ?2
+    AND (?1 IS NULL OR a.user_id = ?1)
+GROUP BY a.user_id, u.login
+ORDER BY u.login ASC
+;"#,
+        user_id,
+        since,
+    ).fetch_all(pool).await {
+        Ok(usage) => Ok(usage),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_attachment_content` inserts a new row into the `attachment_contents` table.
+#[instrument(level = "debug", skip(pool, payload, thumbnail), err)]
+pub async fn insert_attachment_content(
+        pool: &SqlitePool,
+        attachment_id: i64,
+        mime: &str,
+        payload: &[u8],
+        thumbnail: &[u8],
+) -> Result<(), ServerError> {
+    match query!(
+        r#"
+INSERT INTO attachment_contents
+(attachment_id, mime, payload, thumbnail)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        attachment_id,
+        mime,
+        payload,
+        thumbnail,
+    ).execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_attachment_content` fetch a single row of the `attachment_contents` table together
+/// with login of the sender and time of the attachment.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_attachment_content(
+        pool: &SqlitePool,
+        attachment_id: i64,
+) -> Result<Option<DbAttachmentContent>, ServerError> {
+    match query_as!(
+        DbAttachmentContent,
+        r#"
+SELECT
+    a.id,
+    u.login,
+    a.timestamp,
+    ac.mime,
+    ac.payload,
+    ac.thumbnail
+FROM attachment_contents AS ac
+    JOIN attachments AS a ON a.id = ac.attachment_id
+    JOIN users AS u ON u.id = a.user_id
+WHERE ac.attachment_id = ?1
+;"#,
+        attachment_id,
+    ).fetch_optional(pool).await {
+        Ok(content) => Ok(content),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_image_attachments` fetch attachments with a row of the `attachment_contents` table
+/// (optionally just of the given login) ordered from the newest one.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_image_attachments(
+        pool: &SqlitePool,
+        login: &Option<String>,
+) -> Result<Vec<DbImageAttachment>, ServerError> {
+    match query_as!(
+        DbImageAttachment,
+        r#"
+SELECT
+    a.id,
+    u.login,
+    a.timestamp,
+    ac.mime,
+    a.size
+FROM attachment_contents AS ac
+    JOIN attachments AS a ON a.id = ac.attachment_id
+    JOIN users AS u ON u.id = a.user_id
+WHERE ?1 IS NULL OR u.login = ?1
+ORDER BY a.timestamp DESC, a.id DESC
+;"#,
+        login,
+    ).fetch_all(pool).await {
+        Ok(attachments) => Ok(attachments),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_filter_hit` inserts a new row into the `filter_hits` table and returns its ID.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_filter_hit(
+        pool: &SqlitePool,
+        timestamp: &str,
+        user_id: i64,
+        rule: &str,
+        action: &str,
+) -> Result<i64, ServerError> {
+    match query!(
+        r#"
+INSERT INTO filter_hits
+(timestamp, user_id, rule, action)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        timestamp,
+        user_id,
+        rule,
+        action,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_filter_hits` fetch at most `limit` of the newest rows of the `filter_hits` table
+/// (newest first).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_filter_hits(
+        pool: &SqlitePool,
+        limit: i64,
+) -> Result<Vec<DbFilterHit>, ServerError> {
+    match query_as!(
+        DbFilterHit,
+        r#"
+SELECT
+    fh.id,
+    fh.timestamp,
+    u.login,
+    fh.rule,
+    fh.action
+FROM filter_hits AS fh
+    JOIN users AS u ON u.id = fh.user_id
+ORDER BY fh.id DESC
+LIMIT ?1
+;"#,
+        limit,
+    ).fetch_all(pool).await {
+        Ok(hits) => Ok(hits),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_login` inserts a single complete row into the `client_logins` table.
+/// Internal ID comes from a internal DB sequence.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_login(
+        pool: &SqlitePool,
+        user_id: i64,
+        timestamp: &str,
+        address: Option<&str>,
+) -> Result<(), ServerError> {
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match query!(
+        r#"
+INSERT INTO client_logins
+(user_id, timestamp, address)
+VALUES
+(?1, ?2, ?3)
+;"#,
+        user_id,
+        timestamp,
+        address,
+    ).execute(&mut *conn).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_failed_login` inserts a single row into the `failed_logins` table.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_failed_login(
+        pool: &SqlitePool,
+        timestamp: &str,
+        login: Option<&str>,
+        address: &str,
+        reason: &str,
+) -> Result<(), ServerError> {
+    match query!(
+        r#"
+INSERT INTO failed_logins
+(timestamp, login, address, reason)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        timestamp,
+        login,
+        address,
+        reason,
+    ).execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_login_attempts` fetch at most `limit` of the newest rows of `client_logins` and
+/// `failed_logins` tables together (newest first).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_login_attempts(
+        pool: &SqlitePool,
+        limit: i64,
+) -> Result<Vec<DbLoginAttempt>, ServerError> {
+    match query_as!(
+        DbLoginAttempt,
+        r#"
+SELECT
+    timestamp AS "timestamp!: String",
+    login AS "login?: String",
+    address AS "address?: String",
+    succeeded AS "succeeded!: bool",
+    reason AS "reason?: String"
+FROM (
+    SELECT
+        cl.timestamp,
+        u.login,
+        cl.address,
+        1 AS succeeded,
+        NULL AS reason,
+        cl.id
+    FROM client_logins AS cl
+        JOIN users AS u ON u.id = cl.user_id
+    UNION ALL
+    SELECT
+        fl.timestamp,
+        fl.login,
+        fl.address,
+        0 AS succeeded,
+        fl.reason,
+        fl.id
+    FROM failed_logins AS fl
+)
+ORDER BY timestamp DESC, succeeded ASC, id DESC
+LIMIT ?1
+;"#,
+        limit,
+    ).fetch_all(pool).await {
+        Ok(attempts) => Ok(attempts),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_chat_message_by_id` fetch a single chat message (if the message exists).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_chat_message_by_id(
+    pool: &SqlitePool,
+    message_id: i64,
+) -> Result<Option<DbChatMessage>, ServerError> {
+    match query_as!(
+        DbChatMessage,
+        r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE cm.id = ?1
+;"#,
+        message_id,
+    ).fetch_one(pool).await {
+        Ok(chat_message) => Ok(Some(chat_message)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `insert_chat_message` insert a single complete row into the `chat_messages` table
+/// (`reply_to` is ID of a parent message of a reply).
+/// Internal ID comes from a internal DB sequence and it is returned on success.
+#[instrument(level = "debug", skip(pool, text), err)]
+pub async fn insert_chat_message(
+        pool: &SqlitePool,
+        user_id: i64,
+        timestamp: &str,
+        text: &str,
+        reply_to: Option<i64>,
+) -> Result<i64, ServerError> {
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match query!(
+        r#"
+INSERT INTO chat_messages
+(user_id, timestamp, text, reply_to)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        user_id,
+        timestamp,
+        text,
+        reply_to,
+    ).execute(&mut *conn).await {
+        Ok(result) => Ok(result.last_insert_rowid()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_chat_message_author` fetch author of a single chat message (if the message exists).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_chat_message_author(
+        pool: &SqlitePool,
+        message_id: i64,
+) -> Result<Option<DbChatMessageAuthor>, ServerError> {
+    match query_as!(
+        DbChatMessageAuthor,
+        r#"
+SELECT user_id, deleted_at
+FROM chat_messages
+WHERE id = ?1
+;"#,
+        message_id,
+    ).fetch_one(pool).await {
+        Ok(author) => Ok(Some(author)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `update_chat_message_text` replace text of a single chat message and mark it as edited.
+#[instrument(level = "debug", skip(pool, text), err)]
+pub async fn update_chat_message_text(
+        pool: &SqlitePool,
+        message_id: i64,
+        text: &str,
+        edited_at: &str,
+) -> Result<(), ServerError> {
+    match query!(
+        r#"
+UPDATE chat_messages
+SET text = ?2, edited_at = ?3
+WHERE id = ?1
+;"#,
+        message_id,
+        text,
+        edited_at,
+    ).execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `tombstone_chat_message` turns a single chat message into a tombstone: its text is erased,
+/// but the row is kept (with deletion timestamp) so the message ID stays valid. Reactions to
+/// the message are deleted in the same database transaction.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn tombstone_chat_message(
+        pool: &SqlitePool,
+        message_id: i64,
+        deleted_at: &str,
+) -> Result<(), ServerError> {
+    let mut transaction = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    if let Err(err) = query!(
+        r#"
+DELETE FROM message_reactions
+WHERE message_id = ?1
+;"#,
+        message_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    if let Err(err) = query!(
+        r#"
+UPDATE chat_messages
+SET text = '', deleted_at = ?2
+WHERE id = ?1
+;"#,
+        message_id,
+        deleted_at,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    if let Err(err) = transaction.commit().await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+    Ok(())
+}
+
+
+/// `insert_reaction` stores a reaction of the given user to the given chat message.
+/// Returns `false` if the very same reaction was already stored before.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn insert_reaction(
+        pool: &SqlitePool,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+        timestamp: &str,
+) -> Result<bool, ServerError> {
+    match query!(
+        r#"
+INSERT OR IGNORE INTO message_reactions
+(message_id, user_id, emoji, timestamp)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        message_id,
+        user_id,
+        emoji,
+        timestamp,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `delete_reaction` removes a reaction of the given user to the given chat message.
+/// Returns `false` if there was no such reaction.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn delete_reaction(
+        pool: &SqlitePool,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+) -> Result<bool, ServerError> {
+    match query!(
+        r#"
+DELETE FROM message_reactions
+WHERE
+    message_id = ?1
+    AND
+    user_id = ?2
+    AND
+    emoji = ?3
+;"#,
+        message_id,
+        user_id,
+        emoji,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_reaction_summaries` fetch reactions aggregated per chat message and emoji.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_reaction_summaries(
+        pool: &SqlitePool,
+) -> Result<Vec<DbReactionSummary>, ServerError> {
+    match query_as!(
+        DbReactionSummary,
+        r#"
+SELECT
+    mr.message_id AS "message_id!",
+    mr.emoji AS "emoji!",
+    COUNT(*) AS "count!: i64",
+    GROUP_CONCAT(u.login, ', ') AS "logins!: String"
+FROM
+    message_reactions AS mr
+    JOIN users AS u ON u.id = mr.user_id
+GROUP BY mr.message_id, mr.emoji
+ORDER BY mr.message_id ASC, MIN(mr.id) ASC
+;"#,
+    ).fetch_all(pool).await {
+        Ok(summaries) => Ok(summaries),
+        Err(sqlx::Error::RowNotFound) => Ok(vec![]),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `delete_user_by_id` delete user and all his/her related chat messages, topics, sessions,
+/// direct messages, mentions, attachments (with their contents), filter hits and log-in
+/// records in a database transaction.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn delete_user_by_id(pool: &SqlitePool, user_id: i64) -> Result<(), ServerError> {
+    let mut transaction = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    // Delete all reactions of the given user and to the messages of the given user
+    // in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM message_reactions
+WHERE
+    user_id = ?1
+    OR
+    message_id IN (SELECT id FROM chat_messages WHERE user_id = ?1)
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all mentions of the given user and in the messages of the given user
+    // in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM mentions
+WHERE
+    user_id = ?1
+    OR
+    message_id IN (SELECT id FROM chat_messages WHERE user_id = ?1)
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Replies of other users must not reference the deleted messages.
+    if let Err(err) = query!(
+        r#"
+UPDATE chat_messages
+SET reply_to = NULL
+WHERE reply_to IN (SELECT id FROM chat_messages WHERE user_id = ?1)
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all chat messages of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM chat_messages
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all topics set by the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM room_topics
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all sessions of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM sessions
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all direct messages from or to the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM direct_messages
+WHERE
+    sender_id = ?1
+    OR
+    recipient_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all contents of attachments of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM attachment_contents
+WHERE attachment_id IN (SELECT id FROM attachments WHERE user_id = ?1)
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all attachment records of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM attachments
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all content filter hits of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM filter_hits
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete all log-in records of the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM client_logins
+WHERE user_id = ?1
+;"#,
+        user_id,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    // Delete the given user in a transaction.
+    if let Err(err) = query!(
+        r#"
+DELETE FROM users
+WHERE id = ?1
 ;"#,
         user_id,
     ).execute(&mut *transaction).await {
@@ -221,3 +1731,497 @@ WHERE id = ?1
     };
     Ok(())
 }
+
+
+/// `fetch_prunable_chat_messages` fetch at most `limit` chat messages (oldest first) which are
+/// older than `older_than` timestamp or which are not among `keep_last` newest messages.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_prunable_chat_messages(
+        pool: &SqlitePool,
+        older_than: Option<&str>,
+        keep_last: Option<i64>,
+        limit: i64,
+) -> Result<Vec<DbChatMessage>, ServerError> {
+    match query_as!(
+        DbChatMessage,
+        r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    (?1 IS NOT NULL AND cm.timestamp < ?1)
+    OR
+    (?2 IS NOT NULL AND cm.id NOT IN (
+        SELECT id
+        FROM chat_messages
+        ORDER BY timestamp DESC, id DESC
+        LIMIT COALESCE(?2, -1)
+    ))
+ORDER BY cm.timestamp ASC, cm.id ASC
+LIMIT ?3
+;"#,
+        older_than,
+        keep_last,
+        limit,
+    ).fetch_all(pool).await {
+        Ok(chat_messages) => Ok(chat_messages),
+        Err(sqlx::Error::RowNotFound) => Ok(vec![]),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `delete_chat_messages` delete the given chat messages, reactions to them and mentions in them
+/// in a database transaction. Replies to the deleted messages are kept without their parent.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn delete_chat_messages(
+        pool: &SqlitePool,
+        message_ids: &[i64],
+) -> Result<u64, ServerError> {
+    // IDs are passed as a JSON array (expanded by `json_each`), so a single statement fits
+    // any number of them.
+    let ids = json_array(message_ids);
+
+    let mut transaction = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    if let Err(err) = query!(
+        r#"
+DELETE FROM message_reactions
+WHERE message_id IN (SELECT value FROM json_each(?1))
+;"#,
+        ids,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    if let Err(err) = query!(
+        r#"
+DELETE FROM mentions
+WHERE message_id IN (SELECT value FROM json_each(?1))
+;"#,
+        ids,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    if let Err(err) = query!(
+        r#"
+UPDATE chat_messages
+SET reply_to = NULL
+WHERE reply_to IN (SELECT value FROM json_each(?1))
+;"#,
+        ids,
+    ).execute(&mut *transaction).await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+
+    let deleted = match query!(
+        r#"
+DELETE FROM chat_messages
+WHERE id IN (SELECT value FROM json_each(?1))
+;"#,
+        ids,
+    ).execute(&mut *transaction).await {
+        Ok(result) => result.rows_affected(),
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    if let Err(err) = transaction.commit().await {
+        Err(ServerError::DBError(err.to_string()))?;
+    };
+    Ok(deleted)
+}
+
+
+/// `fetch_prunable_logins` fetch at most `limit` log-in records (oldest first) older than
+/// `older_than` timestamp.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_prunable_logins(
+        pool: &SqlitePool,
+        older_than: &str,
+        limit: i64,
+) -> Result<Vec<DbClientLogin>, ServerError> {
+    match query_as!(
+        DbClientLogin,
+        r#"
+SELECT
+    cl.id AS id,
+    cl.user_id AS user_id,
+    u.login AS login,
+    cl.timestamp AS timestamp
+FROM
+    client_logins AS cl
+    JOIN users AS u ON u.id = cl.user_id
+WHERE cl.timestamp < ?1
+ORDER BY cl.timestamp ASC, cl.id ASC
+LIMIT ?2
+;"#,
+        older_than,
+        limit,
+    ).fetch_all(pool).await {
+        Ok(logins) => Ok(logins),
+        Err(sqlx::Error::RowNotFound) => Ok(vec![]),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `delete_logins` delete the given log-in records.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn delete_logins(pool: &SqlitePool, login_ids: &[i64]) -> Result<u64, ServerError> {
+    let ids = json_array(login_ids);
+
+    match query!(
+        r#"
+DELETE FROM client_logins
+WHERE id IN (SELECT value FROM json_each(?1))
+;"#,
+        ids,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `delete_failed_logins` delete rows of the `failed_logins` table older than `older_than`.
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn delete_failed_logins(pool: &SqlitePool, older_than: &str) -> Result<u64, ServerError> {
+    match query!(
+        r#"
+DELETE FROM failed_logins
+WHERE timestamp < ?1
+;"#,
+        older_than,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected()),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_chat_history` fetch chat messages selected by the filter (oldest first).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_chat_history(
+        pool: &SqlitePool,
+        filter: &HistoryFilter,
+) -> Result<Vec<DbChatMessage>, ServerError> {
+    match query_as!(
+        DbChatMessage,
+        r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    (?1 IS NULL OR cm.timestamp >= ?1)
+    AND (?2 IS NULL OR cm.timestamp < ?2)
+    AND (?3 IS NULL OR u.login = ?3)
+ORDER BY cm.timestamp ASC, cm.id ASC
+;"#,
+        filter.since,
+        filter.until,
+        filter.login,
+    ).fetch_all(pool).await {
+        Ok(chat_messages) => Ok(chat_messages),
+        Err(sqlx::Error::RowNotFound) => Ok(vec![]),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `fetch_login_history` fetch log-in records selected by the filter (oldest first).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn fetch_login_history(
+        pool: &SqlitePool,
+        filter: &HistoryFilter,
+) -> Result<Vec<DbClientLogin>, ServerError> {
+    match query_as!(
+        DbClientLogin,
+        r#"
+SELECT
+    cl.id AS id,
+    cl.user_id AS user_id,
+    u.login AS login,
+    cl.timestamp AS timestamp
+FROM
+    client_logins AS cl
+    JOIN users AS u ON u.id = cl.user_id
+WHERE
+    (?1 IS NULL OR cl.timestamp >= ?1)
+    AND (?2 IS NULL OR cl.timestamp < ?2)
+    AND (?3 IS NULL OR u.login = ?3)
+ORDER BY cl.timestamp ASC, cl.id ASC
+;"#,
+        filter.since,
+        filter.until,
+        filter.login,
+    ).fetch_all(pool).await {
+        Ok(logins) => Ok(logins),
+        Err(sqlx::Error::RowNotFound) => Ok(vec![]),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `import_user` returns ID of an existing user with the same login or inserts the given user
+/// under a new ID (the flag tells whether the user was inserted).
+#[instrument(level = "debug", skip(pool, user), fields(login = %user.login), err)]
+pub async fn import_user(pool: &SqlitePool, user: &DbUser) -> Result<(i64, bool), ServerError> {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match query!(
+        r#"
+SELECT id
+FROM users
+WHERE login = ?1
+;"#,
+        user.login,
+    ).fetch_optional(&mut *tx).await {
+        Ok(Some(existing)) => return Ok((existing.id, false)),
+        Ok(None) => {},
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    }
+
+    let id = match query!(
+        r#"
+INSERT INTO users
+(login, password, role, display_name)
+VALUES
+(?1, ?2, ?3, ?4)
+;"#,
+        user.login,
+        user.password,
+        user.role,
+        user.display_name,
+    ).execute(&mut *tx).await {
+        Ok(result) => result.last_insert_rowid(),
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match tx.commit().await {
+        Ok(_) => Ok((id, true)),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `import_chat_message` inserts the given chat message with all its timestamps unless the same
+/// message (author, timestamp and text) already exists. Returns ID of the (existing) message
+/// and the flag whether it was inserted.
+#[instrument(level = "debug", skip(pool, message), fields(timestamp = %message.timestamp), err)]
+pub async fn import_chat_message(
+        pool: &SqlitePool,
+        user_id: i64,
+        message: &DbChatMessage,
+        reply_to: Option<i64>,
+) -> Result<(i64, bool), ServerError> {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match query!(
+        r#"
+SELECT id
+FROM chat_messages
+WHERE user_id = ?1 AND timestamp = ?2 AND text = ?3
+;"#,
+        user_id,
+        message.timestamp,
+        message.text,
+    ).fetch_optional(&mut *tx).await {
+        Ok(Some(existing)) => return Ok((existing.id, false)),
+        Ok(None) => {},
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    }
+
+    let id = match query!(
+        r#"
+INSERT INTO chat_messages
+(user_id, timestamp, text, edited_at, deleted_at, reply_to)
+VALUES
+(?1, ?2, ?3, ?4, ?5, ?6)
+;"#,
+        user_id,
+        message.timestamp,
+        message.text,
+        message.edited_at,
+        message.deleted_at,
+        reply_to,
+    ).execute(&mut *tx).await {
+        Ok(result) => result.last_insert_rowid(),
+        Err(err) => Err(ServerError::DBError(err.to_string()))?,
+    };
+
+    match tx.commit().await {
+        Ok(_) => Ok((id, true)),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `import_login` inserts a log-in record unless the same one already exists (then `false`
+/// is returned).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn import_login(
+        pool: &SqlitePool,
+        user_id: i64,
+        timestamp: &str,
+) -> Result<bool, ServerError> {
+    match query!(
+        r#"
+INSERT INTO client_logins
+(user_id, timestamp)
+SELECT ?1, ?2
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM client_logins
+    WHERE user_id = ?1 AND timestamp = ?2
+)
+;"#,
+        user_id,
+        timestamp,
+    ).execute(pool).await {
+        Ok(result) => Ok(result.rows_affected() > 0),
+        Err(err) => Err(ServerError::DBError(err.to_string())),
+    }
+}
+
+
+/// `backup_into` writes a consistent snapshot of the database into a new file of the given path
+/// (`VACUUM INTO` works while other connections read and write the database).
+#[instrument(level = "debug", skip(pool), err)]
+pub async fn backup_into(pool: &SqlitePool, path: &Path) -> Result<(), ServerError> {
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => Err(ServerError::BackupError(format!("invalid path {}", path.display())))?,
+    };
+
+    match query!(
+        r#"
+VACUUM INTO ?1
+;"#,
+        path,
+    ).execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ServerError::BackupError(err.to_string())),
+    }
+}
+
+
+/// `check_database_file` verifies that the given file is an intact SQLite database of the chat
+/// (`PRAGMA integrity_check` passes and its schema is not newer than this server knows).
+/// Returns number of migrations still to be applied to the database. The file is not modified,
+/// but it has to be writable (integrity check of full-text index needs it).
+pub async fn check_database_file(path: &Path) -> Result<usize, ServerError> {
+    let backup_error = |err: String| {
+        ServerError::BackupError(format!("{}: {}", path.display(), err))
+    };
+
+    let options = SqliteConnectOptions::new().filename(path);
+    let mut conn = match options.connect().await {
+        Ok(conn) => conn,
+        Err(err) => Err(backup_error(err.to_string()))?,
+    };
+
+    match query_scalar::<_, String>("PRAGMA integrity_check;").fetch_all(&mut conn).await {
+        Ok(result) if result == ["ok"] => {},
+        Ok(result) => {
+            Err(backup_error(format!("integrity check failed: {}", result.join("; "))))?
+        },
+        Err(err) => Err(backup_error(err.to_string()))?,
+    }
+
+    let applied = match conn.list_applied_migrations().await {
+        Ok(applied) => applied,
+        Err(err) => Err(backup_error(format!("not a chat database ({})", err)))?,
+    };
+    pending_migrations(&MIGRATOR, &applied, true)
+}
+
+
+/// `json_array` formats the given IDs as JSON array (e.g. `[1,2,3]`).
+fn json_array(ids: &[i64]) -> String {
+    let ids: Vec<String> = ids.iter().map(i64::to_string).collect();
+    format!("[{}]", ids.join(","))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{fts5_query, json_array, SqliteStorage};
+    use crate::storage::{DbUser, Storage};
+
+
+    /// `user` returns a user with the given login to be imported.
+    fn user(login: &str) -> DbUser {
+        DbUser {
+            id: 0,
+            login: login.to_string(),
+            password: String::new(),
+            role: "user".to_string(),
+            display_name: None,
+        }
+    }
+
+
+    #[test]
+    fn test_fts5_query() {
+        assert_eq!(fts5_query(""), "");
+        assert_eq!(fts5_query("  "), "");
+        assert_eq!(fts5_query("rust"), "\"rust\"");
+        assert_eq!(fts5_query(" rust  async "), "\"rust\" \"async\"");
+        assert_eq!(fts5_query("NOT a*"), "\"NOT\" \"a*\"");
+        assert_eq!(fts5_query("say \"hi\""), "\"say\" \"\"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_json_array() {
+        assert_eq!(json_array(&[]), "[]");
+        assert_eq!(json_array(&[1]), "[1]");
+        assert_eq!(json_array(&[1, 20, -3]), "[1,20,-3]");
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_by_id_with_replied_messages() {
+        let storage = SqliteStorage::in_memory().await;
+        let (author_id, _) = storage.import_user(&user("author")).await.unwrap();
+        let (replier_id, _) = storage.import_user(&user("replier")).await.unwrap();
+        let parent = storage.insert_chat_message(author_id, "2023-12-01", "why?", None).await;
+        let parent = parent.unwrap();
+        let own_reply = storage.insert_chat_message(author_id, "2023-12-02", "hm", Some(parent));
+        own_reply.await.unwrap();
+        let reply = storage.insert_chat_message(replier_id, "2023-12-02", "because", Some(parent));
+        let reply = reply.await.unwrap();
+
+        storage.delete_user_by_id(author_id).await.unwrap();
+
+        assert!(storage.fetch_chat_message_by_id(parent).await.unwrap().is_none());
+        let reply = storage.fetch_chat_message_by_id(reply).await.unwrap().unwrap();
+        assert_eq!((reply.text.as_str(), reply.reply_to), ("because", None));
+        let users = storage.fetch_users().await.unwrap();
+        assert!(users.iter().all(|user| user.id != author_id));
+    }
+}
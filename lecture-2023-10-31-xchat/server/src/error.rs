@@ -25,4 +25,16 @@ pub enum ServerError {
     PrometheusRegistrationError(String),
     #[error("join error: {0}")]
     JoinError(String),
+    #[error("invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("DB schema error: {0}")]
+    SchemaError(String),
+    #[error("archive error: {0}")]
+    ArchiveError(String),
+    #[error("history error: {0}")]
+    HistoryError(String),
+    #[error("backup error: {0}")]
+    BackupError(String),
+    #[error("federation error: {0}")]
+    FederationError(String),
 }
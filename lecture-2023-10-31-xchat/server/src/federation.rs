@@ -0,0 +1,751 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinSet;
+use tokio::time::{interval, sleep};
+use tracing::Instrument;
+
+use shared::{FederatedEvent, FederatedKind, Message, OnlineUser, timestamp_to_string};
+use crate::config::{Config, FederationConfig, FederationLinkConfig};
+use crate::error::ServerError;
+use crate::storage::{DbUser, Storage, LOCKED_PASSWORD};
+use crate::web_prometheus::{FEDERATED_EVENT_COUNTER, FEDERATION_LINK_GAUGE};
+use crate::{broadcast, online_users, Clients};
+
+
+/// Time to complete the handshake of a new link.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay between two keep-alive messages of a link.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Link without any message from the other server for this time is closed.
+const LINK_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Maximal delay between two attempts to connect a link.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Number of remembered IDs of received events (to drop duplicates).
+const MAX_SEEN_EVENTS: usize = 10_000;
+
+/// Number of events buffered for a slow link (older ones are dropped).
+const OUTBOUND_CAPACITY: usize = 1024;
+
+
+/// `Inbound` is a notification of the chat loop from federation links.
+pub(crate) enum Inbound {
+    /// Event received from the given linked server (it is neither duplicate nor looped back).
+    Event { peer: String, event: FederatedEvent },
+    /// Link to the given server was closed.
+    LinkDown { peer: String },
+}
+
+
+/// `RemoteUser` is a user online on another server.
+#[derive(Clone, Debug)]
+pub(crate) struct RemoteUser {
+    /// Name of the server the user is logged in.
+    pub origin: String,
+    /// Login of the user on the origin server.
+    pub login: String,
+    pub display_name: Option<String>,
+    pub connected_at: String,
+    /// Linked server the user is known from.
+    pub peer: String,
+}
+
+
+impl RemoteUser {
+    /// `online_user` returns the user as [OnlineUser] with qualified login.
+    pub fn online_user(&self) -> OnlineUser {
+        OnlineUser {
+            login: qualified_login(&self.login, &self.origin),
+            display_name: self.display_name.clone(),
+            connected_at: self.connected_at.clone(),
+        }
+    }
+}
+
+
+/// `qualified_login` returns login of a user of another server as shown to local users
+/// (e.g. `alice@brno`).
+pub(crate) fn qualified_login(login: &str, origin: &str) -> String {
+    format!("{}@{}", login, origin)
+}
+
+
+#[derive(Default)]
+struct FederationState {
+    /// IDs of received events (with their origin) in order of arrival.
+    seen: HashSet<(String, u64)>,
+    seen_order: VecDeque<(String, u64)>,
+    /// Names of currently linked servers.
+    peers: HashSet<String>,
+    /// Users online on other servers by their qualified login.
+    remote_users: HashMap<String, RemoteUser>,
+}
+
+
+impl FederationState {
+    /// `remember` records ID of the event. Returns `false` if it was already seen.
+    fn remember(&mut self, event: &FederatedEvent) -> bool {
+        let key = (event.origin.clone(), event.id);
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.seen_order.push_back(key);
+        if self.seen_order.len() > MAX_SEEN_EVENTS {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+
+/// `Federation` connects the chat loop with links to other servers. Local events are published
+/// to all links; events received from a link are relayed to the other links and passed to
+/// the chat loop.
+///
+/// Every event carries names of servers it passed through, so it is never sent back to any of
+/// them, and a random ID, so the same event coming over two paths is delivered just once.
+pub(crate) struct Federation {
+    server_name: Option<String>,
+    outbound: broadcast::Sender<FederatedEvent>,
+    inbound_sender: UnboundedSender<Inbound>,
+    inbound_receiver: Mutex<UnboundedReceiver<Inbound>>,
+    state: Mutex<FederationState>,
+}
+
+
+impl Federation {
+    /// `new` creates federation of this server of the given name (`None` disables federation,
+    /// published events are then dropped).
+    pub fn new(server_name: Option<String>) -> Arc<Federation> {
+        let (outbound, _) = broadcast::channel(OUTBOUND_CAPACITY);
+        let (inbound_sender, inbound_receiver) = unbounded_channel();
+        Arc::new(Federation {
+            server_name,
+            outbound,
+            inbound_sender,
+            inbound_receiver: Mutex::new(inbound_receiver),
+            state: Mutex::new(FederationState::default()),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FederationState> {
+        // Poisoned state is still consistent (each operation changes it at once at its end).
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// `publish` relays an event of this server to all linked servers.
+    pub fn publish(&self, kind: FederatedKind) {
+        let Some(server_name) = &self.server_name else {
+            return;
+        };
+        let event = FederatedEvent {
+            id: rand::random(),
+            origin: server_name.clone(),
+            via: vec![server_name.clone()],
+            kind,
+        };
+        // Sending fails just if no link is up, then there is nobody to tell.
+        let _ = self.outbound.send(event);
+    }
+
+    /// `try_recv` returns the next notification for the chat loop (if any).
+    pub fn try_recv(&self) -> Option<Inbound> {
+        self.inbound_receiver
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .try_recv()
+            .ok()
+    }
+
+    /// `receive` takes an event received from the given linked server. Events that already
+    /// passed this server and duplicates are dropped, others are relayed to the other links
+    /// and passed to the chat loop.
+    fn receive(&self, peer: &str, event: FederatedEvent) {
+        let Some(server_name) = &self.server_name else {
+            return;
+        };
+        if event.origin == *server_name || event.via.contains(server_name) {
+            tracing::debug!(origin = %event.origin, "dropping looped event");
+            return;
+        }
+        if !self.lock().remember(&event) {
+            tracing::debug!(origin = %event.origin, id = event.id, "dropping duplicate event");
+            return;
+        }
+        FEDERATED_EVENT_COUNTER.inc();
+
+        let mut relayed = event.clone();
+        relayed.via.push(server_name.clone());
+        let _ = self.outbound.send(relayed);
+        let _ = self.inbound_sender.send(Inbound::Event { peer: peer.to_string(), event });
+    }
+
+    /// `is_linked` tells whether a link to the given server is up.
+    fn is_linked(&self, peer: &str) -> bool {
+        self.lock().peers.contains(peer)
+    }
+
+    /// `link_up` marks a link to the given server as up. Returns `false` if it already was.
+    fn link_up(&self, peer: &str) -> bool {
+        let inserted = self.lock().peers.insert(peer.to_string());
+        if inserted {
+            FEDERATION_LINK_GAUGE.inc();
+        }
+        inserted
+    }
+
+    /// `link_down` marks a link to the given server as down and lets the chat loop know.
+    fn link_down(&self, peer: &str) {
+        if self.lock().peers.remove(peer) {
+            FEDERATION_LINK_GAUGE.dec();
+        }
+        let _ = self.inbound_sender.send(Inbound::LinkDown { peer: peer.to_string() });
+    }
+
+    /// `remote_users` returns users online on other servers.
+    pub fn remote_users(&self) -> Vec<OnlineUser> {
+        self.lock().remote_users.values().map(RemoteUser::online_user).collect()
+    }
+
+    /// `update_presence` applies a presence event of the given origin received from the given
+    /// linked server. Returns notifications for local clients about joined and left users.
+    fn update_presence(&self, peer: &str, origin: &str, kind: FederatedKind) -> Vec<Message> {
+        let mut state = self.lock();
+        let remote_user = |user: OnlineUser| RemoteUser {
+            origin: origin.to_string(),
+            login: user.login,
+            display_name: user.display_name,
+            connected_at: user.connected_at,
+            peer: peer.to_string(),
+        };
+        let joined = |user: &RemoteUser| Message::UserJoined {
+            login: qualified_login(&user.login, origin),
+            timestamp: user.connected_at.clone(),
+        };
+
+        match kind {
+            FederatedKind::Joined {user} => {
+                let user = remote_user(user);
+                let message = joined(&user);
+                match state.remote_users.insert(qualified_login(&user.login, origin), user) {
+                    None => vec![message],
+                    Some(_) => vec![],
+                }
+            },
+            FederatedKind::Left {login, timestamp} => {
+                let login = qualified_login(&login, origin);
+                match state.remote_users.remove(&login) {
+                    Some(_) => vec![Message::UserLeft { login, timestamp }],
+                    None => vec![],
+                }
+            },
+            FederatedKind::Roster {users} => {
+                let users: HashMap<String, RemoteUser> = users
+                    .into_iter()
+                    .map(|user| (qualified_login(&user.login, origin), remote_user(user)))
+                    .collect();
+                let timestamp = timestamp_to_string(SystemTime::now());
+
+                let mut messages = vec![];
+                state.remote_users.retain(|login, user| {
+                    let keep = user.origin != origin || users.contains_key(login);
+                    if !keep {
+                        messages.push(Message::UserLeft {
+                            login: login.clone(),
+                            timestamp: timestamp.clone(),
+                        });
+                    }
+                    keep
+                });
+                for (login, user) in users {
+                    if !state.remote_users.contains_key(&login) {
+                        messages.push(joined(&user));
+                    }
+                    state.remote_users.insert(login, user);
+                }
+                messages
+            },
+            FederatedKind::Chat {..} | FederatedKind::Action {..} => vec![],
+        }
+    }
+
+    /// `drop_peer` forgets users known from the given linked server. Returns notifications
+    /// for local clients about left users.
+    fn drop_peer(&self, peer: &str) -> Vec<Message> {
+        let timestamp = timestamp_to_string(SystemTime::now());
+        let mut messages = vec![];
+        self.lock().remote_users.retain(|login, user| {
+            let keep = user.peer != peer;
+            if !keep {
+                let login = login.clone();
+                messages.push(Message::UserLeft { login, timestamp: timestamp.clone() });
+            }
+            keep
+        });
+        messages
+    }
+
+    /// `rosters` returns events with lists of online users sent to a newly linked server:
+    /// users of this server and users of other servers not known from that server.
+    fn rosters(&self, peer: &str, local_users: Vec<OnlineUser>) -> Vec<FederatedEvent> {
+        let Some(server_name) = &self.server_name else {
+            return vec![];
+        };
+
+        let mut by_origin: HashMap<String, Vec<OnlineUser>> = HashMap::new();
+        by_origin.insert(server_name.clone(), local_users);
+        for user in self.lock().remote_users.values() {
+            if user.peer != peer && user.origin != peer {
+                let mut online_user = user.online_user();
+                online_user.login = user.login.clone();
+                by_origin.entry(user.origin.clone()).or_default().push(online_user);
+            }
+        }
+
+        by_origin
+            .into_iter()
+            .map(|(origin, users)| {
+                let mut via = vec![origin.clone()];
+                if origin != *server_name {
+                    via.push(server_name.clone());
+                }
+                FederatedEvent {
+                    id: rand::random(),
+                    origin,
+                    via,
+                    kind: FederatedKind::Roster { users },
+                }
+            })
+            .collect()
+    }
+}
+
+
+/// `spawn_links` spawns the listener of links from other servers and tasks keeping links to
+/// other servers connected.
+pub(crate) fn spawn_links(
+        join_set: &mut JoinSet<Result<(), ServerError>>,
+        config: &FederationConfig,
+        federation: Arc<Federation>,
+        clients: Clients,
+) {
+    let Some(server_name) = config.server_name.clone() else {
+        return;
+    };
+
+    if let Some(address) = config.listen.clone() {
+        let task_links = config.links.clone();
+        let task_server_name = server_name.clone();
+        let task_federation = federation.clone();
+        let task_clients = clients.clone();
+        join_set.spawn(async move {
+            accept_links(address, task_server_name, task_links, task_federation, task_clients).await
+        });
+    }
+
+    for link in config.links.iter().filter(|link| link.address.is_some()) {
+        let task_link = link.clone();
+        let task_server_name = server_name.clone();
+        let task_federation = federation.clone();
+        let task_clients = clients.clone();
+        join_set.spawn(async move {
+            keep_link(task_link, task_server_name, task_federation, task_clients).await
+        });
+    }
+}
+
+
+/// `accept_links` accepts links from other servers (just the configured ones with the right
+/// secret).
+async fn accept_links(
+        address: String,
+        server_name: String,
+        links: Vec<FederationLinkConfig>,
+        federation: Arc<Federation>,
+        clients: Clients,
+) -> Result<(), ServerError> {
+    let listener = match TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(err) => Err(ServerError::PortBindError(err.to_string()))?,
+    };
+    tracing::info!(%address, "listening for federation links");
+
+    loop {
+        let (mut stream, address) = match listener.accept().await {
+            Ok((stream, address)) => (stream, address),
+            Err(err) => Err(ServerError::FederationError(err.to_string()))?,
+        };
+
+        let server_name = server_name.clone();
+        let links = links.clone();
+        let federation = federation.clone();
+        let clients = clients.clone();
+        let span = tracing::info_span!("federation_link", peer = %address);
+        tokio::spawn(async move {
+            let peer = match accept_link(&mut stream, &server_name, &links, &federation).await {
+                Ok(peer) => peer,
+                Err(err) => {
+                    tracing::warn!(error = %err, "federation link refused");
+                    return;
+                },
+            };
+            if let Err(err) = run_link(&federation, &clients, &peer, stream).await {
+                tracing::info!(peer, error = %err, "federation link lost");
+            }
+        }.instrument(span));
+    }
+}
+
+
+/// `accept_link` performs handshake of a link from another server. Returns name of the other
+/// server.
+async fn accept_link(
+        stream: &mut TcpStream,
+        server_name: &str,
+        links: &[FederationLinkConfig],
+        federation: &Federation,
+) -> Result<String, ServerError> {
+    let hello = Message::receive_with_timeout(stream, HANDSHAKE_TIMEOUT).await;
+    let (peer, secret) = match hello {
+        Ok(Some(Message::LinkHello {server, secret})) => (server, secret),
+        Ok(_) => Err(ServerError::FederationError("expected link hello".to_string()))?,
+        Err(err) => Err(ServerError::FederationError(err.to_string()))?,
+    };
+
+    let link = links.iter().find(|link| link.name == peer);
+    let refusal = match link {
+        Some(link) if secrets_match(&link.secret, &secret) && federation.is_linked(&peer) =>
+            Some(format!("server {} is already linked", peer)),
+        Some(link) if secrets_match(&link.secret, &secret) => None,
+        _ => Some(format!("link of server {} is not authorized", peer)),
+    };
+    if let Some(reason) = refusal {
+        let _ = Message::Error(reason.clone()).send(stream).await;
+        Err(ServerError::FederationError(reason))?
+    }
+
+    let welcome = Message::LinkWelcome { server: server_name.to_string() };
+    if let Err(err) = welcome.send(stream).await {
+        Err(ServerError::FederationError(err.to_string()))?
+    }
+    Ok(peer)
+}
+
+
+/// `keep_link` connects a link to another server and connects it again whenever it is lost
+/// (with growing delay between failed attempts).
+async fn keep_link(
+        link: FederationLinkConfig,
+        server_name: String,
+        federation: Arc<Federation>,
+        clients: Clients,
+) -> Result<(), ServerError> {
+    let span = tracing::info_span!("federation_link", peer = %link.name);
+    let mut delay = Duration::from_secs(1);
+
+    loop {
+        let started = Instant::now();
+        let result = async {
+            let stream = connect_link(&link, &server_name).await?;
+            run_link(&federation, &clients, &link.name, stream).await
+        }.instrument(span.clone()).await;
+        if let Err(err) = result {
+            span.in_scope(|| tracing::info!(error = %err, "federation link lost"));
+        }
+
+        // A link that was up for a while is reconnected quickly again.
+        if started.elapsed() > MAX_RECONNECT_DELAY {
+            delay = Duration::from_secs(1);
+        }
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+
+/// `connect_link` connects to another server and performs handshake of the link.
+async fn connect_link(
+        link: &FederationLinkConfig,
+        server_name: &str,
+) -> Result<TcpStream, ServerError> {
+    let address = link.address.clone().unwrap_or_default();
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .map_err(|err| federation_error(format!("{}: {}", address, err)))?;
+
+    let hello = Message::LinkHello {
+        server: server_name.to_string(),
+        secret: link.secret.clone(),
+    };
+    hello.send(&mut stream).await.map_err(|err| federation_error(err.to_string()))?;
+
+    match Message::receive_with_timeout(&mut stream, HANDSHAKE_TIMEOUT).await {
+        Ok(Some(Message::LinkWelcome {server})) if server == link.name => Ok(stream),
+        Ok(Some(Message::LinkWelcome {server})) =>
+            Err(federation_error(format!("expected server {}, got {}", link.name, server))),
+        Ok(Some(Message::Error(reason))) => Err(federation_error(reason)),
+        Ok(_) => Err(federation_error("expected link welcome".to_string())),
+        Err(err) => Err(federation_error(err.to_string())),
+    }
+}
+
+
+/// `run_link` relays events over an established link until it is closed. Users online on this
+/// server (and the ones known from other links) are announced first.
+async fn run_link(
+        federation: &Federation,
+        clients: &Clients,
+        peer: &str,
+        mut stream: TcpStream,
+) -> Result<(), ServerError> {
+    if !federation.link_up(peer) {
+        return Err(ServerError::FederationError(format!("server {} is already linked", peer)));
+    }
+    tracing::info!(peer, "federation link established");
+
+    let result = relay_events(federation, clients, peer, &mut stream).await;
+    federation.link_down(peer);
+    result
+}
+
+
+/// `relay_events` sends events to the other server and receives its events.
+async fn relay_events(
+        federation: &Federation,
+        clients: &Clients,
+        peer: &str,
+        stream: &mut TcpStream,
+) -> Result<(), ServerError> {
+    // Subscribing before the rosters are sent, so no event gets lost in between.
+    let mut outbound = federation.outbound.subscribe();
+    let local_users = online_users(&*clients.lock().await);
+    for event in federation.rosters(peer, local_users) {
+        Message::Federated(event).send(stream).await.map_err(federation_error)?;
+    }
+
+    let mut ping = interval(PING_INTERVAL);
+    let mut last_received = Instant::now();
+    loop {
+        tokio::select! {
+            event = outbound.recv() => match event {
+                Ok(event) if event.via.iter().any(|server| server == peer) => {},
+                Ok(event) =>
+                    Message::Federated(event).send(stream).await.map_err(federation_error)?,
+                Err(RecvError::Lagged(skipped)) =>
+                    tracing::warn!(skipped, "federation link is too slow, events were dropped"),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            _ = ping.tick() => {
+                if last_received.elapsed() > LINK_TIMEOUT {
+                    return Err(ServerError::FederationError("link timed out".to_string()));
+                }
+                Message::LinkPing.send(stream).await.map_err(federation_error)?;
+            },
+            readable = stream.readable() => {
+                readable?;
+                match Message::receive(stream).await.map_err(federation_error)? {
+                    None => {},
+                    Some(Message::LinkPing) => last_received = Instant::now(),
+                    Some(Message::Federated(event)) => {
+                        last_received = Instant::now();
+                        federation.receive(peer, event);
+                    },
+                    Some(_) => tracing::warn!("ignoring unexpected message of linked server"),
+                }
+            },
+        }
+    }
+}
+
+
+/// `federation_error` wraps an error of a link.
+fn federation_error(err: impl ToString) -> ServerError {
+    ServerError::FederationError(err.to_string())
+}
+
+
+/// `secrets_match` compares secrets in time independent of their content.
+fn secrets_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+
+/// `deliver` passes a notification from federation links to local clients. Chat messages of
+/// remote users are stored as messages of (locked) local users with qualified logins, so they
+/// can be replied to, reacted to and searched like local ones.
+pub(crate) async fn deliver(
+        clients: &Clients,
+        storage: &dyn Storage,
+        config: &Config,
+        federation: &Federation,
+        inbound: Inbound,
+) -> Result<(), ServerError> {
+    let (peer, event) = match inbound {
+        Inbound::Event {peer, event} => (peer, event),
+        Inbound::LinkDown {peer} => {
+            let messages = federation.drop_peer(&peer);
+            return broadcast_presence(clients, config, &messages).await;
+        },
+    };
+
+    match event.kind {
+        FederatedKind::Chat {login, display_name, timestamp, text} => {
+            let login = qualified_login(&login, &event.origin);
+            let user = DbUser {
+                id: 0,
+                login: login.clone(),
+                password: LOCKED_PASSWORD.to_string(),
+                role: "user".to_string(),
+                display_name: None,
+            };
+            let (user_id, _) = storage.import_user(&user).await?;
+            let id = storage.insert_chat_message(user_id, &timestamp, &text, None).await?;
+            let message =
+                Message::Chat { id, login, display_name, timestamp, text, reply_to: None };
+            broadcast(clients, &message, None).await
+        },
+        FederatedKind::Action {login, display_name, timestamp, text} => {
+            let login = qualified_login(&login, &event.origin);
+            let message = Message::Action { login, display_name, timestamp, text };
+            broadcast(clients, &message, None).await
+        },
+        kind => {
+            let messages = federation.update_presence(&peer, &event.origin, kind);
+            broadcast_presence(clients, config, &messages).await
+        },
+    }
+}
+
+
+/// `broadcast_presence` sends presence notifications to all clients (if presence is enabled).
+async fn broadcast_presence(
+        clients: &Clients,
+        config: &Config,
+        messages: &[Message],
+) -> Result<(), ServerError> {
+    if !config.features.presence {
+        return Ok(());
+    }
+    for message in messages {
+        broadcast(clients, message, None).await?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use shared::{FederatedEvent, FederatedKind, Message, OnlineUser};
+
+    use super::{secrets_match, Federation, Inbound};
+
+
+    fn event(id: u64, origin: &str, via: &[&str], kind: FederatedKind) -> FederatedEvent {
+        FederatedEvent {
+            id,
+            origin: origin.to_string(),
+            via: via.iter().map(|server| server.to_string()).collect(),
+            kind,
+        }
+    }
+
+    fn user(login: &str) -> OnlineUser {
+        OnlineUser {
+            login: login.to_string(),
+            display_name: None,
+            connected_at: "2024-01-01T10:00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_receive_drops_loops_and_duplicates() {
+        let federation = Federation::new(Some("prague".to_string()));
+        let mut relayed = federation.outbound.subscribe();
+        let left = || FederatedKind::Left {
+            login: "alice".to_string(),
+            timestamp: "2024-01-01T10:00:00".to_string(),
+        };
+
+        federation.receive("brno", event(1, "brno", &["brno"], left()));
+        // The same event coming over another path.
+        federation.receive("ostrava", event(1, "brno", &["brno", "ostrava"], left()));
+        // Events of this server or events which already passed it.
+        federation.receive("brno", event(2, "prague", &["prague", "brno"], left()));
+        federation.receive("brno", event(3, "ostrava", &["ostrava", "prague", "brno"], left()));
+
+        let Some(Inbound::Event {peer, event}) = federation.try_recv() else {
+            panic!("event expected");
+        };
+        assert_eq!((peer.as_str(), event.id), ("brno", 1));
+        assert!(federation.try_recv().is_none());
+
+        // Relayed event is tagged by this server.
+        assert_eq!(relayed.try_recv().unwrap().via, vec!["brno", "prague"]);
+        assert!(relayed.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_update_presence() {
+        let federation = Federation::new(Some("prague".to_string()));
+        let roster = FederatedKind::Roster { users: vec![user("alice"), user("bob")] };
+
+        let messages = federation.update_presence("brno", "brno", roster);
+        assert_eq!(messages.len(), 2);
+        let joined = federation.update_presence("brno", "brno", FederatedKind::Joined {
+            user: user("alice"),
+        });
+        assert!(joined.is_empty());
+
+        let roster = FederatedKind::Roster { users: vec![user("bob")] };
+        let messages = federation.update_presence("brno", "brno", roster);
+        assert!(matches!(&messages[..], [Message::UserLeft {login, ..}] if login == "alice@brno"));
+
+        let mut logins: Vec<String> = federation
+            .remote_users()
+            .into_iter()
+            .map(|user| user.login)
+            .collect();
+        logins.sort();
+        assert_eq!(logins, vec!["bob@brno"]);
+
+        let messages = federation.drop_peer("brno");
+        assert!(matches!(&messages[..], [Message::UserLeft {login, ..}] if login == "bob@brno"));
+        assert!(federation.remote_users().is_empty());
+    }
+
+    #[test]
+    fn test_rosters() {
+        let federation = Federation::new(Some("prague".to_string()));
+        let roster = FederatedKind::Roster { users: vec![user("alice")] };
+        federation.update_presence("brno", "brno", roster);
+
+        // Users known from the newly linked server are not sent back to it.
+        let rosters = federation.rosters("brno", vec![user("carol")]);
+        assert_eq!(rosters.len(), 1);
+        assert_eq!(rosters[0].origin, "prague");
+
+        let mut rosters = federation.rosters("ostrava", vec![]);
+        rosters.sort_by(|a, b| a.origin.cmp(&b.origin));
+        assert_eq!(rosters[0].origin, "brno");
+        assert_eq!(rosters[0].via, vec!["brno", "prague"]);
+        assert!(matches!(
+            &rosters[0].kind,
+            FederatedKind::Roster {users} if users[0].login == "alice"
+        ));
+
+        assert!(secrets_match("s3cret", "s3cret"));
+        assert!(!secrets_match("s3cret", "s3cre7"));
+        assert!(!secrets_match("s3cret", "s3"));
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+
+use shared::timestamp_to_string;
+use crate::error::ServerError;
+use crate::storage::{DbChatMessage, DbClientLogin, DbUser, HistoryFilter, Storage};
+
+
+/// `HISTORY_FORMAT` is the value of `format` field of header of JSON Lines history archives.
+const HISTORY_FORMAT: &str = "xchat-history";
+
+/// `HISTORY_VERSION` is the (only) supported version of JSON Lines history archives.
+const HISTORY_VERSION: u32 = 1;
+
+
+/// `HistoryRecord` is a single line of JSON Lines history archive (JSON object with `type` field
+/// and all the fields of the row). The same records are written to archives of pruned rows
+/// (see [crate::retention]), just without the header and users.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum HistoryRecord {
+    Header { format: String, version: u32, exported_at: String },
+    User(DbUser),
+    ChatMessage(DbChatMessage),
+    ClientLogin(DbClientLogin),
+}
+
+
+/// `HistoryFormat` is a format of exported chat history.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HistoryFormat {
+    /// JSON Lines archive with users, chat messages and log-in records (it might be imported).
+    #[default]
+    Jsonl,
+    /// CSV table of chat messages.
+    Csv,
+    /// Plain-text transcript of chat messages.
+    Text,
+}
+
+
+impl FromStr for HistoryFormat {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source {
+            "jsonl" => Ok(HistoryFormat::Jsonl),
+            "csv" => Ok(HistoryFormat::Csv),
+            "text" => Ok(HistoryFormat::Text),
+            _ => Err(format!("unknown history format `{}` (use `jsonl`, `csv` or `text`)", source)),
+        }
+    }
+}
+
+
+/// `ExportSummary` holds numbers of exported records.
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportSummary {
+    pub users: usize,
+    pub chat_messages: usize,
+    pub client_logins: usize,
+}
+
+
+/// `ImportSummary` holds numbers of imported records. Records already present in the DB are
+/// counted as `duplicates`, records of unknown users as `skipped`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub users_created: usize,
+    pub users_existing: usize,
+    pub chat_messages: usize,
+    pub client_logins: usize,
+    pub duplicates: usize,
+    pub skipped: usize,
+}
+
+
+/// `export_history` writes chat history selected by the filter in the given format.
+///
+/// JSON Lines archive starts with a header followed by users (all users matching the login
+/// filter), chat messages and log-in records. CSV and text transcript contain chat messages only.
+pub async fn export_history(
+        storage: &dyn Storage,
+        filter: &HistoryFilter,
+        format: HistoryFormat,
+        writer: &mut dyn Write,
+) -> Result<ExportSummary, ServerError> {
+    let chat_messages = storage.fetch_chat_history(filter).await?;
+    let mut summary = ExportSummary { chat_messages: chat_messages.len(), ..Default::default() };
+
+    match format {
+        HistoryFormat::Jsonl => {
+            let users: Vec<DbUser> = storage
+                .fetch_users()
+                .await?
+                .into_iter()
+                .filter(|user| filter.login.as_ref().is_none_or(|login| &user.login == login))
+                .collect();
+            let logins = storage.fetch_login_history(filter).await?;
+            summary.users = users.len();
+            summary.client_logins = logins.len();
+
+            let header = HistoryRecord::Header {
+                format: HISTORY_FORMAT.to_string(),
+                version: HISTORY_VERSION,
+                exported_at: timestamp_to_string(SystemTime::now()),
+            };
+            let records = std::iter::once(header)
+                .chain(users.into_iter().map(HistoryRecord::User))
+                .chain(chat_messages.into_iter().map(HistoryRecord::ChatMessage))
+                .chain(logins.into_iter().map(HistoryRecord::ClientLogin));
+            for record in records {
+                match serde_json::to_string(&record) {
+                    Ok(line) => writeln!(writer, "{}", line)?,
+                    Err(err) => Err(ServerError::HistoryError(err.to_string()))?,
+                }
+            }
+        },
+        HistoryFormat::Csv => {
+            writeln!(writer, "id,timestamp,login,text,edited_at,deleted_at,reply_to")?;
+            for message in chat_messages.iter() {
+                let fields = [
+                    message.id.to_string(),
+                    csv_field(&message.timestamp),
+                    csv_field(&message.login),
+                    csv_field(&message.text),
+                    csv_field(message.edited_at.as_deref().unwrap_or_default()),
+                    csv_field(message.deleted_at.as_deref().unwrap_or_default()),
+                    message.reply_to.map(|id| id.to_string()).unwrap_or_default(),
+                ];
+                writeln!(writer, "{}", fields.join(","))?;
+            }
+        },
+        HistoryFormat::Text => {
+            for message in chat_messages.iter() {
+                writeln!(writer, "{}", transcript_line(message))?;
+            }
+        },
+    }
+
+    writer.flush()?;
+    Ok(summary)
+}
+
+
+/// `import_history` imports JSON Lines history archive (or archive of pruned rows) into
+/// the storage.
+///
+/// Users are matched by login: existing users are kept untouched (including their password and
+/// role), missing users are created. Chat messages and log-in records keep their timestamps and
+/// get new IDs, replies are linked to new IDs of their parents (or unlinked if the parent is not
+/// part of the archive). Records already present in the DB are not imported twice, so the same
+/// archive might be imported repeatedly.
+pub async fn import_history(
+        storage: &dyn Storage,
+        reader: &mut dyn BufRead,
+) -> Result<ImportSummary, ServerError> {
+    let mut user_ids: HashMap<String, i64> = storage
+        .fetch_users()
+        .await?
+        .into_iter()
+        .map(|user| (user.login, user.id))
+        .collect();
+    let mut message_ids: HashMap<i64, i64> = HashMap::new();
+    let mut summary = ImportSummary::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: HistoryRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(err) => Err(ServerError::HistoryError(format!("line {}: {}", index + 1, err)))?,
+        };
+
+        match record {
+            HistoryRecord::Header { format, version, .. } => {
+                if format != HISTORY_FORMAT || version != HISTORY_VERSION {
+                    Err(ServerError::HistoryError(
+                        format!("unsupported archive format {} version {}", format, version)
+                    ))?;
+                }
+            },
+            HistoryRecord::User(user) => {
+                let (user_id, created) = storage.import_user(&user).await?;
+                if created {
+                    summary.users_created += 1;
+                } else {
+                    summary.users_existing += 1;
+                }
+                user_ids.insert(user.login, user_id);
+            },
+            HistoryRecord::ChatMessage(message) => {
+                let Some(&user_id) = user_ids.get(&message.login) else {
+                    tracing::warn!(
+                        login = %message.login,
+                        id = message.id,
+                        "skipping message of unknown user",
+                    );
+                    summary.skipped += 1;
+                    continue;
+                };
+                let reply_to = message.reply_to.and_then(|id| message_ids.get(&id).copied());
+                let (message_id, created) =
+                    storage.import_chat_message(user_id, &message, reply_to).await?;
+                if created {
+                    summary.chat_messages += 1;
+                } else {
+                    summary.duplicates += 1;
+                }
+                message_ids.insert(message.id, message_id);
+            },
+            HistoryRecord::ClientLogin(login) => {
+                let Some(&user_id) = user_ids.get(&login.login) else {
+                    tracing::warn!(login = %login.login, "skipping log-in of unknown user");
+                    summary.skipped += 1;
+                    continue;
+                };
+                if storage.import_login(user_id, &login.timestamp).await? {
+                    summary.client_logins += 1;
+                } else {
+                    summary.duplicates += 1;
+                }
+            },
+        }
+    }
+
+    Ok(summary)
+}
+
+
+/// `create_writer` opens the given file for writing (`-` stands for standard output).
+pub(crate) fn create_writer(path: &Path) -> Result<Box<dyn Write>, ServerError> {
+    if path == Path::new("-") {
+        return Ok(Box::new(stdout().lock()));
+    }
+    match File::create(path) {
+        Ok(file) => Ok(Box::new(std::io::BufWriter::new(file))),
+        Err(err) => Err(ServerError::HistoryError(format!("{}: {}", path.display(), err))),
+    }
+}
+
+
+/// `open_reader` opens the given file for reading (`-` stands for standard input). Gzipped
+/// content (e.g. archive of pruned rows) is decompressed transparently.
+pub(crate) fn open_reader(path: &Path) -> Result<Box<dyn BufRead>, ServerError> {
+    let reader: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(stdin().lock())
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => Err(ServerError::HistoryError(format!("{}: {}", path.display(), err)))?,
+        }
+    };
+
+    let mut reader = BufReader::new(reader);
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+    }
+    Ok(Box::new(reader))
+}
+
+
+/// `csv_field` quotes the given value if needed (RFC 4180).
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+
+/// `transcript_line` formats a chat message as line of plain-text transcript (continuation
+/// lines of multi-line messages are indented).
+fn transcript_line(message: &DbChatMessage) -> String {
+    let reply = match message.reply_to {
+        Some(reply_to) => format!(" (re #{})", reply_to),
+        None => String::new(),
+    };
+    let text = match (&message.deleted_at, &message.edited_at) {
+        (Some(deleted_at), _) => format!("<deleted {}>", deleted_at),
+        (None, Some(edited_at)) => format!("{} <edited {}>", message.text, edited_at),
+        (None, None) => message.text.clone(),
+    };
+    format!(
+        "[{}] #{} {}{}: {}",
+        message.timestamp,
+        message.id,
+        message.login,
+        reply,
+        text.replace('\n', "\n    "),
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{HistoryFilter, Storage};
+    use crate::storage_memory::MemoryStorage;
+    use super::{
+        csv_field,
+        export_history,
+        import_history,
+        ExportSummary,
+        HistoryFormat,
+        ImportSummary,
+    };
+
+
+    async fn sample_storage() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        let alice = storage.insert_user("alice", "a", "user");
+        let bob = storage.insert_user("bob", "b", "moderator");
+        let first = storage
+            .insert_chat_message(alice, "2024-01-01T10:00:00", "hello, world", None)
+            .await
+            .unwrap();
+        storage.insert_chat_message(bob, "2024-01-02T10:00:00", "say \"hi\"", Some(first))
+            .await
+            .unwrap();
+        storage.insert_chat_message(alice, "2024-01-03T10:00:00", "bye", None).await.unwrap();
+        storage.insert_login(alice, "2024-01-01T09:00:00", None).await.unwrap();
+        storage.insert_login(bob, "2024-01-02T09:00:00", None).await.unwrap();
+        storage
+    }
+
+    async fn export(
+        storage: &MemoryStorage,
+        filter: &HistoryFilter,
+        format: HistoryFormat,
+    ) -> String {
+        let mut output = vec![];
+        export_history(storage, filter, format, &mut output).await.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_history_filter() {
+        let storage = sample_storage().await;
+
+        let filter = HistoryFilter {
+            since: Some("2024-01-02".to_string()),
+            until: Some("2024-01-03".to_string()),
+            login: None,
+        };
+        let text = export(&storage, &filter, HistoryFormat::Text).await;
+        assert_eq!(text, "[2024-01-02T10:00:00] #2 bob (re #1): say \"hi\"\n");
+
+        let filter = HistoryFilter { login: Some("alice".to_string()), ..Default::default() };
+        let mut output = vec![];
+        let summary = export_history(&storage, &filter, HistoryFormat::Jsonl, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(summary, ExportSummary { users: 1, chat_messages: 2, client_logins: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_export_history_csv() {
+        let storage = sample_storage().await;
+        let csv = export(&storage, &HistoryFilter::default(), HistoryFormat::Csv).await;
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "id,timestamp,login,text,edited_at,deleted_at,reply_to");
+        assert_eq!(lines[1], "1,2024-01-01T10:00:00,alice,\"hello, world\",,,");
+        assert_eq!(lines[2], "2,2024-01-02T10:00:00,bob,\"say \"\"hi\"\"\",,,1");
+        assert_eq!(lines.len(), 4);
+
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[tokio::test]
+    async fn test_import_history() {
+        let source = sample_storage().await;
+        let archive = export(&source, &HistoryFilter::default(), HistoryFormat::Jsonl).await;
+
+        // The target has different IDs: `bob` exists already (and must stay untouched).
+        let target = MemoryStorage::new();
+        target.insert_user("carol", "c", "user");
+        let bob = target.insert_user("bob", "other", "user");
+        let summary = import_history(&target, &mut archive.as_bytes()).await.unwrap();
+        assert_eq!(summary, ImportSummary {
+            users_created: 1,
+            users_existing: 1,
+            chat_messages: 3,
+            client_logins: 2,
+            ..Default::default()
+        });
+
+        let users = target.fetch_users().await.unwrap();
+        let bob_user = users.iter().find(|user| user.id == bob).unwrap();
+        assert_eq!((bob_user.password.as_str(), bob_user.role.as_str()), ("other", "user"));
+
+        let messages = target.fetch_chat_history(&HistoryFilter::default()).await.unwrap();
+        assert_eq!(messages[0].login, "alice");
+        assert_eq!(messages[1].timestamp, "2024-01-02T10:00:00");
+        assert_eq!(messages[1].reply_to, Some(messages[0].id));
+
+        // Repeated import does not duplicate anything.
+        let summary = import_history(&target, &mut archive.as_bytes()).await.unwrap();
+        assert_eq!(summary.duplicates, 5);
+        assert_eq!(summary.chat_messages, 0);
+
+        // Records of unknown users (e.g. from archives of pruned rows) are skipped.
+        let line = r#"{"type":"client_login","id":7,"user_id":7,"login":"dave","timestamp":"x"}"#;
+        let summary = import_history(&target, &mut line.as_bytes()).await.unwrap();
+        assert_eq!(summary.skipped, 1);
+    }
+}
@@ -1,36 +1,101 @@
+mod attachments;
+mod backup;
+mod commands;
+mod config;
+mod connections;
 mod db_queries;
 mod web;
 mod error;
+mod federation;
+mod filters;
+mod history;
+mod lockout;
+mod logging;
+mod mentions;
+mod mime;
+mod plugin;
+mod retention;
+mod session;
+mod storage;
+mod storage_memory;
+#[cfg(feature = "postgres")]
+mod storage_postgres;
 mod web_prometheus;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::ErrorKind;
 use std::net::{SocketAddr};
 use std::sync::{Arc, atomic};
 use std::sync::atomic::Ordering::Relaxed;
-use std::time::{SystemTime};
+use std::time::{Instant, SystemTime};
 
-use sqlx::sqlite::{SqlitePool};
 use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::task::JoinSet;
 use tokio::time::{sleep, Duration};
+use tracing::{Instrument, Span};
 
-use db_queries::{insert_login, insert_chat_message, fetch_user_by_login_and_password};
-use shared::{Message, timestamp_to_string};
-use crate::error::ServerError;
+use attachments::{send_original, share_attachment};
+use commands::CommandRegistry;
+use federation::Federation;
+use filters::ContentFilter;
+use lockout::LoginGuard;
+use mentions::{send_unread_mentions, MentionedMessage};
+use plugin::Plugins;
+use storage::{
+    connect_storage,
+    DbDirectMessage,
+    DbUser,
+    Storage,
+    LOCKED_PASSWORD,
+    ROLE_MODERATOR,
+    ROLE_USER,
+};
+use shared::{
+    FederatedKind,
+    Message,
+    OnlineUser,
+    Quote,
+    SearchHit,
+    is_valid_reaction,
+    timestamp_to_string,
+};
+pub use crate::config::{CliOverrides, Config, LogFormat};
+use crate::config::ConnectionsConfig;
+pub use crate::error::ServerError;
+pub use crate::history::{ExportSummary, HistoryFormat, ImportSummary};
+pub use crate::storage::HistoryFilter;
+pub use crate::logging::init_logging;
+pub use crate::plugin::{Author, BotHandle, HookVerdict, Plugin};
 use crate::web_prometheus::{
+    AUTH_DEADLINE_COUNTER,
     CURRENT_CLIENT_COUNT_GAUGE,
+    LOCKED_OUT_LOGIN_COUNTER,
     NOT_AUTHORIZED_CONNECTION_COUNTER,
     SUCCESSFUL_CONNECTION_COUNTER,
     MESSAGE_COUNTER,
+    REFUSED_CONNECTION_COUNTER,
 };
 
 
 struct ClientRecord {
     stream: TcpStream,
+    /// When the connection was accepted (it has to log in until the authentication deadline).
+    accepted_at: Instant,
     login: Option<String>,
     user_id: Option<i64>,
+    connected_at: Option<String>,
+    display_name: Option<String>,
+    /// Session of the authenticated client (ID and its expiration).
+    session_id: Option<i64>,
+    session_expires_at: Option<String>,
+    last_typing: Option<Instant>,
+    is_moderator: bool,
+    /// Number of failed log-ins by this connection.
+    failed_logins: u32,
+    /// Span of the connection (peer address and login) covering every log record about it.
+    span: Span,
 }
 
 
@@ -43,55 +108,137 @@ struct MessageRecord {
     message: Message,
     login: String,
     user_id: i64,
+    is_moderator: bool,
+    span: Span,
+}
+
+
+/// `LoginRecord` is a log-in request ([Message::Login] or [Message::Resume]) of a client
+/// waiting for authentication.
+struct LoginRecord {
+    address: SocketAddr,
+    message: Message,
+    span: Span,
+}
+
+
+/// `LoginOutcome` tells what is left to be done with a client after its log-in request.
+enum LoginOutcome {
+    /// The user has just come online, [Message::UserJoined] is to be broadcast.
+    Joined(Box<MessageRecord>),
+    /// Nothing (the client is authenticated, or it may try to log in again).
+    Done,
+    /// The connection is to be closed.
+    Close,
 }
 
 
 /// `start_server` is entrypoint of server. It starts main processing loop in a separate thread
 /// while main thread keep8s track on managing new client connections.
-pub async fn start_server(
-        address: &str,
-        db_url: &str,
-        web_port: u16,
+pub async fn start_server(config: Config) -> Result<(), ServerError> {
+    start_server_with_plugins(config, vec![]).await
+}
+
+
+/// `start_server_with_plugins` starts server (see [start_server]) with the given plugins
+/// registered after the built-in plugins enabled by configuration. Hooks of plugins are called
+/// in order of registration.
+pub async fn start_server_with_plugins(
+        config: Config,
+        plugins: Vec<Arc<dyn Plugin>>,
 ) -> Result<(), ServerError> {
+    let config = Arc::new(config);
     let mut join_set = JoinSet::new();
 
     let client_map: ClientMap = HashMap::new();
     let clients: Clients = Arc::new(Mutex::new(client_map));
 
-    let pool = match SqlitePool::connect(db_url).await {
-        Ok(pool) => pool,
-        Err(err) => Err(ServerError::DBError(err.to_string()))?,
-    };
+    let storage = connect_storage(&config.db_url).await?;
+    let applied = storage.migrate(config.auto_migrate).await?;
+    if applied > 0 {
+        tracing::info!(applied, "applied DB schema migrations");
+    }
+
+    let mut all_plugins = Plugins::builtin(&config.plugins.enabled);
+    all_plugins.extend(plugins);
+    let (mut plugins, bot_messages) = Plugins::new(all_plugins, &config.plugins.bot_login);
+    if !plugins.is_empty() {
+        // Nobody can log in as the bot (it is locked and its login is refused).
+        let bot_user = DbUser {
+            id: 0,
+            login: config.plugins.bot_login.clone(),
+            password: LOCKED_PASSWORD.to_string(),
+            role: "user".to_string(),
+            display_name: None,
+        };
+        let (bot_user_id, _) = storage.import_user(&bot_user).await?;
+        plugins.start(bot_user_id).await;
+        tracing::info!(bot_login = %plugins.bot_login(), "plugins started");
+    }
 
     let finish_flag = Arc::new(atomic::AtomicBool::new(false));
+    let federation = Federation::new(config.federation.server_name.clone());
 
     // chat task
     let task_clients = clients.clone();
     let task_ok = finish_flag.clone();
-    let task_pool = pool.clone();
+    let task_storage = storage.clone();
+    let task_config = config.clone();
+    let task_federation = federation.clone();
     join_set.spawn(async move {
-        chat(task_clients, task_ok, &task_pool).await
+        let storage = task_storage.as_ref();
+        let federation = task_federation.as_ref();
+        chat(task_clients, task_ok, storage, &task_config, &plugins, bot_messages, federation).await
     });
 
-    // server task
-    let task_address = address.to_string();
-    let task_clients = clients.clone();
-    let task_finish_flag = finish_flag.clone();
-    join_set.spawn(async move {
-        listen_and_accept(task_address, task_clients, task_finish_flag).await
-    });
+    // server task (one per listener)
+    for address in config.listeners.iter() {
+        let task_address = address.clone();
+        let task_clients = clients.clone();
+        let task_finish_flag = finish_flag.clone();
+        let task_connections = config.connections.clone();
+        join_set.spawn(async move {
+            listen_and_accept(task_address, task_clients, task_finish_flag, task_connections).await
+        });
+    }
+
+    // federation tasks (listener and links to other servers)
+    if config.federation.is_enabled() {
+        federation::spawn_links(&mut join_set, &config.federation, federation, clients.clone());
+    }
+
+    // pruning task (only if there is anything to be pruned)
+    if config.retention.is_enabled() {
+        let task_storage = storage.clone();
+        let task_retention = config.retention.clone();
+        join_set.spawn(async move {
+            retention::prune_periodically(task_storage, task_retention).await
+        });
+    }
+
+    // backup task (only if backups are scheduled)
+    if config.backup.is_scheduled() {
+        let task_storage = storage.clone();
+        let task_backup = config.backup.clone();
+        join_set.spawn(async move {
+            backup::backup_periodically(task_storage, task_backup).await
+        });
+    }
 
     // web task
-    let task_pool = pool.clone();
-    join_set.spawn(async move {
-        web::start_web_server(web_port, task_pool).await
-    });
+    if config.features.web {
+        let task_storage = storage.clone();
+        let task_config = config.clone();
+        join_set.spawn(async move {
+            web::start_web_server(&task_config, task_storage).await
+        });
+    }
 
     while let Some(result) = join_set.join_next().await {
         match result {
             Ok(Ok(_)) => {},
-            Ok(Err(err)) => eprint!("server error: {}", err.to_string()),
-            Err(err) => eprint!("join error: {}", err.to_string()),
+            Ok(Err(err)) => tracing::error!(error = %err, "server task failed"),
+            Err(err) => tracing::error!(error = %err, "server task panicked"),
         }
     };
 
@@ -99,35 +246,136 @@ pub async fn start_server(
 }
 
 
-/// `listen_and_accept` take care of connection of new client connections.
+/// `migrate_database` applies all pending schema migrations to the configured DB regardless
+/// of `auto_migrate` option and returns number of applied migrations (it is meant for deploy
+/// pipelines running the server with `--migrate-only`).
+pub async fn migrate_database(config: &Config) -> Result<usize, ServerError> {
+    let storage = connect_storage(&config.db_url).await?;
+    storage.migrate(true).await
+}
+
+
+/// `set_moderator` grants (or revokes) moderator role to the user with the given login in the
+/// configured DB and returns `false` if there is no such user. Connected clients of the user get
+/// the role at their next log-in.
+pub async fn set_moderator(
+        config: &Config,
+        login: &str,
+        moderator: bool,
+) -> Result<bool, ServerError> {
+    let storage = connect_storage(&config.db_url).await?;
+    storage.migrate(config.auto_migrate).await?;
+    let role = if moderator { ROLE_MODERATOR } else { ROLE_USER };
+    storage.update_role(login, role).await
+}
+
+
+/// `backup_database` creates a backup of the configured DB in the backup directory (it is
+/// consistent even if the server is running meanwhile) and returns its path.
+pub async fn backup_database(config: &Config) -> Result<std::path::PathBuf, ServerError> {
+    let storage = connect_storage(&config.db_url).await?;
+    backup::create_backup(storage.as_ref(), &config.backup, SystemTime::now()).await
+}
+
+
+/// `restore_database` replaces the configured DB by the given backup once the backup is verified
+/// (the server must not be running). Returns path of the restored DB file.
+pub async fn restore_database(
+        config: &Config,
+        backup_path: &std::path::Path,
+) -> Result<std::path::PathBuf, ServerError> {
+    backup::restore_backup(backup_path, &config.db_url).await
+}
+
+
+/// `export_chat_history` exports chat history of the configured DB selected by the filter
+/// to the given file (`-` for standard output).
+pub async fn export_chat_history(
+        config: &Config,
+        path: &std::path::Path,
+        format: HistoryFormat,
+        filter: &HistoryFilter,
+) -> Result<ExportSummary, ServerError> {
+    let storage = connect_storage(&config.db_url).await?;
+    storage.migrate(config.auto_migrate).await?;
+    let mut writer = history::create_writer(path)?;
+    history::export_history(storage.as_ref(), filter, format, &mut writer).await
+}
+
+
+/// `import_chat_history` imports JSON Lines history archive (or gzipped archive of pruned rows)
+/// from the given file (`-` for standard input) into the configured DB.
+pub async fn import_chat_history(
+        config: &Config,
+        path: &std::path::Path,
+) -> Result<ImportSummary, ServerError> {
+    let storage = connect_storage(&config.db_url).await?;
+    storage.migrate(config.auto_migrate).await?;
+    let mut reader = history::open_reader(path)?;
+    history::import_history(storage.as_ref(), &mut reader).await
+}
+
+
+/// `listen_and_accept` take care of connection of new client connections. Connections exceeding
+/// limits of the configuration are refused with [Message::Error] explaining the reason.
 async fn listen_and_accept(
         address: String,
         clients: Clients,
         finish_flag: Arc<atomic::AtomicBool>,
+        connections: ConnectionsConfig,
 ) -> Result<(), ServerError> {
-    let listener = match TcpListener::bind(address).await {
+    let listener = match TcpListener::bind(&address).await {
         Ok(listener) => listener,
         Err(err) => Err(ServerError::PortBindError(err.to_string()))?,
     };
+    tracing::info!(%address, "listening for clients");
 
     loop {
-        let (stream, address) = match listener.accept().await {
+        let (mut stream, address) = match listener.accept().await {
             Ok((stream, address)) => (stream, address),
             Err(err) => Err(ServerError::ClientConnectionError(err.to_string()))?,
         };
 
         // Detection of error from the other thread.s
         if finish_flag.load(Relaxed) {
-            eprintln!("SERVER THREAD: got finish signal");
+            tracing::info!(%address, "got finish signal, no more clients are accepted");
             break
         };
 
+        // The lock is kept until the client is inserted, so concurrent listeners cannot exceed
+        // the limits together.
+        let mut client_map = clients.lock().await;
+        let connected = client_map.keys().map(|connected| connected.ip());
+        if let Err(refusal) = connections::check_limits(&connections, connected, address.ip()) {
+            drop(client_map);
+            tracing::warn!(%address, limit = refusal.limit, "connection refused");
+            REFUSED_CONNECTION_COUNTER.with_label_values(&[refusal.limit]).inc();
+            let _ = Message::Error(refusal.reason).send(&mut stream).await;
+            continue
+        }
+
+        let span = tracing::info_span!(
+            "connection",
+            peer = %address,
+            login = tracing::field::Empty,
+        );
+        span.in_scope(|| tracing::info!("client connected"));
+
         let client_record = ClientRecord{
             stream: stream,
+            accepted_at: Instant::now(),
             login: None,
             user_id: None,
+            connected_at: None,
+            display_name: None,
+            session_id: None,
+            session_expires_at: None,
+            last_typing: None,
+            is_moderator: false,
+            failed_logins: 0,
+            span,
         };
-        clients.lock().await.insert(address, client_record);
+        client_map.insert(address, client_record);
         CURRENT_CLIENT_COUNT_GAUGE.inc();
     }
 
@@ -145,9 +393,24 @@ async fn chat(
         // Map of clients shared across threads.
         clients: Clients,
         finish_flag: Arc<atomic::AtomicBool>,
-        pool: &SqlitePool,
+        storage: &dyn Storage,
+        config: &Config,
+        plugins: &Plugins,
+        mut bot_messages: UnboundedReceiver<String>,
+        federation: &Federation,
 )  -> Result<(), ServerError> {
+    let commands = CommandRegistry::builtin();
+    let typing_throttle = Duration::from_secs(config.limits.typing_throttle_secs);
+    let session_check_interval = Duration::from_secs(config.sessions.check_interval_secs);
+    let mut last_session_check = Instant::now();
+    let mut login_guard = LoginGuard::new(&config.lockout);
+    let mut content_filter = ContentFilter::load(&config.filters)?;
+    let filter_reload_interval = Duration::from_secs(config.filters.reload_interval_secs);
+    let mut last_filter_reload = Instant::now();
+    let auth_deadline = Duration::from_secs(config.connections.auth_deadline_secs);
+
     let mut message_queue: Vec<MessageRecord> = vec![];
+    let mut login_queue: Vec<LoginRecord> = vec![];
     let mut close_queue: Vec<SocketAddr> = vec![];
 
     loop {
@@ -157,6 +420,7 @@ async fn chat(
         }
 
         message_queue.clear();
+        login_queue.clear();
         close_queue.clear();
 
         {
@@ -164,39 +428,60 @@ async fn chat(
 
             // Receiving messages from clients and storing them into `message_queue`.
             for (address, client_record) in client_map.iter_mut() {
-                let message = Message::receive(&mut client_record.stream).await;
-                match message {
-                    Ok(Some(Message::Login {login, pass})) => {
-                        // Searching for login & password in the DB as a part of authorization.
-                        match fetch_user_by_login_and_password(pool, &login, &pass).await {
-                            Ok(Some(user)) => {
-                                let welcome_message = format!("Welcome to x-chat {}!", login);
-
-                                client_record.login = Some(login);
-                                client_record.user_id = Some(user.id);
-
-                                let timestamp = timestamp_to_string(SystemTime::now());
-                                if let Err(err) = insert_login(pool, user.id, &timestamp).await {
-                                    eprintln!("saving login entry failed: {}", err.to_string());
-                                };
+                let span = client_record.span.clone();
 
-                                let response = Message::Welcome {
-                                    motd: welcome_message,
-                                };
+                // Connections that did not log in in time are not polled any more.
+                let deadline_exceeded = client_record.accepted_at.elapsed() >= auth_deadline;
+                if client_record.login.is_none() && deadline_exceeded {
+                    span.in_scope(|| tracing::info!("authentication deadline exceeded"));
+                    AUTH_DEADLINE_COUNTER.inc();
+                    let error = Message::Error("authentication deadline exceeded".to_string());
+                    let _ = error.send(&mut client_record.stream).await;
+                    close_queue.push(*address);
+                    continue
+                }
 
-                                if let Err(err) = response.send(&mut client_record.stream).await {
-                                    eprintln!("failed to send welcome message: {}", err.to_string());
-                                };
+                let message = Message::receive(&mut client_record.stream).await;
+                match message {
+                    // Log-ins are processed once the map of clients is unlocked (they query
+                    // the DB).
+                    Ok(Some(message @ (Message::Login {..} | Message::Resume {..}))) => {
+                        login_queue.push(LoginRecord { address: *address, message, span });
+                    },
+                    Ok(Some(Message::Typing {to})) => {
+                        let (Some(login), Some(user_id)) =
+                            (&client_record.login, client_record.user_id) else { continue };
+                        if !config.features.typing {
+                            continue
+                        }
 
-                                SUCCESSFUL_CONNECTION_COUNTER.inc();
-                            },
-                            Ok(None) => {
-                                // no response -> client is not authorized in timeout
-                                NOT_AUTHORIZED_CONNECTION_COUNTER.inc();
+                        // Typing notifications are throttled to avoid flooding other clients.
+                        let now = Instant::now();
+                        if let Some(last_typing) = client_record.last_typing {
+                            if now.duration_since(last_typing) < typing_throttle {
                                 continue
-                            },
-                            Err(err) => Err(err)?,
-                        };
+                            }
+                        }
+                        client_record.last_typing = Some(now);
+
+                        message_queue.push(MessageRecord{
+                            user_id,
+                            login: login.clone(),
+                            message: Message::UserTyping { login: login.clone(), to },
+                            address: *address,
+                            is_moderator: client_record.is_moderator,
+                            span: client_record.span.clone(),
+                        });
+                    },
+                    // Messages of the server (e.g. chat messages of other users) are never
+                    // forwarded when sent by a client.
+                    Ok(Some(message)) if !is_client_request(&message) => {
+                        span.in_scope(|| tracing::warn!(
+                            kind = message_kind(&message),
+                            "rejecting unexpected message",
+                        ));
+                        let error = Message::Error("unexpected message".to_string());
+                        let _ = error.send(&mut client_record.stream).await;
                     },
                     Ok(Some(message)) => {
                         if let Some(login) = &client_record.login {
@@ -206,9 +491,15 @@ async fn chat(
                                     login: login.clone(),
                                     message: message,
                                     address: address.clone(),
+                                    is_moderator: client_record.is_moderator,
+                                    span: client_record.span.clone(),
                                 };
                                 message_queue.push(message_record);
                             }
+                        } else {
+                            span.in_scope(|| tracing::debug!(
+                                "ignoring message of not authenticated client",
+                            ));
                         }
                     }
                     Ok(None) =>
@@ -218,42 +509,189 @@ async fn chat(
                         Some(err) if err.kind() == ErrorKind::UnexpectedEof =>
                             close_queue.push(address.clone()),
 
-                        Some(err) => eprintln!(
-                            "I/O error: {}; kind: {}",
-                            err.to_string(),
-                            err.kind()
-                        ),
+                        Some(err) => span.in_scope(|| tracing::warn!(
+                            error = %err,
+                            kind = %err.kind(),
+                            "I/O error while receiving message",
+                        )),
 
-                        None => eprintln!("not I/O error"),
+                        None => span.in_scope(|| tracing::warn!(
+                            error = %err,
+                            "failed to receive message",
+                        )),
                     }
                 }
             }
         }
 
-        // Broadcasting messages stored in `message_queue`.
+        // Authentication of clients stored in `login_queue` (newly joined users are announced).
+        for login_record in login_queue.drain(..) {
+            let span = login_record.span.clone();
+            let address = login_record.address;
+            let outcome = log_in(
+                &clients,
+                storage,
+                config,
+                plugins,
+                federation,
+                &mut login_guard,
+                login_record,
+            ).instrument(span).await?;
+            match outcome {
+                LoginOutcome::Joined(message_record) => message_queue.push(*message_record),
+                LoginOutcome::Done => {},
+                LoginOutcome::Close => close_queue.push(address),
+            }
+        }
+
+        // Broadcasting messages stored in `message_queue` (requests are answered to the sender).
         if !message_queue.is_empty() {
-            for message_record in message_queue.drain(..) {
-                match send_to_everyone_else(&clients, message_record, &pool).await {
-                    Ok(_) => {},
-                    Err(err) => eprintln!("sending failed: {}", err.to_string()),
+            for mut message_record in message_queue.drain(..) {
+                let span = message_record.span.clone();
+                let kind = message_kind(&message_record.message);
+
+                if let Some(feature) = disabled_feature(&message_record.message, config) {
+                    span.in_scope(|| tracing::debug!(kind, feature, "rejecting disabled feature"));
+                    let error = Message::Error(format!("{} is disabled on this server", feature));
+                    if let Err(err) = send_to(&clients, &message_record.address, &error).await {
+                        span.in_scope(|| tracing::warn!(error = %err, "sending failed"));
+                    }
+                    continue
                 }
+
+                // Filtering of texts before they are stored or sent to anybody.
+                if let Some(text) = filters::filtered_text(&mut message_record.message) {
+                    let outcome = content_filter.apply(text);
+                    filters::record_hits(storage, message_record.user_id, &outcome.hits)
+                        .instrument(span.clone())
+                        .await;
+                    if let Some(reason) = outcome.rejection {
+                        let error = Message::Error(reason);
+                        if let Err(err) = send_to(&clients, &message_record.address, &error).await {
+                            span.in_scope(|| tracing::warn!(error = %err, "sending failed"));
+                        }
+                        continue
+                    }
+                }
+
+                span.in_scope(|| tracing::debug!(kind, "routing message"));
+                let result = async {
+                    match message_record.message {
+                        Message::UserTyping {..} =>
+                            send_typing_notification(&clients, &message_record).await,
+                        Message::Who =>
+                            send_online_users(&clients, &message_record.address, federation).await,
+                        Message::Search {..} =>
+                            send_search_results(&clients, message_record, storage, config).await,
+                        Message::File {..} | Message::Image(_) =>
+                            share_attachment(&clients, message_record, storage, config).await,
+                        Message::Download {..} =>
+                            send_original(&clients, message_record, storage).await,
+                        Message::UnreadMentions =>
+                            send_unread_mentions(&clients, message_record, storage, config).await,
+                        Message::Edit {..} | Message::Delete {..} =>
+                            modify_chat_message(&clients, message_record, storage).await,
+                        Message::AddReaction {..} | Message::RemoveReaction {..} =>
+                            react_to_chat_message(&clients, message_record, storage).await,
+                        Message::Text(ref text) if commands::is_command(text) => {
+                            let text = text.clone();
+                            commands
+                                .execute(
+                                    &clients,
+                                    storage,
+                                    plugins,
+                                    federation,
+                                    &message_record,
+                                    &text,
+                                )
+                                .await
+                        },
+                        _ => send_to_everyone_else(
+                            &clients,
+                            message_record,
+                            storage,
+                            config,
+                            plugins,
+                            federation,
+                        ).await,
+                    }
+                }.instrument(span.clone()).await;
+                if let Err(err) = result {
+                    span.in_scope(|| tracing::warn!(kind, error = %err, "processing message failed"));
+                }
+            }
+        }
+
+        // Broadcasting messages posted by plugins.
+        while let Ok(text) = bot_messages.try_recv() {
+            if let Err(err) = post_bot_message(&clients, storage, config, plugins, text).await {
+                tracing::warn!(error = %err, "posting bot message failed");
+            }
+        }
+
+        // Delivering events of linked servers.
+        while let Some(inbound) = federation.try_recv() {
+            let delivery = federation::deliver(&clients, storage, config, federation, inbound);
+            if let Err(err) = delivery.await {
+                tracing::warn!(error = %err, "delivering federated event failed");
+            }
+        }
+
+        // Hot reload of content filter rules changed on disk.
+        if last_filter_reload.elapsed() >= filter_reload_interval {
+            last_filter_reload = Instant::now();
+            content_filter.reload_if_changed();
+        }
+
+        // Disconnection of clients of revoked and expired sessions.
+        if last_session_check.elapsed() >= session_check_interval {
+            last_session_check = Instant::now();
+            match close_ended_sessions(&clients, storage).await {
+                Ok(ended) => close_queue.extend(ended),
+                Err(err) => tracing::warn!(error = %err, "checking sessions failed"),
             }
         }
 
         // Removal of disconnected clients (writing also login/address for better debugging).
         if !close_queue.is_empty() {
             for address in close_queue.iter() {
-                let mut clients = clients.lock().await;
-
-                if let Some(client_record) = clients.get(address) {
-                    let unknown_name = "unknown".to_string();
-                    println!(
-                        "Disconnected client {}/{}",
-                        client_record.login.as_ref().unwrap_or(&unknown_name),
-                        address.to_string(),
-                    );
+                let removed = clients.lock().await.remove(address);
+
+                if let Some(client_record) = removed {
+                    client_record.span.in_scope(|| tracing::info!("client disconnected"));
                     CURRENT_CLIENT_COUNT_GAUGE.dec();
-                    clients.remove(address);
+
+                    if let Some(login) = &client_record.login {
+                        plugins.on_disconnect(login).instrument(client_record.span.clone()).await;
+                    }
+
+                    // A user still logged in by another client has not left yet.
+                    let still_online = client_record.user_id.is_some() && clients
+                        .lock()
+                        .await
+                        .values()
+                        .any(|other| other.user_id == client_record.user_id);
+                    if still_online {
+                        continue
+                    }
+
+                    if let Some(login) = &client_record.login {
+                        federation.publish(FederatedKind::Left {
+                            login: login.clone(),
+                            timestamp: timestamp_to_string(SystemTime::now()),
+                        });
+                    }
+
+                    // Only authenticated users were announced, so only they are announced leaving.
+                    if let (Some(login), true) = (client_record.login, config.features.presence) {
+                        let message = Message::UserLeft {
+                            login,
+                            timestamp: timestamp_to_string(SystemTime::now()),
+                        };
+                        if let Err(err) = broadcast(&clients, &message, None).await {
+                            tracing::warn!(error = %err, "broadcasting departure failed");
+                        }
+                    }
                 }
             }
         }
@@ -262,36 +700,277 @@ async fn chat(
 }
 
 
+/// `disabled_feature` returns name of the feature required by the given client request if it is
+/// disabled in configuration.
+fn disabled_feature(message: &Message, config: &Config) -> Option<&'static str> {
+    let features = &config.features;
+    match message {
+        Message::Who if !features.presence => Some("presence"),
+        Message::Search {..} if !features.search => Some("search"),
+        Message::UnreadMentions if !features.mentions => Some("mentions"),
+        Message::AddReaction {..} | Message::RemoveReaction {..} if !features.reactions =>
+            Some("reactions"),
+        _ => None,
+    }
+}
+
+
+/// `is_client_request` tells whether the given message may be sent by clients
+/// (client -> server messages).
+fn is_client_request(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::Login {..}
+            | Message::Resume {..}
+            | Message::Text(_)
+            | Message::Image(_)
+            | Message::File {..}
+            | Message::Download {..}
+            | Message::Who
+            | Message::Typing {..}
+            | Message::Reply {..}
+            | Message::Edit {..}
+            | Message::Delete {..}
+            | Message::AddReaction {..}
+            | Message::RemoveReaction {..}
+            | Message::UnreadMentions
+            | Message::Search {..}
+    )
+}
+
+
+/// `message_kind` returns short name of the message type used in log records.
+fn message_kind(message: &Message) -> &'static str {
+    match message {
+        Message::Login {..} => "login",
+        Message::Resume {..} => "resume",
+        Message::Welcome {..} => "welcome",
+        Message::Session {..} => "session",
+        Message::Text(_) => "text",
+        Message::Image(_) => "image",
+        Message::File {..} => "file",
+        Message::Attachment(_) => "attachment",
+        Message::Download {..} => "download",
+        Message::Who => "who",
+        Message::OnlineUsers {..} => "online_users",
+        Message::UserJoined {..} => "user_joined",
+        Message::UserLeft {..} => "user_left",
+        Message::Typing {..} => "typing",
+        Message::UserTyping {..} => "user_typing",
+        Message::Chat {..} => "chat",
+        Message::Action {..} => "action",
+        Message::Direct {..} => "direct",
+        Message::QueuedDirects {..} => "queued_directs",
+        Message::NickChanged {..} => "nick_changed",
+        Message::Topic {..} => "topic",
+        Message::Reply {..} => "reply",
+        Message::Edit {..} => "edit",
+        Message::Delete {..} => "delete",
+        Message::Edited {..} => "edited",
+        Message::Deleted {..} => "deleted",
+        Message::AddReaction {..} => "add_reaction",
+        Message::RemoveReaction {..} => "remove_reaction",
+        Message::ReactionAdded {..} => "reaction_added",
+        Message::ReactionRemoved {..} => "reaction_removed",
+        Message::Mentioned(_) => "mentioned",
+        Message::UnreadMentions => "unread_mentions",
+        Message::MentionList {..} => "mention_list",
+        Message::Search {..} => "search",
+        Message::SearchResults {..} => "search_results",
+        Message::LinkHello {..} => "link_hello",
+        Message::LinkWelcome {..} => "link_welcome",
+        Message::LinkPing => "link_ping",
+        Message::Federated(_) => "federated",
+        Message::Info(_) => "info",
+        Message::Error(_) => "error",
+    }
+}
+
+
 /// `send_to_everyone_else` process sending of message to every client other to the message sender.
+///
+/// Text messages (including replies) pass plugins, they are stored into DB and they are sent as
+/// [Message::Chat] to every client including the sender, so the sender knows ID of its message.
 async fn send_to_everyone_else(
         clients: &Clients,
         mut message_record: MessageRecord,
-        pool: &SqlitePool,
+        storage: &dyn Storage,
+        config: &Config,
+        plugins: &Plugins,
+        federation: &Federation,
 ) -> Result<(), ServerError> {
-    if let Message::Text(text) = &mut message_record.message {
-        // Saving a row into DB.
-        let timestamp = timestamp_to_string(SystemTime::now());
-        let result = insert_chat_message(
-            pool,
-            message_record.user_id,
-            &timestamp,
-            &text,
-        ).await;
-        if let Err(err) = result {
-            eprintln!("saving chat message entry failed: {}", err.to_string());
-        };
+    let (text, reply_to) = match &mut message_record.message {
+        Message::Text(text) => {
+            commands::unescape(text);
+            (text, None)
+        },
+        Message::Reply {reply_to, text} => (text, Some(*reply_to)),
+        message => return broadcast(clients, message, Some(&message_record.address)).await,
+    };
+
+    let author = plugin::Author {
+        login: message_record.login.clone(),
+        user_id: message_record.user_id,
+        is_moderator: message_record.is_moderator,
+    };
+    if let HookVerdict::Reject(reason) = plugins.on_message(&author, text).await {
+        return send_to(clients, &message_record.address, &Message::Error(reason)).await;
+    }
+
+    // Replies quote an excerpt of their (existing) parent message.
+    let quote = match reply_to {
+        None => None,
+        Some(parent_id) => match storage.fetch_chat_message_by_id(parent_id).await? {
+            Some(parent) if parent.deleted_at.is_none() => Some(Quote {
+                id: parent.id,
+                login: parent.login,
+                excerpt: excerpt(&parent.text, config.limits.quote_excerpt_length),
+            }),
+            _ => {
+                let error = Message::Error(format!("message {} does not exist", parent_id));
+                return send_to(clients, &message_record.address, &error).await;
+            },
+        },
+    };
+
+    // Saving a row into DB (its ID is needed by clients to reference the message later).
+    let timestamp = timestamp_to_string(SystemTime::now());
+    let result = storage.insert_chat_message(
+        message_record.user_id,
+        &timestamp,
+        text,
+        reply_to,
+    ).await;
+    let id = match result {
+        Ok(id) => id,
+        Err(err) => {
+            let error = Message::Error("failed to save the message".to_string());
+            send_to(clients, &message_record.address, &error).await?;
+            Err(err)?
+        },
+    };
+
+    let display_name = commands::display_name_of(clients, &message_record.address).await;
+    federation.publish(FederatedKind::Chat {
+        login: message_record.login.clone(),
+        display_name: display_name.clone(),
+        timestamp: timestamp.clone(),
+        text: text.clone(),
+    });
+    let message = Message::Chat {
+        id,
+        login: message_record.login.clone(),
+        display_name,
+        timestamp: timestamp.clone(),
+        text: text.clone(),
+        reply_to: quote,
+    };
+
+    MESSAGE_COUNTER.inc();
 
-        message_record.message = Message::Text(format!("{}: {}", message_record.login, text));
+    broadcast(clients, &message, None).await?;
 
-        MESSAGE_COUNTER.inc();
+    if !config.features.mentions {
+        return Ok(());
     }
+    let mentioned = MentionedMessage {
+        id,
+        user_id: message_record.user_id,
+        login: &message_record.login,
+        timestamp: &timestamp,
+        text,
+    };
+    mentions::notify_mentions(clients, storage, config, mentioned).await
+}
+
+
+/// `post_bot_message` stores a message posted by a plugin as message of the bot user and
+/// sends it as [Message::Chat] to every client.
+async fn post_bot_message(
+        clients: &Clients,
+        storage: &dyn Storage,
+        config: &Config,
+        plugins: &Plugins,
+        text: String,
+) -> Result<(), ServerError> {
+    let Some(bot_user_id) = plugins.bot_user_id() else {
+        return Ok(());
+    };
+
+    let timestamp = timestamp_to_string(SystemTime::now());
+    let id = storage.insert_chat_message(bot_user_id, &timestamp, &text, None).await?;
+    let message = Message::Chat {
+        id,
+        login: plugins.bot_login().to_string(),
+        display_name: None,
+        timestamp: timestamp.clone(),
+        text: text.clone(),
+        reply_to: None,
+    };
+
+    MESSAGE_COUNTER.inc();
+
+    broadcast(clients, &message, None).await?;
+
+    if !config.features.mentions {
+        return Ok(());
+    }
+    let mentioned = MentionedMessage {
+        id,
+        user_id: bot_user_id,
+        login: plugins.bot_login(),
+        timestamp: &timestamp,
+        text: &text,
+    };
+    mentions::notify_mentions(clients, storage, config, mentioned).await
+}
+
+
+/// `excerpt` shortens the given text to at most `max_chars` characters (plus ellipsis).
+fn excerpt(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}…", &text[..index]),
+        None => text.to_string(),
+    }
+}
+
+
+/// `broadcast` sends the given message to every authenticated client except the optional `skip`
+/// one (clients that have not logged in yet get nothing).
+async fn broadcast(
+        clients: &Clients,
+        message: &Message,
+        skip: Option<&SocketAddr>,
+) -> Result<(), ServerError> {
+    for (address, client_record) in clients.lock().await.iter_mut() {
+        if Some(address) == skip || client_record.login.is_none() {
+            continue
+        }
 
+        if let Err(err) = message.send(&mut client_record.stream).await {
+            Err(ServerError::ForwardMessageError{
+                address: address.to_string(),
+                detail: err.to_string(),
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// `send_to_user` sends the given message to every client of the user with the given login.
+async fn send_to_user(
+        clients: &Clients,
+        login: &str,
+        message: &Message,
+) -> Result<(), ServerError> {
     for (address, client_record) in clients.lock().await.iter_mut() {
-        if address == &message_record.address {
+        if client_record.login.as_deref() != Some(login) {
             continue
         }
 
-        if let Err(err) = message_record.message.send(&mut client_record.stream).await {
+        if let Err(err) = message.send(&mut client_record.stream).await {
             Err(ServerError::ForwardMessageError{
                 address: address.to_string(),
                 detail: err.to_string(),
@@ -301,3 +980,732 @@ async fn send_to_everyone_else(
 
     Ok(())
 }
+
+
+/// `send_typing_notification` sends [Message::UserTyping] to everyone else in the room or, if
+/// a direct message is being composed, just to the clients of its (online) recipient.
+async fn send_typing_notification(
+        clients: &Clients,
+        message_record: &MessageRecord,
+) -> Result<(), ServerError> {
+    let Message::UserTyping {login, to: Some(to)} = &message_record.message else {
+        return broadcast(clients, &message_record.message, Some(&message_record.address)).await;
+    };
+
+    // The recipient is looked up like by `/msg`, typing to offline users is not announced.
+    let recipient = clients
+        .lock()
+        .await
+        .values()
+        .filter_map(|client_record| client_record.login.clone())
+        .find(|online| online.eq_ignore_ascii_case(to));
+    let Some(recipient) = recipient.filter(|recipient| recipient != login) else {
+        return Ok(());
+    };
+
+    let message = Message::UserTyping { login: login.clone(), to: Some(recipient.clone()) };
+    send_to_user(clients, &recipient, &message).await
+}
+
+
+/// `send_to` sends the given message just to the single client with the given `address`.
+/// Already disconnected client is silently skipped.
+async fn send_to(
+        clients: &Clients,
+        address: &SocketAddr,
+        message: &Message,
+) -> Result<(), ServerError> {
+    if let Some(client_record) = clients.lock().await.get_mut(address) {
+        if let Err(err) = message.send(&mut client_record.stream).await {
+            Err(ServerError::ForwardMessageError{
+                address: address.to_string(),
+                detail: err.to_string(),
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// `modify_chat_message` process [Message::Edit] and [Message::Delete] requests.
+///
+/// Only author of the message or a moderator is allowed to modify it. Successful modification
+/// is stored into DB and broadcast to all the clients, otherwise the sender gets
+/// [Message::Error] with explanation.
+async fn modify_chat_message(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+) -> Result<(), ServerError> {
+    let id = match &message_record.message {
+        Message::Edit {id, ..} | Message::Delete {id} => *id,
+        _ => return Ok(()),
+    };
+
+    let rejection = match storage.fetch_chat_message_author(id).await? {
+        None => Some(format!("message {} does not exist", id)),
+        Some(author) if author.deleted_at.is_some() =>
+            Some(format!("message {} was already deleted", id)),
+        Some(author) if author.user_id != message_record.user_id
+            && !message_record.is_moderator =>
+            Some(format!("message {} may be modified only by its author or a moderator", id)),
+        Some(_) => None,
+    };
+    if let Some(reason) = rejection {
+        return send_to(clients, &message_record.address, &Message::Error(reason)).await;
+    }
+
+    let timestamp = timestamp_to_string(SystemTime::now());
+    let notification = match message_record.message {
+        Message::Edit {id, text} => {
+            storage.update_chat_message_text(id, &text, &timestamp).await?;
+            Message::Edited { id, text, timestamp }
+        },
+        Message::Delete {id} => {
+            storage.tombstone_chat_message(id, &timestamp).await?;
+            Message::Deleted { id, timestamp }
+        },
+        _ => return Ok(()),
+    };
+
+    broadcast(clients, &notification, None).await
+}
+
+
+/// `react_to_chat_message` process [Message::AddReaction] and [Message::RemoveReaction]
+/// requests.
+///
+/// Accepted reaction change is stored into DB and broadcast to all the clients, otherwise
+/// the sender gets [Message::Error] with explanation.
+async fn react_to_chat_message(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+) -> Result<(), ServerError> {
+    let (id, emoji) = match &message_record.message {
+        Message::AddReaction {id, emoji} | Message::RemoveReaction {id, emoji} => (*id, emoji),
+        _ => return Ok(()),
+    };
+
+    let rejection = if !is_valid_reaction(emoji) {
+        Some(format!("invalid reaction: {}", emoji))
+    } else {
+        match storage.fetch_chat_message_author(id).await? {
+            None => Some(format!("message {} does not exist", id)),
+            Some(author) if author.deleted_at.is_some() =>
+                Some(format!("message {} was deleted", id)),
+            Some(_) => None,
+        }
+    };
+    if let Some(reason) = rejection {
+        return send_to(clients, &message_record.address, &Message::Error(reason)).await;
+    }
+
+    let login = message_record.login;
+    let user_id = message_record.user_id;
+    let (changed, notification) = match message_record.message {
+        Message::AddReaction {id, emoji} => {
+            let timestamp = timestamp_to_string(SystemTime::now());
+            let changed = storage.insert_reaction(id, user_id, &emoji, &timestamp).await?;
+            (changed, Message::ReactionAdded { id, login, emoji })
+        },
+        Message::RemoveReaction {id, emoji} => {
+            let changed = storage.delete_reaction(id, user_id, &emoji).await?;
+            (changed, Message::ReactionRemoved { id, login, emoji })
+        },
+        _ => return Ok(()),
+    };
+
+    // Repeated addition or removal of a non-existing reaction is not worth broadcasting.
+    if !changed {
+        return Ok(());
+    }
+
+    broadcast(clients, &notification, None).await
+}
+
+
+/// `send_search_results` answers [Message::Search] request by sending the requested page
+/// of full-text search results back to the sender.
+async fn send_search_results(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+        config: &Config,
+) -> Result<(), ServerError> {
+    let Message::Search {query, page} = message_record.message else {
+        return Ok(());
+    };
+    let page = page.max(1);
+
+    let result = storage.search_chat_messages(
+        &query,
+        ("**", "**"),
+        config.limits.search_page_size as i64,
+        (page as i64 - 1) * config.limits.search_page_size as i64,
+    ).await;
+    let (total, hits) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            let error = Message::Error("search failed".to_string());
+            send_to(clients, &message_record.address, &error).await?;
+            Err(err)?
+        },
+    };
+
+    let hits = hits
+        .into_iter()
+        .map(|hit| SearchHit {
+            id: hit.id,
+            login: hit.login,
+            timestamp: hit.timestamp,
+            snippet: hit.snippet,
+        })
+        .collect();
+
+    let response = Message::SearchResults { query, page, total, hits };
+    send_to(clients, &message_record.address, &response).await
+}
+
+
+/// `send_online_users` answers [Message::Who] request by sending the list of authenticated
+/// clients together with users of linked servers (sorted by login) back to the client with
+/// the given `address`.
+async fn send_online_users(
+        clients: &Clients,
+        address: &SocketAddr,
+        federation: &Federation,
+) -> Result<(), ServerError> {
+    let mut users = online_users(&*clients.lock().await);
+    users.extend(federation.remote_users());
+    users.sort_by(|a, b| a.login.cmp(&b.login));
+
+    send_to(clients, address, &Message::OnlineUsers { users }).await
+}
+
+
+/// `online_users` returns users of authenticated clients of the given map (a user logged in
+/// several times is online since the first log-in).
+fn online_users(client_map: &ClientMap) -> Vec<OnlineUser> {
+    let mut users: Vec<OnlineUser> = client_map
+        .values()
+        .filter_map(|client_record| match (&client_record.login, &client_record.connected_at) {
+            (Some(login), Some(connected_at)) => Some(OnlineUser {
+                login: login.clone(),
+                display_name: client_record.display_name.clone(),
+                connected_at: connected_at.clone(),
+            }),
+            _ => None,
+        })
+        .collect();
+    users.sort_by(|a, b| (&a.login, &a.connected_at).cmp(&(&b.login, &b.connected_at)));
+    users.dedup_by(|later, first| later.login == first.login);
+    users
+}
+
+
+/// `log_in` authenticates the client which sent the given log-in request and welcomes it
+/// (followed by direct messages queued for the user and by the topic of the room). The DB is
+/// queried while the map of clients is unlocked, so a slow query does not hold up other clients;
+/// the map is locked just to apply the results.
+async fn log_in(
+        clients: &Clients,
+        storage: &dyn Storage,
+        config: &Config,
+        plugins: &Plugins,
+        federation: &Federation,
+        login_guard: &mut LoginGuard,
+        login_record: LoginRecord,
+) -> Result<LoginOutcome, ServerError> {
+    let LoginRecord { address, message, span } = login_record;
+    let attempted_login = match &message {
+        Message::Login {login, ..} => Some(login.as_str()),
+        _ => None,
+    };
+
+    // Peer addresses and logins with too many failed log-ins are refused without checking their
+    // credentials.
+    let now = Instant::now();
+    if let Some(lockout) = login_guard.locked_for(address.ip(), attempted_login, now) {
+        tracing::warn!(
+            login = attempted_login.unwrap_or("(session token)"),
+            lockout_secs = lockout.as_secs(),
+            "log-in refused due to lockout",
+        );
+        LOCKED_OUT_LOGIN_COUNTER.inc();
+        record_failed_login(storage, attempted_login, &address, "locked out").await;
+        let text = format!("too many failed log-ins, try again in {} s", lockout.as_secs().max(1));
+        if let Some(client_record) = clients.lock().await.get_mut(&address) {
+            let _ = Message::Error(text).send(&mut client_record.stream).await;
+        }
+        return Ok(LoginOutcome::Close);
+    }
+
+    // Checking password (or session token) in the DB as a part of authorization.
+    let result = session::authenticate(storage, &config.sessions, &message, SystemTime::now())
+        .await?;
+    let (user, session) = match result {
+        Some((user, session)) if user.login != plugins.bot_login() => (user, session),
+        _ => {
+            let login = attempted_login.unwrap_or("(session token)");
+            tracing::warn!(%login, "authentication failed");
+            NOT_AUTHORIZED_CONNECTION_COUNTER.inc();
+            login_guard.record_failure(address.ip(), attempted_login, now);
+            record_failed_login(storage, attempted_login, &address, "invalid credentials").await;
+
+            // The connection may not be used for guessing passwords forever.
+            let mut client_map = clients.lock().await;
+            let Some(client_record) = client_map.get_mut(&address) else {
+                return Ok(LoginOutcome::Done);
+            };
+            client_record.failed_logins += 1;
+            if client_record.failed_logins < config.lockout.max_attempts_per_connection {
+                return Ok(LoginOutcome::Done);
+            }
+            let message = Message::Error("too many failed log-ins".into());
+            let _ = message.send(&mut client_record.stream).await;
+            return Ok(LoginOutcome::Close);
+        },
+    };
+    let login = user.login.clone();
+    login_guard.record_success(&login);
+
+    // Everything needed from the DB is fetched before the client is welcomed.
+    let timestamp = timestamp_to_string(SystemTime::now());
+    let peer = address.to_string();
+    if let Err(err) = storage.insert_login(user.id, &timestamp, Some(&peer)).await {
+        tracing::warn!(error = %err, "saving login entry failed");
+    }
+    let queued = match storage.fetch_undelivered_direct_messages(user.id).await {
+        Ok(queued) => queued,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to fetch queued direct messages");
+            vec![]
+        },
+    };
+    let topic = match storage.fetch_topic().await {
+        Ok(topic) => topic,
+        Err(err) => {
+            tracing::warn!(error = %err, "failed to fetch topic");
+            None
+        },
+    };
+
+    let is_moderator = user.role == ROLE_MODERATOR;
+    let (already_online, delivered, delivery) = {
+        let mut client_map = clients.lock().await;
+
+        // Users logged in several times are announced just once.
+        let already_online = client_map
+            .values()
+            .any(|client_record| client_record.user_id == Some(user.id));
+
+        // The client may have disconnected meanwhile (its direct messages stay queued).
+        let Some(client_record) = client_map.get_mut(&address) else {
+            return Ok(LoginOutcome::Done);
+        };
+        client_record.login = Some(login.clone());
+        client_record.user_id = Some(user.id);
+        client_record.is_moderator = is_moderator;
+        client_record.display_name = user.display_name.clone();
+        client_record.session_id = Some(session.id);
+        client_record.session_expires_at = Some(session.expires_at.clone());
+        client_record.connected_at = Some(timestamp.clone());
+        client_record.span.record("login", login.as_str());
+        tracing::info!(
+            user_id = user.id,
+            session_id = session.id,
+            moderator = is_moderator,
+            "client authenticated",
+        );
+
+        let stream = &mut client_record.stream;
+        let response = Message::Welcome {
+            motd: config.motd.replace("{login}", &login),
+        };
+        if let Err(err) = response.send(stream).await {
+            tracing::warn!(error = %err, "failed to send welcome message");
+        };
+        if let Err(err) = session.message(&login).send(stream).await {
+            tracing::warn!(error = %err, "failed to send session");
+        };
+
+        // Direct messages sent while the user was offline follow.
+        let (delivered, delivery) = send_direct_messages(stream, &address, queued).await;
+
+        // The current topic of the room follows the welcome message.
+        if let Some(topic) = topic {
+            let message = Message::Topic {
+                topic: topic.topic,
+                login: topic.login,
+                timestamp: topic.timestamp,
+            };
+            if let Err(err) = message.send(stream).await {
+                tracing::warn!(error = %err, "failed to send topic");
+            };
+        }
+
+        (already_online, delivered, delivery)
+    };
+
+    // Messages not sent (due to a network error) stay queued for the next log-in.
+    if !delivered.is_empty() {
+        let delivered_at = timestamp_to_string(SystemTime::now());
+        let result = storage.mark_direct_messages_delivered(&delivered, &delivered_at).await;
+        match result.and(delivery) {
+            Ok(()) => tracing::info!(count = delivered.len(), "delivered queued direct messages"),
+            Err(err) => tracing::warn!(error = %err, "failed to deliver queued direct messages"),
+        }
+    } else if let Err(err) = delivery {
+        tracing::warn!(error = %err, "failed to deliver queued direct messages");
+    }
+
+    SUCCESSFUL_CONNECTION_COUNTER.inc();
+    plugins.on_login(&login).await;
+
+    if already_online {
+        return Ok(LoginOutcome::Done);
+    }
+    federation.publish(FederatedKind::Joined {
+        user: OnlineUser {
+            login: login.clone(),
+            display_name: user.display_name,
+            connected_at: timestamp.clone(),
+        },
+    });
+
+    // Let everyone else know about the newly joined user.
+    if !config.features.presence {
+        return Ok(LoginOutcome::Done);
+    }
+    Ok(LoginOutcome::Joined(Box::new(MessageRecord {
+        user_id: user.id,
+        login: login.clone(),
+        message: Message::UserJoined { login, timestamp },
+        address,
+        is_moderator,
+        span,
+    })))
+}
+
+
+/// `send_direct_messages` sends the given direct messages queued for a just authenticated user,
+/// preceded by their count. Returns IDs of the sent messages together with the error which
+/// stopped sending (if any).
+async fn send_direct_messages(
+        stream: &mut TcpStream,
+        address: &SocketAddr,
+        queued: Vec<DbDirectMessage>,
+) -> (Vec<i64>, Result<(), ServerError>) {
+    let forward_error = |detail: String| ServerError::ForwardMessageError {
+        address: address.to_string(),
+        detail,
+    };
+
+    let mut delivered = vec![];
+    if queued.is_empty() {
+        return (delivered, Ok(()));
+    }
+
+    let count = Message::QueuedDirects { count: queued.len() as u32 };
+    if let Err(err) = count.send(stream).await {
+        return (delivered, Err(forward_error(err.to_string())));
+    }
+    for direct_message in queued {
+        let message = Message::Direct {
+            login: direct_message.login,
+            display_name: direct_message.display_name,
+            to: direct_message.to,
+            timestamp: direct_message.timestamp,
+            text: direct_message.text,
+        };
+        if let Err(err) = message.send(stream).await {
+            return (delivered, Err(forward_error(err.to_string())));
+        }
+        delivered.push(direct_message.id);
+    }
+
+    (delivered, Ok(()))
+}
+
+
+/// `record_failed_login` stores a failed log-in attempt into the audit trail (a failure
+/// to store it is just logged).
+async fn record_failed_login(
+        storage: &dyn Storage,
+        login: Option<&str>,
+        address: &SocketAddr,
+        reason: &str,
+) {
+    let timestamp = timestamp_to_string(SystemTime::now());
+    let result = storage.insert_failed_login(&timestamp, login, &address.to_string(), reason).await;
+    if let Err(err) = result {
+        tracing::warn!(error = %err, "saving failed login entry failed");
+    }
+}
+
+
+/// `close_ended_sessions` tells clients of revoked and expired sessions why their session ended
+/// and returns their addresses to be disconnected.
+async fn close_ended_sessions(
+        clients: &Clients,
+        storage: &dyn Storage,
+) -> Result<Vec<SocketAddr>, ServerError> {
+    let now = timestamp_to_string(SystemTime::now());
+    let active: HashSet<i64> = storage
+        .fetch_active_sessions(None, &now)
+        .await?
+        .into_iter()
+        .map(|session| session.id)
+        .collect();
+
+    let mut ended = vec![];
+    for (address, client_record) in clients.lock().await.iter_mut() {
+        let (Some(session_id), Some(expires_at)) =
+            (client_record.session_id, &client_record.session_expires_at) else { continue };
+        if active.contains(&session_id) {
+            continue
+        }
+
+        let reason = session::end_reason(expires_at, &now);
+        client_record.span.in_scope(|| tracing::info!(session_id, reason, "session ended"));
+        // The client is disconnected anyway, so failure to explain it is not important.
+        let _ = Message::Error(reason.to_string()).send(&mut client_record.stream).await;
+        ended.push(*address);
+    }
+
+    Ok(ended)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, atomic};
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Instant;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::Mutex;
+    use tokio::time::Duration;
+
+    use super::{chat, ClientRecord, Clients};
+    use crate::config::Config;
+    use crate::federation::Federation;
+    use crate::plugin::Plugins;
+    use crate::storage::Storage;
+    use crate::storage_memory::MemoryStorage;
+    use shared::{Attachment, Message};
+
+
+    /// `connect` opens a connection to the listener, it returns stream of the client and record
+    /// of the connection logged in as the given user.
+    async fn connect(
+            listener: &TcpListener,
+            login: &str,
+            user_id: i64,
+    ) -> (TcpStream, SocketAddr, ClientRecord) {
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server_stream, address) = listener.accept().await.unwrap();
+        let client_record = ClientRecord {
+            stream: server_stream,
+            accepted_at: Instant::now(),
+            login: Some(login.to_string()),
+            user_id: Some(user_id),
+            connected_at: None,
+            display_name: None,
+            session_id: None,
+            session_expires_at: None,
+            last_typing: None,
+            is_moderator: false,
+            failed_logins: 0,
+            span: tracing::Span::none(),
+        };
+        (stream, address, client_record)
+    }
+
+    #[tokio::test]
+    async fn test_server_messages_of_clients_are_not_forwarded() {
+        let storage = MemoryStorage::new();
+        let alice_id = storage.insert_user("alice", "", "user");
+        let bob_id = storage.insert_user("bob", "", "user");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut alice, alice_address, alice_record) = connect(&listener, "alice", alice_id).await;
+        let (mut bob, bob_address, bob_record) = connect(&listener, "bob", bob_id).await;
+        let clients: Clients = Arc::new(Mutex::new(HashMap::from([
+            (alice_address, alice_record),
+            (bob_address, bob_record),
+        ])));
+
+        let finish_flag = Arc::new(atomic::AtomicBool::new(false));
+        let config = Config::default();
+        let (plugins, bot_messages) = Plugins::new(vec![], "xbot");
+        let federation = Federation::new(None);
+        let server = chat(
+            clients,
+            finish_flag.clone(),
+            &storage,
+            &config,
+            &plugins,
+            bot_messages,
+            &federation,
+        );
+
+        let timeout = Duration::from_secs(2);
+        let client = async {
+            let forged = Message::Chat {
+                id: 1,
+                login: "bob".to_string(),
+                display_name: None,
+                timestamp: "2023-12-01 10:00:00".to_string(),
+                text: "forged".to_string(),
+                reply_to: None,
+            };
+            forged.send(&mut alice).await.unwrap();
+            let reply = Message::receive_with_timeout(&mut alice, timeout).await.unwrap();
+            assert_eq!(reply, Some(Message::Error("unexpected message".to_string())));
+
+            let forged = Message::Attachment(Attachment {
+                id: 1,
+                login: "bob".to_string(),
+                timestamp: "2023-12-01 10:00:00".to_string(),
+                filename: Some("../../.bashrc".to_string()),
+                mime: "text/plain".to_string(),
+                size: 4,
+                payload: b"rm\n\n".to_vec(),
+                thumbnail: None,
+            });
+            forged.send(&mut alice).await.unwrap();
+            let reply = Message::receive_with_timeout(&mut alice, timeout).await.unwrap();
+            assert_eq!(reply, Some(Message::Error("unexpected message".to_string())));
+
+            // The first message bob gets is the genuine one sent after the forged one.
+            Message::Text("genuine".to_string()).send(&mut alice).await.unwrap();
+            match Message::receive_with_timeout(&mut bob, timeout).await.unwrap() {
+                Some(Message::Chat {login, text, ..}) =>
+                    assert_eq!((login.as_str(), text.as_str()), ("alice", "genuine")),
+                message => panic!("unexpected message {:?}", message),
+            }
+            finish_flag.store(true, Relaxed);
+        };
+
+        let (result, ()) = tokio::join!(server, client);
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_typing_of_direct_message_goes_only_to_recipient() {
+        let storage = MemoryStorage::new();
+        let alice_id = storage.insert_user("alice", "", "user");
+        let bob_id = storage.insert_user("bob", "", "user");
+        let carol_id = storage.insert_user("carol", "", "user");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut alice, alice_address, alice_record) = connect(&listener, "alice", alice_id).await;
+        let (mut bob, bob_address, bob_record) = connect(&listener, "bob", bob_id).await;
+        let (mut carol, carol_address, carol_record) = connect(&listener, "carol", carol_id).await;
+        let clients: Clients = Arc::new(Mutex::new(HashMap::from([
+            (alice_address, alice_record),
+            (bob_address, bob_record),
+            (carol_address, carol_record),
+        ])));
+
+        let finish_flag = Arc::new(atomic::AtomicBool::new(false));
+        let config = Config::default();
+        let (plugins, bot_messages) = Plugins::new(vec![], "xbot");
+        let federation = Federation::new(None);
+        let server = chat(
+            clients,
+            finish_flag.clone(),
+            &storage,
+            &config,
+            &plugins,
+            bot_messages,
+            &federation,
+        );
+
+        let timeout = Duration::from_secs(2);
+        let client = async {
+            Message::Typing { to: Some("Bob".to_string()) }.send(&mut alice).await.unwrap();
+            let notification = Message::receive_with_timeout(&mut bob, timeout).await.unwrap();
+            let expected = Message::UserTyping {
+                login: "alice".to_string(),
+                to: Some("bob".to_string()),
+            };
+            assert_eq!(notification, Some(expected));
+
+            // Typing to the room reaches everyone else (carol gets it first, the direct message
+            // was not announced to carol).
+            Message::Typing { to: None }.send(&mut bob).await.unwrap();
+            for stream in [&mut alice, &mut carol] {
+                let notification = Message::receive_with_timeout(stream, timeout).await.unwrap();
+                let expected = Message::UserTyping { login: "bob".to_string(), to: None };
+                assert_eq!(notification, Some(expected));
+            }
+            finish_flag.store(true, Relaxed);
+        };
+
+        let (result, ()) = tokio::join!(server, client);
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_log_in_delivers_queued_direct_messages() {
+        let storage = MemoryStorage::new();
+        let alice_id = storage.insert_user("alice", "", "user");
+        let carol_id = storage.insert_user("carol", "secret", "user");
+        storage.insert_direct_message(alice_id, carol_id, "2023-12-01", "psst").await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (mut alice, alice_address, alice_record) = connect(&listener, "alice", alice_id).await;
+        let (mut carol, carol_address, mut carol_record) = connect(&listener, "", 0).await;
+        carol_record.login = None;
+        carol_record.user_id = None;
+        let clients: Clients = Arc::new(Mutex::new(HashMap::from([
+            (alice_address, alice_record),
+            (carol_address, carol_record),
+        ])));
+
+        let finish_flag = Arc::new(atomic::AtomicBool::new(false));
+        let config = Config::default();
+        let (plugins, bot_messages) = Plugins::new(vec![], "xbot");
+        let federation = Federation::new(None);
+        let server = chat(
+            clients,
+            finish_flag.clone(),
+            &storage,
+            &config,
+            &plugins,
+            bot_messages,
+            &federation,
+        );
+
+        let timeout = Duration::from_secs(2);
+        let client = async {
+            let login = Message::Login { login: "carol".to_string(), pass: "secret".to_string() };
+            login.send(&mut carol).await.unwrap();
+            let mut kinds = vec![];
+            for _ in 0..4 {
+                match Message::receive_with_timeout(&mut carol, timeout).await.unwrap() {
+                    Some(Message::Welcome {..}) => kinds.push("welcome"),
+                    Some(Message::Session {..}) => kinds.push("session"),
+                    Some(Message::QueuedDirects {count}) => assert_eq!(count, 1),
+                    Some(Message::Direct {login, text, ..}) =>
+                        assert_eq!((login.as_str(), text.as_str()), ("alice", "psst")),
+                    message => panic!("unexpected message {:?}", message),
+                }
+            }
+            assert_eq!(kinds, vec!["welcome", "session"]);
+
+            match Message::receive_with_timeout(&mut alice, timeout).await.unwrap() {
+                Some(Message::UserJoined {login, ..}) => assert_eq!(login, "carol"),
+                message => panic!("unexpected message {:?}", message),
+            }
+            finish_flag.store(true, Relaxed);
+        };
+
+        let (result, ()) = tokio::join!(server, client);
+        result.unwrap();
+        assert!(storage.fetch_undelivered_direct_messages(carol_id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::LockoutConfig;
+
+
+/// `Failures` tracks failed log-ins of a single peer address or login.
+struct Failures {
+    count: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+
+/// `LoginGuard` tracks failed log-ins per peer IP address and per login. After the configured
+/// number of failures the address (or login) is locked out, each further failure doubles
+/// the lockout (up to `max_secs`). Failures are kept just in memory.
+pub(crate) struct LoginGuard {
+    config: LockoutConfig,
+    addresses: HashMap<IpAddr, Failures>,
+    logins: HashMap<String, Failures>,
+}
+
+
+impl LoginGuard {
+    /// `new` creates a guard without any failures.
+    pub fn new(config: &LockoutConfig) -> LoginGuard {
+        LoginGuard {
+            config: config.clone(),
+            addresses: HashMap::new(),
+            logins: HashMap::new(),
+        }
+    }
+
+    /// `locked_for` returns remaining lockout of the peer address or of the login (the longer
+    /// one), `None` if neither of them is locked out at `now`.
+    pub fn locked_for(&self, ip: IpAddr, login: Option<&str>, now: Instant) -> Option<Duration> {
+        let address_lock = self.addresses.get(&ip).and_then(|failures| remaining(failures, now));
+        let login_lock = login
+            .and_then(|login| self.logins.get(login))
+            .and_then(|failures| remaining(failures, now));
+        address_lock.max(login_lock)
+    }
+
+    /// `record_failure` counts a failed log-in from the peer address (to the login) and locks
+    /// them out if they reached their threshold.
+    pub fn record_failure(&mut self, ip: IpAddr, login: Option<&str>, now: Instant) {
+        let forget_after = Duration::from_secs(self.config.max_secs);
+        let address_threshold = self.config.address_threshold;
+        let login_threshold = self.config.login_threshold;
+
+        record(&mut self.addresses, ip, address_threshold, &self.config, now);
+        forget(&mut self.addresses, forget_after, now);
+        if let Some(login) = login {
+            record(&mut self.logins, login.to_string(), login_threshold, &self.config, now);
+        }
+        forget(&mut self.logins, forget_after, now);
+    }
+
+    /// `record_success` forgets failed log-ins to the login (failures of the peer address are
+    /// kept, one valid account must not unlock guessing of passwords of other accounts).
+    pub fn record_success(&mut self, login: &str) {
+        self.logins.remove(login);
+    }
+}
+
+
+/// `remaining` returns the rest of the lockout at `now` (if any).
+fn remaining(failures: &Failures, now: Instant) -> Option<Duration> {
+    failures.locked_until
+        .filter(|locked_until| *locked_until > now)
+        .map(|locked_until| locked_until - now)
+}
+
+
+/// `record` counts a failure of the given key and sets its lockout once the count reaches
+/// `threshold` (`base_secs` doubled for each failure over the threshold, at most `max_secs`).
+fn record<K: Eq + Hash>(
+        failures: &mut HashMap<K, Failures>,
+        key: K,
+        threshold: u32,
+        config: &LockoutConfig,
+        now: Instant,
+) {
+    let failures = failures.entry(key).or_insert(Failures {
+        count: 0,
+        last_failure: now,
+        locked_until: None,
+    });
+    if now.duration_since(failures.last_failure) > Duration::from_secs(config.max_secs) {
+        failures.count = 0;
+    }
+    failures.count += 1;
+    failures.last_failure = now;
+
+    if failures.count >= threshold {
+        let exponent = (failures.count - threshold).min(31);
+        let secs = config.base_secs.saturating_mul(1 << exponent).min(config.max_secs);
+        failures.locked_until = Some(now + Duration::from_secs(secs));
+    }
+}
+
+
+/// `forget` removes failures older than `forget_after` (their lockouts are over already).
+fn forget<K>(failures: &mut HashMap<K, Failures>, forget_after: Duration, now: Instant) {
+    failures.retain(|_, failures| now.duration_since(failures.last_failure) <= forget_after);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use super::LoginGuard;
+    use crate::config::LockoutConfig;
+
+
+    fn config() -> LockoutConfig {
+        LockoutConfig {
+            address_threshold: 4,
+            login_threshold: 2,
+            base_secs: 10,
+            max_secs: 60,
+            ..LockoutConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let mut guard = LoginGuard::new(&config());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        guard.record_failure(ip, Some("alice"), now);
+        assert_eq!(guard.locked_for(ip, Some("alice"), now), None);
+        guard.record_failure(other_ip, Some("alice"), now);
+        assert_eq!(guard.locked_for(ip, Some("alice"), now), Some(Duration::from_secs(10)));
+        assert_eq!(guard.locked_for(ip, Some("bob"), now), None);
+        assert_eq!(guard.locked_for(ip, None, now), None);
+
+        // each further failure doubles the lockout (up to `max_secs`)
+        guard.record_failure(other_ip, Some("alice"), now);
+        assert_eq!(guard.locked_for(ip, Some("alice"), now), Some(Duration::from_secs(20)));
+        for _ in 0..5 {
+            guard.record_failure(other_ip, Some("alice"), now);
+        }
+        assert_eq!(guard.locked_for(ip, Some("alice"), now), Some(Duration::from_secs(60)));
+
+        let later = now + Duration::from_secs(60);
+        assert_eq!(guard.locked_for(ip, Some("alice"), later), None);
+
+        guard.record_success("alice");
+        guard.record_failure(ip, Some("alice"), later);
+        assert_eq!(guard.locked_for(ip, Some("alice"), later), None);
+    }
+
+    #[test]
+    fn test_address_lockout() {
+        let mut guard = LoginGuard::new(&config());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        for login in ["a", "b", "c", "d"] {
+            assert_eq!(guard.locked_for(ip, Some("e"), now), None);
+            guard.record_failure(ip, Some(login), now);
+        }
+        assert_eq!(guard.locked_for(ip, Some("e"), now), Some(Duration::from_secs(10)));
+        assert_eq!(guard.locked_for(ip, None, now), Some(Duration::from_secs(10)));
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+        assert_eq!(guard.locked_for(other_ip, Some("e"), now), None);
+
+        // a successful log-in does not unlock the address
+        guard.record_success("e");
+        assert_eq!(guard.locked_for(ip, None, now), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_failures_are_forgotten() {
+        let mut guard = LoginGuard::new(&config());
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        guard.record_failure(ip, Some("alice"), now);
+        let later = now + Duration::from_secs(61);
+        guard.record_failure(ip, Some("alice"), later);
+        assert_eq!(guard.locked_for(ip, Some("alice"), later), None);
+        assert_eq!(guard.addresses.len(), 1);
+
+        guard.record_failure(ip, None, later + Duration::from_secs(61));
+        assert!(guard.logins.is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LoggingConfig};
+use crate::error::ServerError;
+
+
+/// `init_logging` installs global `tracing` subscriber writing log records (filtered by
+/// the configured filter) in the configured format to the standard output.
+///
+/// JSON format includes fields of the current span and of all its parents, so each record
+/// of a client connection carries its peer address and login.
+pub fn init_logging(config: &LoggingConfig) -> Result<(), ServerError> {
+    let filter = match EnvFilter::try_new(&config.filter) {
+        Ok(filter) => filter,
+        Err(err) => Err(ServerError::ConfigError(format!("logging.filter: {}", err)))?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match config.format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+
+    result.map_err(|err| ServerError::ConfigError(format!("logging: {}", err)))
+}
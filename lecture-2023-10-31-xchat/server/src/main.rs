@@ -1,80 +1,353 @@
+use std::path::PathBuf;
 use std::process::exit;
 
-use server::start_server;
+use server::{
+    backup_database,
+    export_chat_history,
+    import_chat_history,
+    init_logging,
+    migrate_database,
+    restore_database,
+    set_moderator,
+    start_server,
+    CliOverrides,
+    Config,
+    HistoryFilter,
+    HistoryFormat,
+    LogFormat,
+};
+
+
+/// `Mode` is what the server binary is asked to do.
+enum Mode {
+    /// Run the chat server.
+    Serve,
+    /// Apply pending DB schema migrations and exit.
+    MigrateOnly,
+    /// Create a backup of the DB and exit.
+    Backup,
+    /// Replace the DB by the given backup and exit.
+    Restore { path: PathBuf },
+    /// Export chat history to the given file and exit.
+    Export { path: PathBuf, format: HistoryFormat, filter: HistoryFilter },
+    /// Import chat history from the given file and exit.
+    Import { path: PathBuf },
+    /// Grant (or revoke) moderator role to the given user and exit.
+    SetModerator { login: String, moderator: bool },
+}
 
 
 #[tokio::main]
 async fn main() {
-    let mut comm_hostname = "localhost".to_string();
-    let mut comm_port = 11111_u16;
-    let mut db_url = "sqlite:data.db".to_string();
-    let mut web_port = 8080_u16;
-
-    parse_arguments(&mut comm_hostname, &mut comm_port, &mut db_url, &mut web_port);
+    let (cli, mode) = parse_arguments();
 
-    let address = format!("{}:{}", comm_hostname, comm_port);
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
 
-    if let Err(err) = start_server(&address, &db_url, web_port).await {
+    if let Err(err) = init_logging(&config.logging) {
         eprintln!("{}", err);
+        exit(1);
+    }
+
+    match mode {
+        Mode::Serve => {},
+        Mode::MigrateOnly => {
+            match migrate_database(&config).await {
+                Ok(applied) => tracing::info!(applied, "DB schema is up to date"),
+                Err(err) => {
+                    tracing::error!(error = %err, "DB migration failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+        Mode::Backup => {
+            match backup_database(&config).await {
+                Ok(path) => tracing::info!(path = %path.display(), "database backed up"),
+                Err(err) => {
+                    tracing::error!(error = %err, "backup of database failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+        Mode::Restore { path } => {
+            match restore_database(&config, &path).await {
+                Ok(db_path) => tracing::info!(
+                    backup = %path.display(),
+                    path = %db_path.display(),
+                    "database restored from backup",
+                ),
+                Err(err) => {
+                    tracing::error!(error = %err, "restore of database failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+        Mode::Export { path, format, filter } => {
+            match export_chat_history(&config, &path, format, &filter).await {
+                Ok(summary) => tracing::info!(
+                    users = summary.users,
+                    chat_messages = summary.chat_messages,
+                    client_logins = summary.client_logins,
+                    "chat history exported",
+                ),
+                Err(err) => {
+                    tracing::error!(error = %err, "export of chat history failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+        Mode::Import { path } => {
+            match import_chat_history(&config, &path).await {
+                Ok(summary) => tracing::info!(
+                    users_created = summary.users_created,
+                    users_existing = summary.users_existing,
+                    chat_messages = summary.chat_messages,
+                    client_logins = summary.client_logins,
+                    duplicates = summary.duplicates,
+                    skipped = summary.skipped,
+                    "chat history imported",
+                ),
+                Err(err) => {
+                    tracing::error!(error = %err, "import of chat history failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+        Mode::SetModerator { login, moderator } => {
+            match set_moderator(&config, &login, moderator).await {
+                Ok(true) => tracing::info!(%login, moderator, "role of user changed"),
+                Ok(false) => {
+                    tracing::error!(%login, "user does not exist");
+                    exit(1);
+                }
+                Err(err) => {
+                    tracing::error!(error = %err, "change of role failed");
+                    exit(1);
+                }
+            }
+            return;
+        },
+    }
+
+    if let Err(err) = start_server(config).await {
+        tracing::error!(error = %err, "server failed");
+        exit(1);
     }
 }
 
 
 /// `parse_arguments` uses [argparse](https://crates.io/crates/argparse) crate to parse command-line
-/// options.
-fn parse_arguments(
-    comm_hostname: &mut String,
-    comm_port: &mut u16,
-    db_url: &mut String,
-    web_port: &mut u16,
-) {
-    use argparse::{ArgumentParser, Store};
-
-    let mut _comm_port = comm_port.to_string();
-    let mut _web_port = web_port.to_string();
+/// options. Only the given options override the configuration (see [Config::load]). The mode
+/// (`--migrate-only`, `--backup`, `--restore`, `--export`, `--import`, `--grant-moderator` or
+/// `--revoke-moderator`) is returned separately.
+fn parse_arguments() -> (CliOverrides, Mode) {
+    use argparse::{ArgumentParser, StoreOption, StoreTrue};
+
+    let mut cli = CliOverrides::default();
+    let mut migrate_only = false;
+    let mut backup_only = false;
+    let mut restore_path: Option<PathBuf> = None;
+    let mut _comm_port: Option<String> = None;
+    let mut _web_port: Option<String> = None;
+    let mut _log_format: Option<String> = None;
+    let mut export_path: Option<PathBuf> = None;
+    let mut _export_format: Option<String> = None;
+    let mut import_path: Option<PathBuf> = None;
+    let mut grant_moderator: Option<String> = None;
+    let mut revoke_moderator: Option<String> = None;
+    let mut filter = HistoryFilter::default();
 
     // Extra limited scope where argparse operates.
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Client for chat communication service.");
 
-        ap.refer(comm_hostname)
+        ap.refer(&mut cli.config_path)
+            .add_option(
+                &["-c", "--config"],
+                StoreOption,
+                "Path to TOML configuration file (e.g. `xchat.toml`).",
+            );
+
+        ap.refer(&mut cli.host)
             .add_option(
                 &["-h", "--host"],
-                Store,
+                StoreOption,
                 "Communication server hostname (e.g. `localhost`).",
             );
 
         ap.refer(&mut _comm_port)
             .add_option(
                 &["-p", "--port"],
-                Store,
+                StoreOption,
                 "Communication server port number (e.g. `11111`).",
             );
 
         ap.refer(&mut _web_port)
             .add_option(
                 &["--web-port"],
-                Store,
+                StoreOption,
                 "Web server port number (e.g. 8080).",
             );
 
-        ap.refer(db_url)
-            .add_option(&["--db-url"], Store, "DB URL (e.g. `sqlite:data.db`).");
+        ap.refer(&mut cli.db_url)
+            .add_option(&["--db-url"], StoreOption, "DB URL (e.g. `sqlite:data.db`).");
+
+        ap.refer(&mut cli.log_filter)
+            .add_option(
+                &["--log-filter"],
+                StoreOption,
+                "Log filter (e.g. `info,server=debug`).",
+            );
+
+        ap.refer(&mut _log_format)
+            .add_option(&["--log-format"], StoreOption, "Log format (`text` or `json`).");
+
+        ap.refer(&mut migrate_only)
+            .add_option(
+                &["--migrate-only"],
+                StoreTrue,
+                "Apply pending DB schema migrations and exit.",
+            );
+
+        ap.refer(&mut backup_only)
+            .add_option(
+                &["--backup"],
+                StoreTrue,
+                "Create a backup of the DB in the backup directory and exit.",
+            );
+
+        ap.refer(&mut restore_path)
+            .add_option(
+                &["--restore"],
+                StoreOption,
+                "Replace the DB by the given verified backup and exit (server must be stopped).",
+            );
+
+        ap.refer(&mut export_path)
+            .add_option(
+                &["--export"],
+                StoreOption,
+                "Export chat history to the given file (`-` for stdout) and exit.",
+            );
+
+        ap.refer(&mut _export_format)
+            .add_option(
+                &["--export-format"],
+                StoreOption,
+                "Format of exported history (`jsonl`, `csv` or `text`).",
+            );
+
+        ap.refer(&mut filter.since)
+            .add_option(
+                &["--since"],
+                StoreOption,
+                "Export history since the timestamp (e.g. `2024-01-01`).",
+            );
+
+        ap.refer(&mut filter.until)
+            .add_option(
+                &["--until"],
+                StoreOption,
+                "Export history until the timestamp (excluded, e.g. `2024-02-01`).",
+            );
+
+        ap.refer(&mut filter.login)
+            .add_option(&["--login"], StoreOption, "Export history of the given user only.");
+
+        ap.refer(&mut import_path)
+            .add_option(
+                &["--import"],
+                StoreOption,
+                "Import chat history from the given JSON Lines file (`-` for stdin) and exit.",
+            );
+
+        ap.refer(&mut grant_moderator)
+            .add_option(
+                &["--grant-moderator"],
+                StoreOption,
+                "Grant moderator role to the user with the given login and exit.",
+            );
+
+        ap.refer(&mut revoke_moderator)
+            .add_option(
+                &["--revoke-moderator"],
+                StoreOption,
+                "Revoke moderator role of the user with the given login and exit.",
+            );
 
         if let Err(error_code) = ap.parse_args() {
             exit(error_code);
         }
     }
 
-    _ensure_port_number(comm_port, &_comm_port, "comm_port");
-    _ensure_port_number(web_port, &_web_port, "web_port");
+    cli.port = _ensure_port_number(_comm_port, "comm_port");
+    cli.web_port = _ensure_port_number(_web_port, "web_port");
+    cli.log_format = _log_format.map(|source| match source.parse::<LogFormat>() {
+        Ok(log_format) => log_format,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    });
+
+    let format = match _export_format.map(|source| source.parse::<HistoryFormat>()) {
+        None => HistoryFormat::default(),
+        Some(Ok(format)) => format,
+        Some(Err(err)) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    let mut modes: Vec<Mode> = vec![];
+    if migrate_only {
+        modes.push(Mode::MigrateOnly);
+    }
+    if backup_only {
+        modes.push(Mode::Backup);
+    }
+    if let Some(path) = restore_path {
+        modes.push(Mode::Restore { path });
+    }
+    if let Some(path) = export_path {
+        modes.push(Mode::Export { path, format, filter });
+    }
+    if let Some(path) = import_path {
+        modes.push(Mode::Import { path });
+    }
+    if let Some(login) = grant_moderator {
+        modes.push(Mode::SetModerator { login, moderator: true });
+    }
+    if let Some(login) = revoke_moderator {
+        modes.push(Mode::SetModerator { login, moderator: false });
+    }
+    if modes.len() > 1 {
+        eprintln!(
+            "options --migrate-only, --backup, --restore, --export, --import, --grant-moderator \
+            and --revoke-moderator are mutually exclusive"
+        );
+        exit(1);
+    }
+
+    (cli, modes.pop().unwrap_or(Mode::Serve))
 }
 
 
-fn _ensure_port_number(target: &mut u16, source: &str, arg_name: &str) {
+fn _ensure_port_number(source: Option<String>, arg_name: &str) -> Option<u16> {
+    let source = source?;
     match source.parse::<u16>() {
-        Ok(port_number) => *target = port_number,
+        Ok(port_number) => Some(port_number),
         Err(_) => {
             eprintln!("failed to parse port number {}", arg_name);
             exit(1);
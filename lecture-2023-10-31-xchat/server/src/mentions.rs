@@ -0,0 +1,177 @@
+use std::time::SystemTime;
+
+use shared::{Mention, Message, parse_mentions, timestamp_to_string};
+use crate::config::Config;
+use crate::error::ServerError;
+use crate::storage::{DbUser, Storage};
+use crate::{excerpt, send_to, send_to_user, Clients, MessageRecord};
+
+
+/// `MentionedMessage` is a stored chat message which may mention users.
+pub(crate) struct MentionedMessage<'a> {
+    pub id: i64,
+    pub user_id: i64,
+    pub login: &'a str,
+    pub timestamp: &'a str,
+    pub text: &'a str,
+}
+
+
+/// `notify_mentions` stores mentions (`@login`) of users in the given chat message. Connected
+/// users get [Message::Mentioned] right away (so their mention is read), mentions of offline
+/// users stay unread until they ask for them.
+pub(crate) async fn notify_mentions(
+        clients: &Clients,
+        storage: &dyn Storage,
+        config: &Config,
+        message: MentionedMessage<'_>,
+) -> Result<(), ServerError> {
+    let mentioned = parse_mentions(message.text);
+    if mentioned.is_empty() {
+        return Ok(());
+    }
+
+    let users = storage.fetch_users().await?;
+    let notification = Message::Mentioned(Mention {
+        id: message.id,
+        login: message.login.to_string(),
+        timestamp: message.timestamp.to_string(),
+        excerpt: excerpt(message.text, config.limits.quote_excerpt_length),
+    });
+    for user in mentioned_users(&mentioned, &users, message.user_id) {
+        let online = clients
+            .lock()
+            .await
+            .values()
+            .any(|client_record| client_record.user_id == Some(user.id));
+
+        let read_at = online.then_some(message.timestamp);
+        storage.insert_mention(message.id, user.id, read_at).await?;
+        if online {
+            send_to_user(clients, &user.login, &notification).await?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// `send_unread_mentions` answers [Message::UnreadMentions] request by sending unread mentions
+/// of the sender back to them, the sent mentions become read.
+pub(crate) async fn send_unread_mentions(
+        clients: &Clients,
+        message_record: MessageRecord,
+        storage: &dyn Storage,
+        config: &Config,
+) -> Result<(), ServerError> {
+    let unread = storage.fetch_unread_mentions(message_record.user_id).await?;
+
+    let read_at = timestamp_to_string(SystemTime::now());
+    let ids: Vec<i64> = unread.iter().map(|mention| mention.id).collect();
+    storage.mark_mentions_read(&ids, &read_at).await?;
+
+    let mentions = unread
+        .into_iter()
+        .map(|mention| Mention {
+            id: mention.message_id,
+            login: mention.login,
+            timestamp: mention.timestamp,
+            excerpt: excerpt(&mention.text, config.limits.quote_excerpt_length),
+        })
+        .collect();
+    send_to(clients, &message_record.address, &Message::MentionList { mentions }).await
+}
+
+
+/// `mentioned_users` returns users mentioned by the given logins, a login matches exactly or
+/// (if no login matches exactly) case-insensitively. The author and users who cannot log in
+/// are never mentioned.
+fn mentioned_users<'a>(
+        mentioned: &[String],
+        users: &'a [DbUser],
+        author_id: i64,
+) -> Vec<&'a DbUser> {
+    let mut found: Vec<&DbUser> = vec![];
+    for login in mentioned {
+        let user = users
+            .iter()
+            .find(|user| &user.login == login)
+            .or_else(|| users.iter().find(|user| user.login.eq_ignore_ascii_case(login)));
+        let Some(user) = user else { continue };
+        let known = found.iter().any(|known| known.id == user.id);
+        if user.id != author_id && !user.is_locked() && !known {
+            found.push(user);
+        }
+    }
+    found
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::{mentioned_users, notify_mentions, MentionedMessage};
+    use crate::config::Config;
+    use crate::storage::{DbUser, Storage, LOCKED_PASSWORD};
+    use crate::storage_memory::MemoryStorage;
+
+
+    fn user(id: i64, login: &str, password: &str) -> DbUser {
+        DbUser {
+            id,
+            login: login.to_string(),
+            password: password.to_string(),
+            role: "user".to_string(),
+            display_name: None,
+        }
+    }
+
+    #[test]
+    fn test_mentioned_users() {
+        let users = vec![
+            user(1, "author", ""),
+            user(2, "Reader", ""),
+            user(3, "reader", ""),
+            user(4, "Other", ""),
+            user(5, "locked", LOCKED_PASSWORD),
+        ];
+        let mentioned: Vec<String> = ["reader", "author", "other", "OTHER", "locked", "nobody"]
+            .iter()
+            .map(|login| login.to_string())
+            .collect();
+
+        let ids: Vec<i64> = mentioned_users(&mentioned, &users, 1)
+            .iter()
+            .map(|user| user.id)
+            .collect();
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_notify_offline_users() {
+        let storage = MemoryStorage::new();
+        let author_id = storage.insert_user("author", "", "user");
+        let reader_id = storage.insert_user("reader", "", "user");
+        let text = "hi @Reader and @author";
+        let id = storage.insert_chat_message(author_id, "2023-12-01", text, None).await.unwrap();
+
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let message = MentionedMessage {
+            id,
+            user_id: author_id,
+            login: "author",
+            timestamp: "2023-12-01",
+            text,
+        };
+        notify_mentions(&clients, &storage, &Config::default(), message).await.unwrap();
+
+        let unread = storage.fetch_unread_mentions(reader_id).await.unwrap();
+        assert_eq!(unread.len(), 1);
+        assert_eq!((unread[0].message_id, unread[0].login.as_str()), (id, "author"));
+        assert!(storage.fetch_unread_mentions(author_id).await.unwrap().is_empty());
+    }
+}
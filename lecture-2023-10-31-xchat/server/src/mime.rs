@@ -0,0 +1,224 @@
+use crate::config::AttachmentsConfig;
+
+
+/// MIME type of content which is not recognized.
+pub(crate) const UNKNOWN_TYPE: &str = "application/octet-stream";
+
+
+/// Signatures (magic bytes at the given offset) of recognized content types, more specific
+/// signatures go first. Short signatures common in text (`BM`, `MZ`) are checked separately
+/// with more of the header (see [is_bmp] and [is_pe]).
+const SIGNATURES: [(usize, &[u8], &str); 20] = [
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (8, b"WEBP", "image/webp"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+    (0, b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (0, b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"#!", "text/x-shellscript"),
+];
+
+
+/// Content types implied by extensions of file names (checked besides the sniffed type, so
+/// e.g. a text file named `setup.exe` is refused as well).
+const EXTENSIONS: [(&str, &str); 14] = [
+    ("exe", "application/x-msdownload"),
+    ("dll", "application/x-msdownload"),
+    ("com", "application/x-msdownload"),
+    ("scr", "application/x-msdownload"),
+    ("msi", "application/x-msdownload"),
+    ("bat", "application/x-msdownload"),
+    ("cmd", "application/x-msdownload"),
+    ("ps1", "application/x-msdownload"),
+    ("vbs", "application/x-msdownload"),
+    ("elf", "application/x-executable"),
+    ("dylib", "application/x-mach-binary"),
+    ("jar", "application/java-archive"),
+    ("sh", "text/x-shellscript"),
+    ("bash", "text/x-shellscript"),
+];
+
+
+/// `sniff_type` returns MIME type of the content recognized by its magic bytes. Content without
+/// a known signature is `text/plain` if it is UTF-8 text, `application/octet-stream` otherwise.
+pub(crate) fn sniff_type(content: &[u8]) -> &'static str {
+    let signature = SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| content.get(*offset..offset + magic.len()) == Some(*magic));
+    if let Some((_, _, mime)) = signature {
+        // Java archives are ZIP archives with a manifest near the start.
+        if *mime == "application/zip" && contains(content, b"META-INF/MANIFEST.MF") {
+            return "application/java-archive";
+        }
+        return mime;
+    }
+    if is_bmp(content) {
+        return "image/bmp";
+    }
+    if is_pe(content) {
+        return "application/x-msdownload";
+    }
+
+    match std::str::from_utf8(content) {
+        Ok(text) if !text.contains('\0') => "text/plain",
+        _ => UNKNOWN_TYPE,
+    }
+}
+
+
+/// `is_bmp` tells whether the content starts with a BMP file header: `BM`, the file size
+/// (at least the size of the headers) and reserved zero bytes.
+fn is_bmp(content: &[u8]) -> bool {
+    let (Some(b"BM"), Some(size), Some(reserved)) = (
+        content.get(0..2),
+        read_u32(content, 2),
+        content.get(6..10),
+    ) else {
+        return false;
+    };
+    size >= 26 && reserved == [0; 4]
+}
+
+
+/// `is_pe` tells whether the content is a Windows executable: DOS header `MZ` with offset
+/// (`e_lfanew`) of the `PE\0\0` header.
+fn is_pe(content: &[u8]) -> bool {
+    if content.get(0..2) != Some(b"MZ") {
+        return false;
+    }
+    let Some(offset) = read_u32(content, 0x3c) else {
+        return false;
+    };
+    let offset = offset as usize;
+    content.get(offset..offset + 4) == Some(b"PE\0\0")
+}
+
+
+/// `read_u32` reads little-endian `u32` at the given offset of the content.
+fn read_u32(content: &[u8], offset: usize) -> Option<u32> {
+    let bytes = content.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+
+/// `type_by_extension` returns MIME type implied by extension of the file name (only types
+/// needing special care are known).
+pub(crate) fn type_by_extension(filename: &str) -> Option<&'static str> {
+    let (_, extension) = filename.rsplit_once('.')?;
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, mime)| *mime)
+}
+
+
+/// `is_allowed` tells whether attachments of the given type may be sent (see
+/// [AttachmentsConfig]).
+pub(crate) fn is_allowed(config: &AttachmentsConfig, mime: &str) -> bool {
+    let matching = |patterns: &Vec<String>| {
+        patterns.iter().any(|pattern| matches_pattern(mime, pattern))
+    };
+    (config.allowed_types.is_empty() || matching(&config.allowed_types))
+        && !matching(&config.denied_types)
+}
+
+
+/// `matches_pattern` tells whether the MIME type matches the pattern (`*`, `type/*` or
+/// `type/subtype`, case-insensitively).
+fn matches_pattern(mime: &str, pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_suffix("/*") {
+        Some(kind) => mime
+            .split_once('/')
+            .is_some_and(|(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind)),
+        None => mime.eq_ignore_ascii_case(pattern),
+    }
+}
+
+
+/// `contains` tells whether the content contains the given bytes.
+fn contains(content: &[u8], needle: &[u8]) -> bool {
+    content.windows(needle.len()).any(|window| window == needle)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{is_allowed, sniff_type, type_by_extension};
+    use crate::config::AttachmentsConfig;
+
+
+    #[test]
+    fn test_sniff_type() {
+        assert_eq!(sniff_type(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"), "image/png");
+        assert_eq!(sniff_type(b"\xff\xd8\xff\xe0\x00\x10JFIF"), "image/jpeg");
+        assert_eq!(sniff_type(b"RIFF\x24\x00\x00\x00WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_type(b"\x7fELF\x02\x01\x01"), "application/x-executable");
+        assert_eq!(sniff_type(b"#!/bin/sh\nrm -rf /\n"), "text/x-shellscript");
+        assert_eq!(sniff_type(b"PK\x03\x04....META-INF/MANIFEST.MF"), "application/java-archive");
+        assert_eq!(sniff_type(b"PK\x03\x04....notes.txt"), "application/zip");
+        assert_eq!(sniff_type("plain žluťoučký text".as_bytes()), "text/plain");
+        assert_eq!(sniff_type(b"\x00\x01\x02"), "application/octet-stream");
+        assert_eq!(sniff_type(b""), "text/plain");
+    }
+
+    #[test]
+    fn test_sniff_type_short_signatures() {
+        let mut pe = b"MZ\x90\x00".to_vec();
+        pe.resize(0x40, 0);
+        pe[0x3c] = 0x40;
+        pe.extend_from_slice(b"PE\0\0\x4c\x01");
+        assert_eq!(sniff_type(&pe), "application/x-msdownload");
+
+        let mut bmp = b"BM\x46\x00\x00\x00\x00\x00\x00\x00\x36\x00\x00\x00".to_vec();
+        bmp.resize(0x46, 0);
+        assert_eq!(sniff_type(&bmp), "image/bmp");
+
+        let notes = "MZ notes: the meeting is moved to Friday, bring the laptops.\n".repeat(2);
+        assert_eq!(sniff_type(notes.as_bytes()), "text/plain");
+        assert_eq!(sniff_type(b"BMW specifications and prices\n"), "text/plain");
+        assert_eq!(sniff_type(b"MZ"), "text/plain");
+    }
+
+    #[test]
+    fn test_type_by_extension() {
+        assert_eq!(type_by_extension("setup.EXE"), Some("application/x-msdownload"));
+        assert_eq!(type_by_extension("install.sh"), Some("text/x-shellscript"));
+        assert_eq!(type_by_extension("notes.txt"), None);
+        assert_eq!(type_by_extension("exe"), None);
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let config = AttachmentsConfig::default();
+        assert!(is_allowed(&config, "image/png"));
+        assert!(is_allowed(&config, "application/octet-stream"));
+        assert!(!is_allowed(&config, "application/x-executable"));
+
+        let config = AttachmentsConfig {
+            allowed_types: vec!["image/*".to_string(), "text/plain".to_string()],
+            denied_types: vec!["image/gif".to_string()],
+            ..AttachmentsConfig::default()
+        };
+        assert!(is_allowed(&config, "image/png"));
+        assert!(is_allowed(&config, "TEXT/PLAIN"));
+        assert!(!is_allowed(&config, "image/gif"));
+        assert!(!is_allowed(&config, "application/pdf"));
+        assert!(!is_allowed(&config, "imagex/png"));
+    }
+}
@@ -0,0 +1,361 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rand::Rng;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::Instrument;
+
+
+/// Names of plugins built into the server (they are enabled by `plugins.enabled`).
+pub const BUILTIN_PLUGINS: &[&str] = &["dice", "links"];
+
+
+/// `Author` describes the user whose message is passed to [Plugin::on_message].
+#[derive(Clone, Debug)]
+pub struct Author {
+    pub login: String,
+    pub user_id: i64,
+    pub is_moderator: bool,
+}
+
+
+/// `HookVerdict` tells what happens with a message after [Plugin::on_message].
+#[derive(Debug, PartialEq)]
+pub enum HookVerdict {
+    /// The message goes on (with changes of its text made by the hook).
+    Continue,
+    /// The message is dropped and its author gets the given reason as an error.
+    Reject(String),
+}
+
+
+/// `BotHandle` lets plugins post chat messages as the bot user. Messages are stored and
+/// broadcast by the chat loop in order (after the message being processed, if any).
+#[derive(Clone)]
+pub struct BotHandle {
+    login: String,
+    sender: UnboundedSender<String>,
+}
+
+
+impl BotHandle {
+    /// `login` returns login of the bot user.
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    /// `say` posts the given text to the chat as the bot user.
+    pub fn say(&self, text: impl Into<String>) {
+        if self.sender.send(text.into()).is_err() {
+            tracing::debug!("chat is not running, bot message dropped");
+        }
+    }
+}
+
+
+/// `Plugin` is a server-side extension (bot) called by the chat loop. All the hooks have
+/// no-op default implementations, so a plugin implements just the ones it needs.
+///
+/// Hooks are called one by one from the chat loop, so they should be quick; long-running work
+/// belongs to a task spawned by [Plugin::on_start].
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    /// `name` identifies the plugin in logs.
+    fn name(&self) -> &str;
+
+    /// `on_start` is called once before the server starts accepting clients. The handle might be
+    /// kept (e.g. by a spawned task posting notifications).
+    async fn on_start(&self, _bot: BotHandle) {}
+
+    /// `on_login` is called after a user is authenticated (and welcomed).
+    async fn on_login(&self, _bot: &BotHandle, _login: &str) {}
+
+    /// `on_message` is called for every chat message (or reply) before it is stored and
+    /// broadcast. The hook might change `text` or reject the message.
+    async fn on_message(
+        &self,
+        _bot: &BotHandle,
+        _author: &Author,
+        _text: &mut String,
+    ) -> HookVerdict {
+        HookVerdict::Continue
+    }
+
+    /// `on_disconnect` is called after an authenticated user disconnects.
+    async fn on_disconnect(&self, _bot: &BotHandle, _login: &str) {}
+}
+
+
+/// `Plugins` holds registered plugins in order of registration together with the bot user.
+pub(crate) struct Plugins {
+    plugins: Vec<Arc<dyn Plugin>>,
+    bot: BotHandle,
+    bot_user_id: Option<i64>,
+}
+
+
+impl Plugins {
+    /// `new` registers the given plugins. Returned receiver gets messages posted by the bot.
+    pub fn new(
+        plugins: Vec<Arc<dyn Plugin>>,
+        bot_login: &str,
+    ) -> (Plugins, UnboundedReceiver<String>) {
+        let (sender, receiver) = unbounded_channel();
+        let bot = BotHandle { login: bot_login.to_string(), sender };
+        (Plugins { plugins, bot, bot_user_id: None }, receiver)
+    }
+
+    /// `builtin` creates built-in plugins of the given names (unknown names are skipped, they
+    /// are refused by validation of configuration).
+    pub fn builtin(names: &[String]) -> Vec<Arc<dyn Plugin>> {
+        names
+            .iter()
+            .filter_map(|name| match name.as_str() {
+                "dice" => Some(Arc::new(DicePlugin) as Arc<dyn Plugin>),
+                "links" => Some(Arc::new(LinkLoggerPlugin) as Arc<dyn Plugin>),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn bot_login(&self) -> &str {
+        self.bot.login()
+    }
+
+    /// `bot_user_id` returns ID of the bot user (`None` until it is set by [Plugins::start]).
+    pub fn bot_user_id(&self) -> Option<i64> {
+        self.bot_user_id
+    }
+
+    /// `start` sets ID of the bot user and calls [Plugin::on_start] of all plugins.
+    pub async fn start(&mut self, bot_user_id: i64) {
+        self.bot_user_id = Some(bot_user_id);
+        for plugin in self.plugins.iter() {
+            plugin.on_start(self.bot.clone()).instrument(plugin_span(plugin)).await;
+        }
+    }
+
+    /// `on_login` calls [Plugin::on_login] of all plugins.
+    pub async fn on_login(&self, login: &str) {
+        for plugin in self.plugins.iter() {
+            plugin.on_login(&self.bot, login).instrument(plugin_span(plugin)).await;
+        }
+    }
+
+    /// `on_message` calls [Plugin::on_message] of plugins in order of registration until one
+    /// of them rejects the message (each of them gets the text changed by the previous ones).
+    pub async fn on_message(&self, author: &Author, text: &mut String) -> HookVerdict {
+        for plugin in self.plugins.iter() {
+            let span = plugin_span(plugin);
+            let verdict = plugin
+                .on_message(&self.bot, author, text)
+                .instrument(span.clone())
+                .await;
+            if let HookVerdict::Reject(reason) = &verdict {
+                span.in_scope(|| tracing::info!(%reason, "message rejected by plugin"));
+                return verdict;
+            }
+        }
+        HookVerdict::Continue
+    }
+
+    /// `on_disconnect` calls [Plugin::on_disconnect] of all plugins.
+    pub async fn on_disconnect(&self, login: &str) {
+        for plugin in self.plugins.iter() {
+            plugin.on_disconnect(&self.bot, login).instrument(plugin_span(plugin)).await;
+        }
+    }
+}
+
+
+fn plugin_span(plugin: &Arc<dyn Plugin>) -> tracing::Span {
+    tracing::debug_span!("plugin", name = plugin.name())
+}
+
+
+/// `DicePlugin` rolls dice for messages like `!roll` (a single six-sided die) or `!roll 3d20`.
+struct DicePlugin;
+
+
+/// Maximal number of dice rolled at once.
+const MAX_DICE: u32 = 20;
+
+/// Maximal number of sides of a die.
+const MAX_SIDES: u32 = 1000;
+
+
+#[async_trait]
+impl Plugin for DicePlugin {
+    fn name(&self) -> &str {
+        "dice"
+    }
+
+    async fn on_message(
+        &self,
+        bot: &BotHandle,
+        author: &Author,
+        text: &mut String,
+    ) -> HookVerdict {
+        let mut words = text.split_whitespace();
+        if words.next() != Some("!roll") {
+            return HookVerdict::Continue;
+        }
+        let spec: String = words.collect();
+        let Some((count, sides)) = parse_dice(&spec) else {
+            bot.say(format!(
+                "{}: use `!roll NdM` with up to {} dice of up to {} sides",
+                author.login,
+                MAX_DICE,
+                MAX_SIDES,
+            ));
+            return HookVerdict::Continue;
+        };
+
+        let rolls: Vec<u32> = {
+            let mut rng = rand::thread_rng();
+            (0..count).map(|_| rng.gen_range(1..=sides)).collect()
+        };
+        let total: u32 = rolls.iter().sum();
+        let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+        bot.say(format!(
+            "{} rolled {}d{}: {} = {}",
+            author.login,
+            count,
+            sides,
+            rolls.join(" + "),
+            total,
+        ));
+        HookVerdict::Continue
+    }
+}
+
+
+/// `parse_dice` parses dice specification `NdM` (empty one means `1d6`, `dM` means `1dM`).
+fn parse_dice(spec: &str) -> Option<(u32, u32)> {
+    let spec = spec.trim().to_lowercase();
+    if spec.is_empty() {
+        return Some((1, 6));
+    }
+    let (count, sides) = spec.split_once('d')?;
+    let count = if count.is_empty() { 1 } else { count.parse::<u32>().ok()? };
+    let sides = sides.parse::<u32>().ok()?;
+    ((1..=MAX_DICE).contains(&count) && (2..=MAX_SIDES).contains(&sides)).then_some((count, sides))
+}
+
+
+/// `LinkLoggerPlugin` logs every link posted to the chat (with target `xchat::links`).
+struct LinkLoggerPlugin;
+
+
+#[async_trait]
+impl Plugin for LinkLoggerPlugin {
+    fn name(&self) -> &str {
+        "links"
+    }
+
+    async fn on_message(
+        &self,
+        _bot: &BotHandle,
+        author: &Author,
+        text: &mut String,
+    ) -> HookVerdict {
+        for url in extract_links(text) {
+            tracing::info!(target: "xchat::links", login = %author.login, url, "link posted");
+        }
+        HookVerdict::Continue
+    }
+}
+
+
+/// `extract_links` returns `http(s)://` links of the given text (without trailing punctuation).
+fn extract_links(text: &str) -> Vec<&str> {
+    text.split_whitespace()
+        .map(|word| word.trim_start_matches(['(', '<', '"', '\'']))
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .map(|word| word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>', '"', '\'']))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use super::{extract_links, parse_dice, Author, BotHandle, HookVerdict, Plugin, Plugins};
+
+
+    struct Shouting;
+
+    #[async_trait]
+    impl Plugin for Shouting {
+        fn name(&self) -> &str {
+            "shouting"
+        }
+
+        async fn on_message(&self, _bot: &BotHandle, _: &Author, text: &mut String) -> HookVerdict {
+            *text = text.to_uppercase();
+            HookVerdict::Continue
+        }
+    }
+
+    struct Censor;
+
+    #[async_trait]
+    impl Plugin for Censor {
+        fn name(&self) -> &str {
+            "censor"
+        }
+
+        async fn on_message(&self, bot: &BotHandle, _: &Author, text: &mut String) -> HookVerdict {
+            if text.contains("SECRET") {
+                bot.say("no secrets here");
+                return HookVerdict::Reject("secrets are not allowed".to_string());
+            }
+            HookVerdict::Continue
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugins_on_message() {
+        let plugins: Vec<Arc<dyn Plugin>> = vec![Arc::new(Shouting), Arc::new(Censor)];
+        let (plugins, mut bot_messages) = Plugins::new(plugins, "xbot");
+        let author = Author { login: "user".to_string(), user_id: 1, is_moderator: false };
+
+        let mut text = "hello".to_string();
+        assert_eq!(plugins.on_message(&author, &mut text).await, HookVerdict::Continue);
+        assert_eq!(text, "HELLO");
+        assert!(bot_messages.try_recv().is_err());
+
+        // The second plugin sees text changed by the first one.
+        let mut text = "my secret".to_string();
+        let verdict = plugins.on_message(&author, &mut text).await;
+        assert_eq!(verdict, HookVerdict::Reject("secrets are not allowed".to_string()));
+        assert_eq!(bot_messages.try_recv().unwrap(), "no secrets here");
+    }
+
+    #[test]
+    fn test_parse_dice() {
+        assert_eq!(parse_dice(""), Some((1, 6)));
+        assert_eq!(parse_dice(" 3d20 "), Some((3, 20)));
+        assert_eq!(parse_dice("D8"), Some((1, 8)));
+        assert_eq!(parse_dice("0d6"), None);
+        assert_eq!(parse_dice("2d1"), None);
+        assert_eq!(parse_dice("100d6"), None);
+        assert_eq!(parse_dice("two dice"), None);
+    }
+
+    #[test]
+    fn test_extract_links() {
+        assert_eq!(
+            extract_links("see https://example.com/a?b=c, (http://x.org) and ftp://no"),
+            vec!["https://example.com/a?b=c", "http://x.org"],
+        );
+        assert!(extract_links("no links here").is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+use std::fs::{create_dir_all, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use shared::timestamp_to_string;
+use crate::config::RetentionConfig;
+use crate::error::ServerError;
+use crate::history::HistoryRecord;
+use crate::storage::{SharedStorage, Storage};
+use crate::web_prometheus::{PRUNED_CHAT_MESSAGE_COUNTER, PRUNED_CLIENT_LOGIN_COUNTER};
+
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+
+/// `PruneSummary` holds numbers of rows pruned by a single run of [prune].
+#[derive(Debug, Default, PartialEq)]
+pub struct PruneSummary {
+    pub chat_messages: u64,
+    pub client_logins: u64,
+    pub failed_logins: u64,
+}
+
+
+/// `prune_periodically` prunes chat history according to the retention policy every
+/// `interval_secs` (starting right away). Failed run is just logged and retried next time.
+pub async fn prune_periodically(
+        storage: SharedStorage,
+        retention: RetentionConfig,
+) -> Result<(), ServerError> {
+    let mut interval = tokio::time::interval(Duration::from_secs(retention.interval_secs));
+    loop {
+        interval.tick().await;
+
+        match prune(storage.as_ref(), &retention, SystemTime::now()).await {
+            Ok(summary) if summary != PruneSummary::default() => tracing::info!(
+                chat_messages = summary.chat_messages,
+                client_logins = summary.client_logins,
+                failed_logins = summary.failed_logins,
+                "pruned chat history",
+            ),
+            Ok(_) => {},
+            Err(err) => tracing::warn!(error = %err, "pruning of chat history failed"),
+        }
+    }
+}
+
+
+/// `prune` deletes chat messages and log-in records exceeding limits of the retention policy
+/// (relatively to `now`) in batches. If archive directory is configured, each batch is appended
+/// to the archive of the day first (and nothing is deleted if archiving fails).
+pub async fn prune(
+        storage: &dyn Storage,
+        retention: &RetentionConfig,
+        now: SystemTime,
+) -> Result<PruneSummary, ServerError> {
+    let limit = retention.batch_size as i64;
+    let archive_path = retention.archive_dir
+        .as_ref()
+        .map(|dir| dir.join(format!("pruned-{}.jsonl.gz", &timestamp_to_string(now)[..10])));
+    let mut summary = PruneSummary::default();
+
+    let older_than = cutoff(now, retention.message_max_age_days);
+    let keep_last = (retention.message_max_count > 0).then_some(retention.message_max_count as i64);
+    if older_than.is_some() || keep_last.is_some() {
+        loop {
+            let batch = storage
+                .fetch_prunable_chat_messages(older_than.as_deref(), keep_last, limit)
+                .await?;
+            if batch.is_empty() {
+                break
+            }
+
+            archive(archive_path.as_ref(), batch.iter().cloned().map(HistoryRecord::ChatMessage)).await?;
+            let ids: Vec<i64> = batch.iter().map(|message| message.id).collect();
+            let deleted = storage.delete_chat_messages(&ids).await?;
+            PRUNED_CHAT_MESSAGE_COUNTER.inc_by(deleted);
+            summary.chat_messages += deleted;
+
+            if (batch.len() as i64) < limit || deleted == 0 {
+                break
+            }
+        }
+    }
+
+    if let Some(older_than) = cutoff(now, retention.login_max_age_days) {
+        loop {
+            let batch = storage.fetch_prunable_logins(&older_than, limit).await?;
+            if batch.is_empty() {
+                break
+            }
+
+            archive(archive_path.as_ref(), batch.iter().cloned().map(HistoryRecord::ClientLogin)).await?;
+            let ids: Vec<i64> = batch.iter().map(|login| login.id).collect();
+            let deleted = storage.delete_logins(&ids).await?;
+            PRUNED_CLIENT_LOGIN_COUNTER.inc_by(deleted);
+            summary.client_logins += deleted;
+
+            if (batch.len() as i64) < limit || deleted == 0 {
+                break
+            }
+        }
+
+        // Failed log-in attempts are neither archived nor exported.
+        summary.failed_logins = storage.delete_failed_logins(&older_than).await?;
+    }
+
+    Ok(summary)
+}
+
+
+/// `cutoff` returns timestamp `days` before `now` (`None` for zero days, i.e. no limit).
+fn cutoff(now: SystemTime, days: u32) -> Option<String> {
+    if days == 0 {
+        return None;
+    }
+    let age = Duration::from_secs(days as u64 * SECONDS_PER_DAY);
+    Some(timestamp_to_string(now.checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH)))
+}
+
+
+/// `archive` appends the given records as JSON Lines to the gzipped archive file (if any).
+async fn archive(
+        path: Option<&PathBuf>,
+        records: impl Iterator<Item = HistoryRecord>,
+) -> Result<(), ServerError> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+
+    let mut lines = String::new();
+    for record in records {
+        match serde_json::to_string(&record) {
+            Ok(line) => lines.push_str(&line),
+            Err(err) => Err(ServerError::ArchiveError(err.to_string()))?,
+        }
+        lines.push('\n');
+    }
+
+    let path = path.clone();
+    match tokio::task::spawn_blocking(move || append_gzip(&path, lines.as_bytes())).await {
+        Ok(result) => result,
+        Err(err) => Err(ServerError::JoinError(err.to_string())),
+    }
+}
+
+
+/// `append_gzip` appends the given content as a new gzip member to the file. Readers of gzip
+/// (e.g. `zcat`) read concatenated members as a single stream.
+fn append_gzip(path: &Path, content: &[u8]) -> Result<(), ServerError> {
+    let archive_error = |err: std::io::Error| {
+        ServerError::ArchiveError(format!("{}: {}", path.display(), err))
+    };
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(archive_error)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(archive_error)?;
+
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    encoder.write_all(content).map_err(archive_error)?;
+    encoder.finish().and_then(|file| file.sync_all()).map_err(archive_error)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::{Duration, SystemTime};
+
+    use flate2::read::MultiGzDecoder;
+
+    use shared::timestamp_to_string;
+    use super::{prune, PruneSummary, SECONDS_PER_DAY};
+    use crate::config::RetentionConfig;
+    use crate::db_queries::SqliteStorage;
+    use crate::storage::{DbUser, Storage};
+    use crate::storage_memory::MemoryStorage;
+
+
+    fn days_ago(now: SystemTime, days: u64) -> String {
+        timestamp_to_string(now - Duration::from_secs(days * SECONDS_PER_DAY))
+    }
+
+    #[tokio::test]
+    async fn test_prune_by_age() {
+        let now = SystemTime::now();
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        for days in [40, 35, 31, 2, 1] {
+            storage.insert_chat_message(user_id, &days_ago(now, days), "hi", None).await.unwrap();
+            storage.insert_login(user_id, &days_ago(now, days), None).await.unwrap();
+            storage.insert_failed_login(&days_ago(now, days), None, "10.0.0.1:1234", "wrong")
+                .await
+                .unwrap();
+        }
+
+        let retention = RetentionConfig {
+            message_max_age_days: 30,
+            login_max_age_days: 10,
+            batch_size: 2,
+            ..RetentionConfig::default()
+        };
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary, PruneSummary { chat_messages: 3, client_logins: 3, failed_logins: 3 });
+
+        let messages = storage.fetch_chat_messages(&None).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(storage.fetch_prunable_logins(&days_ago(now, 0), 10).await.unwrap().len() == 2);
+
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary, PruneSummary::default());
+    }
+
+    #[tokio::test]
+    async fn test_prune_by_count() {
+        let now = SystemTime::now();
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        let mut ids = vec![];
+        for days in [5, 4, 3, 2, 1] {
+            let timestamp = days_ago(now, days);
+            ids.push(storage.insert_chat_message(user_id, &timestamp, "hi", None).await.unwrap());
+        }
+        let reply_id = storage
+            .insert_chat_message(user_id, &days_ago(now, 0), "re", Some(ids[0]))
+            .await
+            .unwrap();
+
+        let retention = RetentionConfig {
+            message_max_count: 2,
+            batch_size: 3,
+            ..RetentionConfig::default()
+        };
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary.chat_messages, 4);
+
+        let messages = storage.fetch_chat_messages(&None).await.unwrap();
+        let remaining: Vec<i64> = messages.iter().map(|message| message.id).collect();
+        assert_eq!(remaining, vec![reply_id, ids[4]]);
+        assert_eq!(messages[0].reply_to, None);
+    }
+
+    #[tokio::test]
+    async fn test_prune_sqlite() {
+        let now = SystemTime::now();
+        let storage = SqliteStorage::in_memory().await;
+        let user = DbUser {
+            id: 0,
+            login: "user".to_string(),
+            password: String::new(),
+            role: "user".to_string(),
+            display_name: None,
+        };
+        let (user_id, _) = storage.import_user(&user).await.unwrap();
+        let mut ids = vec![];
+        for days in [40, 35, 5, 4, 3, 2, 1] {
+            let timestamp = days_ago(now, days);
+            ids.push(storage.insert_chat_message(user_id, &timestamp, "hi", None).await.unwrap());
+        }
+        let reply_id = storage
+            .insert_chat_message(user_id, &days_ago(now, 0), "re", Some(ids[0]))
+            .await
+            .unwrap();
+        storage.insert_reaction(ids[0], user_id, "👍", &days_ago(now, 0)).await.unwrap();
+        storage.insert_mention(ids[1], user_id, None).await.unwrap();
+
+        // Messages older than the limit go first (the pruned parent leaves its reply alone).
+        let retention = RetentionConfig {
+            message_max_age_days: 30,
+            batch_size: 1,
+            ..RetentionConfig::default()
+        };
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary.chat_messages, 2);
+        let reply = storage.fetch_chat_message_by_id(reply_id).await.unwrap().unwrap();
+        assert_eq!((reply.text.as_str(), reply.reply_to), ("re", None));
+        assert!(storage.fetch_reaction_summaries().await.unwrap().is_empty());
+        assert!(storage.fetch_unread_mentions(user_id).await.unwrap().is_empty());
+
+        // Then just the newest messages are kept.
+        let retention = RetentionConfig {
+            message_max_count: 3,
+            batch_size: 2,
+            ..RetentionConfig::default()
+        };
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary.chat_messages, 3);
+        let messages = storage.fetch_chat_messages(&None).await.unwrap();
+        let mut remaining: Vec<i64> = messages.iter().map(|message| message.id).collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![ids[5], ids[6], reply_id]);
+
+        let summary = prune(&storage, &retention, now).await.unwrap();
+        assert_eq!(summary, PruneSummary::default());
+    }
+
+    #[tokio::test]
+    async fn test_prune_with_archive() {
+        let now = SystemTime::now();
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        for days in [3, 2, 1] {
+            storage.insert_chat_message(user_id, &days_ago(now, days), "old", None).await.unwrap();
+        }
+        storage.insert_login(user_id, &days_ago(now, 3), None).await.unwrap();
+
+        let archive_dir = std::env::temp_dir()
+            .join(format!("xchat-test-archive-{}", std::process::id()));
+        let retention = RetentionConfig {
+            message_max_age_days: 1,
+            login_max_age_days: 1,
+            batch_size: 1,
+            archive_dir: Some(archive_dir.clone()),
+            ..RetentionConfig::default()
+        };
+        prune(&storage, &retention, now).await.unwrap();
+
+        let path = archive_dir.join(format!("pruned-{}.jsonl.gz", &days_ago(now, 0)[..10]));
+        let mut content = String::new();
+        MultiGzDecoder::new(std::fs::File::open(&path).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+        std::fs::remove_dir_all(&archive_dir).unwrap();
+
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "chat_message");
+        assert_eq!(lines[0]["text"], "old");
+        assert_eq!(lines[1]["type"], "chat_message");
+        assert_eq!(lines[2]["type"], "client_login");
+        assert_eq!(lines[2]["login"], "user");
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::{Duration, SystemTime};
+
+use sha2::{Digest, Sha256};
+
+use shared::{Message, timestamp_to_string};
+use crate::config::SessionsConfig;
+use crate::error::ServerError;
+use crate::storage::{DbUser, Storage};
+
+
+/// Number of random bytes of a session token.
+const TOKEN_BYTES: usize = 32;
+
+
+/// `Session` is a log-in session of a connected client. Its token is never stored, the client
+/// gets it right after log-in by password.
+pub(crate) struct Session {
+    pub id: i64,
+    pub token: String,
+    pub expires_at: String,
+}
+
+
+impl Session {
+    /// `message` returns [Message::Session] announcing the session to its client (logged in
+    /// as `login`).
+    pub fn message(&self, login: &str) -> Message {
+        Message::Session {
+            login: login.to_string(),
+            token: self.token.clone(),
+            expires_at: self.expires_at.clone(),
+        }
+    }
+}
+
+
+/// `new_token` generates a random session token (hexadecimal digits).
+fn new_token() -> String {
+    (0..TOKEN_BYTES).map(|_| format!("{:02x}", rand::random::<u8>())).collect()
+}
+
+
+/// `hash_token` returns hash of the given session token (sessions are stored under hashes
+/// of their tokens, so a leaked DB does not leak sessions).
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+
+/// `authenticate` checks credentials of [Message::Login] or [Message::Resume]. Log-in by
+/// password starts a new session of the user, log-in by token continues an active session.
+/// Returns `None` if the credentials are not valid.
+pub(crate) async fn authenticate(
+        storage: &dyn Storage,
+        config: &SessionsConfig,
+        message: &Message,
+        now: SystemTime,
+) -> Result<Option<(DbUser, Session)>, ServerError> {
+    match message {
+        Message::Login {login, pass} => {
+            let user = match storage.fetch_user_by_login_and_password(login, pass).await? {
+                Some(user) if !user.is_locked() => user,
+                _ => return Ok(None),
+            };
+            let session = start_session(storage, config, user.id, now).await?;
+            Ok(Some((user, session)))
+        },
+        Message::Resume {token} => {
+            let session = match storage.fetch_session_by_token(&hash_token(token)).await? {
+                Some(session) if session.is_active(&timestamp_to_string(now)) => session,
+                _ => return Ok(None),
+            };
+            match storage.fetch_user_by_id(session.user_id).await? {
+                Some(user) if !user.is_locked() => Ok(Some((user, Session {
+                    id: session.id,
+                    token: token.clone(),
+                    expires_at: session.expires_at,
+                }))),
+                _ => Ok(None),
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+
+/// `start_session` stores a new session of the given user valid for the configured time.
+async fn start_session(
+        storage: &dyn Storage,
+        config: &SessionsConfig,
+        user_id: i64,
+        now: SystemTime,
+) -> Result<Session, ServerError> {
+    let token = new_token();
+    let expires_at = timestamp_to_string(now + Duration::from_secs(config.ttl_secs));
+    let id = storage.insert_session(
+        user_id,
+        &hash_token(&token),
+        &timestamp_to_string(now),
+        &expires_at,
+    ).await?;
+    Ok(Session { id, token, expires_at })
+}
+
+
+/// `end_reason` explains to a client why its session (expiring at `expires_at`) is no longer
+/// active at `now`.
+pub(crate) fn end_reason(expires_at: &str, now: &str) -> &'static str {
+    if expires_at <= now {
+        "session expired, log in again"
+    } else {
+        "session was revoked, log in again"
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use shared::{Message, timestamp_to_string};
+
+    use super::{authenticate, end_reason, hash_token, new_token};
+    use crate::config::SessionsConfig;
+    use crate::storage::{Storage, LOCKED_PASSWORD};
+    use crate::storage_memory::MemoryStorage;
+
+
+    #[test]
+    fn test_tokens() {
+        let token = new_token();
+        assert_eq!(token.len(), 64);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, new_token());
+
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let storage = MemoryStorage::new();
+        storage.insert_user("user", "c4ca4238a0b923820dcc509a6f75849b", "user");
+        storage.insert_user("bot", LOCKED_PASSWORD, "user");
+        let config = SessionsConfig { ttl_secs: 3600, ..SessionsConfig::default() };
+        let now = SystemTime::now();
+        let login = |login: &str, pass: &str| Message::Login {
+            login: login.to_string(),
+            pass: pass.to_string(),
+        };
+
+        let wrong = login("user", "wrong");
+        assert!(authenticate(&storage, &config, &wrong, now).await.unwrap().is_none());
+        let locked = login("bot", LOCKED_PASSWORD);
+        assert!(authenticate(&storage, &config, &locked, now).await.unwrap().is_none());
+
+        let valid = login("user", "c4ca4238a0b923820dcc509a6f75849b");
+        let (user, session) = authenticate(&storage, &config, &valid, now).await.unwrap().unwrap();
+        assert_eq!(user.login, "user");
+        assert_eq!(session.expires_at, timestamp_to_string(now + Duration::from_secs(3600)));
+
+        let resume = Message::Resume { token: session.token.clone() };
+        let (user, resumed) = authenticate(&storage, &config, &resume, now).await.unwrap().unwrap();
+        assert_eq!((user.login.as_str(), resumed.id), ("user", session.id));
+
+        let later = now + Duration::from_secs(3601);
+        assert!(authenticate(&storage, &config, &resume, later).await.unwrap().is_none());
+        let unknown = Message::Resume { token: new_token() };
+        assert!(authenticate(&storage, &config, &unknown, now).await.unwrap().is_none());
+
+        storage.revoke_session(session.id, &timestamp_to_string(now)).await.unwrap();
+        assert!(authenticate(&storage, &config, &resume, now).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_end_reason() {
+        assert!(end_reason("2024-01-01T10:00:00", "2024-01-01T10:00:00").contains("expired"));
+        assert!(end_reason("2024-01-01T10:00:00", "2024-01-02T00:00:00").contains("expired"));
+        assert!(end_reason("2024-01-02T00:00:00", "2024-01-01T10:00:00").contains("revoked"));
+    }
+}
@@ -0,0 +1,736 @@
+use std::path::Path;
+use std::result::Result;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{AppliedMigration, Migrator};
+
+use crate::ServerError;
+
+
+/// Value of `users.role` column for users allowed to moderate messages of others.
+pub const ROLE_MODERATOR: &str = "moderator";
+
+/// Value of `users.role` column for ordinary users.
+pub const ROLE_USER: &str = "user";
+
+/// Value of `users.password` column of users nobody can log in as (the bot user and users
+/// of linked servers).
+pub(crate) const LOCKED_PASSWORD: &str = "!";
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbUser {
+    pub id: i64,
+    pub login: String,
+    #[allow(dead_code)]
+    pub(crate) password: String,
+    pub role: String,
+    /// Optional display name chosen by the user (see `/nick`).
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+impl DbUser {
+    /// `is_locked` tells whether nobody can log in as the user.
+    pub fn is_locked(&self) -> bool {
+        self.password == LOCKED_PASSWORD
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbChatMessage {
+    pub id: i64,
+    pub login: String,
+    pub timestamp: String,
+    pub text: String,
+    pub edited_at: Option<String>,
+    pub deleted_at: Option<String>,
+    pub reply_to: Option<i64>,
+}
+
+/// `DbReactionSummary` aggregates all reactions with the same emoji to a single chat message.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbReactionSummary {
+    pub message_id: i64,
+    pub emoji: String,
+    pub count: i64,
+    /// Comma separated list of logins of users who reacted.
+    pub logins: String,
+}
+
+/// `DbSearchHit` is a single chat message found by full-text search.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbSearchHit {
+    pub id: i64,
+    pub login: String,
+    pub timestamp: String,
+    /// Fragment of message text with highlighted matches.
+    pub snippet: String,
+}
+
+/// `DbClientLogin` is a single log-in record of a user.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbClientLogin {
+    pub id: i64,
+    pub user_id: i64,
+    pub login: String,
+    pub timestamp: String,
+}
+
+/// `DbDirectMessage` is a direct message queued for a user who was offline.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbDirectMessage {
+    pub id: i64,
+    /// Login of the sender.
+    pub login: String,
+    /// The current display name of the sender.
+    pub display_name: Option<String>,
+    /// Login of the recipient.
+    pub to: String,
+    pub timestamp: String,
+    pub text: String,
+}
+
+/// `DbMention` is a single unread mention (`@login`) of a user in a chat message.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbMention {
+    pub id: i64,
+    pub message_id: i64,
+    /// Login of the author of the message.
+    pub login: String,
+    pub timestamp: String,
+    pub text: String,
+}
+
+/// `DbAttachmentUsage` is number and total size of attachments sent by a single user.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbAttachmentUsage {
+    pub user_id: i64,
+    pub login: String,
+    pub count: i64,
+    /// Total size of the attachments in bytes.
+    pub bytes: i64,
+}
+
+/// `DbAttachmentContent` is a stored image attachment with its original and thumbnail.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbAttachmentContent {
+    pub id: i64,
+    /// Login of the sender.
+    pub login: String,
+    pub timestamp: String,
+    pub mime: String,
+    pub payload: Vec<u8>,
+    /// PNG thumbnail of the image.
+    pub thumbnail: Vec<u8>,
+}
+
+/// `DbImageAttachment` is metadata of a stored image attachment.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbImageAttachment {
+    pub id: i64,
+    /// Login of the sender.
+    pub login: String,
+    pub timestamp: String,
+    pub mime: String,
+    /// Size of the original in bytes.
+    pub size: i64,
+}
+
+/// `DbFilterHit` is a single hit of a content filter rule of the audit trail.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbFilterHit {
+    pub id: i64,
+    pub timestamp: String,
+    /// Login of the author of the filtered message.
+    pub login: String,
+    pub rule: String,
+    /// Action of the rule (`reject` or `mask`).
+    pub action: String,
+}
+
+/// `DbLoginAttempt` is a single (successful or failed) log-in attempt of the audit trail.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbLoginAttempt {
+    pub timestamp: String,
+    /// Login of the user (missing for failed attempts by a session token).
+    pub login: Option<String>,
+    /// Peer address of the client (missing for log-ins recorded before the audit trail).
+    pub address: Option<String>,
+    pub succeeded: bool,
+    /// Why the attempt failed (missing for successful ones).
+    pub reason: Option<String>,
+}
+
+/// `DbChatMessageAuthor` is a minimal projection of `chat_messages` row used for authorization
+/// of message modifications.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbChatMessageAuthor {
+    pub user_id: i64,
+    pub deleted_at: Option<String>,
+}
+
+/// `DbTopic` is the current topic of the room together with its author.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbTopic {
+    pub topic: String,
+    pub login: String,
+    pub timestamp: String,
+}
+
+/// `DbSession` is a log-in session of a user (its token is known just to the client).
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "postgres", derive(sqlx::FromRow))]
+pub struct DbSession {
+    pub id: i64,
+    pub user_id: i64,
+    pub login: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub revoked_at: Option<String>,
+}
+
+
+impl DbSession {
+    /// `is_active` tells whether the session was neither revoked nor it expired at `now`.
+    pub fn is_active(&self, now: &str) -> bool {
+        self.revoked_at.is_none() && self.expires_at.as_str() > now
+    }
+}
+
+
+/// `HistoryFilter` selects part of chat history (missing criterion selects everything).
+#[derive(Clone, Debug, Default)]
+pub struct HistoryFilter {
+    /// The first included timestamp (e.g. `2024-01-01` or `2024-01-01T10:00:00`).
+    pub since: Option<String>,
+    /// The first excluded timestamp.
+    pub until: Option<String>,
+    /// Login of the author of messages or of the logged-in user.
+    pub login: Option<String>,
+}
+
+
+impl HistoryFilter {
+    /// `matches` tells whether a record with the given timestamp and login is selected.
+    pub fn matches(&self, timestamp: &str, login: &str) -> bool {
+        self.since.as_ref().is_none_or(|since| timestamp >= since.as_str())
+            && self.until.as_ref().is_none_or(|until| timestamp < until.as_str())
+            && self.login.as_ref().is_none_or(|filter| filter == login)
+    }
+}
+
+
+/// `Storage` is a persistence backend of the server (users, chat messages with their reactions
+/// and log-in records). All implementations have to behave the same way, the server does not
+/// know which one it talks to.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// `migrate` checks version of schema of the storage against migrations embedded into
+    /// the server and applies the pending ones if `apply` is set. Returns number of applied
+    /// migrations. Schema newer than the server knows is always refused.
+    async fn migrate(&self, apply: bool) -> Result<usize, ServerError>;
+
+    /// `backup` writes a consistent snapshot of the whole database into a new file of the given
+    /// path while the storage stays in use (it fails if the backend does not support it).
+    async fn backup(&self, path: &Path) -> Result<(), ServerError>;
+
+    /// `fetch_user_by_login_and_password` receives a user with the given login and password
+    /// (compared case-insensitively).
+    async fn fetch_user_by_login_and_password(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<DbUser>, ServerError>;
+
+    /// `fetch_users` fetch all users (sorted by login).
+    async fn fetch_users(&self) -> Result<Vec<DbUser>, ServerError>;
+
+    /// `fetch_user_by_id` fetch a single user (if the user exists).
+    async fn fetch_user_by_id(&self, user_id: i64) -> Result<Option<DbUser>, ServerError>;
+
+    /// `delete_user_by_id` delete user and all his/her related chat messages, reactions,
+    /// topics, sessions, direct messages, mentions, attachments (with their contents), filter
+    /// hits and log-in records at once.
+    async fn delete_user_by_id(&self, user_id: i64) -> Result<(), ServerError>;
+
+    /// `update_display_name` sets (or clears) display name of the given user.
+    async fn update_display_name(
+        &self,
+        user_id: i64,
+        display_name: Option<&str>,
+    ) -> Result<(), ServerError>;
+
+    /// `update_role` sets role of the user with the given login, it returns `false` if there
+    /// is no such user.
+    async fn update_role(&self, login: &str, role: &str) -> Result<bool, ServerError>;
+
+    /// `insert_topic` stores a new topic of the room set by the given user (an empty topic
+    /// clears it).
+    async fn insert_topic(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        topic: &str,
+    ) -> Result<(), ServerError>;
+
+    /// `fetch_topic` fetch the current topic of the room (`None` if it was never set or it was
+    /// cleared).
+    async fn fetch_topic(&self) -> Result<Option<DbTopic>, ServerError>;
+
+    /// `insert_session` stores a new session of the given user identified by hash of its token
+    /// and returns ID of the session.
+    async fn insert_session(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_session_by_token` fetch a session by hash of its token (even a revoked or
+    /// an expired one).
+    async fn fetch_session_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<DbSession>, ServerError>;
+
+    /// `fetch_active_sessions` fetch sessions (of the given user or of all users) which are
+    /// neither revoked nor expired at `now` (sorted by login and creation).
+    async fn fetch_active_sessions(
+        &self,
+        user_id: Option<i64>,
+        now: &str,
+    ) -> Result<Vec<DbSession>, ServerError>;
+
+    /// `revoke_session` marks the given session as revoked. Returns `false` if there is no such
+    /// session or it was already revoked.
+    async fn revoke_session(&self, session_id: i64, revoked_at: &str) -> Result<bool, ServerError>;
+
+    /// `insert_direct_message` queues a direct message for a user who is offline and returns
+    /// its ID.
+    async fn insert_direct_message(
+        &self,
+        sender_id: i64,
+        recipient_id: i64,
+        timestamp: &str,
+        text: &str,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_undelivered_direct_messages` fetch direct messages queued for the given user
+    /// and not delivered yet (oldest first).
+    async fn fetch_undelivered_direct_messages(
+        &self,
+        recipient_id: i64,
+    ) -> Result<Vec<DbDirectMessage>, ServerError>;
+
+    /// `mark_direct_messages_delivered` marks the given direct messages as delivered. Returns
+    /// number of messages which were not marked before.
+    async fn mark_direct_messages_delivered(
+        &self,
+        message_ids: &[i64],
+        delivered_at: &str,
+    ) -> Result<u64, ServerError>;
+
+    /// `insert_mention` stores a mention of the user in the given chat message, `read_at` is
+    /// set for mentions delivered to the user right away. Returns ID of the mention.
+    async fn insert_mention(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        read_at: Option<&str>,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_unread_mentions` fetch unread mentions of the given user in messages which were
+    /// not deleted (oldest first).
+    async fn fetch_unread_mentions(&self, user_id: i64) -> Result<Vec<DbMention>, ServerError>;
+
+    /// `mark_mentions_read` marks the given mentions as read. Returns number of mentions which
+    /// were not marked before.
+    async fn mark_mentions_read(
+        &self,
+        mention_ids: &[i64],
+        read_at: &str,
+    ) -> Result<u64, ServerError>;
+
+    /// `insert_attachment` records an attachment (`kind` is `file` or `image`) of `size` bytes
+    /// sent by the user and returns ID of the record.
+    async fn insert_attachment(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        kind: &str,
+        filename: Option<&str>,
+        size: i64,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_attachment_usage` fetch number and total size of attachments sent (by the given
+    /// user or by every user) since the given timestamp, users without attachments are
+    /// omitted (sorted by login).
+    async fn fetch_attachment_usage(
+        &self,
+        user_id: Option<i64>,
+        since: &str,
+    ) -> Result<Vec<DbAttachmentUsage>, ServerError>;
+
+    /// `insert_attachment_content` stores the original and the thumbnail of an image attachment
+    /// of the given ID.
+    async fn insert_attachment_content(
+        &self,
+        attachment_id: i64,
+        mime: &str,
+        payload: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<(), ServerError>;
+
+    /// `fetch_attachment_content` fetch a stored image attachment (if it exists).
+    async fn fetch_attachment_content(
+        &self,
+        attachment_id: i64,
+    ) -> Result<Option<DbAttachmentContent>, ServerError>;
+
+    /// `fetch_image_attachments` fetch metadata of stored image attachments (optionally just of
+    /// the given login) from the newest one.
+    async fn fetch_image_attachments(
+        &self,
+        login: &Option<String>,
+    ) -> Result<Vec<DbImageAttachment>, ServerError>;
+
+    /// `insert_filter_hit` records a hit of the content filter rule by a message of the user
+    /// and returns ID of the record.
+    async fn insert_filter_hit(
+        &self,
+        timestamp: &str,
+        user_id: i64,
+        rule: &str,
+        action: &str,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_filter_hits` fetch at most `limit` most recent content filter hits (newest
+    /// first).
+    async fn fetch_filter_hits(&self, limit: i64) -> Result<Vec<DbFilterHit>, ServerError>;
+
+    /// `insert_login` stores a log-in record of the given user (connected from `address`).
+    async fn insert_login(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        address: Option<&str>,
+    ) -> Result<(), ServerError>;
+
+    /// `insert_failed_login` stores a failed log-in attempt (`login` is missing for attempts
+    /// by a session token).
+    async fn insert_failed_login(
+        &self,
+        timestamp: &str,
+        login: Option<&str>,
+        address: &str,
+        reason: &str,
+    ) -> Result<(), ServerError>;
+
+    /// `fetch_login_attempts` fetch at most `limit` of the newest successful and failed log-in
+    /// attempts (newest first).
+    async fn fetch_login_attempts(&self, limit: i64) -> Result<Vec<DbLoginAttempt>, ServerError>;
+
+    /// `fetch_chat_messages` fetch chat messages (newest first) optionally filtered by login
+    /// of their author.
+    async fn fetch_chat_messages(
+        &self,
+        filter: &Option<String>,
+    ) -> Result<Vec<DbChatMessage>, ServerError>;
+
+    /// `fetch_chat_message_by_id` fetch a single chat message (if the message exists).
+    async fn fetch_chat_message_by_id(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessage>, ServerError>;
+
+    /// `insert_chat_message` stores a new chat message (`reply_to` is ID of a parent message
+    /// of a reply) and returns its ID.
+    async fn insert_chat_message(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> Result<i64, ServerError>;
+
+    /// `fetch_chat_message_author` fetch author of a single chat message (if the message exists).
+    async fn fetch_chat_message_author(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessageAuthor>, ServerError>;
+
+    /// `update_chat_message_text` replace text of a single chat message and mark it as edited.
+    async fn update_chat_message_text(
+        &self,
+        message_id: i64,
+        text: &str,
+        edited_at: &str,
+    ) -> Result<(), ServerError>;
+
+    /// `tombstone_chat_message` erases text of a single chat message (keeping its ID valid)
+    /// and deletes all reactions to it at once.
+    async fn tombstone_chat_message(
+        &self,
+        message_id: i64,
+        deleted_at: &str,
+    ) -> Result<(), ServerError>;
+
+    /// `insert_reaction` stores a reaction of the given user to the given chat message.
+    /// Returns `false` if the very same reaction was already stored before.
+    async fn insert_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+        timestamp: &str,
+    ) -> Result<bool, ServerError>;
+
+    /// `delete_reaction` removes a reaction of the given user to the given chat message.
+    /// Returns `false` if there was no such reaction.
+    async fn delete_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+    ) -> Result<bool, ServerError>;
+
+    /// `fetch_prunable_chat_messages` fetch at most `limit` chat messages (oldest first) which
+    /// are older than `older_than` timestamp or which are not among `keep_last` newest messages.
+    /// Messages are not pruned by a missing criterion.
+    async fn fetch_prunable_chat_messages(
+        &self,
+        older_than: Option<&str>,
+        keep_last: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DbChatMessage>, ServerError>;
+
+    /// `delete_chat_messages` delete the given chat messages with all reactions to them and
+    /// mentions in them at once (replies to the deleted messages are kept without parent).
+    /// Returns number of deleted messages.
+    async fn delete_chat_messages(&self, message_ids: &[i64]) -> Result<u64, ServerError>;
+
+    /// `fetch_prunable_logins` fetch at most `limit` log-in records (oldest first) older than
+    /// `older_than` timestamp.
+    async fn fetch_prunable_logins(
+        &self,
+        older_than: &str,
+        limit: i64,
+    ) -> Result<Vec<DbClientLogin>, ServerError>;
+
+    /// `delete_logins` delete the given log-in records. Returns number of deleted records.
+    async fn delete_logins(&self, login_ids: &[i64]) -> Result<u64, ServerError>;
+
+    /// `delete_failed_logins` delete failed log-in attempts older than `older_than` timestamp.
+    /// Returns number of deleted records.
+    async fn delete_failed_logins(&self, older_than: &str) -> Result<u64, ServerError>;
+
+    /// `fetch_chat_history` fetch chat messages selected by the filter (oldest first).
+    async fn fetch_chat_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbChatMessage>, ServerError>;
+
+    /// `fetch_login_history` fetch log-in records selected by the filter (oldest first).
+    async fn fetch_login_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbClientLogin>, ServerError>;
+
+    /// `import_user` returns ID of an existing user with login of the given user (keeping
+    /// the existing user untouched) or it stores the given user under a new ID. The flag tells
+    /// whether the user was created.
+    async fn import_user(&self, user: &DbUser) -> Result<(i64, bool), ServerError>;
+
+    /// `import_chat_message` stores the given chat message (with all its timestamps) as message
+    /// of user `user_id` replying to `reply_to`, unless the same message (the same author,
+    /// timestamp and text) is already stored. Returns ID of the message and the flag whether
+    /// it was stored.
+    async fn import_chat_message(
+        &self,
+        user_id: i64,
+        message: &DbChatMessage,
+        reply_to: Option<i64>,
+    ) -> Result<(i64, bool), ServerError>;
+
+    /// `import_login` stores a log-in record unless the same one is already stored.
+    /// Returns `false` if it was already stored.
+    async fn import_login(&self, user_id: i64, timestamp: &str) -> Result<bool, ServerError>;
+
+    /// `fetch_reaction_summaries` fetch reactions aggregated per chat message and emoji
+    /// (in order of the first reaction).
+    async fn fetch_reaction_summaries(&self) -> Result<Vec<DbReactionSummary>, ServerError>;
+
+    /// `search_chat_messages` search chat messages (newest first) containing all the words
+    /// of the given `text`. Matches in the returned snippets are enclosed in the given
+    /// `highlight` pair of markers. Total number of found messages is returned together with
+    /// the requested page of hits.
+    async fn search_chat_messages(
+        &self,
+        text: &str,
+        highlight: (&str, &str),
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<DbSearchHit>), ServerError>;
+}
+
+
+/// `SharedStorage` is a storage backend shared by all tasks of the server.
+pub type SharedStorage = Arc<dyn Storage>;
+
+
+/// `StorageBackend` is a kind of storage selected by scheme of DB URL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageBackend {
+    /// `sqlite:` URLs (e.g. `sqlite:data.db`).
+    Sqlite,
+    /// `postgres:` and `postgresql:` URLs (requires `postgres` feature).
+    Postgres,
+    /// `memory:` URL; nothing survives restart of the server (meant for tests and demos).
+    Memory,
+}
+
+
+impl StorageBackend {
+    /// `from_url` selects storage backend by scheme of the given DB URL.
+    pub fn from_url(db_url: &str) -> Result<StorageBackend, String> {
+        let scheme = db_url.split(':').next().unwrap_or_default().to_lowercase();
+        match scheme.as_str() {
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            "postgres" | "postgresql" if cfg!(feature = "postgres") =>
+                Ok(StorageBackend::Postgres),
+            "postgres" | "postgresql" =>
+                Err("PostgreSQL support is not compiled in (enable `postgres` feature)".to_string()),
+            "memory" => Ok(StorageBackend::Memory),
+            _ => Err(format!(
+                "unsupported DB URL {:?} (expected `sqlite:`, `postgres:` or `memory:` scheme)",
+                db_url,
+            )),
+        }
+    }
+}
+
+
+/// `pending_migrations` returns number of `migrator` migrations not yet `applied` to a database.
+/// It fails if the database contains a migration unknown to the `migrator` (the database was
+/// migrated by a newer server) or if some pending migrations exist and they may not be `applied`.
+pub(crate) fn pending_migrations(
+    migrator: &Migrator,
+    applied: &[AppliedMigration],
+    apply: bool,
+) -> Result<usize, ServerError> {
+    let latest_known = migrator.iter().map(|migration| migration.version).max().unwrap_or(0);
+    let unknown = applied
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !migrator.iter().any(|migration| migration.version == *version))
+        .max();
+    if let Some(version) = unknown {
+        return Err(ServerError::SchemaError(format!(
+            "DB schema version {} is unknown to this server (the latest known is {}), \
+            the DB was probably migrated by a newer server",
+            version,
+            latest_known,
+        )));
+    }
+
+    let pending = migrator
+        .iter()
+        .filter(|migration| !applied.iter().any(|applied| applied.version == migration.version))
+        .count();
+    if pending > 0 && !apply {
+        return Err(ServerError::SchemaError(format!(
+            "DB schema is outdated ({} pending migrations), run server with `--migrate-only` \
+            or enable `auto_migrate`",
+            pending,
+        )));
+    }
+    Ok(pending)
+}
+
+
+/// `connect_storage` connects to the storage backend selected by scheme of the given DB URL.
+pub async fn connect_storage(db_url: &str) -> Result<SharedStorage, ServerError> {
+    let backend = match StorageBackend::from_url(db_url) {
+        Ok(backend) => backend,
+        Err(err) => Err(ServerError::ConfigError(format!("db_url: {}", err)))?,
+    };
+    tracing::info!(?backend, "connecting to storage");
+
+    match backend {
+        StorageBackend::Sqlite =>
+            Ok(Arc::new(crate::db_queries::SqliteStorage::connect(db_url).await?)),
+        #[cfg(feature = "postgres")]
+        StorageBackend::Postgres =>
+            Ok(Arc::new(crate::storage_postgres::PostgresStorage::connect(db_url).await?)),
+        #[cfg(not(feature = "postgres"))]
+        StorageBackend::Postgres =>
+            Err(ServerError::ConfigError("PostgreSQL support is not compiled in".to_string())),
+        StorageBackend::Memory =>
+            Ok(Arc::new(crate::storage_memory::MemoryStorage::with_default_users())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use sqlx::migrate::AppliedMigration;
+
+    use super::{pending_migrations, StorageBackend};
+    use crate::db_queries::MIGRATOR;
+
+
+    #[test]
+    fn test_storage_backend_from_url() {
+        assert_eq!(StorageBackend::from_url("sqlite:data.db"), Ok(StorageBackend::Sqlite));
+        assert_eq!(StorageBackend::from_url("sqlite://data.db"), Ok(StorageBackend::Sqlite));
+        assert_eq!(StorageBackend::from_url("memory:"), Ok(StorageBackend::Memory));
+        assert_eq!(
+            StorageBackend::from_url("postgres://localhost/xchat").is_ok(),
+            cfg!(feature = "postgres"),
+        );
+        assert!(StorageBackend::from_url("mysql://localhost/xchat").is_err());
+        assert!(StorageBackend::from_url("data.db").is_err());
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let applied = |versions: &[i64]| -> Vec<AppliedMigration> {
+            versions
+                .iter()
+                .map(|version| AppliedMigration { version: *version, checksum: Cow::Borrowed(&[]) })
+                .collect()
+        };
+        let known: Vec<i64> = MIGRATOR.iter().map(|migration| migration.version).collect();
+
+        assert_eq!(pending_migrations(&MIGRATOR, &applied(&[]), true).unwrap(), known.len());
+        assert_eq!(pending_migrations(&MIGRATOR, &applied(&known), false).unwrap(), 0);
+        assert_eq!(pending_migrations(&MIGRATOR, &applied(&known[..1]), true).unwrap(), known.len() - 1);
+
+        let err = pending_migrations(&MIGRATOR, &applied(&known[..1]), false).unwrap_err();
+        assert!(err.to_string().contains("outdated"));
+
+        let newer = [known.as_slice(), &[99991231000000]].concat();
+        let err = pending_migrations(&MIGRATOR, &applied(&newer), true).unwrap_err();
+        assert!(err.to_string().contains("99991231000000"));
+    }
+}
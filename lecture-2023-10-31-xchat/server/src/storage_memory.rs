@@ -0,0 +1,1484 @@
+use std::path::Path;
+use std::result::Result;
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+
+use crate::ServerError;
+use crate::storage::{
+    DbAttachmentContent,
+    DbAttachmentUsage,
+    DbChatMessage,
+    DbChatMessageAuthor,
+    DbClientLogin,
+    DbDirectMessage,
+    DbFilterHit,
+    DbImageAttachment,
+    DbLoginAttempt,
+    DbMention,
+    DbReactionSummary,
+    DbSearchHit,
+    DbSession,
+    DbTopic,
+    DbUser,
+    HistoryFilter,
+    Storage,
+    ROLE_USER,
+};
+
+
+struct MemoryChatMessage {
+    id: i64,
+    user_id: i64,
+    timestamp: String,
+    text: String,
+    edited_at: Option<String>,
+    deleted_at: Option<String>,
+    reply_to: Option<i64>,
+}
+
+struct MemoryReaction {
+    message_id: i64,
+    user_id: i64,
+    emoji: String,
+}
+
+struct MemoryLogin {
+    id: i64,
+    user_id: i64,
+    timestamp: String,
+    address: Option<String>,
+}
+
+struct MemoryDirectMessage {
+    id: i64,
+    sender_id: i64,
+    recipient_id: i64,
+    timestamp: String,
+    text: String,
+    delivered_at: Option<String>,
+}
+
+struct MemoryMention {
+    id: i64,
+    message_id: i64,
+    user_id: i64,
+    read_at: Option<String>,
+}
+
+struct MemoryAttachment {
+    id: i64,
+    user_id: i64,
+    timestamp: String,
+    size: i64,
+}
+
+struct MemoryAttachmentContent {
+    attachment_id: i64,
+    mime: String,
+    payload: Vec<u8>,
+    thumbnail: Vec<u8>,
+}
+
+struct MemoryFilterHit {
+    id: i64,
+    timestamp: String,
+    user_id: i64,
+    rule: String,
+    action: String,
+}
+
+struct MemoryFailedLogin {
+    id: i64,
+    timestamp: String,
+    login: Option<String>,
+    address: String,
+    reason: String,
+}
+
+struct MemoryTopic {
+    user_id: i64,
+    timestamp: String,
+    topic: String,
+}
+
+struct MemorySession {
+    id: i64,
+    user_id: i64,
+    token_hash: String,
+    created_at: String,
+    expires_at: String,
+    revoked_at: Option<String>,
+}
+
+/// `MemoryState` holds "tables" of [MemoryStorage] in order of insertion (each table with
+/// its own sequence of IDs).
+#[derive(Default)]
+struct MemoryState {
+    last_user_id: i64,
+    last_chat_message_id: i64,
+    last_login_id: i64,
+    last_session_id: i64,
+    last_failed_login_id: i64,
+    last_direct_message_id: i64,
+    last_mention_id: i64,
+    last_attachment_id: i64,
+    last_filter_hit_id: i64,
+    users: Vec<DbUser>,
+    chat_messages: Vec<MemoryChatMessage>,
+    reactions: Vec<MemoryReaction>,
+    logins: Vec<MemoryLogin>,
+    topics: Vec<MemoryTopic>,
+    sessions: Vec<MemorySession>,
+    failed_logins: Vec<MemoryFailedLogin>,
+    direct_messages: Vec<MemoryDirectMessage>,
+    mentions: Vec<MemoryMention>,
+    attachments: Vec<MemoryAttachment>,
+    attachment_contents: Vec<MemoryAttachmentContent>,
+    filter_hits: Vec<MemoryFilterHit>,
+}
+
+
+/// `MemoryStorage` is [Storage] keeping everything just in memory of the process. It is meant
+/// for tests and demos, nothing survives restart of the server.
+#[derive(Default)]
+pub struct MemoryStorage {
+    state: Mutex<MemoryState>,
+}
+
+
+impl MemoryStorage {
+    /// `new` creates an empty storage (without any users).
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// `with_default_users` creates a storage with the same users as the initial migrations
+    /// of SQLite database.
+    pub fn with_default_users() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        // passwords are MD5 of strings '1', '2' and '3'
+        storage.insert_user("TheOne", "c4ca4238a0b923820dcc509a6f75849b", ROLE_USER);
+        storage.insert_user("JustTwo", "c81e728d9d4c2f636f067f89cc14862c", ROLE_USER);
+        storage.insert_user("Threesome", "eccbc87e4b5ce2fe28308fd9f2a7baf3", ROLE_USER);
+        storage
+    }
+
+    /// `insert_user` adds a new user and returns its ID.
+    pub fn insert_user(&self, login: &str, password: &str, role: &str) -> i64 {
+        let mut state = self.lock();
+        let id = state.next_user_id();
+        state.users.push(DbUser {
+            id,
+            login: login.to_string(),
+            password: password.to_string(),
+            role: role.to_string(),
+            display_name: None,
+        });
+        id
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        // Poisoned state is still consistent (each operation changes it at once at its end).
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+impl MemoryState {
+    fn next_user_id(&mut self) -> i64 {
+        self.last_user_id += 1;
+        self.last_user_id
+    }
+
+    fn next_chat_message_id(&mut self) -> i64 {
+        self.last_chat_message_id += 1;
+        self.last_chat_message_id
+    }
+
+    fn next_login_id(&mut self) -> i64 {
+        self.last_login_id += 1;
+        self.last_login_id
+    }
+
+    fn next_session_id(&mut self) -> i64 {
+        self.last_session_id += 1;
+        self.last_session_id
+    }
+
+    fn next_failed_login_id(&mut self) -> i64 {
+        self.last_failed_login_id += 1;
+        self.last_failed_login_id
+    }
+
+    fn next_direct_message_id(&mut self) -> i64 {
+        self.last_direct_message_id += 1;
+        self.last_direct_message_id
+    }
+
+    fn next_mention_id(&mut self) -> i64 {
+        self.last_mention_id += 1;
+        self.last_mention_id
+    }
+
+    fn next_attachment_id(&mut self) -> i64 {
+        self.last_attachment_id += 1;
+        self.last_attachment_id
+    }
+
+    fn next_filter_hit_id(&mut self) -> i64 {
+        self.last_filter_hit_id += 1;
+        self.last_filter_hit_id
+    }
+
+    fn login_of(&self, user_id: i64) -> String {
+        self.users
+            .iter()
+            .find(|user| user.id == user_id)
+            .map(|user| user.login.clone())
+            .unwrap_or_default()
+    }
+
+    fn to_db_chat_message(&self, message: &MemoryChatMessage) -> DbChatMessage {
+        DbChatMessage {
+            id: message.id,
+            login: self.login_of(message.user_id),
+            timestamp: message.timestamp.clone(),
+            text: message.text.clone(),
+            edited_at: message.edited_at.clone(),
+            deleted_at: message.deleted_at.clone(),
+            reply_to: message.reply_to,
+        }
+    }
+
+    fn to_db_session(&self, session: &MemorySession) -> DbSession {
+        DbSession {
+            id: session.id,
+            user_id: session.user_id,
+            login: self.login_of(session.user_id),
+            created_at: session.created_at.clone(),
+            expires_at: session.expires_at.clone(),
+            revoked_at: session.revoked_at.clone(),
+        }
+    }
+
+    fn chat_message_mut(&mut self, message_id: i64) -> Option<&mut MemoryChatMessage> {
+        self.chat_messages.iter_mut().find(|message| message.id == message_id)
+    }
+}
+
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self, _apply: bool) -> Result<usize, ServerError> {
+        // There is no schema to be migrated.
+        Ok(0)
+    }
+
+    async fn backup(&self, _path: &Path) -> Result<(), ServerError> {
+        Err(ServerError::BackupError("in-memory storage cannot be backed up".to_string()))
+    }
+
+    async fn fetch_user_by_login_and_password(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<DbUser>, ServerError> {
+        Ok(self.lock()
+            .users
+            .iter()
+            .find(|user| user.login == login && user.password.eq_ignore_ascii_case(password))
+            .cloned())
+    }
+
+    async fn fetch_users(&self) -> Result<Vec<DbUser>, ServerError> {
+        let mut users = self.lock().users.clone();
+        users.sort_by(|a, b| a.login.cmp(&b.login));
+        Ok(users)
+    }
+
+    async fn fetch_user_by_id(&self, user_id: i64) -> Result<Option<DbUser>, ServerError> {
+        Ok(self.lock().users.iter().find(|user| user.id == user_id).cloned())
+    }
+
+    async fn delete_user_by_id(&self, user_id: i64) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        let message_ids: Vec<i64> = state.chat_messages
+            .iter()
+            .filter(|message| message.user_id == user_id)
+            .map(|message| message.id)
+            .collect();
+
+        state.reactions.retain(|reaction|
+            reaction.user_id != user_id && !message_ids.contains(&reaction.message_id)
+        );
+        state.mentions.retain(|mention|
+            mention.user_id != user_id && !message_ids.contains(&mention.message_id)
+        );
+        // Replies of other users must not reference the deleted messages.
+        for message in state.chat_messages.iter_mut() {
+            if message.reply_to.is_some_and(|reply_to| message_ids.contains(&reply_to)) {
+                message.reply_to = None;
+            }
+        }
+        state.chat_messages.retain(|message| message.user_id != user_id);
+        state.logins.retain(|login| login.user_id != user_id);
+        state.topics.retain(|topic| topic.user_id != user_id);
+        state.sessions.retain(|session| session.user_id != user_id);
+        let attachment_ids: Vec<i64> = state.attachments
+            .iter()
+            .filter(|attachment| attachment.user_id == user_id)
+            .map(|attachment| attachment.id)
+            .collect();
+        state.attachment_contents.retain(|content|
+            !attachment_ids.contains(&content.attachment_id)
+        );
+        state.attachments.retain(|attachment| attachment.user_id != user_id);
+        state.filter_hits.retain(|hit| hit.user_id != user_id);
+        state.direct_messages.retain(|message|
+            message.sender_id != user_id && message.recipient_id != user_id
+        );
+        state.users.retain(|user| user.id != user_id);
+        Ok(())
+    }
+
+    async fn update_display_name(
+        &self,
+        user_id: i64,
+        display_name: Option<&str>,
+    ) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        if let Some(user) = state.users.iter_mut().find(|user| user.id == user_id) {
+            user.display_name = display_name.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    async fn update_role(&self, login: &str, role: &str) -> Result<bool, ServerError> {
+        let mut state = self.lock();
+        match state.users.iter_mut().find(|user| user.login == login) {
+            Some(user) => {
+                user.role = role.to_string();
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    async fn insert_topic(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        topic: &str,
+    ) -> Result<(), ServerError> {
+        self.lock().topics.push(MemoryTopic {
+            user_id,
+            timestamp: timestamp.to_string(),
+            topic: topic.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn fetch_topic(&self) -> Result<Option<DbTopic>, ServerError> {
+        let state = self.lock();
+        Ok(state.topics
+            .last()
+            .filter(|topic| !topic.topic.is_empty())
+            .map(|topic| DbTopic {
+                topic: topic.topic.clone(),
+                login: state.login_of(topic.user_id),
+                timestamp: topic.timestamp.clone(),
+            }))
+    }
+
+    async fn insert_session(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        if state.sessions.iter().any(|session| session.token_hash == token_hash) {
+            return Err(ServerError::DBError("session token is not unique".to_string()));
+        }
+        let id = state.next_session_id();
+        state.sessions.push(MemorySession {
+            id,
+            user_id,
+            token_hash: token_hash.to_string(),
+            created_at: created_at.to_string(),
+            expires_at: expires_at.to_string(),
+            revoked_at: None,
+        });
+        Ok(id)
+    }
+
+    async fn fetch_session_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<DbSession>, ServerError> {
+        let state = self.lock();
+        Ok(state.sessions
+            .iter()
+            .find(|session| session.token_hash == token_hash)
+            .map(|session| state.to_db_session(session)))
+    }
+
+    async fn fetch_active_sessions(
+        &self,
+        user_id: Option<i64>,
+        now: &str,
+    ) -> Result<Vec<DbSession>, ServerError> {
+        let state = self.lock();
+        let mut sessions: Vec<DbSession> = state.sessions
+            .iter()
+            .filter(|session| user_id.is_none_or(|user_id| session.user_id == user_id))
+            .map(|session| state.to_db_session(session))
+            .filter(|session| session.is_active(now))
+            .collect();
+        sessions.sort_by(|a, b| {
+            (&a.login, &a.created_at, a.id).cmp(&(&b.login, &b.created_at, b.id))
+        });
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, session_id: i64, revoked_at: &str) -> Result<bool, ServerError> {
+        let mut state = self.lock();
+        let session = state.sessions
+            .iter_mut()
+            .find(|session| session.id == session_id && session.revoked_at.is_none());
+        match session {
+            Some(session) => {
+                session.revoked_at = Some(revoked_at.to_string());
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    async fn insert_direct_message(
+        &self,
+        sender_id: i64,
+        recipient_id: i64,
+        timestamp: &str,
+        text: &str,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let id = state.next_direct_message_id();
+        state.direct_messages.push(MemoryDirectMessage {
+            id,
+            sender_id,
+            recipient_id,
+            timestamp: timestamp.to_string(),
+            text: text.to_string(),
+            delivered_at: None,
+        });
+        Ok(id)
+    }
+
+    async fn fetch_undelivered_direct_messages(
+        &self,
+        recipient_id: i64,
+    ) -> Result<Vec<DbDirectMessage>, ServerError> {
+        let state = self.lock();
+        let mut messages: Vec<DbDirectMessage> = state.direct_messages
+            .iter()
+            .filter(|message| message.recipient_id == recipient_id)
+            .filter(|message| message.delivered_at.is_none())
+            .map(|message| DbDirectMessage {
+                id: message.id,
+                login: state.login_of(message.sender_id),
+                display_name: state.users
+                    .iter()
+                    .find(|user| user.id == message.sender_id)
+                    .and_then(|user| user.display_name.clone()),
+                to: state.login_of(message.recipient_id),
+                timestamp: message.timestamp.clone(),
+                text: message.text.clone(),
+            })
+            .collect();
+        messages.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        Ok(messages)
+    }
+
+    async fn mark_direct_messages_delivered(
+        &self,
+        message_ids: &[i64],
+        delivered_at: &str,
+    ) -> Result<u64, ServerError> {
+        let mut state = self.lock();
+        let mut marked = 0;
+        for message in state.direct_messages.iter_mut() {
+            if message_ids.contains(&message.id) && message.delivered_at.is_none() {
+                message.delivered_at = Some(delivered_at.to_string());
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    async fn insert_mention(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        read_at: Option<&str>,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let id = state.next_mention_id();
+        state.mentions.push(MemoryMention {
+            id,
+            message_id,
+            user_id,
+            read_at: read_at.map(str::to_string),
+        });
+        Ok(id)
+    }
+
+    async fn fetch_unread_mentions(&self, user_id: i64) -> Result<Vec<DbMention>, ServerError> {
+        let state = self.lock();
+        let mut mentions: Vec<DbMention> = state.mentions
+            .iter()
+            .filter(|mention| mention.user_id == user_id && mention.read_at.is_none())
+            .filter_map(|mention| {
+                let message = state.chat_messages
+                    .iter()
+                    .find(|message| message.id == mention.message_id)
+                    .filter(|message| message.deleted_at.is_none())?;
+                Some(DbMention {
+                    id: mention.id,
+                    message_id: message.id,
+                    login: state.login_of(message.user_id),
+                    timestamp: message.timestamp.clone(),
+                    text: message.text.clone(),
+                })
+            })
+            .collect();
+        mentions.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        Ok(mentions)
+    }
+
+    async fn mark_mentions_read(
+        &self,
+        mention_ids: &[i64],
+        read_at: &str,
+    ) -> Result<u64, ServerError> {
+        let mut state = self.lock();
+        let mut marked = 0;
+        for mention in state.mentions.iter_mut() {
+            if mention_ids.contains(&mention.id) && mention.read_at.is_none() {
+                mention.read_at = Some(read_at.to_string());
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+
+    async fn insert_attachment(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        _kind: &str,
+        _filename: Option<&str>,
+        size: i64,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let id = state.next_attachment_id();
+        state.attachments.push(MemoryAttachment {
+            id,
+            user_id,
+            timestamp: timestamp.to_string(),
+            size,
+        });
+        Ok(id)
+    }
+
+    async fn fetch_attachment_usage(
+        &self,
+        user_id: Option<i64>,
+        since: &str,
+    ) -> Result<Vec<DbAttachmentUsage>, ServerError> {
+        let state = self.lock();
+        let mut usage: Vec<DbAttachmentUsage> = vec![];
+        let attachments = state.attachments
+            .iter()
+            .filter(|attachment| user_id.is_none_or(|user_id| attachment.user_id == user_id))
+            .filter(|attachment| attachment.timestamp.as_str() >= since);
+        for attachment in attachments {
+            match usage.iter_mut().find(|usage| usage.user_id == attachment.user_id) {
+                Some(usage) => {
+                    usage.count += 1;
+                    usage.bytes += attachment.size;
+                },
+                None => usage.push(DbAttachmentUsage {
+                    user_id: attachment.user_id,
+                    login: state.login_of(attachment.user_id),
+                    count: 1,
+                    bytes: attachment.size,
+                }),
+            }
+        }
+        usage.sort_by(|a, b| a.login.cmp(&b.login));
+        Ok(usage)
+    }
+
+    async fn insert_attachment_content(
+        &self,
+        attachment_id: i64,
+        mime: &str,
+        payload: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        if !state.attachments.iter().any(|attachment| attachment.id == attachment_id) {
+            Err(ServerError::DBError(format!("attachment {} does not exist", attachment_id)))?;
+        }
+        state.attachment_contents.push(MemoryAttachmentContent {
+            attachment_id,
+            mime: mime.to_string(),
+            payload: payload.to_vec(),
+            thumbnail: thumbnail.to_vec(),
+        });
+        Ok(())
+    }
+
+    async fn fetch_attachment_content(
+        &self,
+        attachment_id: i64,
+    ) -> Result<Option<DbAttachmentContent>, ServerError> {
+        let state = self.lock();
+        let content = state.attachment_contents
+            .iter()
+            .find(|content| content.attachment_id == attachment_id);
+        let attachment = state.attachments
+            .iter()
+            .find(|attachment| attachment.id == attachment_id);
+        let (Some(content), Some(attachment)) = (content, attachment) else {
+            return Ok(None);
+        };
+        Ok(Some(DbAttachmentContent {
+            id: attachment.id,
+            login: state.login_of(attachment.user_id),
+            timestamp: attachment.timestamp.clone(),
+            mime: content.mime.clone(),
+            payload: content.payload.clone(),
+            thumbnail: content.thumbnail.clone(),
+        }))
+    }
+
+    async fn fetch_image_attachments(
+        &self,
+        login: &Option<String>,
+    ) -> Result<Vec<DbImageAttachment>, ServerError> {
+        let state = self.lock();
+        let mut attachments: Vec<DbImageAttachment> = state.attachment_contents
+            .iter()
+            .filter_map(|content| {
+                let attachment = state.attachments
+                    .iter()
+                    .find(|attachment| attachment.id == content.attachment_id)?;
+                Some(DbImageAttachment {
+                    id: attachment.id,
+                    login: state.login_of(attachment.user_id),
+                    timestamp: attachment.timestamp.clone(),
+                    mime: content.mime.clone(),
+                    size: attachment.size,
+                })
+            })
+            .filter(|attachment| login.as_ref().is_none_or(|login| &attachment.login == login))
+            .collect();
+        attachments.sort_by(|a, b| (&b.timestamp, b.id).cmp(&(&a.timestamp, a.id)));
+        Ok(attachments)
+    }
+
+    async fn insert_filter_hit(
+        &self,
+        timestamp: &str,
+        user_id: i64,
+        rule: &str,
+        action: &str,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let id = state.next_filter_hit_id();
+        state.filter_hits.push(MemoryFilterHit {
+            id,
+            timestamp: timestamp.to_string(),
+            user_id,
+            rule: rule.to_string(),
+            action: action.to_string(),
+        });
+        Ok(id)
+    }
+
+    async fn fetch_filter_hits(&self, limit: i64) -> Result<Vec<DbFilterHit>, ServerError> {
+        let state = self.lock();
+        let hits = state.filter_hits
+            .iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .map(|hit| DbFilterHit {
+                id: hit.id,
+                timestamp: hit.timestamp.clone(),
+                login: state.login_of(hit.user_id),
+                rule: hit.rule.clone(),
+                action: hit.action.clone(),
+            })
+            .collect();
+        Ok(hits)
+    }
+
+    async fn insert_login(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        address: Option<&str>,
+    ) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        let id = state.next_login_id();
+        state.logins.push(MemoryLogin {
+            id,
+            user_id,
+            timestamp: timestamp.to_string(),
+            address: address.map(|address| address.to_string()),
+        });
+        Ok(())
+    }
+
+    async fn insert_failed_login(
+        &self,
+        timestamp: &str,
+        login: Option<&str>,
+        address: &str,
+        reason: &str,
+    ) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        let id = state.next_failed_login_id();
+        state.failed_logins.push(MemoryFailedLogin {
+            id,
+            timestamp: timestamp.to_string(),
+            login: login.map(|login| login.to_string()),
+            address: address.to_string(),
+            reason: reason.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn fetch_login_attempts(&self, limit: i64) -> Result<Vec<DbLoginAttempt>, ServerError> {
+        let state = self.lock();
+        let succeeded = state.logins.iter().map(|login| (login.id, DbLoginAttempt {
+            timestamp: login.timestamp.clone(),
+            login: Some(state.login_of(login.user_id)),
+            address: login.address.clone(),
+            succeeded: true,
+            reason: None,
+        }));
+        let failed = state.failed_logins.iter().map(|login| (login.id, DbLoginAttempt {
+            timestamp: login.timestamp.clone(),
+            login: login.login.clone(),
+            address: Some(login.address.clone()),
+            succeeded: false,
+            reason: Some(login.reason.clone()),
+        }));
+        let mut attempts: Vec<(i64, DbLoginAttempt)> = succeeded.chain(failed).collect();
+        attempts.sort_by(|(a_id, a), (b_id, b)| {
+            (&b.timestamp, a.succeeded, b_id).cmp(&(&a.timestamp, b.succeeded, a_id))
+        });
+        attempts.truncate(limit.max(0) as usize);
+        Ok(attempts.into_iter().map(|(_, attempt)| attempt).collect())
+    }
+
+    async fn fetch_chat_messages(
+        &self,
+        filter: &Option<String>,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        let state = self.lock();
+        let mut chat_messages: Vec<DbChatMessage> = state.chat_messages
+            .iter()
+            .map(|message| state.to_db_chat_message(message))
+            .filter(|message| filter.as_ref().is_none_or(|login| &message.login == login))
+            .collect();
+        chat_messages.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(chat_messages)
+    }
+
+    async fn fetch_chat_message_by_id(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessage>, ServerError> {
+        let state = self.lock();
+        Ok(state.chat_messages
+            .iter()
+            .find(|message| message.id == message_id)
+            .map(|message| state.to_db_chat_message(message)))
+    }
+
+    async fn insert_chat_message(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> Result<i64, ServerError> {
+        let mut state = self.lock();
+        let id = state.next_chat_message_id();
+        state.chat_messages.push(MemoryChatMessage {
+            id,
+            user_id,
+            timestamp: timestamp.to_string(),
+            text: text.to_string(),
+            edited_at: None,
+            deleted_at: None,
+            reply_to,
+        });
+        Ok(id)
+    }
+
+    async fn fetch_chat_message_author(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessageAuthor>, ServerError> {
+        Ok(self.lock()
+            .chat_messages
+            .iter()
+            .find(|message| message.id == message_id)
+            .map(|message| DbChatMessageAuthor {
+                user_id: message.user_id,
+                deleted_at: message.deleted_at.clone(),
+            }))
+    }
+
+    async fn update_chat_message_text(
+        &self,
+        message_id: i64,
+        text: &str,
+        edited_at: &str,
+    ) -> Result<(), ServerError> {
+        if let Some(message) = self.lock().chat_message_mut(message_id) {
+            message.text = text.to_string();
+            message.edited_at = Some(edited_at.to_string());
+        }
+        Ok(())
+    }
+
+    async fn tombstone_chat_message(
+        &self,
+        message_id: i64,
+        deleted_at: &str,
+    ) -> Result<(), ServerError> {
+        let mut state = self.lock();
+        state.reactions.retain(|reaction| reaction.message_id != message_id);
+        if let Some(message) = state.chat_message_mut(message_id) {
+            message.text = String::new();
+            message.deleted_at = Some(deleted_at.to_string());
+        }
+        Ok(())
+    }
+
+    async fn insert_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+        _timestamp: &str,
+    ) -> Result<bool, ServerError> {
+        let mut state = self.lock();
+        let exists = state.reactions.iter().any(|reaction|
+            reaction.message_id == message_id && reaction.user_id == user_id
+                && reaction.emoji == emoji
+        );
+        if !exists {
+            state.reactions.push(MemoryReaction {
+                message_id,
+                user_id,
+                emoji: emoji.to_string(),
+            });
+        }
+        Ok(!exists)
+    }
+
+    async fn delete_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+    ) -> Result<bool, ServerError> {
+        let mut state = self.lock();
+        let count = state.reactions.len();
+        state.reactions.retain(|reaction|
+            !(reaction.message_id == message_id && reaction.user_id == user_id
+                && reaction.emoji == emoji)
+        );
+        Ok(state.reactions.len() < count)
+    }
+
+    async fn fetch_prunable_chat_messages(
+        &self,
+        older_than: Option<&str>,
+        keep_last: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        let state = self.lock();
+        let mut chat_messages: Vec<&MemoryChatMessage> = state.chat_messages.iter().collect();
+        chat_messages.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+
+        // Everything but the newest `keep_last` messages is over the count limit.
+        let over_count = match keep_last {
+            Some(keep_last) => chat_messages.len().saturating_sub(keep_last.max(0) as usize),
+            None => 0,
+        };
+        Ok(chat_messages
+            .iter()
+            .enumerate()
+            .filter(|(index, message)| *index < over_count
+                || older_than.is_some_and(|older_than| message.timestamp.as_str() < older_than))
+            .take(limit.max(0) as usize)
+            .map(|(_, message)| state.to_db_chat_message(message))
+            .collect())
+    }
+
+    async fn delete_chat_messages(&self, message_ids: &[i64]) -> Result<u64, ServerError> {
+        let mut state = self.lock();
+        state.reactions.retain(|reaction| !message_ids.contains(&reaction.message_id));
+        state.mentions.retain(|mention| !message_ids.contains(&mention.message_id));
+        for message in state.chat_messages.iter_mut() {
+            if message.reply_to.is_some_and(|reply_to| message_ids.contains(&reply_to)) {
+                message.reply_to = None;
+            }
+        }
+        let count = state.chat_messages.len();
+        state.chat_messages.retain(|message| !message_ids.contains(&message.id));
+        Ok((count - state.chat_messages.len()) as u64)
+    }
+
+    async fn fetch_prunable_logins(
+        &self,
+        older_than: &str,
+        limit: i64,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        let state = self.lock();
+        let mut logins: Vec<DbClientLogin> = state.logins
+            .iter()
+            .filter(|login| login.timestamp.as_str() < older_than)
+            .map(|login| DbClientLogin {
+                id: login.id,
+                user_id: login.user_id,
+                login: state.login_of(login.user_id),
+                timestamp: login.timestamp.clone(),
+            })
+            .collect();
+        logins.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        logins.truncate(limit.max(0) as usize);
+        Ok(logins)
+    }
+
+    async fn delete_logins(&self, login_ids: &[i64]) -> Result<u64, ServerError> {
+        let mut state = self.lock();
+        let count = state.logins.len();
+        state.logins.retain(|login| !login_ids.contains(&login.id));
+        Ok((count - state.logins.len()) as u64)
+    }
+
+    async fn delete_failed_logins(&self, older_than: &str) -> Result<u64, ServerError> {
+        let mut state = self.lock();
+        let count = state.failed_logins.len();
+        state.failed_logins.retain(|login| login.timestamp.as_str() >= older_than);
+        Ok((count - state.failed_logins.len()) as u64)
+    }
+
+    async fn fetch_chat_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        let state = self.lock();
+        let mut chat_messages: Vec<DbChatMessage> = state.chat_messages
+            .iter()
+            .map(|message| state.to_db_chat_message(message))
+            .filter(|message| filter.matches(&message.timestamp, &message.login))
+            .collect();
+        chat_messages.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        Ok(chat_messages)
+    }
+
+    async fn fetch_login_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        let state = self.lock();
+        let mut logins: Vec<DbClientLogin> = state.logins
+            .iter()
+            .map(|login| DbClientLogin {
+                id: login.id,
+                user_id: login.user_id,
+                login: state.login_of(login.user_id),
+                timestamp: login.timestamp.clone(),
+            })
+            .filter(|login| filter.matches(&login.timestamp, &login.login))
+            .collect();
+        logins.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        Ok(logins)
+    }
+
+    async fn import_user(&self, user: &DbUser) -> Result<(i64, bool), ServerError> {
+        let mut state = self.lock();
+        if let Some(existing) = state.users.iter().find(|existing| existing.login == user.login) {
+            return Ok((existing.id, false));
+        }
+        let id = state.next_user_id();
+        state.users.push(DbUser { id, ..user.clone() });
+        Ok((id, true))
+    }
+
+    async fn import_chat_message(
+        &self,
+        user_id: i64,
+        message: &DbChatMessage,
+        reply_to: Option<i64>,
+    ) -> Result<(i64, bool), ServerError> {
+        let mut state = self.lock();
+        let existing = state.chat_messages.iter().find(|existing| existing.user_id == user_id
+            && existing.timestamp == message.timestamp
+            && existing.text == message.text);
+        if let Some(existing) = existing {
+            return Ok((existing.id, false));
+        }
+        let id = state.next_chat_message_id();
+        state.chat_messages.push(MemoryChatMessage {
+            id,
+            user_id,
+            timestamp: message.timestamp.clone(),
+            text: message.text.clone(),
+            edited_at: message.edited_at.clone(),
+            deleted_at: message.deleted_at.clone(),
+            reply_to,
+        });
+        Ok((id, true))
+    }
+
+    async fn import_login(&self, user_id: i64, timestamp: &str) -> Result<bool, ServerError> {
+        let mut state = self.lock();
+        let exists = state.logins
+            .iter()
+            .any(|login| login.user_id == user_id && login.timestamp == timestamp);
+        if exists {
+            return Ok(false);
+        }
+        let id = state.next_login_id();
+        state.logins.push(MemoryLogin {
+            id,
+            user_id,
+            timestamp: timestamp.to_string(),
+            address: None,
+        });
+        Ok(true)
+    }
+
+    async fn fetch_reaction_summaries(&self) -> Result<Vec<DbReactionSummary>, ServerError> {
+        let state = self.lock();
+        let mut summaries: Vec<DbReactionSummary> = vec![];
+        for reaction in state.reactions.iter() {
+            let login = state.login_of(reaction.user_id);
+            let summary = summaries.iter_mut().find(|summary|
+                summary.message_id == reaction.message_id && summary.emoji == reaction.emoji
+            );
+            match summary {
+                Some(summary) => {
+                    summary.count += 1;
+                    summary.logins = format!("{}, {}", summary.logins, login);
+                },
+                None => summaries.push(DbReactionSummary {
+                    message_id: reaction.message_id,
+                    emoji: reaction.emoji.clone(),
+                    count: 1,
+                    logins: login,
+                }),
+            }
+        }
+        // Stable sort keeps order of the first reaction within a single message.
+        summaries.sort_by_key(|summary| summary.message_id);
+        Ok(summaries)
+    }
+
+    async fn search_chat_messages(
+        &self,
+        text: &str,
+        highlight: (&str, &str),
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<DbSearchHit>), ServerError> {
+        let words: Vec<String> = text.split_whitespace().map(normalize_word).collect();
+        if words.is_empty() {
+            return Ok((0, vec![]));
+        }
+
+        let state = self.lock();
+        let mut found: Vec<&MemoryChatMessage> = state.chat_messages
+            .iter()
+            .filter(|message| {
+                let message_words: Vec<String> =
+                    message.text.split_whitespace().map(normalize_word).collect();
+                words.iter().all(|word| message_words.contains(word))
+            })
+            .collect();
+        found.sort_by(|a, b| (&b.timestamp, b.id).cmp(&(&a.timestamp, a.id)));
+
+        let (highlight_start, highlight_end) = highlight;
+        let hits = found
+            .iter()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|message| DbSearchHit {
+                id: message.id,
+                login: state.login_of(message.user_id),
+                timestamp: message.timestamp.clone(),
+                snippet: message.text
+                    .split_whitespace()
+                    .map(|word| if words.contains(&normalize_word(word)) {
+                        format!("{}{}{}", highlight_start, word, highlight_end)
+                    } else {
+                        word.to_string()
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            })
+            .collect();
+
+        Ok((found.len() as i64, hits))
+    }
+}
+
+
+/// `normalize_word` lower-cases the given word and strips punctuation around it, so words
+/// are compared the same way as by full-text index of SQLite.
+fn normalize_word(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryStorage;
+    use crate::storage::Storage;
+
+
+    #[tokio::test]
+    async fn test_user_authentication() {
+        let storage = MemoryStorage::with_default_users();
+
+        let user = storage
+            .fetch_user_by_login_and_password("TheOne", "C4CA4238A0B923820DCC509A6F75849B")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.login, "TheOne");
+        assert_eq!(user.role, "user");
+
+        let user = storage.fetch_user_by_login_and_password("TheOne", "wrong").await.unwrap();
+        assert!(user.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_role() {
+        let storage = MemoryStorage::with_default_users();
+
+        assert!(storage.update_role("TheOne", "moderator").await.unwrap());
+        assert!(!storage.update_role("Nobody", "moderator").await.unwrap());
+
+        let users = storage.fetch_users().await.unwrap();
+        let roles: Vec<(&str, &str)> = users
+            .iter()
+            .map(|user| (user.login.as_str(), user.role.as_str()))
+            .collect();
+        assert!(roles.contains(&("TheOne", "moderator")));
+        assert!(roles.contains(&("JustTwo", "user")));
+    }
+
+    #[tokio::test]
+    async fn test_chat_message_lifecycle() {
+        let storage = MemoryStorage::new();
+        let author_id = storage.insert_user("author", "", "user");
+        let reader_id = storage.insert_user("reader", "", "user");
+
+        let id = storage.insert_chat_message(author_id, "2023-12-01", "hi", None).await.unwrap();
+        let reply_id = storage
+            .insert_chat_message(reader_id, "2023-12-02", "hello", Some(id))
+            .await
+            .unwrap();
+
+        let messages = storage.fetch_chat_messages(&None).await.unwrap();
+        assert_eq!(messages.iter().map(|m| m.id).collect::<Vec<i64>>(), vec![reply_id, id]);
+        let messages = storage.fetch_chat_messages(&Some("author".to_string())).await.unwrap();
+        assert_eq!(messages.len(), 1);
+
+        storage.update_chat_message_text(id, "hi all", "2023-12-03").await.unwrap();
+        let message = storage.fetch_chat_message_by_id(id).await.unwrap().unwrap();
+        assert_eq!(message.text, "hi all");
+        assert_eq!(message.edited_at.as_deref(), Some("2023-12-03"));
+
+        assert!(storage.insert_reaction(id, reader_id, "👍", "2023-12-04").await.unwrap());
+        assert!(!storage.insert_reaction(id, reader_id, "👍", "2023-12-04").await.unwrap());
+        assert!(storage.insert_reaction(id, author_id, "👍", "2023-12-04").await.unwrap());
+        let summaries = storage.fetch_reaction_summaries().await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].count, 2);
+        assert_eq!(summaries[0].logins, "reader, author");
+
+        storage.tombstone_chat_message(id, "2023-12-05").await.unwrap();
+        let author = storage.fetch_chat_message_author(id).await.unwrap().unwrap();
+        assert_eq!(author.user_id, author_id);
+        assert_eq!(author.deleted_at.as_deref(), Some("2023-12-05"));
+        assert!(storage.fetch_reaction_summaries().await.unwrap().is_empty());
+        assert!(!storage.delete_reaction(id, reader_id, "👍").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_search_chat_messages() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        storage.insert_chat_message(user_id, "2023-12-01", "Rust is fun", None).await.unwrap();
+        storage.insert_chat_message(user_id, "2023-12-02", "async rust, really", None).await.unwrap();
+        storage.insert_chat_message(user_id, "2023-12-03", "nothing here", None).await.unwrap();
+
+        let (total, hits) = storage.search_chat_messages("RUST", ("[", "]"), 1, 0).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "async [rust,] really");
+
+        let (_, hits) = storage.search_chat_messages("rust", ("[", "]"), 1, 1).await.unwrap();
+        assert_eq!(hits[0].snippet, "[Rust] is fun");
+
+        let (total, _) = storage.search_chat_messages("rust fun", ("", ""), 10, 0).await.unwrap();
+        assert_eq!(total, 1);
+        let (total, _) = storage.search_chat_messages(" ", ("", ""), 10, 0).await.unwrap();
+        assert_eq!(total, 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_by_id() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("leaving", "", "user");
+        let other_id = storage.insert_user("staying", "", "user");
+        let id = storage.insert_chat_message(user_id, "2023-12-01", "bye", None).await.unwrap();
+        let other = storage.insert_chat_message(other_id, "2023-12-01", "hi", None).await.unwrap();
+        let reply = storage.insert_chat_message(other_id, "2023-12-02", "no!", Some(id)).await;
+        let reply = reply.unwrap();
+        storage.insert_reaction(id, other_id, "👋", "2023-12-01").await.unwrap();
+        storage.insert_reaction(other, user_id, "👋", "2023-12-01").await.unwrap();
+        storage.insert_login(user_id, "2023-12-01", None).await.unwrap();
+
+        storage.delete_user_by_id(user_id).await.unwrap();
+
+        let users = storage.fetch_users().await.unwrap();
+        assert_eq!(users.iter().map(|u| u.id).collect::<Vec<i64>>(), vec![other_id]);
+        assert!(storage.fetch_chat_message_by_id(id).await.unwrap().is_none());
+        let reply = storage.fetch_chat_message_by_id(reply).await.unwrap().unwrap();
+        assert_eq!(reply.reply_to, None);
+        assert!(storage.fetch_reaction_summaries().await.unwrap().is_empty());
+        assert!(storage.lock().logins.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        let other_id = storage.insert_user("other", "", "user");
+        let first = storage.insert_session(user_id, "h1", "2023-12-01", "2023-12-08").await;
+        let first = first.unwrap();
+        let second = storage.insert_session(user_id, "h2", "2023-12-02", "2023-12-03").await;
+        let second = second.unwrap();
+        storage.insert_session(other_id, "h3", "2023-12-01", "2023-12-08").await.unwrap();
+        assert!(storage.insert_session(other_id, "h1", "2023-12-01", "2023-12-08").await.is_err());
+
+        let session = storage.fetch_session_by_token("h2").await.unwrap().unwrap();
+        assert_eq!((session.id, session.login.as_str()), (second, "user"));
+        assert!(storage.fetch_session_by_token("h4").await.unwrap().is_none());
+
+        let now = "2023-12-02T10:00:00";
+        let active = storage.fetch_active_sessions(Some(user_id), now).await.unwrap();
+        assert_eq!(active.iter().map(|s| s.id).collect::<Vec<i64>>(), vec![first, second]);
+        let active = storage.fetch_active_sessions(None, "2023-12-04").await.unwrap();
+        let logins = active.iter().map(|s| s.login.as_str()).collect::<Vec<&str>>();
+        assert_eq!(logins, ["other", "user"]);
+
+        assert!(storage.revoke_session(first, "2023-12-04").await.unwrap());
+        assert!(!storage.revoke_session(first, "2023-12-05").await.unwrap());
+        let active = storage.fetch_active_sessions(Some(user_id), "2023-12-04").await.unwrap();
+        assert!(active.is_empty());
+        let session = storage.fetch_session_by_token("h1").await.unwrap().unwrap();
+        assert!(!session.is_active("2023-12-04"));
+    }
+
+    #[tokio::test]
+    async fn test_direct_messages() {
+        let storage = MemoryStorage::new();
+        let sender_id = storage.insert_user("sender", "", "user");
+        let recipient_id = storage.insert_user("recipient", "", "user");
+        let later = storage.insert_direct_message(sender_id, recipient_id, "2023-12-02", "second")
+            .await
+            .unwrap();
+        let first = storage.insert_direct_message(sender_id, recipient_id, "2023-12-01", "first")
+            .await
+            .unwrap();
+        storage.insert_direct_message(recipient_id, sender_id, "2023-12-01", "re").await.unwrap();
+
+        let queued = storage.fetch_undelivered_direct_messages(recipient_id).await.unwrap();
+        let texts: Vec<&str> = queued.iter().map(|message| message.text.as_str()).collect();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!((queued[0].login.as_str(), queued[0].to.as_str()), ("sender", "recipient"));
+
+        let marked = storage.mark_direct_messages_delivered(&[first], "2023-12-03").await;
+        assert_eq!(marked.unwrap(), 1);
+        let marked = storage.mark_direct_messages_delivered(&[first, later], "2023-12-04").await;
+        assert_eq!(marked.unwrap(), 1);
+        assert!(storage.fetch_undelivered_direct_messages(recipient_id).await.unwrap().is_empty());
+
+        storage.delete_user_by_id(recipient_id).await.unwrap();
+        assert!(storage.lock().direct_messages.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mentions() {
+        let storage = MemoryStorage::new();
+        let author_id = storage.insert_user("author", "", "user");
+        let reader_id = storage.insert_user("reader", "", "user");
+        let later = storage.insert_chat_message(author_id, "2023-12-02", "@reader?", None)
+            .await
+            .unwrap();
+        let first = storage.insert_chat_message(author_id, "2023-12-01", "@reader!", None)
+            .await
+            .unwrap();
+        let deleted = storage.insert_chat_message(author_id, "2023-12-01", "@reader", None)
+            .await
+            .unwrap();
+        let later_mention = storage.insert_mention(later, reader_id, None).await.unwrap();
+        storage.insert_mention(first, reader_id, None).await.unwrap();
+        storage.insert_mention(deleted, reader_id, None).await.unwrap();
+        storage.insert_mention(first, author_id, Some("2023-12-01")).await.unwrap();
+        storage.tombstone_chat_message(deleted, "2023-12-03").await.unwrap();
+
+        let unread = storage.fetch_unread_mentions(reader_id).await.unwrap();
+        let texts: Vec<&str> = unread.iter().map(|mention| mention.text.as_str()).collect();
+        assert_eq!(texts, vec!["@reader!", "@reader?"]);
+        assert_eq!((unread[0].message_id, unread[0].login.as_str()), (first, "author"));
+        assert!(storage.fetch_unread_mentions(author_id).await.unwrap().is_empty());
+
+        let marked = storage.mark_mentions_read(&[later_mention], "2023-12-04").await;
+        assert_eq!(marked.unwrap(), 1);
+        let marked = storage.mark_mentions_read(&[later_mention], "2023-12-05").await;
+        assert_eq!(marked.unwrap(), 0);
+        assert_eq!(storage.fetch_unread_mentions(reader_id).await.unwrap().len(), 1);
+
+        storage.delete_chat_messages(&[first]).await.unwrap();
+        assert!(storage.fetch_unread_mentions(reader_id).await.unwrap().is_empty());
+        storage.delete_user_by_id(author_id).await.unwrap();
+        assert!(storage.lock().mentions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_attachment_usage() {
+        let storage = MemoryStorage::new();
+        let one_id = storage.insert_user("one", "", "user");
+        let two_id = storage.insert_user("two", "", "user");
+        storage.insert_attachment(two_id, "2023-12-01", "file", Some("a.txt"), 10).await.unwrap();
+        storage.insert_attachment(two_id, "2023-12-02", "image", None, 20).await.unwrap();
+        storage.insert_attachment(two_id, "2023-12-03", "file", Some("b.txt"), 30).await.unwrap();
+        storage.insert_attachment(one_id, "2023-12-02", "file", Some("c.txt"), 5).await.unwrap();
+
+        let usage = storage.fetch_attachment_usage(None, "2023-12-02").await.unwrap();
+        let usage: Vec<(&str, i64, i64)> = usage
+            .iter()
+            .map(|usage| (usage.login.as_str(), usage.count, usage.bytes))
+            .collect();
+        assert_eq!(usage, vec![("one", 1, 5), ("two", 2, 50)]);
+
+        let usage = storage.fetch_attachment_usage(Some(one_id), "2023-12-03").await.unwrap();
+        assert!(usage.is_empty());
+
+        storage.delete_user_by_id(two_id).await.unwrap();
+        assert_eq!(storage.fetch_attachment_usage(None, "").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_attachment_contents() {
+        let storage = MemoryStorage::new();
+        let one_id = storage.insert_user("one", "", "user");
+        let two_id = storage.insert_user("two", "", "user");
+        let first = storage.insert_attachment(one_id, "2023-12-01", "image", None, 3);
+        let first = first.await.unwrap();
+        let file = storage.insert_attachment(one_id, "2023-12-02", "file", None, 1);
+        let file = file.await.unwrap();
+        let second = storage.insert_attachment(two_id, "2023-12-03", "image", None, 2);
+        let second = second.await.unwrap();
+        storage.insert_attachment_content(first, "image/png", &[1, 2, 3], &[9]).await.unwrap();
+        storage.insert_attachment_content(second, "image/gif", &[4, 5], &[8]).await.unwrap();
+        assert!(storage.insert_attachment_content(42, "image/png", &[], &[]).await.is_err());
+
+        let content = storage.fetch_attachment_content(first).await.unwrap().unwrap();
+        assert_eq!((content.login.as_str(), content.mime.as_str()), ("one", "image/png"));
+        assert_eq!((content.payload, content.thumbnail), (vec![1, 2, 3], vec![9]));
+        assert_eq!(storage.fetch_attachment_content(file).await.unwrap(), None);
+
+        let ids: Vec<i64> = storage.fetch_image_attachments(&None).await.unwrap()
+            .iter()
+            .map(|attachment| attachment.id)
+            .collect();
+        assert_eq!(ids, vec![second, first]);
+        let images = storage.fetch_image_attachments(&Some("two".to_string())).await.unwrap();
+        assert_eq!((images.len(), images[0].size), (1, 2));
+
+        storage.delete_user_by_id(two_id).await.unwrap();
+        assert_eq!(storage.fetch_attachment_content(second).await.unwrap(), None);
+        assert_eq!(storage.fetch_image_attachments(&None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_filter_hits() {
+        let storage = MemoryStorage::new();
+        let one_id = storage.insert_user("one", "", "user");
+        let two_id = storage.insert_user("two", "", "user");
+        storage.insert_filter_hit("2023-12-01", one_id, "api-key", "mask").await.unwrap();
+        storage.insert_filter_hit("2023-12-02", two_id, "slurs", "reject").await.unwrap();
+        storage.insert_filter_hit("2023-12-03", one_id, "slurs", "reject").await.unwrap();
+
+        let hits = storage.fetch_filter_hits(2).await.unwrap();
+        let hits: Vec<(&str, &str, &str)> = hits
+            .iter()
+            .map(|hit| (hit.timestamp.as_str(), hit.login.as_str(), hit.rule.as_str()))
+            .collect();
+        assert_eq!(hits, vec![("2023-12-03", "one", "slurs"), ("2023-12-02", "two", "slurs")]);
+
+        storage.delete_user_by_id(one_id).await.unwrap();
+        let hits = storage.fetch_filter_hits(10).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].login.as_str(), hits[0].action.as_str()), ("two", "reject"));
+    }
+
+    #[tokio::test]
+    async fn test_login_attempts() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("user", "", "user");
+        storage.insert_login(user_id, "2023-12-01", None).await.unwrap();
+        let address = "10.0.0.1:1234";
+        storage.insert_failed_login("2023-12-02", Some("user"), address, "wrong").await.unwrap();
+        storage.insert_failed_login("2023-12-03", None, address, "locked out").await.unwrap();
+        storage.insert_login(user_id, "2023-12-03", Some("10.0.0.2:1111")).await.unwrap();
+
+        let attempts = storage.fetch_login_attempts(10).await.unwrap();
+        let summary: Vec<(&str, bool)> = attempts
+            .iter()
+            .map(|attempt| (attempt.timestamp.as_str(), attempt.succeeded))
+            .collect();
+        assert_eq!(summary, vec![
+            ("2023-12-03", false),
+            ("2023-12-03", true),
+            ("2023-12-02", false),
+            ("2023-12-01", true),
+        ]);
+        assert_eq!(attempts[0].login, None);
+        assert_eq!(attempts[0].reason.as_deref(), Some("locked out"));
+        assert_eq!(attempts[1].address.as_deref(), Some("10.0.0.2:1111"));
+        assert_eq!(storage.fetch_login_attempts(1).await.unwrap().len(), 1);
+
+        assert_eq!(storage.delete_failed_logins("2023-12-03").await.unwrap(), 1);
+        assert_eq!(storage.fetch_login_attempts(10).await.unwrap().len(), 3);
+    }
+}
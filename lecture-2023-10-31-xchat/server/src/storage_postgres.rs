@@ -0,0 +1,1329 @@
+use std::path::Path;
+use std::result::Result;
+
+use async_trait::async_trait;
+use sqlx::{query, query_as, query_scalar};
+use sqlx::migrate::{Migrate, Migrator};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+use crate::ServerError;
+use crate::storage::{
+    DbAttachmentContent,
+    DbAttachmentUsage,
+    DbChatMessage,
+    DbChatMessageAuthor,
+    DbClientLogin,
+    DbDirectMessage,
+    DbFilterHit,
+    DbImageAttachment,
+    DbLoginAttempt,
+    DbMention,
+    DbReactionSummary,
+    DbSearchHit,
+    DbSession,
+    DbTopic,
+    DbUser,
+    HistoryFilter,
+    Storage,
+    pending_migrations,
+};
+
+
+/// `MIGRATOR` holds PostgreSQL schema migrations (`migrations_postgres/`) embedded into
+/// the server.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations_postgres");
+
+
+/// `PostgresStorage` is [Storage] backed by PostgreSQL database.
+///
+/// Queries are checked at runtime only (compile-time checked queries of `sqlx` would require
+/// a running PostgreSQL server for every build).
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+
+impl PostgresStorage {
+    /// `connect` opens pool of connections to PostgreSQL database of the given URL.
+    pub async fn connect(db_url: &str) -> Result<PostgresStorage, ServerError> {
+        match PgPool::connect(db_url).await {
+            Ok(pool) => Ok(PostgresStorage { pool }),
+            Err(err) => Err(ServerError::DBError(err.to_string())),
+        }
+    }
+}
+
+
+/// `db_error` converts error of `sqlx` into [ServerError].
+fn db_error(err: sqlx::Error) -> ServerError {
+    ServerError::DBError(err.to_string())
+}
+
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn migrate(&self, apply: bool) -> Result<usize, ServerError> {
+        let applied = {
+            let mut conn = self.pool.acquire().await.map_err(db_error)?;
+            conn.ensure_migrations_table()
+                .await
+                .map_err(|err| ServerError::SchemaError(err.to_string()))?;
+            conn.list_applied_migrations()
+                .await
+                .map_err(|err| ServerError::SchemaError(err.to_string()))?
+        };
+
+        let pending = pending_migrations(&MIGRATOR, &applied, apply)?;
+        if pending > 0 {
+            MIGRATOR.run(&self.pool)
+                .await
+                .map_err(|err| ServerError::SchemaError(err.to_string()))?;
+        }
+        Ok(pending)
+    }
+
+    async fn backup(&self, _path: &Path) -> Result<(), ServerError> {
+        Err(ServerError::BackupError(
+            "PostgreSQL storage is backed up by its own tools (e.g. `pg_dump`)".to_string()
+        ))
+    }
+
+    #[instrument(level = "debug", skip(self, password), err)]
+    async fn fetch_user_by_login_and_password(
+        &self,
+        login: &str,
+        password: &str,
+    ) -> Result<Option<DbUser>, ServerError> {
+        query_as::<_, DbUser>(r#"
+SELECT id, login, password, role, display_name
+FROM users
+WHERE
+    login = $1
+    AND
+    LOWER(password) = LOWER($2)
+;"#)
+            .bind(login)
+            .bind(password)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_users(&self) -> Result<Vec<DbUser>, ServerError> {
+        query_as::<_, DbUser>(r#"
+SELECT id, login, password, role, display_name
+FROM users
+ORDER BY login ASC
+;"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_user_by_id(&self, user_id: i64) -> Result<Option<DbUser>, ServerError> {
+        query_as::<_, DbUser>(r#"
+SELECT id, login, password, role, display_name
+FROM users
+WHERE id = $1
+;"#)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn delete_user_by_id(&self, user_id: i64) -> Result<(), ServerError> {
+        let mut transaction = self.pool.begin().await.map_err(db_error)?;
+
+        let statements = [
+            r#"
+DELETE FROM message_reactions
+WHERE
+    user_id = $1
+    OR
+    message_id IN (SELECT id FROM chat_messages WHERE user_id = $1)
+;"#,
+            r#"
+DELETE FROM mentions
+WHERE
+    user_id = $1
+    OR
+    message_id IN (SELECT id FROM chat_messages WHERE user_id = $1)
+;"#,
+            // Replies of other users must not reference the deleted messages.
+            r#"
+UPDATE chat_messages
+SET reply_to = NULL
+WHERE reply_to IN (SELECT id FROM chat_messages WHERE user_id = $1)
+;"#,
+            r#"
+DELETE FROM chat_messages
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM room_topics
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM sessions
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM direct_messages
+WHERE sender_id = $1 OR recipient_id = $1
+;"#,
+            r#"
+DELETE FROM attachment_contents
+WHERE attachment_id IN (SELECT id FROM attachments WHERE user_id = $1)
+;"#,            r#"
+DELETE FROM attachments
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM filter_hits
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM client_logins
+WHERE user_id = $1
+;"#,
+            r#"
+DELETE FROM users
+WHERE id = $1
+;"#,
+        ];
+        for statement in statements {
+            query(statement)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await
+                .map_err(db_error)?;
+        }
+
+        transaction.commit().await.map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn update_display_name(
+        &self,
+        user_id: i64,
+        display_name: Option<&str>,
+    ) -> Result<(), ServerError> {
+        query(r#"
+UPDATE users
+SET display_name = $2
+WHERE id = $1
+;"#)
+            .bind(user_id)
+            .bind(display_name)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn update_role(&self, login: &str, role: &str) -> Result<bool, ServerError> {
+        query(r#"
+UPDATE users
+SET role = $2
+WHERE login = $1
+;"#)
+            .bind(login)
+            .bind(role)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_topic(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        topic: &str,
+    ) -> Result<(), ServerError> {
+        query(r#"
+INSERT INTO room_topics
+(user_id, timestamp, topic)
+VALUES
+($1, $2, $3)
+;"#)
+            .bind(user_id)
+            .bind(timestamp)
+            .bind(topic)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_topic(&self) -> Result<Option<DbTopic>, ServerError> {
+        let topic = query_as::<_, DbTopic>(r#"
+SELECT
+    rt.topic,
+    u.login,
+    rt.timestamp
+FROM room_topics AS rt
+    JOIN users AS u ON u.id = rt.user_id
+ORDER BY rt.id DESC
+LIMIT 1
+;"#)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)?;
+        Ok(topic.filter(|topic| !topic.topic.is_empty()))
+    }
+
+    #[instrument(level = "debug", skip(self, token_hash), err)]
+    async fn insert_session(
+        &self,
+        user_id: i64,
+        token_hash: &str,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<i64, ServerError> {
+        query_scalar::<_, i64>(r#"
+INSERT INTO sessions
+(user_id, token_hash, created_at, expires_at)
+VALUES
+($1, $2, $3, $4)
+RETURNING id
+;"#)
+            .bind(user_id)
+            .bind(token_hash)
+            .bind(created_at)
+            .bind(expires_at)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, token_hash), err)]
+    async fn fetch_session_by_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<DbSession>, ServerError> {
+        query_as::<_, DbSession>(r#"
+SELECT
+    s.id,
+    s.user_id,
+    u.login,
+    s.created_at,
+    s.expires_at,
+    s.revoked_at
+FROM sessions AS s
+    JOIN users AS u ON u.id = s.user_id
+WHERE s.token_hash = $1
+;"#)
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_active_sessions(
+        &self,
+        user_id: Option<i64>,
+        now: &str,
+    ) -> Result<Vec<DbSession>, ServerError> {
+        query_as::<_, DbSession>(r#"
+SELECT
+    s.id,
+    s.user_id,
+    u.login,
+    s.created_at,
+    s.expires_at,
+    s.revoked_at
+FROM sessions AS s
+    JOIN users AS u ON u.id = s.user_id
+WHERE
+    s.revoked_at IS NULL
+    AND s.expires_at > $2
+    AND ($1::BIGINT IS NULL OR s.user_id = $1)
+ORDER BY u.login ASC, s.created_at ASC, s.id ASC
+;"#)
+            .bind(user_id)
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn revoke_session(&self, session_id: i64, revoked_at: &str) -> Result<bool, ServerError> {
+        query(r#"
+UPDATE sessions
+SET revoked_at = $2
+WHERE
+    id = $1
+    AND revoked_at IS NULL
+;"#)
+            .bind(session_id)
+            .bind(revoked_at)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, text), err)]
+    async fn insert_direct_message(
+        &self,
+        sender_id: i64,
+        recipient_id: i64,
+        timestamp: &str,
+        text: &str,
+    ) -> Result<i64, ServerError> {
+        query_scalar(r#"
+INSERT INTO direct_messages
+(sender_id, recipient_id, timestamp, text)
+VALUES
+($1, $2, $3, $4)
+RETURNING id
+;"#)
+            .bind(sender_id)
+            .bind(recipient_id)
+            .bind(timestamp)
+            .bind(text)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_undelivered_direct_messages(
+        &self,
+        recipient_id: i64,
+    ) -> Result<Vec<DbDirectMessage>, ServerError> {
+        query_as::<_, DbDirectMessage>(r#"
+SELECT
+    dm.id,
+    s.login,
+    s.display_name,
+    r.login AS "to",
+    dm.timestamp,
+    dm.text
+FROM direct_messages AS dm
+    JOIN users AS s ON s.id = dm.sender_id
+    JOIN users AS r ON r.id = dm.recipient_id
+WHERE
+    dm.recipient_id = $1
+    AND dm.delivered_at IS NULL
+ORDER BY dm.timestamp ASC, dm.id ASC
+;"#)
+            .bind(recipient_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn mark_direct_messages_delivered(
+        &self,
+        message_ids: &[i64],
+        delivered_at: &str,
+    ) -> Result<u64, ServerError> {
+        query(r#"
+UPDATE direct_messages
+SET delivered_at = $2
+WHERE
+    id = ANY($1)
+    AND delivered_at IS NULL
+;"#)
+            .bind(message_ids)
+            .bind(delivered_at)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_mention(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        read_at: Option<&str>,
+    ) -> Result<i64, ServerError> {
+        query_scalar(r#"
+INSERT INTO mentions
+(message_id, user_id, read_at)
+VALUES
+($1, $2, $3)
+RETURNING id
+;"#)
+            .bind(message_id)
+            .bind(user_id)
+            .bind(read_at)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_unread_mentions(&self, user_id: i64) -> Result<Vec<DbMention>, ServerError> {
+        query_as::<_, DbMention>(r#"
+SELECT
+    m.id,
+    m.message_id,
+    u.login,
+    cm.timestamp,
+    cm.text
+FROM mentions AS m
+    JOIN chat_messages AS cm ON cm.id = m.message_id
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    m.user_id = $1
+    AND m.read_at IS NULL
+    AND cm.deleted_at IS NULL
+ORDER BY cm.timestamp ASC, m.id ASC
+;"#)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn mark_mentions_read(
+        &self,
+        mention_ids: &[i64],
+        read_at: &str,
+    ) -> Result<u64, ServerError> {
+        query(r#"
+UPDATE mentions
+SET read_at = $2
+WHERE
+    id = ANY($1)
+    AND read_at IS NULL
+;"#)
+            .bind(mention_ids)
+            .bind(read_at)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_attachment(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        kind: &str,
+        filename: Option<&str>,
+        size: i64,
+    ) -> Result<i64, ServerError> {
+        query_scalar(r#"
+INSERT INTO attachments
+(user_id, timestamp, kind, filename, size)
+VALUES
+($1, $2, $3, $4, $5)
+RETURNING id
+;"#)
+            .bind(user_id)
+            .bind(timestamp)
+            .bind(kind)
+            .bind(filename)
+            .bind(size)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_attachment_usage(
+        &self,
+        user_id: Option<i64>,
+        since: &str,
+    ) -> Result<Vec<DbAttachmentUsage>, ServerError> {
+        query_as::<_, DbAttachmentUsage>(r#"
+SELECT
+    a.user_id,
+    u.login,
+    COUNT(*) AS count,
+    SUM(a.size)::BIGINT AS bytes
+FROM attachments AS a
+    JOIN users AS u ON u.id = a.user_id
+WHERE
+    a.timestamp >= $2
+    AND ($1::BIGINT IS NULL OR a.user_id = $1)
+GROUP BY a.user_id, u.login
+ORDER BY u.login ASC
+;"#)
+            .bind(user_id)
+            .bind(since)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, payload, thumbnail), err)]
+    async fn insert_attachment_content(
+        &self,
+        attachment_id: i64,
+        mime: &str,
+        payload: &[u8],
+        thumbnail: &[u8],
+    ) -> Result<(), ServerError> {
+        query(r#"
+INSERT INTO attachment_contents
+(attachment_id, mime, payload, thumbnail)
+VALUES
+($1, $2, $3, $4)
+;"#)
+            .bind(attachment_id)
+            .bind(mime)
+            .bind(payload)
+            .bind(thumbnail)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_attachment_content(
+        &self,
+        attachment_id: i64,
+    ) -> Result<Option<DbAttachmentContent>, ServerError> {
+        query_as::<_, DbAttachmentContent>(r#"
+SELECT
+    a.id,
+    u.login,
+    a.timestamp,
+    ac.mime,
+    ac.payload,
+    ac.thumbnail
+FROM attachment_contents AS ac
+    JOIN attachments AS a ON a.id = ac.attachment_id
+    JOIN users AS u ON u.id = a.user_id
+WHERE ac.attachment_id = $1
+;"#)
+            .bind(attachment_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_image_attachments(
+        &self,
+        login: &Option<String>,
+    ) -> Result<Vec<DbImageAttachment>, ServerError> {
+        query_as::<_, DbImageAttachment>(r#"
+SELECT
+    a.id,
+    u.login,
+    a.timestamp,
+    ac.mime,
+    a.size
+FROM attachment_contents AS ac
+    JOIN attachments AS a ON a.id = ac.attachment_id
+    JOIN users AS u ON u.id = a.user_id
+WHERE $1::TEXT IS NULL OR u.login = $1
+ORDER BY a.timestamp DESC, a.id DESC
+;"#)
+            .bind(login)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_filter_hit(
+        &self,
+        timestamp: &str,
+        user_id: i64,
+        rule: &str,
+        action: &str,
+    ) -> Result<i64, ServerError> {
+        query_scalar(r#"
+INSERT INTO filter_hits
+(timestamp, user_id, rule, action)
+VALUES
+($1, $2, $3, $4)
+RETURNING id
+;"#)
+            .bind(timestamp)
+            .bind(user_id)
+            .bind(rule)
+            .bind(action)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_filter_hits(&self, limit: i64) -> Result<Vec<DbFilterHit>, ServerError> {
+        query_as::<_, DbFilterHit>(r#"
+SELECT
+    fh.id,
+    fh.timestamp,
+    u.login,
+    fh.rule,
+    fh.action
+FROM filter_hits AS fh
+    JOIN users AS u ON u.id = fh.user_id
+ORDER BY fh.id DESC
+LIMIT $1
+;"#)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_login(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        address: Option<&str>,
+    ) -> Result<(), ServerError> {
+        query(r#"
+INSERT INTO client_logins
+(user_id, timestamp, address)
+VALUES
+($1, $2, $3)
+;"#)
+            .bind(user_id)
+            .bind(timestamp)
+            .bind(address)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_failed_login(
+        &self,
+        timestamp: &str,
+        login: Option<&str>,
+        address: &str,
+        reason: &str,
+    ) -> Result<(), ServerError> {
+        query(r#"
+INSERT INTO failed_logins
+(timestamp, login, address, reason)
+VALUES
+($1, $2, $3, $4)
+;"#)
+            .bind(timestamp)
+            .bind(login)
+            .bind(address)
+            .bind(reason)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_login_attempts(&self, limit: i64) -> Result<Vec<DbLoginAttempt>, ServerError> {
+        query_as::<_, DbLoginAttempt>(r#"
+SELECT
+    timestamp,
+    login,
+    address,
+    succeeded,
+    reason
+FROM (
+    SELECT
+        cl.timestamp,
+        u.login,
+        cl.address,
+        TRUE AS succeeded,
+        NULL::TEXT AS reason,
+        cl.id
+    FROM client_logins AS cl
+        JOIN users AS u ON u.id = cl.user_id
+    UNION ALL
+    SELECT
+        fl.timestamp,
+        fl.login,
+        fl.address,
+        FALSE AS succeeded,
+        fl.reason,
+        fl.id
+    FROM failed_logins AS fl
+) AS attempts
+ORDER BY timestamp DESC, succeeded ASC, id DESC
+LIMIT $1
+;"#)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_chat_messages(
+        &self,
+        filter: &Option<String>,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        query_as::<_, DbChatMessage>(r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE $1::TEXT IS NULL OR u.login = $1
+ORDER BY timestamp DESC
+;"#)
+            .bind(filter)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_chat_message_by_id(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessage>, ServerError> {
+        query_as::<_, DbChatMessage>(r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE cm.id = $1
+;"#)
+            .bind(message_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, text), err)]
+    async fn insert_chat_message(
+        &self,
+        user_id: i64,
+        timestamp: &str,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> Result<i64, ServerError> {
+        query_scalar::<_, i64>(r#"
+INSERT INTO chat_messages
+(user_id, timestamp, text, reply_to)
+VALUES
+($1, $2, $3, $4)
+RETURNING id
+;"#)
+            .bind(user_id)
+            .bind(timestamp)
+            .bind(text)
+            .bind(reply_to)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_chat_message_author(
+        &self,
+        message_id: i64,
+    ) -> Result<Option<DbChatMessageAuthor>, ServerError> {
+        query_as::<_, DbChatMessageAuthor>(r#"
+SELECT user_id, deleted_at
+FROM chat_messages
+WHERE id = $1
+;"#)
+            .bind(message_id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, text), err)]
+    async fn update_chat_message_text(
+        &self,
+        message_id: i64,
+        text: &str,
+        edited_at: &str,
+    ) -> Result<(), ServerError> {
+        query(r#"
+UPDATE chat_messages
+SET text = $2, edited_at = $3
+WHERE id = $1
+;"#)
+            .bind(message_id)
+            .bind(text)
+            .bind(edited_at)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn tombstone_chat_message(
+        &self,
+        message_id: i64,
+        deleted_at: &str,
+    ) -> Result<(), ServerError> {
+        let mut transaction = self.pool.begin().await.map_err(db_error)?;
+
+        query(r#"
+DELETE FROM message_reactions
+WHERE message_id = $1
+;"#)
+            .bind(message_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+
+        query(r#"
+UPDATE chat_messages
+SET text = '', deleted_at = $2
+WHERE id = $1
+;"#)
+            .bind(message_id)
+            .bind(deleted_at)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+
+        transaction.commit().await.map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn insert_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+        timestamp: &str,
+    ) -> Result<bool, ServerError> {
+        query(r#"
+INSERT INTO message_reactions
+(message_id, user_id, emoji, timestamp)
+VALUES
+($1, $2, $3, $4)
+ON CONFLICT DO NOTHING
+;"#)
+            .bind(message_id)
+            .bind(user_id)
+            .bind(emoji)
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn delete_reaction(
+        &self,
+        message_id: i64,
+        user_id: i64,
+        emoji: &str,
+    ) -> Result<bool, ServerError> {
+        query(r#"
+DELETE FROM message_reactions
+WHERE
+    message_id = $1
+    AND
+    user_id = $2
+    AND
+    emoji = $3
+;"#)
+            .bind(message_id)
+            .bind(user_id)
+            .bind(emoji)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_prunable_chat_messages(
+        &self,
+        older_than: Option<&str>,
+        keep_last: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        query_as::<_, DbChatMessage>(r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    ($1::TEXT IS NOT NULL AND cm.timestamp < $1)
+    OR
+    ($2::BIGINT IS NOT NULL AND cm.id NOT IN (
+        SELECT id
+        FROM chat_messages
+        ORDER BY timestamp DESC, id DESC
+        LIMIT $2
+    ))
+ORDER BY cm.timestamp ASC, cm.id ASC
+LIMIT $3
+;"#)
+            .bind(older_than)
+            .bind(keep_last)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn delete_chat_messages(&self, message_ids: &[i64]) -> Result<u64, ServerError> {
+        let mut transaction = self.pool.begin().await.map_err(db_error)?;
+
+        query(r#"
+DELETE FROM message_reactions
+WHERE message_id = ANY($1)
+;"#)
+            .bind(message_ids)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+
+        query(r#"
+DELETE FROM mentions
+WHERE message_id = ANY($1)
+;"#)
+            .bind(message_ids)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+
+        query(r#"
+UPDATE chat_messages
+SET reply_to = NULL
+WHERE reply_to = ANY($1)
+;"#)
+            .bind(message_ids)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+
+        let deleted = query(r#"
+DELETE FROM chat_messages
+WHERE id = ANY($1)
+;"#)
+            .bind(message_ids)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?
+            .rows_affected();
+
+        transaction.commit().await.map_err(db_error)?;
+        Ok(deleted)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_prunable_logins(
+        &self,
+        older_than: &str,
+        limit: i64,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        query_as::<_, DbClientLogin>(r#"
+SELECT
+    cl.id AS id,
+    cl.user_id AS user_id,
+    u.login AS login,
+    cl.timestamp AS timestamp
+FROM
+    client_logins AS cl
+    JOIN users AS u ON u.id = cl.user_id
+WHERE cl.timestamp < $1
+ORDER BY cl.timestamp ASC, cl.id ASC
+LIMIT $2
+;"#)
+            .bind(older_than)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn delete_logins(&self, login_ids: &[i64]) -> Result<u64, ServerError> {
+        query(r#"
+DELETE FROM client_logins
+WHERE id = ANY($1)
+;"#)
+            .bind(login_ids)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn delete_failed_logins(&self, older_than: &str) -> Result<u64, ServerError> {
+        query(r#"
+DELETE FROM failed_logins
+WHERE timestamp < $1
+;"#)
+            .bind(older_than)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_chat_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbChatMessage>, ServerError> {
+        query_as::<_, DbChatMessage>(r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    cm.text AS text,
+    cm.edited_at AS edited_at,
+    cm.deleted_at AS deleted_at,
+    cm.reply_to AS reply_to
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE
+    ($1::TEXT IS NULL OR cm.timestamp >= $1)
+    AND ($2::TEXT IS NULL OR cm.timestamp < $2)
+    AND ($3::TEXT IS NULL OR u.login = $3)
+ORDER BY cm.timestamp ASC, cm.id ASC
+;"#)
+            .bind(&filter.since)
+            .bind(&filter.until)
+            .bind(&filter.login)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_login_history(
+        &self,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<DbClientLogin>, ServerError> {
+        query_as::<_, DbClientLogin>(r#"
+SELECT
+    cl.id AS id,
+    cl.user_id AS user_id,
+    u.login AS login,
+    cl.timestamp AS timestamp
+FROM
+    client_logins AS cl
+    JOIN users AS u ON u.id = cl.user_id
+WHERE
+    ($1::TEXT IS NULL OR cl.timestamp >= $1)
+    AND ($2::TEXT IS NULL OR cl.timestamp < $2)
+    AND ($3::TEXT IS NULL OR u.login = $3)
+ORDER BY cl.timestamp ASC, cl.id ASC
+;"#)
+            .bind(&filter.since)
+            .bind(&filter.until)
+            .bind(&filter.login)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self, user), fields(login = %user.login), err)]
+    async fn import_user(&self, user: &DbUser) -> Result<(i64, bool), ServerError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        let existing = query_scalar::<_, i64>(r#"
+SELECT id
+FROM users
+WHERE login = $1
+;"#)
+            .bind(&user.login)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if let Some(id) = existing {
+            return Ok((id, false));
+        }
+
+        let id = query_scalar::<_, i64>(r#"
+INSERT INTO users
+(login, password, role, display_name)
+VALUES
+($1, $2, $3, $4)
+RETURNING id
+;"#)
+            .bind(&user.login)
+            .bind(&user.password)
+            .bind(&user.role)
+            .bind(&user.display_name)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+        Ok((id, true))
+    }
+
+    #[instrument(level = "debug", skip(self, message), fields(timestamp = %message.timestamp), err)]
+    async fn import_chat_message(
+        &self,
+        user_id: i64,
+        message: &DbChatMessage,
+        reply_to: Option<i64>,
+    ) -> Result<(i64, bool), ServerError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        let existing = query_scalar::<_, i64>(r#"
+SELECT id
+FROM chat_messages
+WHERE user_id = $1 AND timestamp = $2 AND text = $3
+;"#)
+            .bind(user_id)
+            .bind(&message.timestamp)
+            .bind(&message.text)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if let Some(id) = existing {
+            return Ok((id, false));
+        }
+
+        let id = query_scalar::<_, i64>(r#"
+INSERT INTO chat_messages
+(user_id, timestamp, text, edited_at, deleted_at, reply_to)
+VALUES
+($1, $2, $3, $4, $5, $6)
+RETURNING id
+;"#)
+            .bind(user_id)
+            .bind(&message.timestamp)
+            .bind(&message.text)
+            .bind(&message.edited_at)
+            .bind(&message.deleted_at)
+            .bind(reply_to)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+        Ok((id, true))
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn import_login(&self, user_id: i64, timestamp: &str) -> Result<bool, ServerError> {
+        query(r#"
+INSERT INTO client_logins
+(user_id, timestamp)
+SELECT $1, $2
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM client_logins
+    WHERE user_id = $1 AND timestamp = $2
+)
+;"#)
+            .bind(user_id)
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn fetch_reaction_summaries(&self) -> Result<Vec<DbReactionSummary>, ServerError> {
+        query_as::<_, DbReactionSummary>(r#"
+SELECT
+    mr.message_id AS message_id,
+    mr.emoji AS emoji,
+    COUNT(*) AS count,
+    STRING_AGG(u.login, ', ' ORDER BY mr.id) AS logins
+FROM
+    message_reactions AS mr
+    JOIN users AS u ON u.id = mr.user_id
+GROUP BY mr.message_id, mr.emoji
+ORDER BY mr.message_id ASC, MIN(mr.id) ASC
+;"#)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)
+    }
+
+    #[instrument(level = "debug", skip(self), err)]
+    async fn search_chat_messages(
+        &self,
+        text: &str,
+        highlight: (&str, &str),
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<DbSearchHit>), ServerError> {
+        if text.split_whitespace().next().is_none() {
+            return Ok((0, vec![]));
+        }
+
+        let total = query_scalar::<_, i64>(r#"
+SELECT COUNT(*)
+FROM chat_messages
+WHERE to_tsvector('simple', text) @@ plainto_tsquery('simple', $1)
+;"#)
+            .bind(text)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        let (highlight_start, highlight_end) = highlight;
+        let headline_options = format!(
+            "StartSel=\"{}\", StopSel=\"{}\", MaxWords=12, MinWords=6, FragmentDelimiter=\"…\"",
+            highlight_start.replace('"', ""),
+            highlight_end.replace('"', ""),
+        );
+        let hits = query_as::<_, DbSearchHit>(r#"
+SELECT
+    cm.id AS id,
+    u.login AS login,
+    cm.timestamp AS timestamp,
+    ts_headline('simple', cm.text, plainto_tsquery('simple', $1), $2) AS snippet
+FROM
+    chat_messages AS cm
+    JOIN users AS u ON u.id = cm.user_id
+WHERE to_tsvector('simple', cm.text) @@ plainto_tsquery('simple', $1)
+ORDER BY cm.timestamp DESC, cm.id DESC
+LIMIT $3 OFFSET $4
+;"#)
+            .bind(text)
+            .bind(headline_options)
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        Ok((total, hits))
+    }
+}
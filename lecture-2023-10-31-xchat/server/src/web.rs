@@ -1,46 +1,96 @@
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use axum::{Router, routing::get, response::Html, Extension};
 use axum::{extract::Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use serde::Deserialize;
-use sqlx::SqlitePool;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tracing::Level;
 
+use crate::attachments::{day_start, format_bytes};
+use crate::backup::create_backup;
+use crate::config::{BackupConfig, Config, QuotasConfig};
 use crate::error::ServerError;
-use crate::db_queries::{fetch_chat_messages, fetch_users, delete_user_by_id};
+use crate::storage::{DbChatMessage, DbImageAttachment, SharedStorage};
 use crate::web_prometheus::{register_prometheus, prometheus_metrics_handler};
-use shared::concat;
+use shared::{concat, timestamp_to_string};
+
+
+/// Number of the newest log-in attempts shown on the audit page.
+const LOGIN_ATTEMPTS_LIMIT: i64 = 200;
+
+/// Number of the newest content filter hits shown on the audit page.
+const FILTER_HITS_LIMIT: i64 = 200;
+
+/// Markers of matches in search snippets. They are control characters kept by HTML escaping,
+/// so they are replaced by `mark` elements only after the snippet is escaped.
+const HIGHLIGHT_MARKERS: (&str, &str) = ("\u{2}", "\u{3}");
 
 
 struct AppState {
-    db_pool: SqlitePool,
+    storage: SharedStorage,
     host: String,
+    /// Number of hits on a single page of search results (`None` if search is disabled).
+    search_page_size: Option<i64>,
+    /// Configuration of backups (`None` if backups are disabled).
+    backup: Option<BackupConfig>,
+    quotas: QuotasConfig,
 }
 
 
 /// `start_web_server` is entrypoint for web server part of server crate.
 pub async fn start_web_server(
-    port_number: u16,
-    pool: SqlitePool,
+    config: &Config,
+    storage: SharedStorage,
 ) -> Result<(), ServerError> {
-    let address = format!("0.0.0.0:{}", port_number);
+    let address = config.web.address.clone();
 
     let state = Arc::new(AppState {
-        db_pool: pool,
+        storage,
         host: address.clone(),
+        search_page_size: config.features.search
+            .then_some(config.limits.web_search_page_size as i64),
+        backup: config.backup.dir.is_some().then(|| config.backup.clone()),
+        quotas: config.quotas.clone(),
     });
 
-    register_prometheus()?;
-
-    let router = Router::new()
+    let mut router = Router::new()
         .route("/", get(user_list))
         .route("/delete_user", get(delete_user))
-        .route("/metrics", get(prometheus_metrics_handler))
-        .layer(Extension(state));
+        .route("/sessions", get(session_list))
+        .route("/revoke_session", get(revoke_session))
+        .route("/logins", get(login_attempts))
+        .route("/attachments", get(attachment_usage))
+        .route("/attachment", get(attachment))
+        .route("/filter_hits", get(filter_hits));
+
+    if config.features.search {
+        router = router.route("/search", get(search));
+    }
 
-    let listener = match tokio::net::TcpListener::bind(address).await {
+    if config.backup.dir.is_some() {
+        router = router.route("/backup", get(backup));
+    }
+
+    if config.features.metrics {
+        register_prometheus()?;
+        router = router.route("/metrics", get(prometheus_metrics_handler));
+    }
+
+    let router = router
+        .layer(Extension(state))
+        .layer(TraceLayer::new_for_http()
+            .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+            .on_response(DefaultOnResponse::new().level(Level::INFO)));
+
+    let listener = match tokio::net::TcpListener::bind(&address).await {
         Ok(listener) => listener,
         Err(err) => Err(ServerError::WebServerError(err.to_string()))?,
     };
+    tracing::info!(%address, "web server listening");
 
     match axum::serve(listener, router.into_make_service()).await {
         Ok(_) => Ok(()),
@@ -60,8 +110,7 @@ async fn user_list(
     state: Extension<Arc<AppState>>,
     login_filter: Query<LoginFilterParam>,
 ) -> Html<String> {
-    let db_result = fetch_chat_messages(
-        &state.db_pool,
+    let db_result = state.storage.fetch_chat_messages(
         &login_filter.login,
     ).await;
     if let Err(_) = db_result {
@@ -69,12 +118,33 @@ async fn user_list(
     }
     let chat_messages = db_result.unwrap();
 
-    let db_result = fetch_users(&state.db_pool).await;
+    let db_result = state.storage.fetch_users().await;
     if let Err(_) = db_result {
         return Html("Failed to fetch user list!".to_string())
     }
     let users = db_result.unwrap();
 
+    let images = match state.storage.fetch_image_attachments(&login_filter.login).await {
+        Ok(images) => images,
+        Err(_) => return Html("Failed to fetch images!".to_string()),
+    };
+
+    let summaries = match state.storage.fetch_reaction_summaries().await {
+        Ok(summaries) => summaries,
+        Err(_) => return Html("Failed to fetch reactions!".to_string()),
+    };
+
+    // Reactions are aggregated per message (emoji with count, reacting users in a tooltip).
+    let mut reactions: HashMap<i64, Vec<String>> = HashMap::new();
+    for summary in summaries {
+        reactions.entry(summary.message_id).or_default().push(format!(
+            "<span title='{}'>{} {}</span>",
+            escape_html(&summary.logins),
+            escape_html(&summary.emoji),
+            summary.count,
+        ));
+    }
+
     // Preparing a links for filtering by login and user deletion.
     let mut filter_links: Vec<String> = vec![
         format!("<p>Login filter: <a href='http://{}/'>all</a>", state.host),
@@ -102,14 +172,40 @@ async fn user_list(
     filter_links_html.push_str("</p>");
     let mut delete_links_html = concat(&delete_links);
     delete_links_html.push_str("</p>");
+    let backup_link_html = match state.backup {
+        Some(_) => format!("<p><a href='http://{}/backup'>Create backup</a></p>", state.host),
+        None => String::new(),
+    };
+    let sessions_link_html =
+        format!("<p><a href='http://{}/sessions'>Active sessions</a></p>", state.host);
+    let logins_link_html =
+        format!("<p><a href='http://{}/logins'>Log-in attempts</a></p>", state.host);
+    let attachments_link_html =
+        format!("<p><a href='http://{}/attachments'>Attachment usage</a></p>", state.host);
+    let filter_hits_link_html =
+        format!("<p><a href='http://{}/filter_hits'>Content filter hits</a></p>", state.host);
 
     // Construction of the top-level page layout.
+    let search_form_html = match state.search_page_size {
+        Some(_) => search_form(&state.host, ""),
+        None => String::new(),
+    };
+
     let mut page: Vec<String> = vec![
+        search_form_html,
         filter_links_html,
         delete_links_html,
+        backup_link_html,
+        sessions_link_html,
+        logins_link_html,
+        attachments_link_html,
+        filter_hits_link_html,
         "<table>".to_string(),
         " <tr>".to_string(),
         "  <th>".to_string(),
+        "   id".to_string(),
+        "  </th>".to_string(),
+        "  <th>".to_string(),
         "   timestamp".to_string(),
         "  </th>".to_string(),
         "  <th>".to_string(),
@@ -118,34 +214,274 @@ async fn user_list(
         "  <th>".to_string(),
         "   message".to_string(),
         "  </th>".to_string(),
+        "  <th>".to_string(),
+        "   reactions".to_string(),
+        "  </th>".to_string(),
+        " </tr>".to_string(),
+    ];
+
+    // Replies are grouped into threads under their top-most parent message (if it is shown).
+    let parents: HashMap<i64, Option<i64>> = chat_messages
+        .iter()
+        .map(|chat_message| (chat_message.id, chat_message.reply_to))
+        .collect();
+    let thread_root = |mut id: i64| {
+        while let Some(Some(parent_id)) = parents.get(&id) {
+            if !parents.contains_key(parent_id) {
+                break
+            }
+            id = *parent_id;
+        }
+        id
+    };
+
+    let mut threads: HashMap<i64, Vec<&DbChatMessage>> = HashMap::new();
+    let mut top_level: Vec<&DbChatMessage> = vec![];
+    // Messages are ordered from the newest one, but threads are read from the oldest reply.
+    for chat_message in chat_messages.iter().rev() {
+        let root = thread_root(chat_message.id);
+        if root == chat_message.id {
+            top_level.push(chat_message);
+        } else {
+            threads.entry(root).or_default().push(chat_message);
+        }
+    }
+
+    // Construction of table row for each top-level chat message (followed by its thread), images
+    // are shown as thumbnails among the messages.
+    let mut images = images.iter().peekable();
+    for chat_message in top_level.into_iter().rev() {
+        while let Some(image) = images.next_if(|image| image.timestamp > chat_message.timestamp) {
+            page.append(&mut image_row(&state.host, image));
+        }
+        page.append(&mut chat_message_row(chat_message, &reactions));
+
+        if let Some(replies) = threads.get(&chat_message.id) {
+            page.push(" <tr>".to_string());
+            page.push("  <td></td>".to_string());
+            page.push("  <td colspan='4'>".to_string());
+            page.push(format!("   <details><summary>{} replies</summary>", replies.len()));
+            page.push("   <table>".to_string());
+            for reply in replies {
+                page.append(&mut chat_message_row(reply, &reactions));
+            }
+            page.push("   </table>".to_string());
+            page.push("   </details>".to_string());
+            page.push("  </td>".to_string());
+            page.push(" </tr>".to_string());
+        }
+    }
+
+    for image in images {
+        page.append(&mut image_row(&state.host, image));
+    }
+
+    page.push("</table>".to_string());
+
+    Html(concat(&page))
+}
+
+
+/// `image_row` construct a table row for a single image attachment (its thumbnail linked to
+/// the original).
+fn image_row(host: &str, image: &DbImageAttachment) -> Vec<String> {
+    let url = format!("http://{}/attachment?id={}", host, image.id);
+    let thumbnail = format!(
+        "<a href='{0}'><img src='{0}&thumbnail=true' alt='image {1}'></a>",
+        url,
+        image.id,
+    );
+
+    vec![
+        " <tr>".to_string(),
+        "  <td>".to_string(),
+        format!("   image {}", image.id),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", escape_html(&image.timestamp)),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", escape_html(&image.login)),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!(
+            "   {} <i>({}, {})</i>",
+            thumbnail,
+            escape_html(&image.mime),
+            format_bytes(image.size as u64),
+        ),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        "  </td>".to_string(),
+        " </tr>".to_string(),
+    ]
+}
+
+
+/// `chat_message_row` construct a table row for a single chat message.
+fn chat_message_row(
+    chat_message: &DbChatMessage,
+    reactions: &HashMap<i64, Vec<String>>,
+) -> Vec<String> {
+    // Deleted messages are shown as tombstones, edited ones with time of the last edit.
+    let mut text = match (&chat_message.deleted_at, &chat_message.edited_at) {
+        (Some(deleted_at), _) => format!("<i>deleted at {}</i>", escape_html(deleted_at)),
+        (None, Some(edited_at)) => format!(
+            "{} <i>(edited at {})</i>",
+            escape_html(&chat_message.text),
+            escape_html(edited_at),
+        ),
+        (None, None) => escape_html(&chat_message.text),
+    };
+    if let Some(reply_to) = chat_message.reply_to {
+        text = format!("<i>&#8618; #{}</i> {}", reply_to, text);
+    }
+
+    vec![
+        " <tr>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", chat_message.id),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", chat_message.timestamp),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", escape_html(&chat_message.login)),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!("   {}", text),
+        "  </td>".to_string(),
+        "  <td>".to_string(),
+        format!(
+            "   {}",
+            reactions.get(&chat_message.id).map(|r| r.join(" ")).unwrap_or_default(),
+        ),
+        "  </td>".to_string(),
         " </tr>".to_string(),
+    ]
+}
+
+
+/// `search_form` construct a form for full-text search over chat messages.
+fn search_form(host: &str, query: &str) -> String {
+    format!(
+        "<form action='http://{}/search'>\
+         <input type='search' name='q' value='{}'> <input type='submit' value='Search'>\
+         </form>",
+        host,
+        escape_html(query),
+    )
+}
+
+
+#[derive(Deserialize)]
+struct SearchParam {
+    q: Option<String>,
+    page: Option<i64>,
+}
+
+
+/// `search` is a web endpoint for full-text search over chat messages with highlighted snippets
+/// and pagination.
+async fn search(
+    state: Extension<Arc<AppState>>,
+    search_param: Query<SearchParam>,
+) -> Html<String> {
+    let query = search_param.q.clone().unwrap_or_default();
+    let page_number = search_param.page.unwrap_or(1).max(1);
+    let page_size = state.search_page_size.unwrap_or(1);
+
+    let mut page: Vec<String> = vec![
+        format!("<p><a href='http://{}/'>Return back to user list.</a></p>", state.host),
+        search_form(&state.host, &query),
     ];
 
-    // Construction of table row for each chat message.
-    for chat_message in chat_messages {
-        let mut line: Vec<String> = vec![
-            " <tr>".to_string(),
-            "  <td>".to_string(),
-            format!("   {}", chat_message.timestamp),
-            "  </td>".to_string(),
-            "  <td>".to_string(),
-            format!("   {}", chat_message.login),
-            "  </td>".to_string(),
-            "  <td>".to_string(),
-            format!("   {}", chat_message.text),
-            "  </td>".to_string(),
-            " </tr>".to_string(),
-        ];
-
-        page.append(&mut line);
+    if query.trim().is_empty() {
+        return Html(concat(&page));
     }
 
+    // Matches are highlighted in snippets by HTML `mark` element.
+    let result = state.storage.search_chat_messages(
+        &query,
+        HIGHLIGHT_MARKERS,
+        page_size,
+        (page_number - 1).saturating_mul(page_size),
+    ).await;
+    let (total, hits) = match result {
+        Ok(result) => result,
+        Err(_) => return Html("Failed to search chat messages!".to_string()),
+    };
+
+    let page_count = ((total + page_size - 1) / page_size).max(1);
+    page.push(format!("<p>Found {} messages (page {} of {}).</p>", total, page_number, page_count));
+
+    page.push("<table>".to_string());
+    page.push(" <tr><th>id</th><th>timestamp</th><th>user</th><th>message</th></tr>".to_string());
+    for hit in hits {
+        page.push(format!(
+            " <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            hit.id,
+            escape_html(&hit.timestamp),
+            escape_html(&hit.login),
+            highlighted_snippet(&hit.snippet),
+        ));
+    }
     page.push("</table>".to_string());
 
+    // Links to the previous and the next page (if there are any).
+    let mut pagination: Vec<String> = vec![];
+    if page_number > 1 {
+        pagination.push(format!(
+            "<a href='http://{}/search?q={}&page={}'>previous</a>",
+            state.host,
+            url_encode(&query),
+            page_number - 1,
+        ));
+    }
+    if page_number < page_count {
+        pagination.push(format!(
+            "<a href='http://{}/search?q={}&page={}'>next</a>",
+            state.host,
+            url_encode(&query),
+            page_number + 1,
+        ));
+    }
+    page.push(format!("<p>{}</p>", pagination.join(" | ")));
+
     Html(concat(&page))
 }
 
 
+/// `escape_html` escapes characters with special meaning in HTML (text and attribute values).
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+
+/// `highlighted_snippet` escapes the search snippet for HTML and encloses its matches (marked
+/// by [HIGHLIGHT_MARKERS]) in `mark` elements.
+fn highlighted_snippet(snippet: &str) -> String {
+    let (start, end) = HIGHLIGHT_MARKERS;
+    escape_html(snippet).replace(start, "<mark>").replace(end, "</mark>")
+}
+
+
+/// `url_encode` percent-encodes the given text to be used as a value of URL query parameter.
+fn url_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' =>
+                (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+
 #[derive(Deserialize)]
 struct UserDeleteParam {
     id: Option<i64>,
@@ -174,7 +510,7 @@ async fn delete_user(
     let param_user_id = user_delete_id.id.unwrap();
     let param_user_login = user_delete_id.login.clone().unwrap();
 
-    let db_result = fetch_users(&state.db_pool).await;
+    let db_result = state.storage.fetch_users().await;
     if let Err(_) = db_result {
         return Html("Failed to fetch user list.".to_string())
     }
@@ -185,7 +521,7 @@ async fn delete_user(
         return Html(format!("User not found. {}", go_back));
     };
 
-    let db_result = delete_user_by_id(&state.db_pool, param_user_id).await;
+    let db_result = state.storage.delete_user_by_id(param_user_id).await;
     if let Err(_) = db_result {
         return Html(format!("Failed to delete user. {}", go_back));
     };
@@ -197,3 +533,327 @@ async fn delete_user(
         go_back,
     ))
 }
+
+
+/// `backup` is a web endpoint that creates an online backup of the database (older backups
+/// over the configured limit are deleted).
+async fn backup(state: Extension<Arc<AppState>>) -> Html<String> {
+    let go_back = format!(
+        "<a href='http://{}'>Return back to user list.</a>",
+        state.host,
+    );
+
+    let Some(backup_config) = &state.backup else {
+        return Html(format!("Backups are disabled. {}", go_back));
+    };
+
+    match create_backup(state.storage.as_ref(), backup_config, SystemTime::now()).await {
+        Ok(path) => {
+            tracing::info!(path = %path.display(), "database backed up on request");
+            Html(format!("Successfully created backup {}. {}", path.display(), go_back))
+        },
+        Err(err) => {
+            tracing::warn!(error = %err, "backup of database failed");
+            Html(format!("Failed to create backup. {}", go_back))
+        },
+    }
+}
+
+
+/// `session_list` is a web endpoint listing active sessions of all users with links for their
+/// revocation.
+async fn session_list(state: Extension<Arc<AppState>>) -> Html<String> {
+    let now = timestamp_to_string(SystemTime::now());
+    let sessions = match state.storage.fetch_active_sessions(None, &now).await {
+        Ok(sessions) => sessions,
+        Err(_) => return Html("Failed to fetch sessions!".to_string()),
+    };
+
+    let mut page: Vec<String> = vec![
+        format!("<p><a href='http://{}/'>Return back to user list.</a></p>", state.host),
+        format!("<p>Active sessions ({}):</p>", sessions.len()),
+        "<table>".to_string(),
+        " <tr><th>id</th><th>user</th><th>created</th><th>expires</th><th></th></tr>".to_string(),
+    ];
+    for session in sessions {
+        page.push(format!(
+            " <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td><a href='http://{}/revoke_session?id={}'>revoke</a></td></tr>",
+            session.id,
+            escape_html(&session.login),
+            session.created_at,
+            session.expires_at,
+            state.host,
+            session.id,
+        ));
+    }
+    page.push("</table>".to_string());
+
+    Html(concat(&page))
+}
+
+
+/// `login_attempts` is a web endpoint listing the newest successful and failed log-in attempts
+/// with peer addresses of their clients.
+async fn login_attempts(state: Extension<Arc<AppState>>) -> Html<String> {
+    let attempts = match state.storage.fetch_login_attempts(LOGIN_ATTEMPTS_LIMIT).await {
+        Ok(attempts) => attempts,
+        Err(_) => return Html("Failed to fetch log-in attempts!".to_string()),
+    };
+
+    let mut page: Vec<String> = vec![
+        format!("<p><a href='http://{}/'>Return back to user list.</a></p>", state.host),
+        format!("<p>The newest log-in attempts ({}):</p>", attempts.len()),
+        "<table>".to_string(),
+        " <tr><th>timestamp</th><th>login</th><th>address</th><th>result</th></tr>".to_string(),
+    ];
+    for attempt in attempts {
+        let result = match (attempt.succeeded, &attempt.reason) {
+            (true, _) => "ok".to_string(),
+            (false, Some(reason)) => format!("failed ({})", escape_html(reason)),
+            (false, None) => "failed".to_string(),
+        };
+        page.push(format!(
+            " <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            attempt.timestamp,
+            escape_html(attempt.login.as_deref().unwrap_or("(session token)")),
+            escape_html(attempt.address.as_deref().unwrap_or("-")),
+            result,
+        ));
+    }
+    page.push("</table>".to_string());
+
+    Html(concat(&page))
+}
+
+
+/// `attachment_usage` is a web endpoint listing number and size of attachments sent by each
+/// user today (counted into daily quotas) and in total.
+async fn attachment_usage(state: Extension<Arc<AppState>>) -> Html<String> {
+    let today = day_start(&timestamp_to_string(SystemTime::now()));
+    let usage_today = match state.storage.fetch_attachment_usage(None, &today).await {
+        Ok(usage) => usage,
+        Err(_) => return Html("Failed to fetch attachment usage!".to_string()),
+    };
+    let usage_total = match state.storage.fetch_attachment_usage(None, "").await {
+        Ok(usage) => usage,
+        Err(_) => return Html("Failed to fetch attachment usage!".to_string()),
+    };
+
+    let limit = |value: u64, text: String| match value {
+        0 => "unlimited".to_string(),
+        _ => text,
+    };
+    let quotas = &state.quotas;
+    let mut page: Vec<String> = vec![
+        format!("<p><a href='http://{}/'>Return back to user list.</a></p>", state.host),
+        format!(
+            "<p>Daily quotas per user: {} attachments, {}.</p>",
+            limit(quotas.attachments_per_day as u64, quotas.attachments_per_day.to_string()),
+            limit(quotas.attachment_bytes_per_day, format_bytes(quotas.attachment_bytes_per_day)),
+        ),
+        "<table>".to_string(),
+        " <tr><th>user</th><th>today</th><th>size today</th><th>total</th><th>total size</th></tr>"
+            .to_string(),
+    ];
+    for total in usage_total {
+        let (count, bytes) = usage_today
+            .iter()
+            .find(|today| today.user_id == total.user_id)
+            .map_or((0, 0), |today| (today.count, today.bytes));
+        page.push(format!(
+            " <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape_html(&total.login),
+            count,
+            format_bytes(bytes as u64),
+            total.count,
+            format_bytes(total.bytes as u64),
+        ));
+    }
+    page.push("</table>".to_string());
+
+    Html(concat(&page))
+}
+
+
+/// `filter_hits` is a web endpoint listing the newest messages matched by content filter rules
+/// (just the rules and their actions, never the matched text).
+async fn filter_hits(state: Extension<Arc<AppState>>) -> Html<String> {
+    let hits = match state.storage.fetch_filter_hits(FILTER_HITS_LIMIT).await {
+        Ok(hits) => hits,
+        Err(_) => return Html("Failed to fetch content filter hits!".to_string()),
+    };
+
+    let mut page: Vec<String> = vec![
+        format!("<p><a href='http://{}/'>Return back to user list.</a></p>", state.host),
+        format!("<p>The newest content filter hits ({}):</p>", hits.len()),
+        "<table>".to_string(),
+        " <tr><th>timestamp</th><th>login</th><th>rule</th><th>action</th></tr>".to_string(),
+    ];
+    for hit in hits {
+        page.push(format!(
+            " <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            hit.timestamp,
+            escape_html(&hit.login),
+            escape_html(&hit.rule),
+            escape_html(&hit.action),
+        ));
+    }
+    page.push("</table>".to_string());
+
+    Html(concat(&page))
+}
+
+
+#[derive(Deserialize)]
+struct AttachmentParam {
+    id: i64,
+    thumbnail: Option<bool>,
+}
+
+
+/// `attachment` is a web endpoint serving the original (or the thumbnail) of an image
+/// attachment.
+async fn attachment(
+    state: Extension<Arc<AppState>>,
+    param: Query<AttachmentParam>,
+) -> Response {
+    let content = match state.storage.fetch_attachment_content(param.id).await {
+        Ok(Some(content)) => content,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No such image!").into_response(),
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch image!").into_response()
+        },
+    };
+
+    let (mime, body) = match param.thumbnail {
+        Some(true) => ("image/png".to_string(), content.thumbnail),
+        _ => (content.mime, content.payload),
+    };
+    let headers = [
+        (header::CONTENT_TYPE, mime),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    (headers, body).into_response()
+}
+
+
+#[derive(Deserialize)]
+struct SessionRevokeParam {
+    id: Option<i64>,
+}
+
+
+/// `revoke_session` is a web endpoint that revokes a single session by ID (its client is
+/// disconnected by the chat server within `sessions.check_interval_secs`).
+async fn revoke_session(
+    state: Extension<Arc<AppState>>,
+    session_revoke: Query<SessionRevokeParam>,
+) -> Html<String> {
+    let go_back = format!(
+        "<a href='http://{}/sessions'>Return back to session list.</a>",
+        state.host,
+    );
+
+    let Some(session_id) = session_revoke.id else {
+        return Html(format!("Missing id parameter. {}", go_back));
+    };
+
+    let revoked_at = timestamp_to_string(SystemTime::now());
+    match state.storage.revoke_session(session_id, &revoked_at).await {
+        Ok(true) => {
+            tracing::info!(session_id, "session revoked");
+            Html(format!("Successfully revoked session {}. {}", session_id, go_back))
+        },
+        Ok(false) => Html(format!("Session not found or already revoked. {}", go_back)),
+        Err(_) => Html(format!("Failed to revoke session. {}", go_back)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::Extension;
+    use axum::extract::Query;
+
+    use super::{image_row, search, user_list, AppState, LoginFilterParam, SearchParam};
+    use crate::config::QuotasConfig;
+    use crate::storage::{DbImageAttachment, Storage};
+    use crate::storage_memory::MemoryStorage;
+
+
+    /// `app_state` returns state of the web server using the given storage.
+    fn app_state(storage: MemoryStorage) -> Arc<AppState> {
+        Arc::new(AppState {
+            storage: Arc::new(storage),
+            host: "localhost:8080".to_string(),
+            search_page_size: Some(10),
+            backup: None,
+            quotas: QuotasConfig::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_search_escapes_messages() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("<b>mallory</b>", "", "user");
+        let text = "look <script>alert(1)</script> here";
+        storage.insert_chat_message(user_id, "2023-12-01", text, None).await.unwrap();
+
+        let state = app_state(storage);
+        let param = SearchParam { q: Some("look".to_string()), page: None };
+        let html = search(Extension(state), Query(param)).await.0;
+
+        assert!(html.contains("<mark>look</mark> &lt;script&gt;alert(1)&lt;/script&gt; here"));
+        assert!(html.contains("&lt;b&gt;mallory&lt;/b&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[tokio::test]
+    async fn test_user_list_escapes_edited_messages() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("mallory", "", "user");
+        let id = storage.insert_chat_message(user_id, "2023-12-01", "hi", None).await.unwrap();
+        let text = "<script>alert(1)</script>";
+        storage.update_chat_message_text(id, text, "2023-12-02").await.unwrap();
+
+        let state = app_state(storage);
+        let html = user_list(Extension(state), Query(LoginFilterParam { login: None })).await.0;
+
+        let escaped = "&lt;script&gt;alert(1)&lt;/script&gt; <i>(edited at 2023-12-02)</i>";
+        assert!(html.contains(escaped));
+        assert!(!html.contains("<script>"));
+    }
+
+    #[tokio::test]
+    async fn test_user_list_escapes_reactions() {
+        let storage = MemoryStorage::new();
+        let user_id = storage.insert_user("mallory", "", "user");
+        let reacting_id = storage.insert_user("o'neil<b>", "", "user");
+        let id = storage.insert_chat_message(user_id, "2023-12-01", "hi", None).await.unwrap();
+        storage.insert_reaction(id, reacting_id, "👍", "2023-12-02").await.unwrap();
+
+        let state = app_state(storage);
+        let html = user_list(Extension(state), Query(LoginFilterParam { login: None })).await.0;
+
+        assert!(html.contains("<span title='o&#39;neil&lt;b&gt;'>👍 1</span>"));
+    }
+
+    #[test]
+    fn test_image_row_escapes_login() {
+        let image = DbImageAttachment {
+            id: 1,
+            login: "<b>eve</b>".to_string(),
+            timestamp: "2023-12-01".to_string(),
+            mime: "image/png".to_string(),
+            size: 2048,
+        };
+        let html = image_row("localhost:8080", &image).join("\n");
+
+        assert!(html.contains("&lt;b&gt;eve&lt;/b&gt;"));
+        assert!(!html.contains("<b>"));
+    }
+}
@@ -1,7 +1,7 @@
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use lazy_static::lazy_static;
-use prometheus::{Encoder, TextEncoder, IntCounter, IntGauge};
+use prometheus::{Encoder, TextEncoder, IntCounter, IntCounterVec, IntGauge, Opts};
 use crate::error::ServerError;
 
 
@@ -26,6 +26,57 @@ lazy_static! {
         "How many authorizations from clients failed."
     ).unwrap();
 
+    pub static ref LOCKED_OUT_LOGIN_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_locked_out_login",
+        "How many log-ins were refused due to lockout after failed log-ins."
+    ).unwrap();
+
+    pub static ref PRUNED_CHAT_MESSAGE_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_pruned_chat_message",
+        "How many chat messages were pruned by retention policy."
+    ).unwrap();
+
+    pub static ref PRUNED_CLIENT_LOGIN_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_pruned_client_login",
+        "How many log-in records were pruned by retention policy."
+    ).unwrap();
+
+    pub static ref BACKUP_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_backup",
+        "How many online backups of the database were created."
+    ).unwrap();
+
+    pub static ref FEDERATED_EVENT_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_federated_event",
+        "How many events were received from linked servers (without duplicates)."
+    ).unwrap();
+
+    pub static ref FILTER_HIT_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_metrics_counter_filter_hit",
+            "How many times content filter rules matched messages (by rule and action)."
+        ),
+        &["rule", "action"],
+    ).unwrap();
+
+    pub static ref REFUSED_CONNECTION_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "http_metrics_counter_refused_connection",
+            "How many connections were refused due to connection limits (by the limit)."
+        ),
+        &["limit"],
+    ).unwrap();
+
+    pub static ref AUTH_DEADLINE_COUNTER: IntCounter = IntCounter::new(
+        "http_metrics_counter_auth_deadline_exceeded",
+        "How many connections were closed because they did not log in in time."
+    ).unwrap();
+
+    pub static ref FEDERATION_LINK_GAUGE: IntGauge = IntGauge::new(
+        "http_metrics_gauge_federation_link",
+        "How many links to other servers are currently up."
+    ).unwrap();
+
     pub static ref CURRENT_CLIENT_COUNT_GAUGE: IntGauge = IntGauge::new(
         "http_metrics_gauge_current_client_count",
         "How many clients are currently connected."
@@ -39,6 +90,12 @@ pub fn register_prometheus() -> Result<(), ServerError> {
         Box::new(MESSAGE_COUNTER.clone()),
         Box::new(SUCCESSFUL_CONNECTION_COUNTER.clone()),
         Box::new(NOT_AUTHORIZED_CONNECTION_COUNTER.clone()),
+        Box::new(LOCKED_OUT_LOGIN_COUNTER.clone()),
+        Box::new(PRUNED_CHAT_MESSAGE_COUNTER.clone()),
+        Box::new(PRUNED_CLIENT_LOGIN_COUNTER.clone()),
+        Box::new(BACKUP_COUNTER.clone()),
+        Box::new(FEDERATED_EVENT_COUNTER.clone()),
+        Box::new(AUTH_DEADLINE_COUNTER.clone()),
     ];
 
     for counter in counters {
@@ -47,8 +104,20 @@ pub fn register_prometheus() -> Result<(), ServerError> {
         }
     };
 
+    let counter_vecs = vec![
+        Box::new(FILTER_HIT_COUNTER.clone()),
+        Box::new(REFUSED_CONNECTION_COUNTER.clone()),
+    ];
+
+    for counter_vec in counter_vecs {
+        if let Err(err) = prometheus::default_registry().register(counter_vec) {
+            Err(ServerError::PrometheusRegistrationError(err.to_string()))?;
+        }
+    };
+
     let gauges = vec![
         Box::new(CURRENT_CLIENT_COUNT_GAUGE.clone()),
+        Box::new(FEDERATION_LINK_GAUGE.clone()),
     ];
 
     for gauge in gauges {
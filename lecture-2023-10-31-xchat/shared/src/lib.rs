@@ -1,9 +1,22 @@
+mod mention;
 mod message;
 mod panic;
+mod reaction;
 mod timestamp;
 
-pub use message::Message;
+pub use mention::{is_mentioned, parse_mentions};
+pub use message::{
+    Attachment,
+    FederatedEvent,
+    FederatedKind,
+    Mention,
+    Message,
+    OnlineUser,
+    Quote,
+    SearchHit,
+};
 pub use panic::panic_to_text;
+pub use reaction::is_valid_reaction;
 pub use timestamp::timestamp_to_string;
 
 
@@ -0,0 +1,58 @@
+/// `parse_mentions` returns logins mentioned in the text as `@login` (in order of their first
+/// occurrence, without case-insensitive duplicates). A mention starts with `@` not preceded by
+/// an alphanumeric character (so e-mail addresses are not mentions) and it consists of ASCII
+/// alphanumeric characters, `_`, `-` and `.` (trailing `.` and `-` end the sentence instead).
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = vec![];
+    let mut previous: Option<char> = None;
+
+    for (index, c) in text.char_indices() {
+        let starts_mention = c == '@' && !previous.is_some_and(|c| c.is_alphanumeric());
+        previous = Some(c);
+        if !starts_mention {
+            continue
+        }
+
+        let rest = &text[index + 1..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')))
+            .unwrap_or(rest.len());
+        let login = rest[..end].trim_end_matches(['.', '-']);
+        if !login.is_empty() && !mentions.iter().any(|known| known.eq_ignore_ascii_case(login)) {
+            mentions.push(login.to_string());
+        }
+    }
+
+    mentions
+}
+
+
+/// `is_mentioned` tells whether the text mentions the given login (case-insensitively).
+pub fn is_mentioned(text: &str, login: &str) -> bool {
+    parse_mentions(text).iter().any(|mention| mention.eq_ignore_ascii_case(login))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{is_mentioned, parse_mentions};
+
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!(parse_mentions("hi @TheOne and @just_two."), vec!["TheOne", "just_two"]);
+        assert_eq!(parse_mentions("@a @A @b-c- (@d.e)"), vec!["a", "b-c", "d.e"]);
+        assert!(parse_mentions("mail me at me@example.com").is_empty());
+        assert!(parse_mentions("@ @. @@").is_empty());
+        assert_eq!(parse_mentions("@TheOne@brno"), vec!["TheOne"]);
+        assert_eq!(parse_mentions("žluť@no ž @ok"), vec!["ok"]);
+    }
+
+
+    #[test]
+    fn test_is_mentioned() {
+        assert!(is_mentioned("ping @theone", "TheOne"));
+        assert!(!is_mentioned("ping @theones", "TheOne"));
+        assert!(!is_mentioned("ping theone", "TheOne"));
+    }
+}
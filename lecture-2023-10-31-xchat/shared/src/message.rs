@@ -19,22 +19,345 @@ pub enum Message {
         pass: String,
     },
 
+    /// Login by a token of an existing session instead of password (client -> server). It is
+    /// answered the same way as [Message::Login].
+    Resume{
+        token: String,
+    },
+
     /// Welcome message (server -> client).
     Welcome{
         motd: String,
     },
 
+    /// Session of the logged-in client sent after [Message::Welcome] (server -> client).
+    /// The token may be used by [Message::Resume] until the session expires or it is revoked.
+    Session{
+        login: String,
+        token: String,
+        expires_at: String,
+    },
+
     /// Simple text message (server <-> client).
     Text(String),
 
-    /// Content of image file (server <-> client).
+    /// Content of image file (client -> server).
     Image(Vec<u8>),
 
-    /// General file to be transferred (server <-> client).
+    /// General file to be transferred (client -> server).
     File{
         filename: String,
         payload: Vec<u8>,
-    }
+    },
+
+    /// File or image of another user validated by the server (server -> client). Images come
+    /// just with a thumbnail, their originals are sent as a response to [Message::Download].
+    Attachment(Attachment),
+
+    /// Request for the original of an image attachment of the given ID (client -> server).
+    Download{
+        id: i64,
+    },
+
+    /// Request for the list of currently online users (client -> server).
+    Who,
+
+    /// List of currently online users as a response to [Message::Who] (server -> client).
+    OnlineUsers{
+        users: Vec<OnlineUser>,
+    },
+
+    /// Notification about a user that has just logged in (server -> client).
+    UserJoined{
+        login: String,
+        timestamp: String,
+    },
+
+    /// Notification about a user that has just disconnected (server -> client).
+    UserLeft{
+        login: String,
+        timestamp: String,
+    },
+
+    /// Lightweight signal that the sender is composing a message (client -> server).
+    /// It is never persisted.
+    Typing{
+        /// Recipient of the direct message being composed (the room if missing).
+        to: Option<String>,
+    },
+
+    /// Notification that the given user is composing a message (server -> client), either
+    /// a direct message to the receiving user or a message to the room.
+    UserTyping{
+        login: String,
+        to: Option<String>,
+    },
+
+    /// Text message stored by the server together with its server-side ID (server -> client).
+    /// Replies carry a quote of the message they reply to.
+    Chat{
+        id: i64,
+        login: String,
+        /// Display name of the author set by `/nick` (if any).
+        display_name: Option<String>,
+        timestamp: String,
+        text: String,
+        reply_to: Option<Quote>,
+    },
+
+    /// Action of a user written in third person (`/me waves`) (server -> client). Actions are
+    /// not stored.
+    Action{
+        login: String,
+        display_name: Option<String>,
+        timestamp: String,
+        text: String,
+    },
+
+    /// Direct message of a user to another user (`/msg`) delivered to all sessions of both
+    /// of them (server -> client).
+    Direct{
+        login: String,
+        display_name: Option<String>,
+        to: String,
+        timestamp: String,
+        text: String,
+    },
+
+    /// Number of direct messages sent to the user while they were offline; the messages
+    /// follow right after (server -> client).
+    QueuedDirects{
+        count: u32,
+    },
+
+    /// Notification about changed (or cleared) display name of a user (server -> client).
+    NickChanged{
+        login: String,
+        display_name: Option<String>,
+    },
+
+    /// Topic of the room sent after [Message::Welcome] and whenever it is changed
+    /// (server -> client). Empty topic means it was cleared.
+    Topic{
+        topic: String,
+        login: String,
+        timestamp: String,
+    },
+
+    /// Text message replying to the message with the given ID (client -> server).
+    Reply{
+        reply_to: i64,
+        text: String,
+    },
+
+    /// Request for replacement of text of the given message (client -> server).
+    Edit{
+        id: i64,
+        text: String,
+    },
+
+    /// Request for deletion of the given message (client -> server).
+    Delete{
+        id: i64,
+    },
+
+    /// Notification about an edited message (server -> client).
+    Edited{
+        id: i64,
+        text: String,
+        timestamp: String,
+    },
+
+    /// Notification about a deleted message (server -> client).
+    Deleted{
+        id: i64,
+        timestamp: String,
+    },
+
+    /// Request for adding a reaction (Unicode emoji or `:shortcode:`) to the given message
+    /// (client -> server).
+    AddReaction{
+        id: i64,
+        emoji: String,
+    },
+
+    /// Request for removal of own reaction from the given message (client -> server).
+    RemoveReaction{
+        id: i64,
+        emoji: String,
+    },
+
+    /// Notification about a reaction added to the given message (server -> client).
+    ReactionAdded{
+        id: i64,
+        login: String,
+        emoji: String,
+    },
+
+    /// Notification about a reaction removed from the given message (server -> client).
+    ReactionRemoved{
+        id: i64,
+        login: String,
+        emoji: String,
+    },
+
+    /// Notification that the receiver was mentioned (`@login`) in a chat message
+    /// (server -> client).
+    Mentioned(Mention),
+
+    /// Request for the list of unread mentions of the sender; listed mentions become read
+    /// (client -> server).
+    UnreadMentions,
+
+    /// Unread mentions (oldest first) as a response to [Message::UnreadMentions]
+    /// (server -> client).
+    MentionList{
+        mentions: Vec<Mention>,
+    },
+
+    /// Request for full-text search over chat history; `page` is numbered from 1
+    /// (client -> server).
+    Search{
+        query: String,
+        page: u32,
+    },
+
+    /// A single page of results as a response to [Message::Search] (server -> client).
+    SearchResults{
+        query: String,
+        page: u32,
+        /// Total number of found messages (on all pages).
+        total: i64,
+        hits: Vec<SearchHit>,
+    },
+
+    /// Authentication of a federation link by the connecting server (server -> server).
+    LinkHello{
+        server: String,
+        secret: String,
+    },
+
+    /// Acceptance of a federation link by the other server (server -> server).
+    LinkWelcome{
+        server: String,
+    },
+
+    /// Keep-alive of a federation link (server -> server).
+    LinkPing,
+
+    /// Event of the chat room relayed over a federation link (server -> server).
+    Federated(FederatedEvent),
+
+    /// Informational answer meant just for the receiver, e.g. output of a server command
+    /// (server -> client).
+    Info(String),
+
+    /// Error report of a rejected request (server -> client).
+    Error(String),
+}
+
+
+/// `Quote` is a short excerpt of a message being replied to.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Quote {
+    pub id: i64,
+    pub login: String,
+    pub excerpt: String,
+}
+
+
+/// `Attachment` is a file ([Message::File]) or an image ([Message::Image]) relayed by the
+/// server together with its MIME type sniffed from the content.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Attachment {
+    /// Server-side ID of the attachment (to download the original of an image).
+    pub id: i64,
+    pub login: String,
+    pub timestamp: String,
+    /// Name of the file (without directories), missing for images.
+    pub filename: Option<String>,
+    pub mime: String,
+    /// Size of the original content in bytes.
+    pub size: u64,
+    /// Content of a file or of a downloaded image, empty if just the thumbnail is sent.
+    pub payload: Vec<u8>,
+    /// Small PNG preview of an image (missing for files and downloaded images).
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+
+/// `Mention` is a chat message mentioning a user (with an excerpt of its text).
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Mention {
+    pub id: i64,
+    pub login: String,
+    pub timestamp: String,
+    pub excerpt: String,
+}
+
+
+/// `SearchHit` is a single chat message found by [Message::Search]. Matched words in `snippet`
+/// are enclosed in `**`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SearchHit {
+    pub id: i64,
+    pub login: String,
+    pub timestamp: String,
+    pub snippet: String,
+}
+
+
+/// `FederatedEvent` is an event of the chat room relayed between linked servers.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FederatedEvent {
+    /// Random ID of the event (unique together with `origin`), so duplicates are dropped.
+    pub id: u64,
+    /// Name of the server where the event happened.
+    pub origin: String,
+    /// Names of servers the event passed through (starting with `origin`).
+    pub via: Vec<String>,
+    pub kind: FederatedKind,
+}
+
+
+/// `FederatedKind` is content of [FederatedEvent]. Logins are local to the origin server.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum FederatedKind {
+    Chat{
+        login: String,
+        display_name: Option<String>,
+        timestamp: String,
+        text: String,
+    },
+    Action{
+        login: String,
+        display_name: Option<String>,
+        timestamp: String,
+        text: String,
+    },
+    Joined{
+        user: OnlineUser,
+    },
+    Left{
+        login: String,
+        timestamp: String,
+    },
+    /// Complete list of users online on the origin server (sent when a link is established).
+    Roster{
+        users: Vec<OnlineUser>,
+    },
+}
+
+
+/// `OnlineUser` describes a single authenticated client connected to the server.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OnlineUser {
+    pub login: String,
+    /// Display name set by `/nick` (if any).
+    #[serde(default)]
+    pub display_name: Option<String>,
+    /// Timestamp of successful log-in (see [crate::timestamp_to_string]).
+    pub connected_at: String,
 }
 
 
@@ -216,5 +539,22 @@ mod tests {
         assert!(decoded.as_ref().is_ok());
         assert_eq!(decoded.unwrap(), sample_file);
     }
-}
 
+
+    #[test]
+    fn test_serialization_of_who() {
+        let sample_who: Message = Message::Who;
+        let expected: Vec<u8> = vec![
+            0x63,                           // text(3)
+            0x57, 0x68, 0x6f,                 // "Who"
+        ];
+
+        let encoded = sample_who.serialize();
+        assert!(encoded.as_ref().is_ok());
+        assert_eq!(encoded.as_ref().unwrap(), &expected);
+
+        let decoded = Message::deserialize(&encoded.unwrap()[..]);
+        assert!(decoded.as_ref().is_ok());
+        assert_eq!(decoded.unwrap(), sample_who);
+    }
+}
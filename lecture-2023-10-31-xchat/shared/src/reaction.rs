@@ -0,0 +1,57 @@
+/// Maximal number of characters of a Unicode emoji reaction (enough for ZWJ sequences).
+const MAX_EMOJI_CHARS: usize = 10;
+
+/// Maximal length of a `:shortcode:` reaction (including both colons).
+const MAX_SHORTCODE_LEN: usize = 32;
+
+
+/// `is_valid_reaction` checks that the given reaction is either a `:shortcode:` (ASCII
+/// alphanumeric characters, `_`, `+` or `-` enclosed in colons) or a short sequence of non-ASCII
+/// characters without any whitespace (i.e. Unicode emoji, possibly with modifiers).
+pub fn is_valid_reaction(reaction: &str) -> bool {
+    if reaction.starts_with(':') {
+        return reaction.len() > 2
+            && reaction.len() <= MAX_SHORTCODE_LEN
+            && reaction.ends_with(':')
+            && reaction[1..reaction.len() - 1]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'));
+    }
+
+    let count = reaction.chars().count();
+    count > 0
+        && count <= MAX_EMOJI_CHARS
+        && reaction.chars().all(|c| !c.is_ascii() && !c.is_whitespace())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_reaction;
+
+
+    #[test]
+    fn test_is_valid_reaction_with_shortcode() {
+        assert!(is_valid_reaction(":thumbsup:"));
+        assert!(is_valid_reaction(":+1:"));
+        assert!(is_valid_reaction(":man-shrugging:"));
+
+        assert!(!is_valid_reaction("::"));
+        assert!(!is_valid_reaction(":thumbsup"));
+        assert!(!is_valid_reaction(":thumbs up:"));
+        assert!(!is_valid_reaction(":<script>:"));
+    }
+
+
+    #[test]
+    fn test_is_valid_reaction_with_emoji() {
+        assert!(is_valid_reaction("👍"));
+        assert!(is_valid_reaction("👍🏽"));
+        assert!(is_valid_reaction("👨‍👩‍👧‍👦"));
+
+        assert!(!is_valid_reaction(""));
+        assert!(!is_valid_reaction("ok"));
+        assert!(!is_valid_reaction("👍 👍"));
+        assert!(!is_valid_reaction("👍👍👍👍👍👍👍👍👍👍👍"));
+    }
+}